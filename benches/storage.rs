@@ -0,0 +1,132 @@
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use prompt_wrapper::config::{StorageConfig, StorageFallbackPolicy, StorageType};
+use prompt_wrapper::models::{CacheKey, Saying, SayingSource};
+use prompt_wrapper::storage::Storage;
+
+// Sayings per user when filling storage, so every size has the same history lengths and
+// only the total number of records changes. Also the history limit, so saving a saying
+// drops the oldest one and the dataset stays the same size while it's measured.
+const SAYINGS_PER_USER: usize = 100;
+
+// Step through the preloaded sayings with a stride so reads don't walk Sled's pages in order
+const STRIDE: usize = 7919;
+
+// Saying counts to fill each backend with, overridden by STORAGE_BENCH_SIZES (comma-separated)
+const DEFAULT_SIZES: [usize; 2] = [10_000, 100_000];
+
+// Compare memory and Sled storage on get_saying_by_id, find_cached_saying and save_saying,
+// with each backend preloaded with every size in turn
+fn storage_benches(criterion: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+    let dir = tempfile::tempdir().unwrap();
+
+    for size in sizes() {
+        for (name, type_) in [("memory", StorageType::Memory), ("sled", StorageType::Sled)] {
+            let storage = open(type_, &dir.path().join(format!("{}-{}", name, size)));
+            println!("Filling {} storage with {} sayings", name, size);
+            let stored = runtime.block_on(fill(&storage, size));
+
+            let mut group = criterion.benchmark_group(format!("storage/{}/{}", name, size));
+            group.throughput(Throughput::Elements(1));
+
+            let mut i = 0;
+            group.bench_function("get_saying_by_id", |b| {
+                b.iter(|| {
+                    i = (i + STRIDE) % stored.len();
+                    runtime.block_on(storage.get_saying_by_id(&stored[i].0)).unwrap().expect("preloaded saying not found")
+                })
+            });
+
+            let mut i = 0;
+            group.bench_function("find_cached_saying", |b| {
+                b.iter(|| {
+                    i = (i + STRIDE) % stored.len();
+                    runtime.block_on(storage.find_cached_saying(&stored[i].1)).unwrap().expect("preloaded saying not cached")
+                })
+            });
+
+            // Last, since it replaces preloaded sayings. Each save goes to a full history, so
+            // Sled rewrites it and prunes the oldest saying; the new one takes the cache entry
+            // of the saying it copies, so the cache doesn't grow either.
+            let mut next = 0;
+            group.bench_function("save_saying", |b| {
+                b.iter_batched(
+                    || {
+                        next += 1;
+                        (user_id(next % size), Arc::new(Saying { id: uuid::Uuid::new_v4().to_string(), ..saying(next % size) }))
+                    },
+                    |(user_id, saying)| runtime.block_on(storage.save_saying(&user_id, saying)).unwrap(),
+                    BatchSize::SmallInput,
+                )
+            });
+
+            group.finish();
+        }
+    }
+}
+
+fn sizes() -> Vec<usize> {
+    match std::env::var("STORAGE_BENCH_SIZES") {
+        Ok(sizes) => sizes
+            .split(',')
+            .map(|size| size.trim().parse().expect("STORAGE_BENCH_SIZES must be comma-separated saying counts"))
+            .collect(),
+        Err(_) => DEFAULT_SIZES.to_vec(),
+    }
+}
+
+fn open(type_: StorageType, path: &Path) -> Storage {
+    Storage::new(StorageConfig {
+        connection_string: match type_ {
+            StorageType::Sled => path.to_string_lossy().into_owned(),
+            _ => "memory".to_string(),
+        },
+        type_,
+        dedupe_by_content: false,
+        max_sayings_per_user: SAYINGS_PER_USER,
+        retention_days: 0,
+        // A benchmark of the wrong backend is worse than none
+        fallback_policy: StorageFallbackPolicy::Fail,
+        compression_level: 0,
+        user_id_salt: None,
+    })
+    .unwrap()
+}
+
+// Save `size` sayings, returning the ID and cache key of each
+async fn fill(storage: &Storage, size: usize) -> Vec<(String, CacheKey)> {
+    let mut stored = Vec::with_capacity(size);
+    for n in 0..size {
+        let saying = saying(n);
+        stored.push((saying.id.clone(), CacheKey::from_saying(&saying)));
+        storage.save_saying(&user_id(n), Arc::new(saying)).await.unwrap();
+    }
+    stored
+}
+
+fn user_id(n: usize) -> String {
+    format!("bench_user_{}", n / SAYINGS_PER_USER)
+}
+
+// Not from the model, so it also goes into the global cache
+fn saying(n: usize) -> Saying {
+    Saying {
+        preset_id: Some(format!("preset_{}", n % 10)),
+        ..Saying::new(
+            format!("Saying number {}, about the length of a typical generated one.", n),
+            format!("Prompt number {}", n),
+            SayingSource::Database,
+        )
+    }
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(20).measurement_time(Duration::from_secs(5));
+    targets = storage_benches
+}
+criterion_main!(benches);
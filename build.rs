@@ -0,0 +1,14 @@
+fn main() {
+    // The gRPC stubs are only generated when the `grpc` feature is enabled
+    #[cfg(feature = "grpc")]
+    {
+        // Use the vendored protoc so building doesn't require a system install
+        let protoc = protoc_bin_vendored::protoc_bin_path().expect("Failed to locate vendored protoc");
+        std::env::set_var("PROTOC", protoc);
+
+        tonic_build::configure()
+            .build_client(false)
+            .compile_protos(&["proto/prompt_wrapper.proto"], &["proto"])
+            .expect("Failed to compile protobuf definitions");
+    }
+}
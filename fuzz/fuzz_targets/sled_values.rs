@@ -0,0 +1,52 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::sync::Arc;
+
+// The service is a binary crate, so the models are compiled in here directly. These two are
+// all models.rs takes from the rest of the crate; notification settings aren't fuzzed.
+mod email {
+    pub type EmailPreferences = serde_json::Value;
+}
+mod notifier {
+    pub type NotificationTarget = serde_json::Value;
+}
+
+#[path = "../../src/models.rs"]
+#[allow(dead_code)]
+mod models;
+
+#[path = "../../src/compression.rs"]
+#[allow(dead_code)]
+mod compression;
+
+use models::{CacheKey, Collection, Conversation, PresetSelectionRecord, Saying, UserStats};
+
+// Whatever bytes a Sled tree holds, decoding fails cleanly instead of panicking, and what
+// does decode is written back unchanged
+fn check<T: Serialize + DeserializeOwned>(data: &[u8]) {
+    let Ok(value) = serde_json::from_slice::<T>(data) else {
+        return;
+    };
+    let encoded = serde_json::to_value(&value).expect("decoded value doesn't serialize");
+    let decoded: T = serde_json::from_value(encoded.clone()).expect("re-encoded value doesn't decode");
+    assert_eq!(serde_json::to_value(&decoded).unwrap(), encoded);
+}
+
+fuzz_target!(|data: &[u8]| {
+    // Compressed values are checked as the JSON they hold
+    let Ok(data) = compression::decode(data) else {
+        return;
+    };
+    let data = data.as_ref();
+
+    check::<Saying>(data);
+    check::<Vec<Arc<Saying>>>(data);
+    check::<CacheKey>(data);
+    check::<UserStats>(data);
+    check::<Collection>(data);
+    check::<Conversation>(data);
+    check::<PresetSelectionRecord>(data);
+});
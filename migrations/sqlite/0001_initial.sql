@@ -0,0 +1,27 @@
+-- Sayings, the global cache and preset selections, as copied from Sled by `migrate`.
+-- Records are stored as the same JSON the Sled backend writes, keyed for lookups.
+
+CREATE TABLE IF NOT EXISTS sayings (
+    id TEXT PRIMARY KEY NOT NULL,
+    user_id TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    data TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS sayings_user_created ON sayings (user_id, created_at);
+
+CREATE TABLE IF NOT EXISTS global_cache (
+    cache_key TEXT PRIMARY KEY NOT NULL,
+    saying_id TEXT NOT NULL,
+    data TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS preset_selections (
+    user_id TEXT PRIMARY KEY NOT NULL,
+    data TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS recent_presets (
+    user_id TEXT PRIMARY KEY NOT NULL,
+    data TEXT NOT NULL
+);
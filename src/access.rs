@@ -0,0 +1,281 @@
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRequestParts},
+    http::{header, request::Parts, HeaderMap},
+};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+use crate::bans::{BanList, BanMode};
+use crate::config::{AccessConfig, AccessPolicyKind};
+use crate::user_hash::{self, UserIdHasher};
+
+// Proxies (TRUSTED_PROXIES) whose X-Forwarded-For is believed, as a request extension so
+// the Caller extractor can see them
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxies(pub Arc<Vec<IpAddr>>);
+
+// Who is making a request, as far as the transport can tell
+#[derive(Debug, Clone, Default)]
+pub struct Caller {
+    // The client's address: the peer's, or behind a trusted proxy the one it forwarded
+    pub ip: Option<IpAddr>,
+    // Address and port of the connection, which tells keep-alive connections apart
+    pub peer: Option<SocketAddr>,
+    // Bearer token from the Authorization header
+    pub token: Option<String>,
+}
+
+impl Caller {
+    pub fn from_headers(headers: &HeaderMap, peer: Option<SocketAddr>, trusted_proxies: &[IpAddr]) -> Self {
+        let token = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(|token| token.to_string());
+        let ip = peer.map(|peer| client_ip(headers, peer.ip(), trusted_proxies));
+
+        Self { ip, peer, token }
+    }
+}
+
+// Walk X-Forwarded-For back from the peer while the hops are trusted proxies; the first
+// address a trusted proxy vouches for and that isn't one itself is the client. Anyone can
+// send the header, so it is ignored unless the peer is trusted.
+fn client_ip(headers: &HeaderMap, peer: IpAddr, trusted_proxies: &[IpAddr]) -> IpAddr {
+    let forwarded: Vec<IpAddr> = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map_while(|hop| hop.trim().parse().ok())
+        .collect();
+
+    let mut client = peer;
+    for hop in forwarded.into_iter().rev() {
+        if !trusted_proxies.contains(&client) {
+            break;
+        }
+        client = hop;
+    }
+    client
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Caller {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        // Only present when served with connect info (not in in-process benchmarks)
+        let peer = parts.extensions.get::<ConnectInfo<SocketAddr>>().map(|ConnectInfo(addr)| *addr);
+        let trusted_proxies = parts.extensions.get::<TrustedProxies>().cloned().unwrap_or_default();
+        Ok(Caller::from_headers(&parts.headers, peer, &trusted_proxies.0))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Access {
+    Allow,
+    // Serve stored sayings only, never call the LLM
+    CachedOnly,
+    Deny(String),
+}
+
+// Decides whether a caller may act as a user. Deployments with their own rules implement
+// this and install it as `AppState::access`. The user ID is the one from the request, or for
+// checks on a stored record its owner, which is hashed when storage hashes user IDs.
+pub trait AccessPolicy: Send + Sync {
+    fn check(&self, caller: &Caller, user_id: &str) -> Access;
+}
+
+// Runs every policy; the strictest decision wins
+pub struct PolicyChain(pub Vec<Box<dyn AccessPolicy>>);
+
+impl AccessPolicy for PolicyChain {
+    fn check(&self, caller: &Caller, user_id: &str) -> Access {
+        let mut access = Access::Allow;
+        for policy in &self.0 {
+            match policy.check(caller, user_id) {
+                Access::Deny(reason) => return Access::Deny(reason),
+                Access::CachedOnly => access = Access::CachedOnly,
+                Access::Allow => {}
+            }
+        }
+        access
+    }
+}
+
+pub struct AllowAll;
+
+impl AccessPolicy for AllowAll {
+    fn check(&self, _caller: &Caller, _user_id: &str) -> Access {
+        Access::Allow
+    }
+}
+
+// Bans and shadow bans managed under /admin/bans
+pub struct BanListPolicy {
+    pub bans: Arc<BanList>,
+}
+
+impl AccessPolicy for BanListPolicy {
+    fn check(&self, caller: &Caller, user_id: &str) -> Access {
+        match self.bans.mode_for(user_id, caller.ip) {
+            Some(BanMode::Ban) => {
+                tracing::warn!("Blocked request from banned user {} ({:?})", user_id, caller.ip);
+                Access::Deny("This user is banned".to_string())
+            }
+            Some(BanMode::ShadowBan) => Access::CachedOnly,
+            None => Access::Allow,
+        }
+    }
+}
+
+// Each token may act as one user, or as any user when mapped to `*`
+pub struct TokenPolicy {
+    pub tokens: HashMap<String, String>,
+    // Storage's, so the token's user also matches the hashed owners of stored records
+    pub user_ids: Option<UserIdHasher>,
+}
+
+impl AccessPolicy for TokenPolicy {
+    fn check(&self, caller: &Caller, user_id: &str) -> Access {
+        let Some(allowed) = caller.token.as_ref().and_then(|token| self.tokens.get(token)) else {
+            return Access::Deny("A valid API token is required".to_string());
+        };
+
+        let user_key = |user_id: &str| user_hash::user_key(self.user_ids.as_ref(), user_id);
+        if allowed == "*" || user_key(allowed) == user_key(user_id) {
+            Access::Allow
+        } else {
+            Access::Deny("This token may not act as this user".to_string())
+        }
+    }
+}
+
+// Each token belongs to a tenant and may only act as that tenant's users, `<tenant>:<user>`
+pub struct TenantPolicy {
+    pub tenants: HashMap<String, String>,
+}
+
+impl AccessPolicy for TenantPolicy {
+    fn check(&self, caller: &Caller, user_id: &str) -> Access {
+        let Some(tenant) = caller.token.as_ref().and_then(|token| self.tenants.get(token)) else {
+            return Access::Deny("A valid API token is required".to_string());
+        };
+
+        // The tenant went into the hash along with the rest of the ID
+        if user_hash::is_hashed(user_id) {
+            return Access::Deny("Records of hashed user IDs can't be matched to a tenant".to_string());
+        }
+
+        match user_id.split_once(':') {
+            Some((prefix, user)) if prefix == tenant && !user.is_empty() => Access::Allow,
+            _ => Access::Deny(format!("User IDs must be of the form {}:<user>", tenant)),
+        }
+    }
+}
+
+// The configured policy; ban rules apply to every kind except allow_all. `user_ids` is
+// storage's hasher, if it hashes user IDs.
+pub fn from_config(config: &AccessConfig, bans: Arc<BanList>, user_ids: Option<UserIdHasher>) -> Box<dyn AccessPolicy> {
+    let mut chain: Vec<Box<dyn AccessPolicy>> = vec![Box::new(BanListPolicy { bans })];
+
+    match config.policy {
+        AccessPolicyKind::AllowAll => return Box::new(AllowAll),
+        AccessPolicyKind::Open => {}
+        AccessPolicyKind::Token => chain.push(Box::new(TokenPolicy { tokens: config.tokens.clone(), user_ids })),
+        AccessPolicyKind::Tenant => chain.push(Box::new(TenantPolicy { tenants: config.tokens.clone() })),
+    }
+
+    Box::new(PolicyChain(chain))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn caller(token: Option<&str>) -> Caller {
+        Caller { ip: None, peer: None, token: token.map(|token| token.to_string()) }
+    }
+
+    #[test]
+    fn test_token_and_tenant_policies() {
+        let tokens: HashMap<String, String> = [("t1", "alice"), ("svc", "*")]
+            .into_iter()
+            .map(|(token, user)| (token.to_string(), user.to_string()))
+            .collect();
+        let policy = TokenPolicy { tokens, user_ids: None };
+
+        assert_eq!(policy.check(&caller(Some("t1")), "alice"), Access::Allow);
+        assert!(matches!(policy.check(&caller(Some("t1")), "bob"), Access::Deny(_)));
+        assert_eq!(policy.check(&caller(Some("svc")), "bob"), Access::Allow);
+        assert!(matches!(policy.check(&caller(None), "alice"), Access::Deny(_)));
+
+        let policy = TenantPolicy { tenants: HashMap::from([("acme-token".to_string(), "acme".to_string())]) };
+        assert_eq!(policy.check(&caller(Some("acme-token")), "acme:alice"), Access::Allow);
+        assert!(matches!(policy.check(&caller(Some("acme-token")), "globex:alice"), Access::Deny(_)));
+        assert!(matches!(policy.check(&caller(Some("acme-token")), "alice"), Access::Deny(_)));
+    }
+
+    #[test]
+    fn test_token_policy_matches_hashed_owners() {
+        let hasher = UserIdHasher::new("pepper");
+        let policy = TokenPolicy {
+            tokens: HashMap::from([("t1".to_string(), "alice".to_string())]),
+            user_ids: Some(hasher.clone()),
+        };
+
+        // Owners of stored records come back hashed
+        assert_eq!(policy.check(&caller(Some("t1")), &hasher.hash("alice")), Access::Allow);
+        assert_eq!(policy.check(&caller(Some("t1")), "alice"), Access::Allow);
+        assert!(matches!(policy.check(&caller(Some("t1")), &hasher.hash("bob")), Access::Deny(_)));
+
+        let policy = TenantPolicy { tenants: HashMap::from([("acme-token".to_string(), "acme".to_string())]) };
+        assert!(matches!(policy.check(&caller(Some("acme-token")), &hasher.hash("acme:alice")), Access::Deny(_)));
+    }
+
+    #[test]
+    fn test_chain_applies_strictest_decision() {
+        struct Fixed(Access);
+        impl AccessPolicy for Fixed {
+            fn check(&self, _caller: &Caller, _user_id: &str) -> Access {
+                self.0.clone()
+            }
+        }
+
+        let chain = PolicyChain(vec![
+            Box::new(AllowAll),
+            Box::new(Fixed(Access::CachedOnly)),
+        ]);
+        assert_eq!(chain.check(&caller(None), "alice"), Access::CachedOnly);
+
+        let chain = PolicyChain(vec![
+            Box::new(Fixed(Access::CachedOnly)),
+            Box::new(Fixed(Access::Deny("no".to_string()))),
+        ]);
+        assert_eq!(chain.check(&caller(None), "alice"), Access::Deny("no".to_string()));
+        assert_eq!(PolicyChain(vec![]).check(&caller(None), "alice"), Access::Allow);
+    }
+
+    #[test]
+    fn test_forwarded_for_is_only_believed_from_trusted_proxies() {
+        let proxy: IpAddr = "10.0.0.1".parse().unwrap();
+        let peer = |ip: IpAddr| Some(SocketAddr::new(ip, 443));
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", "198.51.100.9, 203.0.113.7".parse().unwrap());
+
+        // Direct connections can't pick their address
+        let direct: IpAddr = "192.0.2.1".parse().unwrap();
+        assert_eq!(Caller::from_headers(&headers, peer(direct), &[proxy]).ip, Some(direct));
+        assert_eq!(Caller::from_headers(&headers, peer(proxy), &[]).ip, Some(proxy));
+
+        // Only the hop the trusted proxy saw counts, not what the client claimed before it
+        assert_eq!(Caller::from_headers(&headers, peer(proxy), &[proxy]).ip, Some("203.0.113.7".parse().unwrap()));
+        let chained: IpAddr = "203.0.113.7".parse().unwrap();
+        assert_eq!(Caller::from_headers(&headers, peer(proxy), &[proxy, chained]).ip, Some("198.51.100.9".parse().unwrap()));
+        assert_eq!(Caller::from_headers(&HeaderMap::new(), peer(proxy), &[proxy]).ip, Some(proxy));
+    }
+}
@@ -0,0 +1,168 @@
+use chrono::{DateTime, FixedOffset, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::models::UserStats;
+use crate::streaks;
+
+// Badges a user can earn. The serialized name is the stable badge ID.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AchievementKind {
+    FirstSaying,
+    PresetExplorer,
+    WeekStreak,
+    Polyglot,
+}
+
+const PRESET_EXPLORER_PRESETS: usize = 10;
+const WEEK_STREAK_DAYS: u32 = 7;
+const POLYGLOT_LANGUAGES: usize = 3;
+
+impl AchievementKind {
+    pub const ALL: [AchievementKind; 4] = [
+        AchievementKind::FirstSaying,
+        AchievementKind::PresetExplorer,
+        AchievementKind::WeekStreak,
+        AchievementKind::Polyglot,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            AchievementKind::FirstSaying => "First Words",
+            AchievementKind::PresetExplorer => "Preset Explorer",
+            AchievementKind::WeekStreak => "Seven Day Streak",
+            AchievementKind::Polyglot => "Polyglot",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            AchievementKind::FirstSaying => "Generate your first saying",
+            AchievementKind::PresetExplorer => "Try 10 different presets",
+            AchievementKind::WeekStreak => "Generate a saying 7 days in a row",
+            AchievementKind::Polyglot => "Receive sayings in 3 different languages",
+        }
+    }
+}
+
+// A badge as persisted for a user
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Achievement {
+    pub kind: AchievementKind,
+    pub awarded_at: DateTime<Utc>,
+}
+
+// Work out every badge a user's stats earn, stamped with the time of the saying that
+// earned it. Streak days are counted in the user's timezone, and the week streak is
+// stamped with the earliest saying known on the day that completed it.
+pub fn evaluate(stats: &UserStats, offset: FixedOffset) -> Vec<Achievement> {
+    let mut earned: Vec<Achievement> = Vec::new();
+    let mut award = |kind: AchievementKind, at: Option<DateTime<Utc>>| {
+        if let Some(awarded_at) = at {
+            earned.push(Achievement { kind, awarded_at });
+        }
+    };
+
+    award(AchievementKind::FirstSaying, stats.first_activity_at);
+    award(AchievementKind::PresetExplorer, nth_first_use(&stats.preset_first_used, PRESET_EXPLORER_PRESETS));
+    award(AchievementKind::Polyglot, nth_first_use(&stats.language_first_used, POLYGLOT_LANGUAGES));
+    award(AchievementKind::WeekStreak, week_streak_completed_at(stats, offset));
+
+    earned.sort_by_key(|a| a.awarded_at);
+    earned
+}
+
+// When the nth different preset or language was first used
+fn nth_first_use(first_used: &BTreeMap<String, DateTime<Utc>>, n: usize) -> Option<DateTime<Utc>> {
+    let mut times: Vec<DateTime<Utc>> = first_used.values().copied().collect();
+    times.sort_unstable();
+    times.get(n.checked_sub(1)?).copied()
+}
+
+fn week_streak_completed_at(stats: &UserStats, offset: FixedOffset) -> Option<DateTime<Utc>> {
+    let day = streaks::daily_runs(stats.activity_bounds(), offset)
+        .into_iter()
+        .find(|&(_, run)| run >= WEEK_STREAK_DAYS)
+        .map(|(day, _)| day)?;
+
+    stats.activity_bounds().find(|at| at.with_timezone(&offset).date_naive() == day)
+}
+
+// Add newly earned badges to the stored ones, keeping the original award times.
+// Returns the merged list and whether anything new was awarded.
+pub fn merge(stored: Vec<Achievement>, earned: Vec<Achievement>) -> (Vec<Achievement>, bool) {
+    let mut merged = stored;
+    let mut changed = false;
+
+    for achievement in earned {
+        if !merged.iter().any(|a| a.kind == achievement.kind) {
+            merged.push(achievement);
+            changed = true;
+        }
+    }
+
+    merged.sort_by_key(|a| a.awarded_at);
+    (merged, changed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Saying, SayingSource};
+    use crate::streaks::offset_from_minutes;
+    use chrono::{Duration, TimeZone};
+
+    fn saying(created_at: DateTime<Utc>, preset_id: &str, language_id: &str) -> Saying {
+        Saying {
+            created_at,
+            preset_id: Some(preset_id.to_string()),
+            language_id: Some(language_id.to_string()),
+            ..Saying::new("c".to_string(), "p".to_string(), SayingSource::LLM)
+        }
+    }
+
+    fn stats(history: &[Saying]) -> UserStats {
+        UserStats::from_sayings("user", history)
+    }
+
+    fn awarded_at(earned: &[Achievement], kind: AchievementKind) -> Option<DateTime<Utc>> {
+        earned.iter().find(|a| a.kind == kind).map(|a| a.awarded_at)
+    }
+
+    #[test]
+    fn test_badges_are_stamped_with_the_saying_that_earned_them() {
+        let start = Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap();
+        let history: Vec<_> = (0..10)
+            .map(|i| saying(start + Duration::days(i), &format!("preset_{}", i), ["en", "fr", "de"][i as usize % 3]))
+            .collect();
+
+        let earned = evaluate(&stats(&history), offset_from_minutes(0));
+        assert_eq!(awarded_at(&earned, AchievementKind::FirstSaying), Some(start));
+        assert_eq!(awarded_at(&earned, AchievementKind::Polyglot), Some(start + Duration::days(2)));
+        assert_eq!(awarded_at(&earned, AchievementKind::WeekStreak), Some(start + Duration::days(6)));
+        assert_eq!(awarded_at(&earned, AchievementKind::PresetExplorer), Some(start + Duration::days(9)));
+
+        // Badges already stored keep their time; only new ones count as a change
+        let (merged, changed) = merge(earned.clone(), evaluate(&stats(&history[..1]), offset_from_minutes(0)));
+        assert!(!changed);
+        assert_eq!(merged.len(), earned.len());
+    }
+
+    #[test]
+    fn test_week_streak_days_follow_the_user_timezone() {
+        // 23:30 UTC every day but the 4th, and 00:15 UTC on the 5th, which is still the 4th
+        // an hour west of UTC
+        let start = Utc.with_ymd_and_hms(2024, 3, 1, 23, 30, 0).unwrap();
+        let mut history: Vec<_> = [0, 1, 2, 4, 5, 6, 7].iter()
+            .map(|&day| saying(start + Duration::days(day), "oracle", "en"))
+            .collect();
+        history.push(saying(Utc.with_ymd_and_hms(2024, 3, 5, 0, 15, 0).unwrap(), "oracle", "en"));
+
+        assert_eq!(awarded_at(&evaluate(&stats(&history), offset_from_minutes(0)), AchievementKind::WeekStreak), None);
+        assert_eq!(
+            awarded_at(&evaluate(&stats(&history), offset_from_minutes(-60)), AchievementKind::WeekStreak),
+            Some(start + Duration::days(6)),
+        );
+    }
+}
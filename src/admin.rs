@@ -0,0 +1,881 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{Html, IntoResponse, Response},
+};
+use chrono::Utc;
+use maud::{html, Markup, DOCTYPE};
+use axum::Json;
+use serde::Deserialize;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use crate::audit;
+use crate::bans::{Ban, BanMode, BanSubject};
+use crate::exemptions::{ExemptSubject, Exemption};
+use crate::flags::{FlagOverride, FlagRule};
+use crate::config::ProviderType;
+use crate::handlers::ApiError;
+use crate::invalidation::Invalidation;
+use crate::models::PresetStats;
+use crate::preset::{Preset, PresetExport};
+use crate::prompt_rotation;
+use crate::quality::{self, RankBy};
+use crate::AppState;
+
+const RECENT_SAYINGS: usize = 20;
+const DEFAULT_FREEFORM_LIMIT: usize = 100;
+const DEFAULT_DEBUG_LIMIT: usize = 50;
+const DEFAULT_SHADOW_LIMIT: usize = 100;
+const DEFAULT_AUDIT_LIMIT: usize = 100;
+const REFRESH_SECONDS: u32 = 10;
+
+#[derive(Debug, Deserialize)]
+pub struct AdminQuery {
+    // Browsers can't set headers on a plain page load, so the token may also come in the URL
+    pub token: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FreeformPromptsQuery {
+    pub token: Option<String>,
+    pub limit: Option<usize>,
+}
+
+// Check the admin token from `Authorization: Bearer <token>` or `?token=`.
+// Admin routes don't exist at all unless ADMIN_TOKEN is configured.
+pub fn require_admin(state: &AppState, headers: &HeaderMap, query_token: Option<&str>) -> Result<(), ApiError> {
+    let Some(expected) = state.config.server.admin_token.as_deref() else {
+        return Err(ApiError::NotFound("Not found".to_string()));
+    };
+
+    let header_token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+
+    match header_token.or(query_token) {
+        Some(token) if constant_time_eq(token.as_bytes(), expected.as_bytes()) => Ok(()),
+        _ => Err(ApiError::AccessDenied("Invalid admin token".to_string())),
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+// Append a change made through the admin API to the audit log. By now the change has taken
+// effect, so a failed write is reported to the caller rather than undone.
+async fn record(state: &AppState, action: &str, detail: serde_json::Value) -> Result<(), ApiError> {
+    state.audit.record(&state.storage, state.clock.now(), action, detail).await
+        .map(|_| ())
+        .map_err(|e| ApiError::InternalError(format!("Change applied but not recorded in the audit log: {}", e)))
+}
+
+// How an exemption subject appears in the audit log; tokens are credentials, so only their kind
+fn audited_exemption(subject: &ExemptSubject) -> String {
+    match subject {
+        ExemptSubject::User(_) => subject.key(),
+        ExemptSubject::Token(_) => "token".to_string(),
+    }
+}
+
+// GET /admin - Server-rendered operator dashboard
+pub async fn dashboard(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(query): Query<AdminQuery>,
+) -> Result<Response, ApiError> {
+    require_admin(&state, &headers, query.token.as_deref())?;
+
+    let recent = state.storage.get_recent_sayings(RECENT_SAYINGS).await
+        .map_err(|e| ApiError::InternalError(format!("Failed to get recent sayings: {}", e)))?;
+
+    let metrics = state.metrics.snapshot();
+    let now = Utc::now();
+
+    let mut limits = state.rate_limiter.snapshot();
+    limits.retain(|info| info.reset_at > now);
+    limits.sort_by_key(|info| info.available());
+    let exhausted = limits.iter().filter(|info| info.available() == 0).count();
+
+    let provider = match state.config.openrouter.provider {
+        ProviderType::OpenRouter => "openrouter",
+        ProviderType::Mock => "mock",
+    };
+    let health = state.openrouter.health();
+    let models = health.snapshot(Utc::now());
+    let events = health.events();
+    let canary = state.openrouter.canary();
+    let limiter = state.openrouter.limiter();
+    let concurrency = match limiter.max() {
+        0 => "unlimited".to_string(),
+        max => {
+            let (in_flight, waiting) = limiter.usage();
+            format!("{} of {} in flight, {} waiting", in_flight, max, waiting)
+        }
+    };
+
+    let page = layout(html! {
+        h1 { "prompt-wrapper" }
+        p.muted { "Started " (metrics.started_at.format("%Y-%m-%d %H:%M:%S UTC")) ", refreshes every " (REFRESH_SECONDS) "s" }
+
+        h2 { "Stats" }
+        table {
+            tr { th { "Sayings generated" } td { (metrics.sayings_generated) } }
+            tr { th { "Served from cache" } td { (metrics.cache_served) } }
+            tr { th { "Rate limited" } td { (metrics.rate_limited) } }
+            tr { th { "Queued" } td { (metrics.queued_requests) } }
+            tr { th { "Exempt requests" } td { (metrics.exempt_requests) } }
+            tr { th { "Glossary misses" } td { (metrics.glossary_violations) } }
+            tr { th { "Handler panics" } td { (metrics.handler_panics) } }
+            tr { th { "Request timeouts" } td { (metrics.request_timeouts) } }
+        }
+
+        h2 { "Upstream" }
+        table {
+            tr { th { "Provider" } td { (provider) } }
+            tr { th { "Model" } td {
+                (state.config.openrouter.model)
+                @if health.is_degraded() { " (degraded, using the fallback)" }
+            } }
+            tr { th { "Translator" } td { (state.translator.name()) } }
+            tr { th { "Concurrency" } td { (concurrency) } }
+            tr { th { "Errors" } td { (metrics.upstream_errors) } }
+            tr { th { "Degenerate responses" } td { (metrics.degenerate_responses) } }
+            tr { th { "Model failovers" } td { (metrics.model_failovers) } }
+            @if let Some(model) = canary.model() {
+                tr { th { "Canary" } td { (model) " (" (canary.percent()) "% of new requests)" } }
+            }
+        }
+
+        @if !models.is_empty() {
+            table {
+                tr { th { "Model" } th { "Calls" } th { "Errors" } th { "Avg latency" } }
+                @for stats in &models {
+                    tr {
+                        td { (stats.model) }
+                        td { (stats.calls) }
+                        td { (stats.error_percent) "%" }
+                        td { @if let Some(latency) = stats.avg_latency_ms { (latency) " ms" } @else { "-" } }
+                    }
+                }
+            }
+        }
+        @if !events.is_empty() {
+            table {
+                tr { th { "Routing" } th { "Model" } th { "Reason" } }
+                @for event in &events {
+                    tr {
+                        td { (event.at.format("%Y-%m-%d %H:%M:%S")) " " (format!("{:?}", event.kind).to_lowercase()) }
+                        td { (event.model) }
+                        td { (event.reason) }
+                    }
+                }
+            }
+        }
+
+        h2 { "Rate limits" }
+        p {
+            (limits.len()) " users in the current window, " (exhausted) " exhausted "
+            "(" (state.config.rate_limit.max_requests) " requests per " (state.config.rate_limit.window_seconds) "s)"
+        }
+        @if !limits.is_empty() {
+            table {
+                tr { th { "User" } th { "Remaining" } th { "Credits" } th { "Resets" } }
+                @for info in limits.iter().take(RECENT_SAYINGS) {
+                    tr {
+                        td { (info.user_id) }
+                        td { (info.remaining_requests) }
+                        td { (info.credits) }
+                        td { (info.reset_at.format("%H:%M:%S")) }
+                    }
+                }
+            }
+        }
+
+        h2 { "Recent sayings" }
+        @if recent.is_empty() {
+            p.muted { "No sayings yet" }
+        } @else {
+            table {
+                tr { th { "Created" } th { "User" } th { "Source" } th { "Saying" } }
+                @for (user_id, saying) in &recent {
+                    tr {
+                        td { (saying.created_at.format("%Y-%m-%d %H:%M:%S")) }
+                        td { (user_id) }
+                        td { (format!("{:?}", saying.source)) }
+                        td { (saying.content) }
+                    }
+                }
+            }
+        }
+    });
+
+    Ok(Html(page.into_string()).into_response())
+}
+
+// GET /admin/freeform-prompts - Audit log of raw user prompts, newest first
+pub async fn freeform_prompts(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(query): Query<FreeformPromptsQuery>,
+) -> Result<Response, ApiError> {
+    require_admin(&state, &headers, query.token.as_deref())?;
+
+    let prompts = state.storage.get_freeform_prompts(query.limit.unwrap_or(DEFAULT_FREEFORM_LIMIT)).await
+        .map_err(|e| ApiError::InternalError(format!("Failed to get freeform prompts: {}", e)))?;
+
+    Ok(Json(serde_json::json!({ "prompts": prompts })).into_response())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AuditLogQuery {
+    pub token: Option<String>,
+    pub limit: Option<usize>,
+}
+
+// GET /admin/audit - Changes made through the admin API, newest first, with whether the hash
+// chain over the whole log is intact
+pub async fn audit_log(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(query): Query<AuditLogQuery>,
+) -> Result<Response, ApiError> {
+    require_admin(&state, &headers, query.token.as_deref())?;
+
+    let entries = state.storage.get_audit_entries().await
+        .map_err(|e| ApiError::InternalError(format!("Failed to get audit log: {}", e)))?;
+    let broken_at = audit::first_broken(&entries);
+    if let Some(seq) = broken_at {
+        tracing::warn!("Audit log hash chain is broken at entry {}", seq);
+    }
+
+    let total = entries.len();
+    let newest: Vec<_> = entries.into_iter().rev().take(query.limit.unwrap_or(DEFAULT_AUDIT_LIMIT)).collect();
+    Ok(Json(serde_json::json!({
+        "entries": newest,
+        "total": total,
+        "verified": broken_at.is_none(),
+        "broken_at": broken_at,
+    })).into_response())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DebugLogQuery {
+    pub token: Option<String>,
+    pub limit: Option<usize>,
+}
+
+// GET /admin/debug/recent - Scrubbed request and upstream payloads, newest first
+pub async fn debug_recent(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(query): Query<DebugLogQuery>,
+) -> Result<Response, ApiError> {
+    require_admin(&state, &headers, query.token.as_deref())?;
+
+    if !state.debug_log.enabled() {
+        return Err(ApiError::NotFound("Debug logging is not enabled".to_string()));
+    }
+
+    let entries = state.debug_log.recent(query.limit.unwrap_or(DEFAULT_DEBUG_LIMIT));
+    Ok(Json(serde_json::json!({ "entries": entries })).into_response())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ShadowOutputsQuery {
+    pub token: Option<String>,
+    pub limit: Option<usize>,
+}
+
+// GET /admin/shadow - Shadow model answers next to the ones users got, newest first
+pub async fn shadow_outputs(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(query): Query<ShadowOutputsQuery>,
+) -> Result<Response, ApiError> {
+    require_admin(&state, &headers, query.token.as_deref())?;
+
+    let outputs = state.storage.get_shadow_outputs(query.limit.unwrap_or(DEFAULT_SHADOW_LIMIT)).await
+        .map_err(|e| ApiError::InternalError(format!("Failed to get shadow outputs: {}", e)))?;
+
+    Ok(Json(serde_json::json!({ "outputs": outputs })).into_response())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct QualityQuery {
+    pub token: Option<String>,
+    // `preset` (default) or `model`
+    pub by: Option<String>,
+}
+
+// GET /admin/quality - Presets or models ranked by the judge's average marks
+pub async fn quality(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(query): Query<QualityQuery>,
+) -> Result<Response, ApiError> {
+    require_admin(&state, &headers, query.token.as_deref())?;
+
+    let by = match query.by.as_deref() {
+        None | Some("preset") => RankBy::Preset,
+        Some("model") => RankBy::Model,
+        Some(other) => return Err(ApiError::BadRequest(format!("Unknown ranking: {}", other))),
+    };
+    let scores = state.storage.list_quality_scores().await
+        .map_err(|e| ApiError::InternalError(format!("Failed to get quality scores: {}", e)))?;
+
+    Ok(Json(serde_json::json!({
+        "scored": scores.len(),
+        "rankings": quality::rank(&scores, by),
+    })).into_response())
+}
+
+// GET /admin/prompts/underperforming - Preset user prompts rated poorly enough to be
+// picked less often, worst first
+pub async fn underperforming_prompts(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(query): Query<AdminQuery>,
+) -> Result<Response, ApiError> {
+    require_admin(&state, &headers, query.token.as_deref())?;
+
+    let mut prompts = prompt_rotation::current(&state).await
+        .map_err(|e| ApiError::InternalError(format!("Failed to rate prompts: {}", e)))?;
+    prompts.retain(|performance| performance.weight < 1.0);
+
+    Ok(Json(serde_json::json!({
+        "rotation_enabled": state.config.prompt_rotation.enabled,
+        "prompts": prompts,
+    })).into_response())
+}
+
+// GET /admin/canary - Canary rollout settings and per-model totals since startup
+pub async fn canary(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(query): Query<AdminQuery>,
+) -> Result<Response, ApiError> {
+    require_admin(&state, &headers, query.token.as_deref())?;
+
+    let canary = state.openrouter.canary();
+    Ok(Json(serde_json::json!({
+        "primary": state.config.openrouter.model,
+        "canary": canary.model(),
+        "percent": canary.percent(),
+        "models": canary.comparison(),
+    })).into_response())
+}
+
+// GET /admin/presets - Every preset with its usage statistics, most used first
+pub async fn presets(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(query): Query<AdminQuery>,
+) -> Result<Response, ApiError> {
+    require_admin(&state, &headers, query.token.as_deref())?;
+
+    let mut stats: HashMap<String, PresetStats> = state.storage.list_preset_stats().await
+        .map_err(|e| ApiError::InternalError(format!("Failed to get preset stats: {}", e)))?
+        .into_iter()
+        .map(|stats| (stats.preset_id.clone(), stats))
+        .collect();
+
+    let mut presets: Vec<(Preset, PresetStats)> = state.presets.get_all_presets()
+        .into_iter()
+        .map(|preset| {
+            let stats = stats.remove(&preset.id).unwrap_or_else(|| PresetStats::new(&preset.id));
+            (preset, stats)
+        })
+        .collect();
+    presets.sort_by_key(|(_, stats)| Reverse(stats.sayings));
+
+    let presets: Vec<_> = presets.iter()
+        .map(|(preset, stats)| serde_json::json!({
+            "id": preset.id,
+            "name": preset.name,
+            "tags": preset.tags,
+            "sayings": stats.sayings,
+            "feedback_count": stats.feedback_count,
+            "average_feedback": stats.average_feedback(),
+            "last_used_at": stats.last_used_at,
+        }))
+        .collect();
+
+    Ok(Json(serde_json::json!({ "presets": presets })).into_response())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PresetExportQuery {
+    pub token: Option<String>,
+    // `json` (default) or `yaml`
+    pub format: Option<String>,
+}
+
+// GET /admin/presets/:preset_id/export - One preset in the portable export format, as a download
+pub async fn export_preset(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path(preset_id): Path<String>,
+    Query(query): Query<PresetExportQuery>,
+) -> Result<Response, ApiError> {
+    require_admin(&state, &headers, query.token.as_deref())?;
+
+    // Any preset, hidden, tiered and rated-out ones included
+    let preset = state.presets.get_preset_by_id(&preset_id)
+        .ok_or_else(|| ApiError::PresetNotFound(preset_id.clone()))?;
+    let mut export = PresetExport::new(preset);
+    export.signature = state.preset_keys.sign(&export.canonical_preset());
+
+    let (body, content_type, extension) = match query.format.as_deref().unwrap_or("json") {
+        "json" => (serde_json::to_string_pretty(&export).map_err(|e| e.to_string()), "application/json", "json"),
+        "yaml" => (serde_yaml::to_string(&export).map_err(|e| e.to_string()), "application/yaml", "yaml"),
+        other => return Err(ApiError::BadRequest(format!("Unknown export format: {}", other))),
+    };
+    let body = body.map_err(|e| ApiError::InternalError(format!("Failed to serialize preset: {}", e)))?;
+    let disposition = format!("attachment; filename=\"{}.preset.{}\"", preset_id, extension);
+
+    Ok((
+        [(header::CONTENT_TYPE, content_type.to_string()), (header::CONTENT_DISPOSITION, disposition)],
+        body,
+    ).into_response())
+}
+
+// POST /admin/presets/import - Add a preset exported by this or another deployment. The body is
+// the export as JSON or YAML, signed by a trusted publisher; re-importing an ID replaces the
+// earlier import.
+pub async fn import_preset(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(query): Query<AdminQuery>,
+    body: String,
+) -> Result<Response, ApiError> {
+    require_admin(&state, &headers, query.token.as_deref())?;
+
+    // YAML is a superset of JSON, so one parser reads both
+    let export: PresetExport = serde_yaml::from_str(&body)
+        .map_err(|e| ApiError::BadRequest(format!("Not a preset export: {}", e)))?;
+    let publisher = state.preset_keys.verify(&export.canonical_preset(), export.signature.as_ref())
+        .map_err(|e| ApiError::AccessDenied(format!("Refusing preset: {}", e)))?;
+    let preset = export.into_preset()
+        .map_err(|e| ApiError::BadRequest(e.to_string()))?;
+
+    let replaced = state.presets.is_imported(&preset.id);
+    if !replaced && state.presets.get_preset_by_id(&preset.id).is_some() {
+        return Err(ApiError::BadRequest(format!("Preset {} comes from the presets file and can't be replaced", preset.id)));
+    }
+    let id = preset.id.clone();
+    state.presets.import(&state.storage, preset).await
+        .map_err(|e| ApiError::InternalError(format!("Failed to import preset: {}", e)))?;
+    tracing::info!("Imported preset {} from publisher {:?} (replaced: {})", id, publisher, replaced);
+    record(&state, "POST /admin/presets/import", serde_json::json!({
+        "preset_id": id, "publisher": publisher, "replaced": replaced,
+    })).await?;
+
+    let status = if replaced { StatusCode::OK } else { StatusCode::CREATED };
+    Ok((status, Json(serde_json::json!({ "id": id, "replaced": replaced, "publisher": publisher }))).into_response())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CacheInvalidationQuery {
+    pub token: Option<String>,
+    pub preset_id: Option<String>,
+    pub prompt: Option<String>,
+}
+
+// GET /admin/cache - Global cache entries with their sizes and ages, newest first
+pub async fn list_cache(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(query): Query<AdminQuery>,
+) -> Result<Response, ApiError> {
+    require_admin(&state, &headers, query.token.as_deref())?;
+
+    let entries = state.storage.list_cache_entries().await
+        .map_err(|e| ApiError::InternalError(format!("Failed to list cache entries: {}", e)))?;
+    let total_bytes: usize = entries.iter().map(|entry| entry.size_bytes).sum();
+
+    Ok(Json(serde_json::json!({ "entries": entries, "total_bytes": total_bytes })).into_response())
+}
+
+// DELETE /admin/cache - Invalidate the global cache entries for a preset and/or prompt
+pub async fn invalidate_cache(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(query): Query<CacheInvalidationQuery>,
+) -> Result<Response, ApiError> {
+    require_admin(&state, &headers, query.token.as_deref())?;
+
+    // Refuse to wipe everything by accident
+    if query.preset_id.is_none() && query.prompt.is_none() {
+        return Err(ApiError::BadRequest("Give preset_id, prompt or both".to_string()));
+    }
+
+    let removed = state.storage.invalidate_cache(query.preset_id.as_deref(), query.prompt.as_deref()).await
+        .map_err(|e| ApiError::InternalError(format!("Failed to invalidate cache: {}", e)))?;
+    tracing::info!("Invalidated {} cache entries (preset {:?}, prompt {:?})", removed, query.preset_id, query.prompt);
+    record(&state, "DELETE /admin/cache", serde_json::json!({
+        "preset_id": query.preset_id, "prompt": query.prompt, "removed": removed,
+    })).await?;
+    state.invalidations.publish(Invalidation::Cache { preset_id: query.preset_id, prompt: query.prompt }).await;
+
+    Ok(Json(serde_json::json!({ "removed": removed })).into_response())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BanRequest {
+    // Exactly one of user_id and ip
+    pub user_id: Option<String>,
+    pub ip: Option<IpAddr>,
+    pub mode: BanMode,
+    pub reason: Option<String>,
+}
+
+// GET /admin/bans - List bans and shadow bans
+pub async fn list_bans(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(query): Query<AdminQuery>,
+) -> Result<Response, ApiError> {
+    require_admin(&state, &headers, query.token.as_deref())?;
+
+    Ok(Json(serde_json::json!({ "bans": state.bans.list() })).into_response())
+}
+
+// POST /admin/bans - Ban or shadow-ban a user ID or IP address
+pub async fn create_ban(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(query): Query<AdminQuery>,
+    Json(payload): Json<BanRequest>,
+) -> Result<Response, ApiError> {
+    require_admin(&state, &headers, query.token.as_deref())?;
+
+    let subject = match (payload.user_id, payload.ip) {
+        // Under the user's hash when storage hashes user IDs, so the ban covers their records too
+        (Some(user_id), None) if !user_id.is_empty() => BanSubject::User(state.storage.user_key(&user_id)),
+        (None, Some(ip)) => BanSubject::Ip(ip),
+        _ => return Err(ApiError::BadRequest("Provide exactly one of user_id and ip".to_string())),
+    };
+
+    let ban = Ban {
+        subject,
+        mode: payload.mode,
+        reason: payload.reason.filter(|reason| !reason.is_empty()),
+        created_at: Utc::now(),
+    };
+
+    state.bans.add(&state.storage, ban.clone()).await
+        .map_err(|e| ApiError::InternalError(format!("Failed to save ban: {}", e)))?;
+    tracing::info!("Added {:?} for {}", ban.mode, ban.subject.key());
+    record(&state, "POST /admin/bans", serde_json::json!({
+        "subject": ban.subject.key(), "mode": ban.mode, "reason": ban.reason,
+    })).await?;
+
+    Ok((StatusCode::CREATED, Json(ban)).into_response())
+}
+
+// DELETE /admin/bans/:kind/:subject - Lift a ban; kind is `user` or `ip`
+pub async fn delete_ban(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(query): Query<AdminQuery>,
+    Path((kind, subject)): Path<(String, String)>,
+) -> Result<Response, ApiError> {
+    require_admin(&state, &headers, query.token.as_deref())?;
+
+    let subject = match kind.as_str() {
+        "user" => BanSubject::User(state.storage.user_key(&subject)),
+        "ip" => BanSubject::Ip(subject.parse()
+            .map_err(|_| ApiError::BadRequest(format!("Invalid IP address: {}", subject)))?),
+        _ => return Err(ApiError::BadRequest(format!("Unknown ban kind: {}", kind))),
+    };
+
+    let removed = state.bans.remove(&state.storage, &subject).await
+        .map_err(|e| ApiError::InternalError(format!("Failed to delete ban: {}", e)))?;
+    if removed.is_none() {
+        return Err(ApiError::NotFound(format!("No ban for {}", subject.key())));
+    }
+
+    record(&state, "DELETE /admin/bans", serde_json::json!({ "subject": subject.key() })).await?;
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreditsRequest {
+    pub user_id: String,
+    pub credits: u32,
+    pub reason: Option<String>,
+}
+
+// POST /admin/credits - Grant a user extra requests for their current window
+pub async fn grant_credits(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(query): Query<AdminQuery>,
+    Json(payload): Json<CreditsRequest>,
+) -> Result<Response, ApiError> {
+    require_admin(&state, &headers, query.token.as_deref())?;
+
+    if payload.user_id.is_empty() || payload.credits == 0 {
+        return Err(ApiError::BadRequest("Provide a user_id and a positive number of credits".to_string()));
+    }
+
+    let info = state.rate_limiter.grant_credits(&payload.user_id, payload.credits).await;
+    tracing::info!("Granted {} credits to {} ({})", payload.credits, payload.user_id,
+                   payload.reason.as_deref().unwrap_or("no reason given"));
+    record(&state, "POST /admin/credits", serde_json::json!({
+        "user_id": state.storage.user_key(&payload.user_id), "credits": payload.credits, "reason": payload.reason,
+    })).await?;
+
+    Ok(Json(info).into_response())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OwnerRequest {
+    pub user_id: String,
+    // The user's existing token; a new one is issued when left out
+    pub token: Option<String>,
+}
+
+// POST /admin/owners - Bind a user ID to a history token, for users who already had history
+// when PROTECT_USER_HISTORY was turned on and so can't be claimed by generating
+pub async fn set_owner(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(query): Query<AdminQuery>,
+    Json(payload): Json<OwnerRequest>,
+) -> Result<Response, ApiError> {
+    require_admin(&state, &headers, query.token.as_deref())?;
+
+    if payload.user_id.is_empty() || payload.token.as_deref() == Some("") {
+        return Err(ApiError::BadRequest("Provide a user_id, and a non-empty token if any".to_string()));
+    }
+    let token = payload.token.unwrap_or_else(|| uuid::Uuid::new_v4().simple().to_string());
+
+    state.storage.set_user_owner(&payload.user_id, &token).await
+        .map_err(|e| ApiError::InternalError(format!("Failed to set user owner: {}", e)))?;
+    // The token is a credential, so only the user goes to the log
+    tracing::info!("Bound user {} to a history token", payload.user_id);
+    record(&state, "POST /admin/owners", serde_json::json!({ "user_id": state.storage.user_key(&payload.user_id) })).await?;
+
+    Ok(Json(serde_json::json!({ "user_id": payload.user_id, "token": token })).into_response())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExemptionRequest {
+    // Exactly one of user_id and token
+    pub user_id: Option<String>,
+    pub token: Option<String>,
+    pub reason: Option<String>,
+}
+
+// GET /admin/exemptions - List rate-limit exemptions added through the API
+pub async fn list_exemptions(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(query): Query<AdminQuery>,
+) -> Result<Response, ApiError> {
+    require_admin(&state, &headers, query.token.as_deref())?;
+
+    Ok(Json(serde_json::json!({
+        "exemptions": state.exemptions.list(),
+        "configured_users": state.config.rate_limit.exempt_users,
+    })).into_response())
+}
+
+// POST /admin/exemptions - Exempt a user ID or API token from rate limits
+pub async fn create_exemption(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(query): Query<AdminQuery>,
+    Json(payload): Json<ExemptionRequest>,
+) -> Result<Response, ApiError> {
+    require_admin(&state, &headers, query.token.as_deref())?;
+
+    let subject = match (payload.user_id, payload.token) {
+        (Some(user_id), None) if !user_id.is_empty() => ExemptSubject::User(state.storage.user_key(&user_id)),
+        (None, Some(token)) if !token.is_empty() => ExemptSubject::Token(token),
+        _ => return Err(ApiError::BadRequest("Provide exactly one of user_id and token".to_string())),
+    };
+
+    let exemption = Exemption {
+        subject,
+        reason: payload.reason.filter(|reason| !reason.is_empty()),
+        created_at: Utc::now(),
+    };
+
+    state.exemptions.add(&state.storage, exemption.clone()).await
+        .map_err(|e| ApiError::InternalError(format!("Failed to save exemption: {}", e)))?;
+    // Tokens are credentials, so only user IDs go to the log
+    match &exemption.subject {
+        ExemptSubject::User(user_id) => tracing::info!("Exempted user {} from rate limits", user_id),
+        ExemptSubject::Token(_) => tracing::info!("Exempted an API token from rate limits"),
+    }
+    record(&state, "POST /admin/exemptions", serde_json::json!({
+        "subject": audited_exemption(&exemption.subject), "reason": exemption.reason,
+    })).await?;
+
+    Ok((StatusCode::CREATED, Json(exemption)).into_response())
+}
+
+// DELETE /admin/exemptions/:kind/:subject - Remove an exemption; kind is `user` or `token`
+pub async fn delete_exemption(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(query): Query<AdminQuery>,
+    Path((kind, subject)): Path<(String, String)>,
+) -> Result<Response, ApiError> {
+    require_admin(&state, &headers, query.token.as_deref())?;
+
+    let subject = match kind.as_str() {
+        "user" => ExemptSubject::User(state.storage.user_key(&subject)),
+        "token" => ExemptSubject::Token(subject),
+        _ => return Err(ApiError::BadRequest(format!("Unknown exemption kind: {}", kind))),
+    };
+
+    let removed = state.exemptions.remove(&state.storage, &subject).await
+        .map_err(|e| ApiError::InternalError(format!("Failed to delete exemption: {}", e)))?;
+    if removed.is_none() {
+        return Err(ApiError::NotFound(format!("No exemption for {}", subject.key())));
+    }
+
+    record(&state, "DELETE /admin/exemptions", serde_json::json!({ "subject": audited_exemption(&subject) })).await?;
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+// GET /admin/flags - List feature flags with their effective rules
+pub async fn list_flags(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(query): Query<AdminQuery>,
+) -> Result<Response, ApiError> {
+    require_admin(&state, &headers, query.token.as_deref())?;
+
+    Ok(Json(serde_json::json!({ "flags": state.flags.list() })).into_response())
+}
+
+// PUT /admin/flags/:name - Override a feature flag's rule
+pub async fn set_flag(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(query): Query<AdminQuery>,
+    Path(name): Path<String>,
+    Json(rule): Json<FlagRule>,
+) -> Result<Response, ApiError> {
+    require_admin(&state, &headers, query.token.as_deref())?;
+
+    rule.validate().map_err(|e| ApiError::BadRequest(e.to_string()))?;
+    // Stored like the rest of the user records, hashed when storage hashes user IDs
+    let users = rule.users.iter().map(|user_id| state.storage.user_key(user_id)).collect();
+    let flag = FlagOverride { name, rule: FlagRule { users, ..rule }, updated_at: Utc::now() };
+
+    state.flags.set(&state.storage, flag.clone()).await
+        .map_err(|e| ApiError::InternalError(format!("Failed to save feature flag: {}", e)))?;
+    tracing::info!("Feature flag {} set to {}% and {} users", flag.name, flag.rule.percentage, flag.rule.users.len());
+    record(&state, "PUT /admin/flags", serde_json::json!({ "name": flag.name, "rule": flag.rule })).await?;
+
+    Ok(Json(flag).into_response())
+}
+
+// DELETE /admin/flags/:name - Remove an override, reverting the flag to FEATURE_FLAGS_FILE
+pub async fn delete_flag(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(query): Query<AdminQuery>,
+    Path(name): Path<String>,
+) -> Result<Response, ApiError> {
+    require_admin(&state, &headers, query.token.as_deref())?;
+
+    let removed = state.flags.remove(&state.storage, &name).await
+        .map_err(|e| ApiError::InternalError(format!("Failed to delete feature flag: {}", e)))?;
+    if removed.is_none() {
+        return Err(ApiError::NotFound(format!("No override for feature flag {}", name)));
+    }
+
+    record(&state, "DELETE /admin/flags", serde_json::json!({ "name": name })).await?;
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+fn layout(body: Markup) -> Markup {
+    html! {
+        (DOCTYPE)
+        html {
+            head {
+                meta charset="utf-8";
+                meta http-equiv="refresh" content=(REFRESH_SECONDS);
+                title { "prompt-wrapper admin" }
+                style {
+                    "body { font-family: sans-serif; margin: 2rem; color: #222; }"
+                    "table { border-collapse: collapse; margin-bottom: 1rem; }"
+                    "th, td { border: 1px solid #ddd; padding: 0.3rem 0.6rem; text-align: left; vertical-align: top; }"
+                    ".muted { color: #777; }"
+                }
+            }
+            body { (body) }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, StorageType};
+    use crate::models::{Saying, SayingSource};
+
+    fn state(admin_token: Option<&str>) -> Arc<AppState> {
+        let mut config = Config::from_env_with_provider(ProviderType::Mock);
+        config.storage.type_ = StorageType::Memory;
+        config.server.admin_token = admin_token.map(str::to_string);
+        crate::build_app_state(config).unwrap()
+    }
+
+    // The rendered page, or the status the request was refused with
+    async fn load(state: &Arc<AppState>, bearer: Option<&str>, query: Option<&str>) -> Result<String, StatusCode> {
+        let mut headers = HeaderMap::new();
+        if let Some(token) = bearer {
+            headers.insert(header::AUTHORIZATION, format!("Bearer {}", token).parse().unwrap());
+        }
+        let query = AdminQuery { token: query.map(str::to_string) };
+
+        match dashboard(State(state.clone()), headers, Query(query)).await {
+            Ok(response) => {
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                Ok(String::from_utf8(body.to_vec()).unwrap())
+            }
+            Err(e) => Err(e.into_response().status()),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dashboard_requires_the_admin_token() {
+        // Without a configured token the page doesn't exist
+        assert_eq!(load(&state(None), Some("secret"), None).await.unwrap_err(), StatusCode::NOT_FOUND);
+
+        let state = state(Some("secret"));
+        assert_eq!(load(&state, None, None).await.unwrap_err(), StatusCode::FORBIDDEN);
+        assert_eq!(load(&state, Some("wrong"), None).await.unwrap_err(), StatusCode::FORBIDDEN);
+        assert_eq!(load(&state, None, Some("secre")).await.unwrap_err(), StatusCode::FORBIDDEN);
+        assert!(load(&state, Some("secret"), None).await.is_ok());
+        assert!(load(&state, None, Some("secret")).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_dashboard_shows_stats_rate_limits_and_recent_sayings() {
+        let state = state(Some("secret"));
+        let saying = Saying::new("Patience is a tree".to_string(), "prompt".to_string(), SayingSource::LLM);
+        state.storage.save_saying("alice", Arc::new(saying)).await.unwrap();
+        state.rate_limiter.check("bob").await.unwrap();
+
+        let page = load(&state, Some("secret"), None).await.unwrap();
+        assert!(page.contains("Sayings generated"));
+        assert!(page.contains("<td>mock</td>"));
+        assert!(page.contains("1 users in the current window, 0 exhausted"));
+        assert!(page.contains("<td>bob</td>"));
+        assert!(page.contains("<td>alice</td>"));
+        assert!(page.contains("Patience is a tree"));
+    }
+}
@@ -0,0 +1,129 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::sync::Mutex;
+
+use crate::storage::Storage;
+
+// One admin action. Each entry's hash covers the one before it, so changing or removing an
+// entry breaks the chain from there on.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    // Position in the log, from 0
+    pub seq: u64,
+    pub at: DateTime<Utc>,
+    // Method and route, e.g. `POST /admin/bans`
+    pub action: String,
+    // What the action changed, as the endpoint saw it; never credentials
+    pub detail: serde_json::Value,
+    pub prev_hash: String,
+    // SHA-256 over every other field
+    pub hash: String,
+}
+
+impl AuditEntry {
+    fn new(previous: Option<&AuditEntry>, at: DateTime<Utc>, action: &str, detail: serde_json::Value) -> Self {
+        let mut entry = Self {
+            seq: previous.map_or(0, |previous| previous.seq + 1),
+            at,
+            action: action.to_string(),
+            detail,
+            prev_hash: previous.map_or_else(genesis_hash, |previous| previous.hash.clone()),
+            hash: String::new(),
+        };
+        entry.hash = entry.digest();
+        entry
+    }
+
+    fn digest(&self) -> String {
+        let fields = (self.seq, self.at, &self.action, &self.detail, &self.prev_hash);
+        let encoded = serde_json::to_vec(&fields).expect("audit fields serialize");
+        Sha256::digest(&encoded).iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+}
+
+// What the first entry's prev_hash points at
+fn genesis_hash() -> String {
+    "0".repeat(64)
+}
+
+// Appends admin actions to the audit log in storage
+#[derive(Default)]
+pub struct AuditLog {
+    // An append reads the last entry to chain onto, so appends go one at a time
+    append: Mutex<()>,
+}
+
+impl AuditLog {
+    pub async fn record(&self, storage: &Storage, at: DateTime<Utc>, action: &str, detail: serde_json::Value) -> Result<AuditEntry> {
+        let _append = self.append.lock().await;
+        let last = storage.last_audit_entry().await?;
+        let entry = AuditEntry::new(last.as_ref(), at, action, detail);
+        storage.append_audit_entry(&entry).await?;
+        Ok(entry)
+    }
+}
+
+// The seq of the first entry, oldest first, that was changed or doesn't follow on from the
+// entry before it. Entries cut off the end of the log leave no trace in the chain, which is
+// why readers get its length too.
+pub fn first_broken(entries: &[AuditEntry]) -> Option<u64> {
+    let mut prev_hash = genesis_hash();
+    for (seq, entry) in (0u64..).zip(entries) {
+        if entry.seq != seq || entry.prev_hash != prev_hash || entry.hash != entry.digest() {
+            return Some(entry.seq);
+        }
+        prev_hash = entry.hash.clone();
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{StorageConfig, StorageFallbackPolicy, StorageType};
+    use serde_json::json;
+
+    fn storage() -> Storage {
+        Storage::new(StorageConfig {
+            type_: StorageType::Memory,
+            connection_string: "memory".to_string(),
+            dedupe_by_content: false,
+            max_sayings_per_user: 0,
+            retention_days: 0,
+            fallback_policy: StorageFallbackPolicy::Fail,
+            compression_level: 0,
+            user_id_salt: None,
+        })
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_edited_or_removed_entries_break_the_chain() {
+        let storage = storage();
+        let log = AuditLog::default();
+        for user_id in ["alice", "bob", "carol"] {
+            log.record(&storage, Utc::now(), "POST /admin/bans", json!({ "subject": format!("user:{}", user_id) })).await.unwrap();
+        }
+
+        let entries = storage.get_audit_entries().await.unwrap();
+        assert_eq!(entries.iter().map(|entry| entry.seq).collect::<Vec<_>>(), [0, 1, 2]);
+        assert_eq!(first_broken(&entries), None);
+
+        let mut edited = entries.clone();
+        edited[1].detail = json!({ "subject": "user:mallory" });
+        assert_eq!(first_broken(&edited), Some(1));
+
+        // Rehashing the edit doesn't help, the next entry still points at the original
+        edited[1].hash = edited[1].digest();
+        assert_eq!(first_broken(&edited), Some(2));
+
+        let mut removed = entries.clone();
+        removed.remove(1);
+        assert_eq!(first_broken(&removed), Some(2));
+
+        // Entries are never overwritten
+        assert!(storage.append_audit_entry(&entries[1]).await.is_err());
+    }
+}
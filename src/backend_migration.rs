@@ -0,0 +1,290 @@
+use anyhow::{anyhow, Context, Result};
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePool, SqlitePoolOptions};
+use sqlx::{Sqlite, Transaction};
+use std::collections::HashSet;
+use std::str::FromStr;
+
+use crate::compression;
+use crate::migrations;
+use crate::models::{Saying, PresetSelectionRecord};
+use crate::storage::{PRESET_SELECTIONS_TREE, RECENT_PRESETS_TREE};
+
+// Print a progress line every this many records
+const PROGRESS_EVERY: usize = 1000;
+
+// `migrate --from sled --to sqlite`
+#[derive(Debug, Clone)]
+pub struct MigrateArgs {
+    // Sled database to read; defaults to STORAGE_CONNECTION_STRING
+    pub source: Option<String>,
+    // SQLite file to create or update; defaults to the source path with `.sqlite3` appended
+    pub target: Option<String>,
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct CopyReport {
+    pub users: usize,
+    pub sayings: usize,
+    pub cache_entries: usize,
+    pub preset_selections: usize,
+    pub recent_presets: usize,
+}
+
+// Open (creating if needed) a SQLite database and bring its schema up to date with the
+// migrations embedded from migrations/sqlite
+pub async fn open_sqlite(path: &str) -> Result<SqlitePool> {
+    let options = SqliteConnectOptions::from_str(&format!("sqlite://{}", path))
+        .with_context(|| format!("Invalid SQLite path: {}", path))?
+        .create_if_missing(true);
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect_with(options)
+        .await
+        .with_context(|| format!("Failed to open SQLite database {}", path))?;
+
+    sqlx::migrate!("./migrations/sqlite").run(&pool).await.context("Failed to apply SQLite migrations")?;
+    Ok(pool)
+}
+
+pub async fn run_cli(default_source: &str, args: MigrateArgs) -> Result<()> {
+    let source = args.source.unwrap_or_else(|| default_source.to_string());
+    let target = args.target.unwrap_or_else(|| format!("{}.sqlite3", source));
+
+    // Opening a missing path would create an empty database there
+    if !std::path::Path::new(&source).exists() {
+        return Err(anyhow!("No Sled database at {}", source));
+    }
+    let db = sled::open(&source).with_context(|| format!("Failed to open Sled database {}", source))?;
+    check_schema(&db)?;
+
+    let pool = open_sqlite(&target).await?;
+    println!("Copying {} (Sled) -> {} (SQLite)", source, target);
+
+    let report = copy(&db, &pool).await?;
+    println!(
+        "Copied {} sayings of {} users, {} cache entries, {} preset selections and {} recent preset lists",
+        report.sayings, report.users, report.cache_entries, report.preset_selections, report.recent_presets
+    );
+
+    verify(&db, &pool, &report).await?;
+    println!("Verified: every record is present in {}", target);
+    Ok(())
+}
+
+// The source is only read, so it has to be at the current layout already; migrating it is
+// left to `--migrate`, which the operator runs (and backs up for) deliberately
+fn check_schema(db: &sled::Db) -> Result<()> {
+    let version = match existing_tree(db, migrations::META_TREE)? {
+        Some(_) => migrations::schema_version(db)?,
+        None => 0,
+    };
+
+    if version != migrations::CURRENT_SCHEMA_VERSION {
+        return Err(anyhow!(
+            "Source schema version is {}, this build copies version {}; run `prompt-wrapper --migrate` on it first",
+            version, migrations::CURRENT_SCHEMA_VERSION
+        ));
+    }
+    Ok(())
+}
+
+// Opening a tree creates it, so only open the ones the source already has
+fn existing_tree(db: &sled::Db, name: &str) -> Result<Option<sled::Tree>> {
+    if !db.tree_names().iter().any(|tree| tree.as_ref() == name.as_bytes()) {
+        return Ok(None);
+    }
+    db.open_tree(name).map(Some).with_context(|| format!("Failed to open {} tree", name))
+}
+
+// Copy everything in one transaction, so an interrupted run leaves the target as it was.
+// Rows are upserted, so running it again refreshes the target.
+pub async fn copy(db: &sled::Db, pool: &SqlitePool) -> Result<CopyReport> {
+    let mut report = CopyReport::default();
+    let mut tx = pool.begin().await.context("Failed to start SQLite transaction")?;
+
+    for entry in db.iter() {
+        let (key, value) = entry.context("Failed to iterate Sled database")?;
+        // Internal keys, not users
+        if key.starts_with(b"__") {
+            continue;
+        }
+
+        let user_id = String::from_utf8_lossy(&key).into_owned();
+        let sayings: Vec<Saying> = serde_json::from_slice(&compression::decode(&value)?)
+            .with_context(|| format!("Failed to deserialize sayings of user {}", user_id))?;
+        for saying in &sayings {
+            insert_saying(&mut tx, &user_id, saying).await?;
+            report.sayings += 1;
+            progress("sayings", report.sayings);
+        }
+        report.users += 1;
+    }
+
+    for entry in existing_tree(db, "global_cache")?.iter().flat_map(sled::Tree::iter) {
+        let (key, value) = entry.context("Failed to iterate global cache")?;
+        // SQLite rows hold plain JSON, whether or not Sled compressed it
+        let value = compression::decode(&value)?;
+        let saying: Saying = serde_json::from_slice(&value).context("Failed to deserialize saying from global cache")?;
+        sqlx::query("INSERT OR REPLACE INTO global_cache (cache_key, saying_id, data) VALUES (?, ?, ?)")
+            .bind(String::from_utf8_lossy(&key).into_owned())
+            .bind(&saying.id)
+            .bind(String::from_utf8_lossy(&value).into_owned())
+            .execute(&mut *tx)
+            .await
+            .context("Failed to insert cache entry")?;
+        report.cache_entries += 1;
+        progress("cache entries", report.cache_entries);
+    }
+
+    for entry in existing_tree(db, PRESET_SELECTIONS_TREE)?.iter().flat_map(sled::Tree::iter) {
+        let (key, value) = entry.context("Failed to iterate preset selections")?;
+        // Only to reject corrupt records before they reach the target
+        let _: PresetSelectionRecord = serde_json::from_slice(&value).context("Failed to deserialize preset selection")?;
+        insert_user_record(&mut tx, "preset_selections", &key, &value).await?;
+        report.preset_selections += 1;
+        progress("preset selections", report.preset_selections);
+    }
+
+    for entry in existing_tree(db, RECENT_PRESETS_TREE)?.iter().flat_map(sled::Tree::iter) {
+        let (key, value) = entry.context("Failed to iterate recent presets")?;
+        let _: Vec<String> = serde_json::from_slice(&value).context("Failed to deserialize recent presets")?;
+        insert_user_record(&mut tx, "recent_presets", &key, &value).await?;
+        report.recent_presets += 1;
+        progress("recent preset lists", report.recent_presets);
+    }
+
+    tx.commit().await.context("Failed to commit SQLite transaction")?;
+    Ok(report)
+}
+
+async fn insert_saying(tx: &mut Transaction<'_, Sqlite>, user_id: &str, saying: &Saying) -> Result<()> {
+    let data = serde_json::to_string(saying).context("Failed to serialize saying")?;
+    sqlx::query("INSERT OR REPLACE INTO sayings (id, user_id, created_at, data) VALUES (?, ?, ?, ?)")
+        .bind(&saying.id)
+        .bind(user_id)
+        .bind(saying.created_at.to_rfc3339())
+        .bind(data)
+        .execute(&mut **tx)
+        .await
+        .with_context(|| format!("Failed to insert saying {}", saying.id))?;
+    Ok(())
+}
+
+// `table` is one of our own table names, never user input
+async fn insert_user_record(tx: &mut Transaction<'_, Sqlite>, table: &str, user_id: &[u8], data: &[u8]) -> Result<()> {
+    sqlx::query(&format!("INSERT OR REPLACE INTO {} (user_id, data) VALUES (?, ?)", table))
+        .bind(String::from_utf8_lossy(user_id).into_owned())
+        .bind(String::from_utf8_lossy(data).into_owned())
+        .execute(&mut **tx)
+        .await
+        .with_context(|| format!("Failed to insert into {}", table))?;
+    Ok(())
+}
+
+fn progress(what: &str, count: usize) {
+    if count.is_multiple_of(PROGRESS_EVERY) {
+        println!("  {} {}...", count, what);
+    }
+}
+
+// Every saying ID from the source is in the target, and no table holds fewer rows than were
+// copied. The target may hold more when it already had data of its own.
+async fn verify(db: &sled::Db, pool: &SqlitePool, report: &CopyReport) -> Result<()> {
+    let tables = [
+        ("sayings", report.sayings),
+        ("global_cache", report.cache_entries),
+        ("preset_selections", report.preset_selections),
+        ("recent_presets", report.recent_presets),
+    ];
+    for (table, copied) in tables {
+        let (count,): (i64,) = sqlx::query_as(&format!("SELECT COUNT(*) FROM {}", table))
+            .fetch_one(pool)
+            .await
+            .with_context(|| format!("Failed to count {}", table))?;
+        if (count as usize) < copied {
+            return Err(anyhow!("Verification failed: {} has {} rows, expected at least {}", table, count, copied));
+        }
+    }
+
+    let target_ids: HashSet<String> = sqlx::query_as::<_, (String,)>("SELECT id FROM sayings")
+        .fetch_all(pool)
+        .await
+        .context("Failed to read saying IDs")?
+        .into_iter()
+        .map(|(id,)| id)
+        .collect();
+    for key in existing_tree(db, migrations::SAYING_INDEX_TREE)?.iter().flat_map(|tree| tree.iter().keys()) {
+        let key = key.context("Failed to iterate saying index")?;
+        let saying_id = String::from_utf8_lossy(&key);
+        if !target_ids.contains(saying_id.as_ref()) {
+            return Err(anyhow!("Verification failed: saying {} is missing from the target", saying_id));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::SayingSource;
+    use chrono::Utc;
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn test_sled_data_is_copied_to_sqlite() {
+        let temp_dir = tempdir().unwrap();
+        let db = sled::open(temp_dir.path().join("db")).unwrap();
+        migrations::run_migrations(&db, false).unwrap();
+
+        let saying = Saying::new("content".to_string(), "prompt".to_string(), SayingSource::LLM);
+        db.insert(b"alice", serde_json::to_vec(&vec![&saying]).unwrap()).unwrap();
+        db.open_tree(migrations::SAYING_INDEX_TREE).unwrap().insert(saying.id.as_bytes(), b"alice".to_vec()).unwrap();
+        db.open_tree("global_cache").unwrap().insert(b"key", serde_json::to_vec(&saying).unwrap()).unwrap();
+        let selection = PresetSelectionRecord {
+            preset_id: "oracle".to_string(),
+            selected_at: Utc::now(),
+            expires_at: Utc::now(),
+            pinned: false,
+        };
+        db.open_tree(PRESET_SELECTIONS_TREE).unwrap().insert(b"alice", serde_json::to_vec(&selection).unwrap()).unwrap();
+        db.open_tree(RECENT_PRESETS_TREE).unwrap().insert(b"alice", br#"["oracle"]"#.to_vec()).unwrap();
+
+        let pool = open_sqlite(&temp_dir.path().join("target.sqlite3").to_string_lossy()).await.unwrap();
+        let expected = CopyReport { users: 1, sayings: 1, cache_entries: 1, preset_selections: 1, recent_presets: 1 };
+        assert_eq!(copy(&db, &pool).await.unwrap(), expected);
+        verify(&db, &pool, &expected).await.unwrap();
+
+        // A second run refreshes rows rather than duplicating them
+        assert_eq!(copy(&db, &pool).await.unwrap(), expected);
+        let (data,): (String,) = sqlx::query_as("SELECT data FROM sayings WHERE user_id = 'alice'").fetch_one(&pool).await.unwrap();
+        assert_eq!(serde_json::from_str::<Saying>(&data).unwrap().id, saying.id);
+    }
+
+    #[tokio::test]
+    async fn test_unmigrated_source_is_refused_and_left_as_it_was() {
+        let temp_dir = tempdir().unwrap();
+        let source = temp_dir.path().join("db");
+        {
+            let db = sled::open(&source).unwrap();
+            let saying = Saying::new("content".to_string(), "prompt".to_string(), SayingSource::LLM);
+            db.insert(b"alice", serde_json::to_vec(&vec![&saying]).unwrap()).unwrap();
+            db.flush().unwrap();
+        }
+
+        let args = MigrateArgs {
+            source: Some(source.to_string_lossy().into_owned()),
+            target: Some(temp_dir.path().join("target.sqlite3").to_string_lossy().into_owned()),
+        };
+        let err = run_cli("unused", args).await.unwrap_err();
+        assert!(err.to_string().contains("--migrate"));
+
+        // No schema version stamped, no trees created, nothing written to the target
+        let db = sled::open(&source).unwrap();
+        assert_eq!(db.tree_names().len(), 1);
+        assert!(!temp_dir.path().join("target.sqlite3").exists());
+
+        let missing = MigrateArgs { source: Some(temp_dir.path().join("nope").to_string_lossy().into_owned()), target: None };
+        assert!(run_cli("unused", missing).await.is_err());
+        assert!(!temp_dir.path().join("nope").exists());
+    }
+}
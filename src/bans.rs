@@ -0,0 +1,130 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+
+use crate::storage::Storage;
+use crate::user_hash::{self, UserIdHasher};
+
+// Ordered by severity, so the strictest of several matching bans wins
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BanMode {
+    // Requests look normal to the user but are only ever answered from cache
+    ShadowBan,
+    // Requests are refused with 403
+    Ban,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BanSubject {
+    User(String),
+    Ip(IpAddr),
+}
+
+impl BanSubject {
+    // Storage key, e.g. `user:alice` or `ip:203.0.113.7`
+    pub fn key(&self) -> String {
+        match self {
+            BanSubject::User(user_id) => format!("user:{}", user_id),
+            BanSubject::Ip(ip) => format!("ip:{}", ip),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Ban {
+    pub subject: BanSubject,
+    pub mode: BanMode,
+    #[serde(default)]
+    pub reason: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+// In-memory copy of the stored bans, consulted on every request
+#[derive(Debug, Default)]
+pub struct BanList {
+    bans: DashMap<String, Ban>,
+    // Storage's; user bans are stored under hashed IDs when it has one
+    user_ids: Option<UserIdHasher>,
+}
+
+impl BanList {
+    pub fn new(user_ids: Option<UserIdHasher>) -> Self {
+        Self { bans: DashMap::new(), user_ids }
+    }
+
+    // Replace the in-memory list with what is in storage
+    pub async fn load(&self, storage: &Storage) -> Result<()> {
+        let bans = storage.list_bans().await?;
+        self.bans.clear();
+        for ban in bans {
+            self.bans.insert(ban.subject.key(), ban);
+        }
+
+        tracing::info!("Loaded {} bans", self.bans.len());
+        Ok(())
+    }
+
+    // Persist a ban (replacing any existing one for the same subject) and apply it immediately
+    pub async fn add(&self, storage: &Storage, ban: Ban) -> Result<()> {
+        storage.save_ban(&ban).await?;
+        self.bans.insert(ban.subject.key(), ban);
+        Ok(())
+    }
+
+    pub async fn remove(&self, storage: &Storage, subject: &BanSubject) -> Result<Option<Ban>> {
+        storage.delete_ban(&subject.key()).await?;
+        Ok(self.bans.remove(&subject.key()).map(|(_, ban)| ban))
+    }
+
+    pub fn list(&self) -> Vec<Ban> {
+        let mut bans: Vec<Ban> = self.bans.iter().map(|entry| entry.value().clone()).collect();
+        bans.sort_by_key(|ban| ban.created_at);
+        bans
+    }
+
+    // The strictest ban covering this user or address, if any. The user ID may be plain or
+    // hashed, so the owners of stored records are covered too.
+    pub fn mode_for(&self, user_id: &str, ip: Option<IpAddr>) -> Option<BanMode> {
+        let user_id = user_hash::user_key(self.user_ids.as_ref(), user_id);
+        let by_user = self.bans.get(&BanSubject::User(user_id).key()).map(|ban| ban.mode);
+        let by_ip = ip.and_then(|ip| self.bans.get(&BanSubject::Ip(ip).key()).map(|ban| ban.mode));
+        by_user.max(by_ip)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ban(subject: BanSubject, mode: BanMode) -> Ban {
+        Ban { subject, mode, reason: None, created_at: Utc::now() }
+    }
+
+    #[test]
+    fn test_strictest_matching_ban_wins() {
+        let list = BanList::new(None);
+        let ip: IpAddr = "203.0.113.7".parse().unwrap();
+        list.bans.insert("user:alice".to_string(), ban(BanSubject::User("alice".to_string()), BanMode::ShadowBan));
+        list.bans.insert("ip:203.0.113.7".to_string(), ban(BanSubject::Ip(ip), BanMode::Ban));
+
+        assert_eq!(list.mode_for("alice", None), Some(BanMode::ShadowBan));
+        assert_eq!(list.mode_for("alice", Some(ip)), Some(BanMode::Ban));
+        assert_eq!(list.mode_for("bob", Some("198.51.100.1".parse().unwrap())), None);
+    }
+
+    #[test]
+    fn test_hashed_user_bans_cover_plain_and_hashed_ids() {
+        let hasher = UserIdHasher::new("pepper");
+        let list = BanList::new(Some(hasher.clone()));
+        let subject = BanSubject::User(hasher.hash("alice"));
+        list.bans.insert(subject.key(), ban(subject, BanMode::Ban));
+
+        assert_eq!(list.mode_for("alice", None), Some(BanMode::Ban));
+        assert_eq!(list.mode_for(&hasher.hash("alice"), None), Some(BanMode::Ban));
+        assert_eq!(list.mode_for("bob", None), None);
+    }
+}
@@ -0,0 +1,169 @@
+use anyhow::{anyhow, Result};
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+};
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+use tokio::task::JoinSet;
+use tower::ServiceExt;
+
+use crate::config::{Config, ProviderType, StorageConfig, StorageFallbackPolicy, StorageType};
+
+// Requests are spaced by a ticker with nanosecond resolution, which can't tick any faster
+pub const MAX_RPS: u64 = 1_000_000_000;
+
+// Every request's sample is kept for the report, so the run has to fit in memory
+pub const MAX_REQUESTS: u64 = 10_000_000;
+
+#[derive(Debug, Clone)]
+pub struct BenchArgs {
+    pub users: usize,
+    pub rps: u64,
+    pub duration_secs: u64,
+    pub mock_latency_ms: u64,
+}
+
+impl Default for BenchArgs {
+    fn default() -> Self {
+        Self {
+            users: 100,
+            rps: 50,
+            duration_secs: 10,
+            mock_latency_ms: 0,
+        }
+    }
+}
+
+impl BenchArgs {
+    // Requests the run sends, if that's few enough to run
+    pub fn total_requests(&self) -> Result<u64> {
+        self.rps
+            .checked_mul(self.duration_secs)
+            .filter(|&total| total <= MAX_REQUESTS)
+            .ok_or_else(|| anyhow!("--rps times --duration can be at most {} requests", MAX_REQUESTS))
+    }
+}
+
+// Outcome of a single synthetic request
+struct Sample {
+    status: StatusCode,
+    latency: Duration,
+}
+
+// Run a fixed-rate load test against the in-process router using the mock provider.
+// Rate limits come from the regular config so quota behavior can be observed as well.
+pub async fn run(args: BenchArgs) -> Result<()> {
+    let mut config = Config::from_env_with_provider(ProviderType::Mock);
+    config.openrouter.mock_latency_ms = args.mock_latency_ms;
+    // Never touch the real database during a benchmark
+    config.storage = StorageConfig {
+        type_: StorageType::Memory,
+        connection_string: "memory".to_string(),
+        dedupe_by_content: false,
+        max_sayings_per_user: 0,
+        retention_days: 0,
+        fallback_policy: StorageFallbackPolicy::Fail,
+        compression_level: 0,
+        user_id_salt: None,
+    };
+
+    let total_requests = args.total_requests()?;
+    let app = crate::build_router(crate::build_app_state(config)?);
+
+    println!(
+        "Running bench: {} users, {} req/s for {}s (mock latency {}ms)",
+        args.users, args.rps, args.duration_secs, args.mock_latency_ms
+    );
+
+    let mut ticker = tokio::time::interval(Duration::from_secs_f64(1.0 / args.rps as f64));
+    let mut tasks = JoinSet::new();
+    let started = Instant::now();
+
+    for i in 0..total_requests {
+        ticker.tick().await;
+
+        let app = app.clone();
+        let user_id = format!("bench_user_{}", i as usize % args.users);
+
+        tasks.spawn(async move {
+            let request = Request::post(format!("/sayings?user_id={}", user_id))
+                .header("Content-Type", "application/json")
+                .body(Body::from("{}"))
+                .expect("valid bench request");
+
+            let sent_at = Instant::now();
+            let status = match app.oneshot(request).await {
+                Ok(response) => response.status(),
+                Err(never) => match never {},
+            };
+
+            Sample { status, latency: sent_at.elapsed() }
+        });
+    }
+
+    let mut samples = Vec::with_capacity(total_requests as usize);
+    while let Some(result) = tasks.join_next().await {
+        samples.push(result?);
+    }
+
+    report(&samples, started.elapsed());
+
+    Ok(())
+}
+
+fn report(samples: &[Sample], elapsed: Duration) {
+    let mut latencies: Vec<Duration> = samples.iter().map(|s| s.latency).collect();
+    latencies.sort();
+
+    let mut by_status: BTreeMap<u16, usize> = BTreeMap::new();
+    for sample in samples {
+        *by_status.entry(sample.status.as_u16()).or_default() += 1;
+    }
+
+    let count = |status: StatusCode| by_status.get(&status.as_u16()).copied().unwrap_or(0);
+
+    println!();
+    println!("Requests:        {}", samples.len());
+    println!("Elapsed:         {:.2}s", elapsed.as_secs_f64());
+    println!("Achieved rate:   {:.1} req/s", samples.len() as f64 / elapsed.as_secs_f64());
+    println!();
+    println!("Latency p50:     {:?}", percentile(&latencies, 50.0));
+    println!("Latency p95:     {:?}", percentile(&latencies, 95.0));
+    println!("Latency p99:     {:?}", percentile(&latencies, 99.0));
+    println!("Latency max:     {:?}", latencies.last().copied().unwrap_or_default());
+    println!();
+    println!("Generated (201): {}", count(StatusCode::CREATED));
+    println!("Cooldown (200):  {}", count(StatusCode::OK));
+    println!("Limited (429):   {}", count(StatusCode::TOO_MANY_REQUESTS));
+
+    for (status, n) in &by_status {
+        if ![201, 200, 429].contains(status) {
+            println!("Other ({}):     {}", status, n);
+        }
+    }
+}
+
+// Nearest-rank percentile over sorted latencies
+fn percentile(sorted: &[Duration], pct: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+
+    let rank = ((pct / 100.0) * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_total_requests_are_bounded() {
+        let args = |rps, duration_secs| BenchArgs { rps, duration_secs, ..BenchArgs::default() };
+
+        assert_eq!(args(50, 10).total_requests().unwrap(), 500);
+        assert!(args(MAX_RPS, 11).total_requests().is_err());
+        assert!(args(u64::MAX, 2).total_requests().is_err());
+    }
+}
@@ -0,0 +1,178 @@
+use anyhow::{Context, Result};
+use font8x8::{UnicodeFonts, BASIC_FONTS, GREEK_FONTS, HIRAGANA_FONTS, LATIN_FONTS};
+
+// Open Graph images are 1200x630; everything below is laid out on that canvas
+const WIDTH: usize = 1200;
+const HEIGHT: usize = 630;
+const MARGIN: usize = 80;
+const BORDER: usize = 12;
+
+// Glyphs are 8x8 bitmaps drawn at these scales
+const TEXT_SCALE: usize = 4;
+const FOOTER_SCALE: usize = 2;
+const LINE_GAP: usize = 14;
+
+const BACKGROUND_TOP: [u8; 3] = [0x1f, 0x24, 0x3a];
+const BACKGROUND_BOTTOM: [u8; 3] = [0x3b, 0x2a, 0x4f];
+const ACCENT: [u8; 3] = [0xf2, 0xb1, 0x34];
+const TEXT: [u8; 3] = [0xf5, 0xf1, 0xe8];
+const MUTED: [u8; 3] = [0xb8, 0xb0, 0xc8];
+
+fn glyph(c: char) -> Option<[u8; 8]> {
+    BASIC_FONTS.get(c)
+        .or_else(|| LATIN_FONTS.get(c))
+        .or_else(|| GREEK_FONTS.get(c))
+        .or_else(|| HIRAGANA_FONTS.get(c))
+}
+
+// Whether every character of the text has a glyph, so it can be drawn as is
+pub fn can_render(text: &str) -> bool {
+    text.chars().all(|c| c.is_whitespace() || glyph(c).is_some())
+}
+
+struct Canvas {
+    pixels: Vec<u8>,
+}
+
+impl Canvas {
+    // The card template: a vertical gradient framed by an accent border
+    fn template() -> Self {
+        let mut pixels = Vec::with_capacity(WIDTH * HEIGHT * 3);
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                let framed = x < BORDER || y < BORDER || x >= WIDTH - BORDER || y >= HEIGHT - BORDER;
+                let color = if framed {
+                    ACCENT
+                } else {
+                    let t = y as f32 / HEIGHT as f32;
+                    std::array::from_fn(|i| {
+                        (BACKGROUND_TOP[i] as f32 + (BACKGROUND_BOTTOM[i] as f32 - BACKGROUND_TOP[i] as f32) * t) as u8
+                    })
+                };
+                pixels.extend_from_slice(&color);
+            }
+        }
+        Self { pixels }
+    }
+
+    fn fill(&mut self, x: usize, y: usize, width: usize, height: usize, color: [u8; 3]) {
+        for row in y..(y + height).min(HEIGHT) {
+            for column in x..(x + width).min(WIDTH) {
+                let offset = (row * WIDTH + column) * 3;
+                self.pixels[offset..offset + 3].copy_from_slice(&color);
+            }
+        }
+    }
+
+    // Draw a line of text from its top-left corner; characters without a glyph become `?`
+    fn text(&mut self, line: &str, x: usize, y: usize, scale: usize, color: [u8; 3]) {
+        let advance = 8 * scale;
+        for (index, c) in line.chars().enumerate() {
+            let rows = glyph(c).or_else(|| glyph('?')).unwrap_or_default();
+            for (row, bits) in rows.iter().enumerate() {
+                for bit in 0..8 {
+                    // The least significant bit is the leftmost pixel
+                    if bits & (1 << bit) != 0 {
+                        self.fill(x + index * advance + bit * scale, y + row * scale, scale, scale, color);
+                    }
+                }
+            }
+        }
+    }
+
+    fn encode(&self) -> Result<Vec<u8>> {
+        let mut png = Vec::new();
+        let mut encoder = png::Encoder::new(&mut png, WIDTH as u32, HEIGHT as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().context("Failed to write PNG header")?;
+        writer.write_image_data(&self.pixels).context("Failed to encode PNG")?;
+        writer.finish().context("Failed to finish PNG")?;
+        Ok(png)
+    }
+}
+
+// Greedy word wrap to `width` characters, breaking words that don't fit on a line of their own.
+// Text beyond `max_lines` is cut and the last line ends in an ellipsis.
+fn wrap(text: &str, width: usize, max_lines: usize) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for paragraph in text.lines() {
+        let mut line = String::new();
+        for word in paragraph.split_whitespace() {
+            let mut word: Vec<char> = word.chars().collect();
+            while word.len() > width {
+                if !line.is_empty() {
+                    lines.push(std::mem::take(&mut line));
+                }
+                lines.push(word.drain(..width).collect());
+            }
+            let word: String = word.into_iter().collect();
+            let needed = if line.is_empty() { word.chars().count() } else { line.chars().count() + 1 + word.chars().count() };
+            if needed > width {
+                lines.push(std::mem::take(&mut line));
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(&word);
+        }
+        if !line.is_empty() {
+            lines.push(line);
+        }
+    }
+
+    if lines.len() > max_lines {
+        lines.truncate(max_lines);
+        if let Some(last) = lines.last_mut() {
+            let kept: String = last.chars().take(width.saturating_sub(3)).collect();
+            *last = format!("{}...", kept.trim_end());
+        }
+    }
+    lines
+}
+
+// Render a saying onto the card template as a PNG, with a footer line (e.g. the service name)
+pub fn render(text: &str, footer: &str) -> Result<Vec<u8>> {
+    let mut canvas = Canvas::template();
+
+    let line_height = 8 * TEXT_SCALE + LINE_GAP;
+    let footer_height = 8 * FOOTER_SCALE;
+    let columns = (WIDTH - 2 * MARGIN) / (8 * TEXT_SCALE);
+    let rows = (HEIGHT - 2 * MARGIN - footer_height - LINE_GAP * 2) / line_height;
+    let lines = wrap(text, columns, rows);
+
+    // Center the block of text vertically in the space above the footer
+    let block_height = lines.len() * line_height;
+    let top = MARGIN + (HEIGHT - 2 * MARGIN - footer_height - block_height) / 2;
+    for (index, line) in lines.iter().enumerate() {
+        canvas.text(line, MARGIN, top + index * line_height, TEXT_SCALE, TEXT);
+    }
+
+    canvas.fill(MARGIN, HEIGHT - MARGIN - footer_height - LINE_GAP, 8 * TEXT_SCALE * 2, 4, ACCENT);
+    let footer = wrap(footer, (WIDTH - 2 * MARGIN) / (8 * FOOTER_SCALE), 1).join("");
+    canvas.text(&footer, MARGIN, HEIGHT - MARGIN - footer_height + LINE_GAP / 2, FOOTER_SCALE, MUTED);
+
+    canvas.encode()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wrap_breaks_words_and_truncates() {
+        assert_eq!(wrap("the quick brown fox", 10, 5), vec!["the quick", "brown fox"]);
+        assert_eq!(wrap("abcdefghijkl", 5, 5), vec!["abcde", "fghij", "kl"]);
+        assert_eq!(wrap("one two three four", 5, 2), vec!["one", "tw..."]);
+        // Footers are cut to a single line
+        assert_eq!(wrap("prompt-wrapper - Stoic Sage", 12, 1), vec!["prompt-wr..."]);
+    }
+
+    #[test]
+    fn test_render_produces_png() {
+        let png = render("Stay hungry, stay foolish.", "prompt-wrapper").unwrap();
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        assert!(can_render("Ça va? Ελλάδα ひらがな"));
+        assert!(!can_render("正體中文"));
+    }
+}
@@ -0,0 +1,194 @@
+use chrono::Utc;
+use dashmap::DashMap;
+use std::ops::Range;
+use std::sync::Arc;
+use tokio::sync::{Mutex, OwnedMutexGuard};
+
+use crate::config::ChatConfig;
+use crate::models::{Conversation, ConversationContextSummary};
+use crate::openrouter::{GenerationOptions, Message};
+use crate::tokens;
+use crate::AppState;
+
+const SUMMARY_SYSTEM_PROMPT: &str = "You condense conversations so they can be continued without the full transcript. \
+Summarize the conversation below in one short paragraph, building on the earlier summary when there is one. \
+Keep names, facts, decisions and open questions; leave out greetings and small talk. Reply with the summary only.";
+
+fn message(role: &str, content: String) -> Message {
+    Message {
+        role: role.to_string(),
+        content,
+    }
+}
+
+// The messages sent for the next reply: the system prompt, with the summary of older turns
+// appended when there is one, then every message the summary doesn't cover
+pub fn context(system_prompt: &str, conversation: &Conversation) -> Vec<Message> {
+    let (system_prompt, covered) = match &conversation.summary {
+        Some(summary) => (
+            format!("{}\n\nSummary of the conversation so far:\n{}", system_prompt.trim_end(), summary.content),
+            summary.covers,
+        ),
+        None => (system_prompt.to_string(), 0),
+    };
+
+    std::iter::once(message("system", system_prompt))
+        .chain(conversation.messages.iter().skip(covered).map(|m| message(m.role.as_str(), m.content.clone())))
+        .collect()
+}
+
+// Messages to fold into the summary: none while the context fits, otherwise all those not yet
+// summarized apart from the CHAT_KEEP_RECENT_MESSAGES latest
+fn to_summarize(config: &ChatConfig, conversation: &Conversation, context_tokens: usize) -> Option<Range<usize>> {
+    if context_tokens <= config.max_context_tokens {
+        return None;
+    }
+
+    let covered = conversation.summary.as_ref().map_or(0, |summary| summary.covers);
+    let end = conversation.messages.len().saturating_sub(config.keep_recent_messages);
+    (end > covered).then_some(covered..end)
+}
+
+fn transcript(conversation: &Conversation, range: Range<usize>) -> String {
+    let mut transcript = String::new();
+    if let Some(summary) = &conversation.summary {
+        transcript.push_str(&format!("Earlier summary:\n{}\n\n", summary.content));
+    }
+    for message in &conversation.messages[range] {
+        transcript.push_str(&format!("{}: {}\n\n", message.role.as_str(), message.content));
+    }
+    transcript
+}
+
+// Context for the next reply, kept within CHAT_MAX_CONTEXT_TOKENS where it can be. Older turns
+// are replaced by a summary from CHAT_SUMMARY_MODEL, which is kept on the conversation; its
+// messages stay whole. When summarizing fails the longer context is sent as it is.
+pub async fn prepare_context(state: &AppState, conversation: &mut Conversation, system_prompt: &str, priority: u32) -> Vec<Message> {
+    let config = &state.config.chat;
+    let messages = context(system_prompt, conversation);
+    let context_tokens = tokens::estimate_messages(messages.iter().map(|message| message.content.as_str()));
+    let Some(range) = to_summarize(config, conversation, context_tokens) else {
+        return messages;
+    };
+
+    let options = GenerationOptions {
+        model: config.summary_model.clone(),
+        priority,
+        mock: state.config.access.is_sandbox(&conversation.user_id),
+        ..GenerationOptions::default()
+    };
+    let prompt = transcript(conversation, range.clone());
+    match state.openrouter.get_saying_with_options(SUMMARY_SYSTEM_PROMPT, &prompt, &options).await {
+        Ok(summary) => {
+            tracing::info!(
+                "Conversation {} is about {} tokens, summarized its first {} messages",
+                conversation.id, context_tokens, range.end
+            );
+            conversation.summary = Some(ConversationContextSummary {
+                content: summary.content,
+                covers: range.end,
+                created_at: Utc::now(),
+            });
+            context(system_prompt, conversation)
+        }
+        Err(e) => {
+            tracing::warn!("Failed to summarize conversation {}: {:#}", conversation.id, e);
+            messages
+        }
+    }
+}
+
+// Runs one turn at a time per conversation. A turn loads the conversation, waits for the
+// reply and saves it, so two running at once would each save without the other's messages.
+#[derive(Default)]
+pub struct TurnLocks {
+    locks: DashMap<String, Arc<Mutex<()>>>,
+}
+
+impl TurnLocks {
+    // Wait for the conversation's running turn, if any; the turn lasts until the guard drops
+    pub async fn lock(&self, user_id: &str, conversation_id: &str) -> TurnGuard<'_> {
+        let key = format!("{}\0{}", user_id, conversation_id);
+        let lock = self.locks.entry(key.clone()).or_default().clone();
+        TurnGuard { guard: Some(lock.lock_owned().await), locks: self, key }
+    }
+}
+
+pub struct TurnGuard<'a> {
+    guard: Option<OwnedMutexGuard<()>>,
+    locks: &'a TurnLocks,
+    key: String,
+}
+
+impl Drop for TurnGuard<'_> {
+    fn drop(&mut self) {
+        self.guard.take();
+        // Waiters clone the lock under the same shard lock, so nobody is left waiting on a
+        // removed one
+        self.locks.locks.remove_if(&self.key, |_, lock| Arc::strong_count(lock) == 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::MessageRole;
+
+    fn conversation(messages: usize) -> Conversation {
+        let mut conversation = Conversation::new("user".to_string(), None);
+        for i in 0..messages {
+            let role = if i % 2 == 0 { MessageRole::User } else { MessageRole::Assistant };
+            conversation.push(role, format!("message {}", i));
+        }
+        conversation
+    }
+
+    #[test]
+    fn test_older_turns_are_summarized_once_over_budget() {
+        let config = ChatConfig {
+            max_context_tokens: 100,
+            keep_recent_messages: 4,
+            summary_model: None,
+        };
+        let mut conversation = conversation(10);
+
+        assert_eq!(to_summarize(&config, &conversation, 100), None);
+        assert_eq!(to_summarize(&config, &conversation, 101), Some(0..6));
+
+        conversation.summary = Some(ConversationContextSummary {
+            content: "They talked.".to_string(),
+            covers: 6,
+            created_at: Utc::now(),
+        });
+        // Nothing new to fold in until more messages arrive
+        assert_eq!(to_summarize(&config, &conversation, 101), None);
+
+        let messages = context("Be brief.", &conversation);
+        assert_eq!(messages.len(), 5);
+        assert!(messages[0].content.ends_with("They talked."));
+        assert_eq!(messages[1].content, "message 6");
+        // The transcript itself is untouched
+        assert_eq!(conversation.messages.len(), 10);
+    }
+
+    #[tokio::test]
+    async fn test_turns_of_one_conversation_run_one_at_a_time() {
+        let locks = Arc::new(TurnLocks::default());
+        let first = locks.lock("alice", "c1").await;
+        // Other conversations aren't held up
+        drop(locks.lock("alice", "c2").await);
+
+        let waiting = tokio::spawn({
+            let locks = locks.clone();
+            async move {
+                drop(locks.lock("alice", "c1").await);
+            }
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        assert!(!waiting.is_finished());
+
+        drop(first);
+        waiting.await.unwrap();
+        assert!(locks.locks.is_empty());
+    }
+}
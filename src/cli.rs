@@ -0,0 +1,122 @@
+use anyhow::{anyhow, Context, Result};
+
+#[cfg(feature = "sqlite-migration")]
+use crate::backend_migration::MigrateArgs;
+use crate::bench::{BenchArgs, MAX_RPS};
+
+// Top-level command selected from the command line
+#[derive(Debug)]
+pub enum Command {
+    // Run the HTTP server (default when no subcommand is given)
+    Serve,
+    // Drive the in-process router with synthetic load
+    Bench(BenchArgs),
+    // Apply pending storage schema migrations, optionally hash stored user IDs, and exit
+    Migrate { dry_run: bool, hash_user_ids: bool },
+    // Copy data from one storage backend to another
+    #[cfg(feature = "sqlite-migration")]
+    MigrateBackend(MigrateArgs),
+    // Check storage, presets, languages and optionally OpenRouter, then exit
+    SelfTest,
+}
+
+pub fn parse_args<I: IntoIterator<Item = String>>(args: I) -> Result<Command> {
+    let mut args = args.into_iter();
+
+    match args.next().as_deref() {
+        None | Some("serve") => Ok(Command::Serve),
+        Some("--migrate") => {
+            let (mut dry_run, mut hash_user_ids) = (false, false);
+            for arg in args {
+                match arg.as_str() {
+                    "--dry-run" => dry_run = true,
+                    "--hash-user-ids" => hash_user_ids = true,
+                    _ => return Err(anyhow!("Unknown flag for --migrate: {}", arg)),
+                }
+            }
+
+            Ok(Command::Migrate { dry_run, hash_user_ids })
+        }
+        Some("--self-test") => Ok(Command::SelfTest),
+        #[cfg(feature = "sqlite-migration")]
+        Some("migrate") => {
+            let mut migrate = MigrateArgs { source: None, target: None };
+            let (mut from, mut to) = (None, None);
+
+            for (name, value) in parse_flags(args)? {
+                match name.as_str() {
+                    "from" => from = Some(value),
+                    "to" => to = Some(value),
+                    "source" => migrate.source = Some(value),
+                    "target" => migrate.target = Some(value),
+                    _ => return Err(anyhow!("Unknown flag for migrate: --{}", name)),
+                }
+            }
+
+            match (from.as_deref(), to.as_deref()) {
+                (Some("sled"), Some("sqlite")) => Ok(Command::MigrateBackend(migrate)),
+                _ => Err(anyhow!("Only `migrate --from sled --to sqlite` is supported")),
+            }
+        }
+        Some("bench") => {
+            let mut bench = BenchArgs::default();
+            let flags = parse_flags(args)?;
+
+            for (name, value) in flags {
+                match name.as_str() {
+                    "users" => bench.users = parse_value(&name, &value)?,
+                    "rps" => bench.rps = parse_value(&name, &value)?,
+                    "duration" => bench.duration_secs = parse_value(&name, &value)?,
+                    "mock-latency-ms" => bench.mock_latency_ms = parse_value(&name, &value)?,
+                    _ => return Err(anyhow!("Unknown flag for bench: --{}", name)),
+                }
+            }
+
+            if bench.users == 0 || bench.rps == 0 || bench.duration_secs == 0 {
+                return Err(anyhow!("--users, --rps and --duration must be greater than zero"));
+            }
+            if bench.rps > MAX_RPS {
+                return Err(anyhow!("--rps can be at most {}", MAX_RPS));
+            }
+            bench.total_requests()?;
+
+            Ok(Command::Bench(bench))
+        }
+        // Nothing can serve from SQLite yet, so the copy tool is opt-in at build time
+        #[cfg(not(feature = "sqlite-migration"))]
+        Some("migrate") => Err(anyhow!("`migrate` needs a build with --features sqlite-migration")),
+        Some(other) => Err(anyhow!("Unknown command: {}", other)),
+    }
+}
+
+// Collect `--name value` and `--name=value` pairs
+fn parse_flags<I: Iterator<Item = String>>(mut args: I) -> Result<Vec<(String, String)>> {
+    let mut flags = Vec::new();
+
+    while let Some(arg) = args.next() {
+        let name = arg
+            .strip_prefix("--")
+            .ok_or_else(|| anyhow!("Unexpected argument: {}", arg))?;
+
+        match name.split_once('=') {
+            Some((name, value)) => flags.push((name.to_string(), value.to_string())),
+            None => {
+                let value = args
+                    .next()
+                    .ok_or_else(|| anyhow!("Missing value for --{}", name))?;
+                flags.push((name.to_string(), value));
+            }
+        }
+    }
+
+    Ok(flags)
+}
+
+fn parse_value<T: std::str::FromStr>(name: &str, value: &str) -> Result<T>
+where
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    value
+        .parse()
+        .with_context(|| format!("Invalid value for --{}: {}", name, value))
+}
@@ -0,0 +1,60 @@
+use anyhow::{Context, Result};
+use std::borrow::Cow;
+
+// First byte of a zstd-compressed Sled value. JSON can't start with it, so values written
+// as plain JSON, before compression was turned on or while it is off, read as they are.
+const ZSTD_HEADER: u8 = 0x01;
+
+// Values shorter than this aren't worth a zstd frame
+const MIN_COMPRESSED_LEN: usize = 256;
+
+// Compress serialized JSON at the given zstd level (0 leaves it as it is), keeping it plain
+// when it is short or doesn't shrink
+pub fn encode(json: Vec<u8>, level: i32) -> Result<Vec<u8>> {
+    if level == 0 || json.len() < MIN_COMPRESSED_LEN {
+        return Ok(json);
+    }
+
+    let compressed = zstd::bulk::compress(&json, level).context("Failed to compress value")?;
+    if compressed.len() + 1 >= json.len() {
+        return Ok(json);
+    }
+
+    let mut value = Vec::with_capacity(compressed.len() + 1);
+    value.push(ZSTD_HEADER);
+    value.extend_from_slice(&compressed);
+    Ok(value)
+}
+
+// The JSON in a stored value, whether or not it was compressed
+pub fn decode(value: &[u8]) -> Result<Cow<'_, [u8]>> {
+    match value.split_first() {
+        Some((&ZSTD_HEADER, compressed)) => zstd::stream::decode_all(compressed)
+            .map(Cow::Owned)
+            .context("Failed to decompress value"),
+        _ => Ok(Cow::Borrowed(value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_values_round_trip_with_and_without_compression() {
+        let long = serde_json::to_vec(&vec!["Ein langer Spruch, 一句很长的话, une longue citation"; 40]).unwrap();
+        let short = br#"{"id":"1"}"#.to_vec();
+
+        let compressed = encode(long.clone(), 3).unwrap();
+        assert_eq!(compressed[0], ZSTD_HEADER);
+        assert!(compressed.len() < long.len());
+        assert_eq!(decode(&compressed).unwrap().as_ref(), long.as_slice());
+
+        // Short values and compression turned off leave plain JSON, which reads as it is
+        assert_eq!(encode(short.clone(), 3).unwrap(), short);
+        assert_eq!(encode(long.clone(), 0).unwrap(), long);
+        assert_eq!(decode(&long).unwrap().as_ref(), long.as_slice());
+
+        assert!(decode(&[ZSTD_HEADER, 1, 2, 3]).is_err());
+    }
+}
@@ -0,0 +1,159 @@
+use anyhow::{anyhow, Result};
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::oneshot;
+
+// Caps upstream calls in flight. Once every slot is taken, callers wait and freed slots go to
+// the highest priority (the user's tier) first, oldest first within a priority.
+pub struct PriorityLimiter {
+    // 0 for no limit
+    max: usize,
+    state: Mutex<LimiterState>,
+}
+
+impl std::fmt::Debug for PriorityLimiter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (in_flight, waiting) = self.usage();
+        f.debug_struct("PriorityLimiter")
+            .field("max", &self.max)
+            .field("in_flight", &in_flight)
+            .field("waiting", &waiting)
+            .finish()
+    }
+}
+
+#[derive(Default)]
+struct LimiterState {
+    in_flight: usize,
+    waiting: BinaryHeap<Waiter>,
+    // Arrival order, to keep waiters of the same priority first come, first served
+    next_seq: u64,
+}
+
+struct Waiter {
+    priority: u32,
+    seq: u64,
+    slot: oneshot::Sender<Permit>,
+}
+
+impl PartialEq for Waiter {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Waiter {}
+
+impl PartialOrd for Waiter {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Waiter {
+    // The heap pops the greatest: higher priority, then the earlier arrival
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.priority.cmp(&other.priority).then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+// A slot held for one upstream call; dropping it hands the slot to the next waiter
+pub struct Permit {
+    limiter: Option<Arc<PriorityLimiter>>,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        if let Some(limiter) = self.limiter.take() {
+            limiter.release();
+        }
+    }
+}
+
+impl PriorityLimiter {
+    pub fn new(max: usize) -> Arc<Self> {
+        Arc::new(Self {
+            max,
+            state: Mutex::new(LimiterState::default()),
+        })
+    }
+
+    pub async fn acquire(self: &Arc<Self>, priority: u32) -> Result<Permit> {
+        match self.try_acquire(priority) {
+            Ok(permit) => Ok(permit),
+            Err(slot) => slot.await.map_err(|_| anyhow!("Concurrency limiter dropped a waiting request")),
+        }
+    }
+
+    // A permit right away if a slot is free and nobody is waiting, otherwise a place in line
+    fn try_acquire(self: &Arc<Self>, priority: u32) -> std::result::Result<Permit, oneshot::Receiver<Permit>> {
+        if self.max == 0 {
+            return Ok(Permit { limiter: None });
+        }
+
+        let mut state = self.state.lock().unwrap();
+        if state.in_flight < self.max && state.waiting.is_empty() {
+            state.in_flight += 1;
+            return Ok(Permit { limiter: Some(self.clone()) });
+        }
+
+        let (slot, receiver) = oneshot::channel();
+        let seq = state.next_seq;
+        state.next_seq += 1;
+        state.waiting.push(Waiter { priority, seq, slot });
+        Err(receiver)
+    }
+
+    // Pass the slot straight on, skipping waiters that gave up, or free it
+    fn release(self: &Arc<Self>) {
+        let mut state = self.state.lock().unwrap();
+        while let Some(waiter) = state.waiting.pop() {
+            match waiter.slot.send(Permit { limiter: Some(self.clone()) }) {
+                Ok(()) => return,
+                // Disarm the returned permit so dropping it doesn't release again
+                Err(mut permit) => permit.limiter = None,
+            }
+        }
+        state.in_flight -= 1;
+    }
+
+    pub fn max(&self) -> usize {
+        self.max
+    }
+
+    // Calls in flight and callers waiting for a slot
+    pub fn usage(&self) -> (usize, usize) {
+        let state = self.state.lock().unwrap();
+        (state.in_flight, state.waiting.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_freed_slots_go_to_higher_tiers_first() {
+        let limiter = PriorityLimiter::new(1);
+        let running = limiter.try_acquire(0).ok().unwrap();
+
+        let mut free_early = limiter.try_acquire(0).err().unwrap();
+        let mut premium = limiter.try_acquire(2).err().unwrap();
+        let mut free_late = limiter.try_acquire(0).err().unwrap();
+        assert_eq!(limiter.usage(), (1, 3));
+
+        drop(running);
+        let premium_permit = premium.try_recv().unwrap();
+        assert!(free_early.try_recv().is_err());
+
+        // A waiter that gave up is skipped
+        drop(free_early);
+        drop(premium_permit);
+        let late_permit = free_late.try_recv().unwrap();
+        assert_eq!(limiter.usage(), (1, 0));
+
+        drop(late_permit);
+        assert_eq!(limiter.usage(), (0, 0));
+    }
+}
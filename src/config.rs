@@ -1,43 +1,676 @@
 use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap};
 use std::env;
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+
+use crate::preset::ContentRating;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
+    pub profile: ConfigProfile,
     pub server: ServerConfig,
     pub openrouter: OpenRouterConfig,
     pub rate_limit: RateLimitConfig,
+    pub read_rate_limit: ReadRateLimitConfig,
     pub storage: StorageConfig,
     pub presets: PresetsConfig,
+    pub leaderboard: LeaderboardConfig,
+    pub notifications: NotificationsConfig,
+    pub http_client: HttpClientConfig,
+    pub prompt_limits: PromptLimitsConfig,
+    pub freeform: FreeformConfig,
+    pub branding: BrandingConfig,
+    pub access: AccessConfig,
+    pub cache_warmup: CacheWarmupConfig,
+    pub cache_refresh: CacheRefreshConfig,
+    pub languages: LanguagesConfig,
+    pub translation: TranslationConfig,
+    pub daily_saying: DailySayingConfig,
+    pub attribution: AttributionConfig,
+    pub quality: QualityConfig,
+    pub prompt_rotation: PromptRotationConfig,
+    pub chat: ChatConfig,
+    pub debug_log: DebugLogConfig,
+    pub self_test: SelfTestConfig,
+    pub invalidation: InvalidationConfig,
+    pub leader_election: LeaderElectionConfig,
+    pub feature_flags: FeatureFlagsConfig,
+    // Variables set neither in the environment nor in a config file, so their defaults apply; sorted
+    #[serde(skip)]
+    pub unset_variables: Vec<String>,
+}
+
+// Deployment stage from CONFIG_PROFILE, choosing config.<profile>.toml and the defaults that
+// differ between development and production
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ConfigProfile {
+    // `test_user` sandboxed, storage falls back to memory; the default in debug builds
+    #[serde(rename = "dev")]
+    Dev,
+    #[serde(rename = "staging")]
+    Staging,
+    // The default in release builds
+    #[serde(rename = "prod")]
+    Prod,
+}
+
+impl ConfigProfile {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConfigProfile::Dev => "dev",
+            ConfigProfile::Staging => "staging",
+            ConfigProfile::Prod => "prod",
+        }
+    }
+}
+
+// Settings by variable name, from the environment over config.<profile>.toml over
+// config.base.toml; both files are optional and live in CONFIG_DIR
+struct ConfigLayers {
+    profile: ConfigProfile,
+    files: HashMap<String, String>,
+}
+
+impl ConfigLayers {
+    // A config file that exists but can't be read is fatal, like a missing API key
+    fn load() -> Self {
+        let profile = match env::var("CONFIG_PROFILE").as_deref() {
+            Ok("dev") => ConfigProfile::Dev,
+            Ok("staging") => ConfigProfile::Staging,
+            Ok("prod") => ConfigProfile::Prod,
+            Ok(other) => panic!("CONFIG_PROFILE must be dev, staging or prod, not {}", other),
+            Err(_) if cfg!(debug_assertions) => ConfigProfile::Dev,
+            Err(_) => ConfigProfile::Prod,
+        };
+        let dir = env::var("CONFIG_DIR").unwrap_or_else(|_| ".".to_string());
+
+        Self::from_files(profile, Path::new(&dir)).unwrap_or_else(|e| panic!("{:#}", e))
+    }
+
+    fn from_files(profile: ConfigProfile, dir: &Path) -> anyhow::Result<Self> {
+        let mut files = HashMap::new();
+        for name in ["base", profile.as_str()] {
+            let path = dir.join(format!("config.{}.toml", name));
+            if !path.exists() {
+                continue;
+            }
+            let content = fs::read_to_string(&path)
+                .map_err(|e| anyhow::anyhow!("Failed to read config file {:?}: {}", path, e))?;
+            let table: toml::value::Table = toml::from_str(&content)
+                .map_err(|e| anyhow::anyhow!("Failed to parse config file {:?}: {}", path, e))?;
+            for (key, value) in table {
+                let value = match value {
+                    toml::Value::String(value) => value,
+                    // Lists are comma-separated, as in the environment
+                    toml::Value::Array(values) => values.iter()
+                        .map(|value| value.as_str().map(str::to_string).unwrap_or_else(|| value.to_string()))
+                        .collect::<Vec<_>>()
+                        .join(","),
+                    toml::Value::Table(_) => anyhow::bail!("{} in {:?} must be a value, not a table", key, path),
+                    value => value.to_string(),
+                };
+                files.insert(key, value);
+            }
+            tracing::info!("Loaded config file {:?}", path);
+        }
+
+        Ok(Self { profile, files })
+    }
+
+    fn var(&self, name: &str) -> Result<String, env::VarError> {
+        env::var(name).or_else(|e| self.files.get(name).cloned().ok_or(e))
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ServerConfig {
     pub host: String,
     pub port: u16,
+    // Bearer token for /admin endpoints; admin access is disabled when unset
+    pub admin_token: Option<String>,
+    // Port for the gRPC interface (only served when built with the `grpc` feature)
+    pub grpc_port: u16,
+    // Serve stored content only: writes get 503 and nothing is generated
+    pub read_only: bool,
+    // Requests still running after this long are answered with 503 (0 = no limit)
+    pub request_timeout_seconds: u64,
+    // Requests handled at once; more wait for a slot (0 = no limit)
+    pub max_concurrent_requests: usize,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OpenRouterConfig {
+    pub provider: ProviderType,
     pub api_key: String,
     pub model: String,
     pub base_url: String,
+    // Artificial latency for the mock provider, to make load tests more realistic
+    pub mock_latency_ms: u64,
+    // Default provider routing, presets can override individual fields
+    pub provider_preferences: ProviderPreferences,
+    // Proxy for OpenRouter calls only (HTTP(S) or SOCKS5), overriding the shared one
+    pub proxy_url: Option<String>,
+    // Extra attempts when the model returns empty or unusable output
+    pub empty_retries: u32,
+    // Model used for those extra attempts, defaults to `model`
+    pub fallback_model: Option<String>,
+    // Extra attempts at a call that was rate limited or failed with a 5xx
+    pub upstream_retries: u32,
+    // Wait before the first of those, doubling for each one after
+    pub retry_backoff_ms: u64,
+    // Upstream calls in flight at once; callers beyond it wait, higher tiers first (0 for no limit)
+    pub max_concurrent: usize,
+    // Moving traffic to `fallback_model` while the primary model is slow or failing
+    pub routing: ModelRoutingConfig,
+    // Sending a share of new requests to a candidate model before switching to it
+    pub canary: CanaryConfig,
+    // Asking a second model too, in the background, for offline comparison
+    pub shadow: ShadowConfig,
+    // Model -> price, for POST /sayings/estimate
+    pub prices: HashMap<String, ModelPrice>,
+    // Completion length estimates assume when the preset sets no limit
+    pub estimate_completion_tokens: u32,
+}
+
+// USD per million tokens
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct ModelPrice {
+    pub prompt: f64,
+    pub completion: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelRoutingConfig {
+    pub enabled: bool,
+    // Latency and errors are judged over the calls of this many recent seconds
+    pub window_seconds: u64,
+    // Calls needed in the window before a model is judged either way
+    pub min_samples: usize,
+    pub max_error_percent: u32,
+    // Average latency of successful calls
+    pub max_latency_ms: u64,
+    // While routed away, one request this often still goes to the primary to see if it recovered
+    pub probe_seconds: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CanaryConfig {
+    pub model: Option<String>,
+    // Share of new requests (0-100) sent to `model` instead of the primary
+    pub percent: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ShadowConfig {
+    pub model: Option<String>,
+    // Share of generated sayings (0-100) also sent to `model`; its answers are stored, never served
+    pub sample_percent: u32,
+}
+
+// OpenRouter provider routing, sent as the `provider` object of a completion request
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ProviderPreferences {
+    // Providers to try, in order
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub order: Option<Vec<String>>,
+    // Whether OpenRouter may fall back to providers outside `order`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allow_fallbacks: Option<bool>,
+    // `deny` restricts routing to providers that don't store or train on prompts
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data_collection: Option<DataCollection>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DataCollection {
+    Allow,
+    Deny,
+}
+
+impl ProviderPreferences {
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+
+    // Fields set here win over the ones in `base`
+    pub fn merged_over(&self, base: &ProviderPreferences) -> ProviderPreferences {
+        ProviderPreferences {
+            order: self.order.clone().or_else(|| base.order.clone()),
+            allow_fallbacks: self.allow_fallbacks.or(base.allow_fallbacks),
+            data_collection: self.data_collection.or(base.data_collection),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ProviderType {
+    #[serde(rename = "openrouter")]
+    OpenRouter,
+    // Canned responses without any network calls (benchmarks, local development)
+    #[serde(rename = "mock")]
+    Mock,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RateLimitConfig {
     pub max_requests: u32,
     pub window_seconds: u64,
+    pub window: RateLimitWindow,
+    // At most `burst_max` generations per `burst_seconds`, on top of the window quota (0 disables)
+    pub burst_max: u32,
+    pub burst_seconds: u64,
+    // Users and API tokens that bypass both limits; more can be added under /admin/exemptions
+    pub exempt_users: Vec<String>,
+    pub exempt_tokens: Vec<String>,
+    // Users tracked at once; past it new users are refused until windows end (0 for no bound)
+    pub max_entries: usize,
+    // How often expired windows are swept out of memory
+    pub cleanup_interval_seconds: u64,
+    // What happens to generation requests over the limit
+    pub mode: RateLimitMode,
+    // Requests waiting at once in queue mode; further ones are rejected as usual
+    pub queue_size: usize,
+    // Requests one user may have waiting at once, so one user can't fill the queue (0 for no limit)
+    pub queue_per_user: usize,
+}
+
+// Limit on history and status reads, per client rather than per user
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReadRateLimitConfig {
+    // 0 disables the read limit
+    pub max_requests: u32,
+    pub window_seconds: u64,
+}
+
+// Whether over-quota requests are turned away or wait for the user's capacity
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum RateLimitMode {
+    // 429, or a stored saying during cooldown
+    #[serde(rename = "reject")]
+    Reject,
+    // 202 with a job that generates once the window resets or the burst passes
+    #[serde(rename = "queue")]
+    Queue,
+}
+
+// How a user's quota window is measured
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum RateLimitWindow {
+    // `window_seconds` from the first request of the window
+    #[serde(rename = "rolling")]
+    Rolling,
+    // Until the user's next local midnight
+    #[serde(rename = "calendar_day")]
+    CalendarDay,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct StorageConfig {
     pub type_: StorageType,
     pub connection_string: String,
+    // Serve each distinct quote at most once from the fallback pool
+    pub dedupe_by_content: bool,
+    // Most sayings kept per user; older unpinned ones are dropped on save. 0 keeps all.
+    pub max_sayings_per_user: usize,
+    // Unpinned sayings older than this many days are dropped on save. 0 keeps them forever.
+    pub retention_days: i64,
+    // What happens when the configured backend can't be used
+    pub fallback_policy: StorageFallbackPolicy,
+    // zstd level (1-22) for Sled values holding sayings. 0 stores them as plain JSON.
+    pub compression_level: i32,
+    // When set, records are keyed on salted hashes of user IDs instead of the IDs themselves
+    pub user_id_salt: Option<String>,
+}
+
+// Whether a storage backend that fails to open stops the boot or is replaced by memory storage
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum StorageFallbackPolicy {
+    // Refuse to start; the default outside the dev profile, where memory storage would lose data
+    #[serde(rename = "fail")]
+    Fail,
+    // Warn and carry on with memory storage; the default in the dev profile
+    #[serde(rename = "memory")]
+    Memory,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PresetsConfig {
     pub file_path: String,
+    // A new daily pick avoids the user's last this-many presets
+    pub no_repeat: usize,
+    // Strongest preset rating this deployment serves
+    pub max_rating: ContentRating,
+    // Hex Ed25519 private key preset exports are signed with; exports are unsigned when unset
+    pub signing_key: Option<String>,
+    // Publisher name -> hex Ed25519 public key whose signed presets may be imported
+    pub trusted_keys: Vec<(String, String)>,
+    // Import unsigned presets and ones no trusted key verifies, with a warning
+    pub allow_unverified_imports: bool,
+    // Seeds random preset and prompt picks so a run can be replayed; seeded from the OS when unset
+    pub rng_seed: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LeaderboardConfig {
+    pub enabled: bool,
+    // Only activity within this many days counts towards the ranking
+    pub window_days: i64,
+    pub refresh_seconds: u64,
+    pub size: usize,
+}
+
+// Outbound HTTP client shared by all upstream calls
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HttpClientConfig {
+    pub pool_max_idle_per_host: usize,
+    // Idle pooled connections are closed after this long
+    pub pool_idle_timeout_secs: u64,
+    pub tcp_keepalive_secs: u64,
+    pub connect_timeout_secs: u64,
+    // Speak HTTP/2 without negotiation, for upstreams or proxies known to support it
+    pub http2_prior_knowledge: bool,
+    // Ping interval that keeps idle HTTP/2 connections open
+    pub http2_keep_alive_secs: Option<u64>,
+    // Route all outbound traffic through this proxy
+    pub proxy_url: Option<String>,
+}
+
+// Token budgets checked before a prompt is sent upstream
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PromptLimitsConfig {
+    pub max_user_tokens: usize,
+    // System prompt plus preset context and translation instructions
+    pub max_system_tokens: usize,
+    pub overflow: PromptOverflow,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AccessConfig {
+    pub policy: AccessPolicyKind,
+    // API token -> user ID (or `*`) for `token`, token -> tenant for `tenant`
+    pub tokens: HashMap<String, String>,
+    // User ID -> tier, unlisted users are tier 0
+    pub tiers: HashMap<String, u32>,
+    // Bind each user ID to the bearer token that first generated for it, and require that
+    // token to read the user's history
+    pub protect_history: bool,
+    // Who requests without a user ID act as
+    pub default_user: DefaultUserMode,
+    // Users whose generations come from the mock provider instead of upstream, through the
+    // rest of the pipeline (limits, storage, history); `test_user` in the dev profile by default
+    pub sandbox_users: Vec<String>,
+    // Reverse proxies whose X-Forwarded-For gives the client address for IP bans and limits
+    pub trusted_proxies: Vec<IpAddr>,
+}
+
+impl AccessConfig {
+    pub fn tier(&self, user_id: &str) -> u32 {
+        self.tiers.get(user_id).copied().unwrap_or(0)
+    }
+
+    pub fn is_sandbox(&self, user_id: &str) -> bool {
+        self.sandbox_users.iter().any(|sandbox_user| sandbox_user == user_id)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DefaultUserMode {
+    // All anonymous traffic shares DEFAULT_USER_ID, its history and its quota
+    Shared,
+    // Requests must name a user (400 otherwise)
+    Reject,
+    // Each connection gets its own throwaway ID
+    Ephemeral,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AccessPolicyKind {
+    // Anyone may act as any user, subject to bans
+    Open,
+    // No checks at all, for trusted internal deployments
+    AllowAll,
+    // Requests need a bearer token mapped to the user
+    Token,
+    // Requests need a tenant token, and user IDs are namespaced by tenant
+    Tenant,
+}
+
+// Pre-generating sayings at startup so the fallback pool isn't empty on a fresh deployment
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CacheWarmupConfig {
+    pub enabled: bool,
+    // One saying per preset is generated in each of these languages
+    pub languages: Vec<String>,
+    // Upper bound on LLM requests made by a single warm-up
+    pub budget: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LanguagesConfig {
+    // Language ID -> the ID to try next when it isn't supported, for read paths
+    pub fallbacks: HashMap<String, String>,
+    // YAML file of key terms and their preferred translations
+    pub glossary_path: Option<String>,
+    // Check generated translations against the glossary and report misses
+    pub validate_glossary: bool,
+    // Requests without a language get the one their prompt is written in
+    pub detect_prompt_language: bool,
+}
+
+// Who translates sayings into languages other than English
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TranslationConfig {
+    pub provider: TranslatorKind,
+    // API key for DeepL or Google Translate
+    pub api_key: String,
+    // Overrides the service's default endpoint, e.g. for a proxy
+    pub base_url: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TranslatorKind {
+    // The model writes the translation itself, as instructed by the generation prompt
+    Llm,
+    DeepL,
+    Google,
+}
+
+// One saying per preset and language per day, shared by everyone (GET /sayings/daily)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DailySayingConfig {
+    pub enabled: bool,
+    // Presets to generate for; empty means every public preset
+    pub presets: Vec<String>,
+    pub languages: Vec<String>,
+    // How often to look for pairs that have no saying for today yet
+    pub check_seconds: u64,
+}
+
+// Grading generated sayings with a second LLM call, in the background
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QualityConfig {
+    pub enabled: bool,
+    // Model asked to grade; the regular routing picks one when unset
+    pub judge_model: Option<String>,
+    // Share of generated sayings (0-100) that get graded
+    pub sample_percent: u32,
+}
+
+// Picking preset user prompts with poor feedback and quality marks less often
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PromptRotationConfig {
+    pub enabled: bool,
+    pub refresh_seconds: u64,
+    // Ratings (feedback and judge marks) a prompt needs before it is judged
+    pub min_ratings: usize,
+    // Prompts averaging below this, on the 1-5 scale, count as poor
+    pub poor_score: f64,
+    // Selection weight of a poor prompt, against 1 for the others
+    pub poor_weight: f64,
+}
+
+// Multi-turn chat over stored conversations
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatConfig {
+    // Estimated prompt size above which older turns are folded into a summary
+    pub max_context_tokens: usize,
+    // Latest messages always sent word for word
+    pub keep_recent_messages: usize,
+    // Cheap model that writes the summaries; the regular routing picks one when unset
+    pub summary_model: Option<String>,
+}
+
+// Credits printed on exports and share cards
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AttributionConfig {
+    // Name the model on shared collections and share cards; owners always see it in their exports
+    pub show_model: bool,
+}
+
+// Recording full request and upstream payloads, scrubbed of secrets and PII, for troubleshooting
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DebugLogConfig {
+    pub enabled: bool,
+    // Entries kept in memory for GET /admin/debug/recent
+    pub capacity: usize,
+    // Also append every entry to this file as a JSON line
+    pub file_path: Option<String>,
+    // Longer bodies are cut off
+    pub max_body_bytes: usize,
+}
+
+// Keeping per-instance caches consistent across replicas over Redis pub/sub
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InvalidationConfig {
+    // Broadcasting is off when unset
+    pub redis_url: Option<String>,
+    pub channel: String,
+}
+
+// Running scheduled jobs on only one of several instances sharing a Redis server
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LeaderElectionConfig {
+    pub enabled: bool,
+    // How long the leader's lease lasts; it is renewed at a third of this
+    pub lease_seconds: u64,
+    // Where the lease is kept; required when enabled
+    pub redis_url: Option<String>,
+}
+
+// Experimental behaviours rolled out to a share of users
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FeatureFlagsConfig {
+    // YAML file of flag rules; without it every flag is off until an admin override
+    pub file_path: Option<String>,
+}
+
+// Checking storage, presets, languages and optionally OpenRouter before serving
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SelfTestConfig {
+    // Strict mode: refuse to start when a startup check fails, rather than only reporting it
+    pub on_startup: bool,
+    // Include a request to OpenRouter, which needs network access and a valid key
+    pub ping_upstream: bool,
+}
+
+// Regenerating each preset's cached saying on a schedule so the fallback pool rotates
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CacheRefreshConfig {
+    pub enabled: bool,
+    pub interval_hours: u64,
+}
+
+// Requests that bring their own prompt instead of using a preset
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FreeformConfig {
+    // System prompt sent with every freeform prompt; users can't replace it
+    pub system_prompt: String,
+    // Language ID -> system prompt replacing `system_prompt` for sayings in that language
+    pub language_prompts: HashMap<String, String>,
+    // Keep freeform prompts for review under /admin/freeform-prompts
+    pub audit_log: bool,
+    // Newest entries the audit log keeps; older ones are dropped as new ones come in
+    pub audit_log_max_entries: usize,
+}
+
+impl FreeformConfig {
+    pub fn system_prompt_for(&self, language_id: &str) -> &str {
+        self.language_prompts.get(language_id).unwrap_or(&self.system_prompt)
+    }
+}
+
+// White-label voice shared by every preset and freeform prompt of a deployment
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BrandingConfig {
+    pub app_name: Option<String>,
+    // Appended to every system prompt, with `{app_name}` replaced
+    pub persona_suffix: Option<String>,
+}
+
+impl BrandingConfig {
+    // The system prompt with the deployment's persona appended; unchanged without branding
+    pub fn apply(&self, system_prompt: String) -> String {
+        let suffix = match (&self.persona_suffix, &self.app_name) {
+            (Some(suffix), app_name) => suffix.replace("{app_name}", app_name.as_deref().unwrap_or_default()),
+            (None, Some(app_name)) => format!("You are writing on behalf of {}.", app_name),
+            (None, None) => return system_prompt,
+        };
+        format!("{}\n\n{}", system_prompt.trim_end(), suffix.trim())
+    }
+}
+
+// Per-language settings are read from `<PREFIX>_<ID>`, the language ID uppercased with
+// dashes as underscores, e.g. FREEFORM_SYSTEM_PROMPT_ZH_TW
+fn language_overrides(prefix: &str) -> HashMap<String, String> {
+    crate::languages::get_all_languages()
+        .into_iter()
+        .filter_map(|language| {
+            let name = format!("{}_{}", prefix, language.id.to_uppercase().replace('-', "_"));
+            let value = env::var(name).ok().filter(|value| !value.trim().is_empty())?;
+            Some((language.id, value))
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PromptOverflow {
+    // Fail the request with 400
+    Reject,
+    // Cut the prompt down to the budget and carry on
+    Truncate,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NotificationsConfig {
+    pub enabled: bool,
+    // Daily sayings go out from this hour (UTC) onwards
+    pub daily_hour: u32,
+    pub check_seconds: u64,
+    // Extra attempts per delivery before giving up for the day
+    pub max_retries: u32,
+    pub telegram_bot_token: Option<String>,
+    pub email: EmailConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmailConfig {
+    // Email delivery is disabled unless a host is set
+    pub smtp_host: Option<String>,
+    pub smtp_port: u16,
+    pub smtp_username: Option<String>,
+    pub smtp_password: Option<String>,
+    pub smtp_starttls: bool,
+    pub from: String,
+    // Externally reachable base URL, used for unsubscribe links
+    pub public_url: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -52,50 +685,624 @@ pub enum StorageType {
     Sled,
 }
 
-// Test user ID - only valid in debug builds
-#[cfg(debug_assertions)]
-pub const TEST_USER_ID: &str = "test_user";
-
-#[cfg(not(debug_assertions))]
-pub const TEST_USER_ID: &str = "invalid_test_user";
+// Shared user ID for requests without one, with DEFAULT_USER_MODE=shared
+pub const DEFAULT_USER_ID: &str = "default_user";
 
 impl Config {
     pub fn from_env() -> Self {
-        Config {
+        let layers = ConfigLayers::load();
+        let provider = match layers.var("LLM_PROVIDER").unwrap_or_else(|_| "openrouter".to_string()).as_str() {
+            "mock" => ProviderType::Mock,
+            _ => ProviderType::OpenRouter,
+        };
+
+        Self::from_layers(provider, &layers)
+    }
+
+    pub fn from_env_with_provider(provider: ProviderType) -> Self {
+        Self::from_layers(provider, &ConfigLayers::load())
+    }
+
+    fn from_layers(provider: ProviderType, layers: &ConfigLayers) -> Self {
+        let profile = layers.profile;
+        // Every variable that isn't set, for the startup report
+        let unset = RefCell::new(BTreeSet::new());
+        let var = |name: &str| {
+            let value = layers.var(name);
+            if value.is_err() {
+                unset.borrow_mut().insert(name.to_string());
+            }
+            value
+        };
+
+        // The mock provider never talks to OpenRouter, so it doesn't need a key
+        let api_key = match provider {
+            ProviderType::OpenRouter => var("OPENROUTER_API_KEY").expect("OPENROUTER_API_KEY must be set"),
+            ProviderType::Mock => var("OPENROUTER_API_KEY").unwrap_or_default(),
+        };
+
+        let config = Config {
+            profile,
             server: ServerConfig {
-                host: env::var("SERVER_HOST").unwrap_or_else(|_| "127.0.0.1".to_string()),
-                port: env::var("SERVER_PORT")
+                host: var("SERVER_HOST").unwrap_or_else(|_| "127.0.0.1".to_string()),
+                port: var("SERVER_PORT")
                     .unwrap_or_else(|_| "3000".to_string())
                     .parse()
                     .unwrap_or(3000),
+                admin_token: var("ADMIN_TOKEN").ok().filter(|token| !token.is_empty()),
+                grpc_port: var("GRPC_PORT")
+                    .unwrap_or_else(|_| "50051".to_string())
+                    .parse()
+                    .unwrap_or(50051),
+                read_only: var("READ_ONLY")
+                    .map(|v| v == "true")
+                    .unwrap_or(false),
+                request_timeout_seconds: var("REQUEST_TIMEOUT_SECONDS")
+                    .unwrap_or_else(|_| "60".to_string())
+                    .parse()
+                    .unwrap_or(60),
+                max_concurrent_requests: var("MAX_CONCURRENT_REQUESTS")
+                    .unwrap_or_else(|_| "0".to_string())
+                    .parse()
+                    .unwrap_or(0),
             },
             openrouter: OpenRouterConfig {
-                api_key: env::var("OPENROUTER_API_KEY").expect("OPENROUTER_API_KEY must be set"),
-                model: env::var("OPENROUTER_MODEL").unwrap_or_else(|_| "mistralai/mistral-7b-instruct".to_string()),
-                base_url: env::var("OPENROUTER_BASE_URL").unwrap_or_else(|_| "https://openrouter.ai/api/v1".to_string()),
+                provider,
+                api_key,
+                model: var("OPENROUTER_MODEL").unwrap_or_else(|_| "mistralai/mistral-7b-instruct".to_string()),
+                base_url: var("OPENROUTER_BASE_URL").unwrap_or_else(|_| "https://openrouter.ai/api/v1".to_string()),
+                mock_latency_ms: var("LLM_MOCK_LATENCY_MS")
+                    .unwrap_or_else(|_| "0".to_string())
+                    .parse()
+                    .unwrap_or(0),
+                provider_preferences: ProviderPreferences {
+                    order: var("OPENROUTER_PROVIDER_ORDER").ok()
+                        .map(|v| v.split(',').map(|p| p.trim().to_string()).filter(|p| !p.is_empty()).collect::<Vec<_>>())
+                        .filter(|order| !order.is_empty()),
+                    allow_fallbacks: var("OPENROUTER_ALLOW_FALLBACKS").ok()
+                        .map(|v| v == "true"),
+                    data_collection: match var("OPENROUTER_DATA_COLLECTION").as_deref() {
+                        Ok("deny") => Some(DataCollection::Deny),
+                        Ok("allow") => Some(DataCollection::Allow),
+                        _ => None,
+                    },
+                },
+                proxy_url: var("OPENROUTER_PROXY").ok().filter(|url| !url.is_empty()),
+                empty_retries: var("LLM_EMPTY_RETRIES")
+                    .unwrap_or_else(|_| "2".to_string())
+                    .parse()
+                    .unwrap_or(2),
+                fallback_model: var("OPENROUTER_FALLBACK_MODEL").ok().filter(|model| !model.is_empty()),
+                upstream_retries: var("LLM_UPSTREAM_RETRIES")
+                    .unwrap_or_else(|_| "2".to_string())
+                    .parse()
+                    .unwrap_or(2),
+                retry_backoff_ms: var("LLM_RETRY_BACKOFF_MS")
+                    .unwrap_or_else(|_| "500".to_string())
+                    .parse()
+                    .unwrap_or(500),
+                max_concurrent: var("LLM_MAX_CONCURRENT")
+                    .unwrap_or_else(|_| "0".to_string())
+                    .parse()
+                    .unwrap_or(0),
+                routing: ModelRoutingConfig {
+                    enabled: var("MODEL_ROUTING_ENABLED")
+                        .map(|v| v == "true")
+                        .unwrap_or(false),
+                    window_seconds: var("MODEL_ROUTING_WINDOW_SECONDS")
+                        .unwrap_or_else(|_| "300".to_string())
+                        .parse()
+                        .unwrap_or(300),
+                    min_samples: var("MODEL_ROUTING_MIN_SAMPLES")
+                        .unwrap_or_else(|_| "5".to_string())
+                        .parse()
+                        .unwrap_or(5),
+                    max_error_percent: var("MODEL_ROUTING_MAX_ERROR_PERCENT")
+                        .unwrap_or_else(|_| "50".to_string())
+                        .parse()
+                        .unwrap_or(50),
+                    max_latency_ms: var("MODEL_ROUTING_MAX_LATENCY_MS")
+                        .unwrap_or_else(|_| "15000".to_string())
+                        .parse()
+                        .unwrap_or(15000),
+                    probe_seconds: var("MODEL_ROUTING_PROBE_SECONDS")
+                        .unwrap_or_else(|_| "30".to_string())
+                        .parse()
+                        .unwrap_or(30),
+                },
+                canary: CanaryConfig {
+                    model: var("CANARY_MODEL").ok().filter(|model| !model.is_empty()),
+                    percent: var("CANARY_PERCENT")
+                        .unwrap_or_else(|_| "0".to_string())
+                        .parse::<u32>()
+                        .unwrap_or(0)
+                        .min(100),
+                },
+                shadow: ShadowConfig {
+                    model: var("SHADOW_MODEL").ok().filter(|model| !model.is_empty()),
+                    sample_percent: var("SHADOW_SAMPLE_PERCENT")
+                        .unwrap_or_else(|_| "0".to_string())
+                        .parse::<u32>()
+                        .unwrap_or(0)
+                        .min(100),
+                },
+                // model=prompt:completion pairs, e.g. openai/gpt-4o-mini=0.15:0.6
+                prices: var("MODEL_PRICES")
+                    .unwrap_or_default()
+                    .split(',')
+                    .filter_map(|pair| pair.split_once('='))
+                    .filter_map(|(model, price)| {
+                        let (prompt, completion) = price.split_once(':')?;
+                        let price = ModelPrice { prompt: prompt.trim().parse().ok()?, completion: completion.trim().parse().ok()? };
+                        Some((model.trim().to_string(), price))
+                    })
+                    .collect(),
+                estimate_completion_tokens: var("ESTIMATE_COMPLETION_TOKENS")
+                    .unwrap_or_else(|_| "200".to_string())
+                    .parse()
+                    .unwrap_or(200),
             },
             rate_limit: RateLimitConfig {
-                max_requests: env::var("RATE_LIMIT_MAX_REQUESTS")
+                max_requests: var("RATE_LIMIT_MAX_REQUESTS")
                     .unwrap_or_else(|_| "10".to_string())
                     .parse()
                     .unwrap_or(10),
-                window_seconds: env::var("RATE_LIMIT_WINDOW_SECONDS")
+                window_seconds: var("RATE_LIMIT_WINDOW_SECONDS")
                     .unwrap_or_else(|_| "3600".to_string())
                     .parse()
                     .unwrap_or(3600),
+                window: match var("RATE_LIMIT_WINDOW").unwrap_or_else(|_| "rolling".to_string()).as_str() {
+                    "calendar_day" => RateLimitWindow::CalendarDay,
+                    _ => RateLimitWindow::Rolling,
+                },
+                burst_max: var("RATE_LIMIT_BURST_MAX")
+                    .unwrap_or_else(|_| "0".to_string())
+                    .parse()
+                    .unwrap_or(0),
+                burst_seconds: var("RATE_LIMIT_BURST_SECONDS")
+                    .unwrap_or_else(|_| "60".to_string())
+                    .parse()
+                    .unwrap_or(60),
+                exempt_users: var("RATE_LIMIT_EXEMPT_USERS")
+                    .unwrap_or_default()
+                    .split(',')
+                    .map(|user_id| user_id.trim().to_string())
+                    .filter(|user_id| !user_id.is_empty())
+                    .collect(),
+                exempt_tokens: var("RATE_LIMIT_EXEMPT_TOKENS")
+                    .unwrap_or_default()
+                    .split(',')
+                    .map(|token| token.trim().to_string())
+                    .filter(|token| !token.is_empty())
+                    .collect(),
+                max_entries: var("RATE_LIMIT_MAX_ENTRIES")
+                    .unwrap_or_else(|_| "100000".to_string())
+                    .parse()
+                    .unwrap_or(100_000),
+                cleanup_interval_seconds: var("RATE_LIMIT_CLEANUP_INTERVAL_SECONDS")
+                    .unwrap_or_else(|_| "300".to_string())
+                    .parse()
+                    .unwrap_or(300),
+                mode: match var("RATE_LIMIT_MODE").as_deref() {
+                    Ok("queue") => RateLimitMode::Queue,
+                    _ => RateLimitMode::Reject,
+                },
+                queue_size: var("RATE_LIMIT_QUEUE_SIZE")
+                    .unwrap_or_else(|_| "100".to_string())
+                    .parse()
+                    .unwrap_or(100),
+                queue_per_user: var("RATE_LIMIT_QUEUE_PER_USER")
+                    .unwrap_or_else(|_| "5".to_string())
+                    .parse()
+                    .unwrap_or(5),
+            },
+            read_rate_limit: ReadRateLimitConfig {
+                max_requests: var("READ_RATE_LIMIT_MAX_REQUESTS")
+                    .unwrap_or_else(|_| "300".to_string())
+                    .parse()
+                    .unwrap_or(300),
+                window_seconds: var("READ_RATE_LIMIT_WINDOW_SECONDS")
+                    .unwrap_or_else(|_| "60".to_string())
+                    .parse()
+                    .unwrap_or(60),
             },
             storage: StorageConfig {
-                type_: match env::var("STORAGE_TYPE").unwrap_or_else(|_| "memory".to_string()).as_str() {
+                type_: match var("STORAGE_TYPE").unwrap_or_else(|_| "memory".to_string()).as_str() {
                     "sqlite" => StorageType::SQLite,
                     "redis" => StorageType::Redis,
                     "sled" => StorageType::Sled,
                     _ => StorageType::Memory,
                 },
-                connection_string: env::var("STORAGE_CONNECTION_STRING").unwrap_or_else(|_| "memory".to_string()),
+                connection_string: var("STORAGE_CONNECTION_STRING").unwrap_or_else(|_| "memory".to_string()),
+                dedupe_by_content: var("CACHE_DEDUPE_BY_CONTENT")
+                    .map(|v| v == "true")
+                    .unwrap_or(false),
+                max_sayings_per_user: var("HISTORY_MAX_SAYINGS_PER_USER")
+                    .unwrap_or_else(|_| "0".to_string())
+                    .parse()
+                    .unwrap_or(0),
+                retention_days: var("HISTORY_RETENTION_DAYS")
+                    .unwrap_or_else(|_| "0".to_string())
+                    .parse()
+                    .unwrap_or(0),
+                fallback_policy: match var("STORAGE_FALLBACK_POLICY").as_deref() {
+                    Ok("fail") => StorageFallbackPolicy::Fail,
+                    Ok("memory") => StorageFallbackPolicy::Memory,
+                    _ if profile == ConfigProfile::Dev => StorageFallbackPolicy::Memory,
+                    _ => StorageFallbackPolicy::Fail,
+                },
+                compression_level: var("STORAGE_COMPRESSION_LEVEL")
+                    .unwrap_or_else(|_| "0".to_string())
+                    .parse::<i32>()
+                    .map(|level| level.clamp(0, 22))
+                    .unwrap_or(0),
+                user_id_salt: var("STORAGE_USER_ID_SALT").ok().filter(|salt| !salt.is_empty()),
             },
             presets: PresetsConfig {
-                file_path: env::var("PRESETS_FILE_PATH").unwrap_or_else(|_| "./presets.yaml".to_string()),
+                file_path: var("PRESETS_FILE_PATH").unwrap_or_else(|_| "./presets.yaml".to_string()),
+                no_repeat: var("PRESET_NO_REPEAT")
+                    .unwrap_or_else(|_| "1".to_string())
+                    .parse()
+                    .unwrap_or(1),
+                max_rating: match var("PRESETS_MAX_RATING").as_deref() {
+                    Ok("all-ages") => ContentRating::AllAges,
+                    Ok("teen") => ContentRating::Teen,
+                    _ => ContentRating::Mature,
+                },
+                signing_key: var("PRESETS_SIGNING_KEY").ok().filter(|key| !key.trim().is_empty()),
+                trusted_keys: var("PRESETS_TRUSTED_KEYS")
+                    .unwrap_or_default()
+                    .split(',')
+                    .filter_map(|pair| pair.split_once('='))
+                    .map(|(publisher, key)| (publisher.trim().to_string(), key.trim().to_string()))
+                    .filter(|(publisher, key)| !publisher.is_empty() && !key.is_empty())
+                    .collect(),
+                allow_unverified_imports: var("PRESETS_ALLOW_UNVERIFIED")
+                    .map(|v| v == "true")
+                    .unwrap_or(false),
+                rng_seed: var("PRESETS_RNG_SEED").ok().and_then(|seed| seed.trim().parse().ok()),
             },
+            leaderboard: LeaderboardConfig {
+                enabled: var("LEADERBOARD_ENABLED")
+                    .map(|v| v == "true")
+                    .unwrap_or(false),
+                window_days: var("LEADERBOARD_WINDOW_DAYS")
+                    .unwrap_or_else(|_| "7".to_string())
+                    .parse()
+                    .unwrap_or(7),
+                refresh_seconds: var("LEADERBOARD_REFRESH_SECONDS")
+                    .unwrap_or_else(|_| "300".to_string())
+                    .parse()
+                    .unwrap_or(300),
+                size: var("LEADERBOARD_SIZE")
+                    .unwrap_or_else(|_| "10".to_string())
+                    .parse()
+                    .unwrap_or(10),
+            },
+            notifications: NotificationsConfig {
+                enabled: var("NOTIFICATIONS_ENABLED")
+                    .map(|v| v == "true")
+                    .unwrap_or(false),
+                daily_hour: var("NOTIFICATIONS_DAILY_HOUR")
+                    .unwrap_or_else(|_| "9".to_string())
+                    .parse()
+                    .unwrap_or(9),
+                check_seconds: var("NOTIFICATIONS_CHECK_SECONDS")
+                    .unwrap_or_else(|_| "300".to_string())
+                    .parse()
+                    .unwrap_or(300),
+                max_retries: var("NOTIFICATIONS_MAX_RETRIES")
+                    .unwrap_or_else(|_| "3".to_string())
+                    .parse()
+                    .unwrap_or(3),
+                telegram_bot_token: var("TELEGRAM_BOT_TOKEN").ok().filter(|token| !token.is_empty()),
+                email: EmailConfig {
+                    smtp_host: var("SMTP_HOST").ok().filter(|host| !host.is_empty()),
+                    smtp_port: var("SMTP_PORT")
+                        .unwrap_or_else(|_| "587".to_string())
+                        .parse()
+                        .unwrap_or(587),
+                    smtp_username: var("SMTP_USERNAME").ok(),
+                    smtp_password: var("SMTP_PASSWORD").ok(),
+                    smtp_starttls: var("SMTP_STARTTLS")
+                        .map(|v| v != "false")
+                        .unwrap_or(true),
+                    from: var("SMTP_FROM").unwrap_or_else(|_| "Prompt Wrapper <noreply@localhost>".to_string()),
+                    public_url: var("PUBLIC_URL").unwrap_or_else(|_| "http://localhost:3000".to_string()),
+                },
+            },
+            http_client: HttpClientConfig {
+                pool_max_idle_per_host: var("HTTP_POOL_MAX_IDLE_PER_HOST")
+                    .unwrap_or_else(|_| "32".to_string())
+                    .parse()
+                    .unwrap_or(32),
+                pool_idle_timeout_secs: var("HTTP_POOL_IDLE_TIMEOUT_SECS")
+                    .unwrap_or_else(|_| "90".to_string())
+                    .parse()
+                    .unwrap_or(90),
+                tcp_keepalive_secs: var("HTTP_TCP_KEEPALIVE_SECS")
+                    .unwrap_or_else(|_| "60".to_string())
+                    .parse()
+                    .unwrap_or(60),
+                connect_timeout_secs: var("HTTP_CONNECT_TIMEOUT_SECS")
+                    .unwrap_or_else(|_| "10".to_string())
+                    .parse()
+                    .unwrap_or(10),
+                http2_prior_knowledge: var("HTTP2_PRIOR_KNOWLEDGE")
+                    .map(|v| v == "true")
+                    .unwrap_or(false),
+                http2_keep_alive_secs: var("HTTP2_KEEP_ALIVE_SECS").ok()
+                    .and_then(|v| v.parse().ok()),
+                proxy_url: var("OUTBOUND_PROXY").ok().filter(|url| !url.is_empty()),
+            },
+            prompt_limits: PromptLimitsConfig {
+                max_user_tokens: var("MAX_PROMPT_TOKENS")
+                    .unwrap_or_else(|_| "512".to_string())
+                    .parse()
+                    .unwrap_or(512),
+                max_system_tokens: var("MAX_SYSTEM_PROMPT_TOKENS")
+                    .unwrap_or_else(|_| "2048".to_string())
+                    .parse()
+                    .unwrap_or(2048),
+                overflow: match var("PROMPT_OVERFLOW").as_deref() {
+                    Ok("truncate") => PromptOverflow::Truncate,
+                    _ => PromptOverflow::Reject,
+                },
+            },
+            access: AccessConfig {
+                policy: match var("ACCESS_POLICY").as_deref() {
+                    Ok("allow_all") => AccessPolicyKind::AllowAll,
+                    Ok("token") => AccessPolicyKind::Token,
+                    Ok("tenant") => AccessPolicyKind::Tenant,
+                    _ => AccessPolicyKind::Open,
+                },
+                tokens: var("ACCESS_TOKENS")
+                    .unwrap_or_default()
+                    .split(',')
+                    .filter_map(|pair| pair.split_once('='))
+                    .map(|(token, scope)| (token.trim().to_string(), scope.trim().to_string()))
+                    .filter(|(token, scope)| !token.is_empty() && !scope.is_empty())
+                    .collect(),
+                tiers: var("USER_TIERS")
+                    .unwrap_or_default()
+                    .split(',')
+                    .filter_map(|pair| pair.split_once('='))
+                    .filter_map(|(user_id, tier)| Some((user_id.trim().to_string(), tier.trim().parse().ok()?)))
+                    .collect(),
+                protect_history: var("PROTECT_USER_HISTORY")
+                    .map(|v| v == "true")
+                    .unwrap_or(false),
+                default_user: match var("DEFAULT_USER_MODE").as_deref() {
+                    Ok("reject") => DefaultUserMode::Reject,
+                    Ok("ephemeral") => DefaultUserMode::Ephemeral,
+                    _ => DefaultUserMode::Shared,
+                },
+                sandbox_users: var("SANDBOX_USERS")
+                    .unwrap_or_else(|_| if profile == ConfigProfile::Dev { "test_user".to_string() } else { String::new() })
+                    .split(',')
+                    .map(|user_id| user_id.trim().to_string())
+                    .filter(|user_id| !user_id.is_empty())
+                    .collect(),
+                trusted_proxies: var("TRUSTED_PROXIES")
+                    .unwrap_or_default()
+                    .split(',')
+                    .filter_map(|ip| ip.trim().parse().ok())
+                    .collect(),
+            },
+            freeform: FreeformConfig {
+                system_prompt: var("FREEFORM_SYSTEM_PROMPT")
+                    .ok()
+                    .filter(|prompt| !prompt.trim().is_empty())
+                    .unwrap_or_else(|| "You are a helpful assistant.".to_string()),
+                language_prompts: language_overrides("FREEFORM_SYSTEM_PROMPT"),
+                audit_log: var("FREEFORM_AUDIT_LOG")
+                    .map(|v| v != "false")
+                    .unwrap_or(true),
+                audit_log_max_entries: var("FREEFORM_AUDIT_LOG_MAX_ENTRIES")
+                    .unwrap_or_else(|_| "10000".to_string())
+                    .parse()
+                    .unwrap_or(10000),
+            },
+            branding: BrandingConfig {
+                app_name: var("BRANDING_APP_NAME").ok().filter(|name| !name.trim().is_empty()),
+                persona_suffix: var("BRANDING_PERSONA_SUFFIX").ok().filter(|suffix| !suffix.trim().is_empty()),
+            },
+            cache_warmup: CacheWarmupConfig {
+                enabled: var("CACHE_WARMUP_ENABLED")
+                    .map(|v| v == "true")
+                    .unwrap_or(false),
+                languages: var("CACHE_WARMUP_LANGUAGES")
+                    .unwrap_or_else(|_| "en".to_string())
+                    .split(',')
+                    .map(|language| language.trim().to_string())
+                    .filter(|language| !language.is_empty())
+                    .collect(),
+                budget: var("CACHE_WARMUP_BUDGET")
+                    .unwrap_or_else(|_| "20".to_string())
+                    .parse()
+                    .unwrap_or(20),
+            },
+            cache_refresh: CacheRefreshConfig {
+                enabled: var("CACHE_REFRESH_ENABLED")
+                    .map(|v| v == "true")
+                    .unwrap_or(false),
+                interval_hours: var("CACHE_REFRESH_INTERVAL_HOURS")
+                    .unwrap_or_else(|_| "24".to_string())
+                    .parse()
+                    .unwrap_or(24),
+            },
+            languages: LanguagesConfig {
+                fallbacks: var("LANGUAGE_FALLBACKS")
+                    .unwrap_or_else(|_| "zh-HK=zh-TW,zh-MO=zh-TW,zh-SG=zh-CN".to_string())
+                    .split(',')
+                    .filter_map(|pair| pair.split_once('='))
+                    .map(|(from, to)| (from.trim().to_string(), to.trim().to_string()))
+                    .filter(|(from, to)| !from.is_empty() && !to.is_empty())
+                    .collect(),
+                glossary_path: var("GLOSSARY_FILE_PATH").ok().filter(|path| !path.trim().is_empty()),
+                validate_glossary: var("GLOSSARY_VALIDATE")
+                    .map(|v| v == "true")
+                    .unwrap_or(false),
+                detect_prompt_language: var("LANGUAGE_DETECTION_ENABLED")
+                    .map(|v| v != "false")
+                    .unwrap_or(true),
+            },
+            daily_saying: DailySayingConfig {
+                enabled: var("DAILY_SAYING_ENABLED")
+                    .map(|v| v == "true")
+                    .unwrap_or(false),
+                presets: var("DAILY_SAYING_PRESETS")
+                    .unwrap_or_default()
+                    .split(',')
+                    .map(|preset_id| preset_id.trim().to_string())
+                    .filter(|preset_id| !preset_id.is_empty())
+                    .collect(),
+                languages: var("DAILY_SAYING_LANGUAGES")
+                    .unwrap_or_else(|_| "en".to_string())
+                    .split(',')
+                    .map(|language| language.trim().to_string())
+                    .filter(|language| !language.is_empty())
+                    .collect(),
+                check_seconds: var("DAILY_SAYING_CHECK_SECONDS")
+                    .unwrap_or_else(|_| "300".to_string())
+                    .parse()
+                    .unwrap_or(300),
+            },
+            attribution: AttributionConfig {
+                show_model: var("ATTRIBUTION_SHOW_MODEL")
+                    .map(|v| v == "true")
+                    .unwrap_or(false),
+            },
+            quality: QualityConfig {
+                enabled: var("QUALITY_SCORING_ENABLED")
+                    .map(|v| v == "true")
+                    .unwrap_or(false),
+                judge_model: var("QUALITY_JUDGE_MODEL").ok().filter(|model| !model.is_empty()),
+                sample_percent: var("QUALITY_SAMPLE_PERCENT")
+                    .unwrap_or_else(|_| "100".to_string())
+                    .parse::<u32>()
+                    .unwrap_or(100)
+                    .min(100),
+            },
+            prompt_rotation: PromptRotationConfig {
+                enabled: var("PROMPT_ROTATION_ENABLED")
+                    .map(|v| v == "true")
+                    .unwrap_or(false),
+                refresh_seconds: var("PROMPT_ROTATION_REFRESH_SECONDS")
+                    .unwrap_or_else(|_| "3600".to_string())
+                    .parse()
+                    .unwrap_or(3600),
+                min_ratings: var("PROMPT_ROTATION_MIN_RATINGS")
+                    .unwrap_or_else(|_| "5".to_string())
+                    .parse()
+                    .unwrap_or(5),
+                poor_score: var("PROMPT_ROTATION_POOR_SCORE")
+                    .unwrap_or_else(|_| "2.5".to_string())
+                    .parse()
+                    .unwrap_or(2.5),
+                poor_weight: var("PROMPT_ROTATION_POOR_WEIGHT")
+                    .unwrap_or_else(|_| "0.2".to_string())
+                    .parse::<f64>()
+                    .unwrap_or(0.2)
+                    .clamp(0.0, 1.0),
+            },
+            chat: ChatConfig {
+                max_context_tokens: var("CHAT_MAX_CONTEXT_TOKENS")
+                    .unwrap_or_else(|_| "3000".to_string())
+                    .parse()
+                    .unwrap_or(3000),
+                keep_recent_messages: var("CHAT_KEEP_RECENT_MESSAGES")
+                    .unwrap_or_else(|_| "6".to_string())
+                    .parse()
+                    .unwrap_or(6),
+                summary_model: var("CHAT_SUMMARY_MODEL").ok().filter(|model| !model.is_empty()),
+            },
+            debug_log: DebugLogConfig {
+                enabled: var("DEBUG_LOG_ENABLED")
+                    .map(|v| v == "true")
+                    .unwrap_or(false),
+                capacity: var("DEBUG_LOG_SIZE")
+                    .unwrap_or_else(|_| "200".to_string())
+                    .parse()
+                    .unwrap_or(200),
+                file_path: var("DEBUG_LOG_FILE").ok().filter(|path| !path.trim().is_empty()),
+                max_body_bytes: var("DEBUG_LOG_MAX_BODY_BYTES")
+                    .unwrap_or_else(|_| "16384".to_string())
+                    .parse()
+                    .unwrap_or(16384),
+            },
+            invalidation: InvalidationConfig {
+                redis_url: var("INVALIDATION_REDIS_URL").ok().filter(|url| !url.trim().is_empty()),
+                channel: var("INVALIDATION_CHANNEL").unwrap_or_else(|_| "prompt-wrapper:invalidations".to_string()),
+            },
+            leader_election: LeaderElectionConfig {
+                enabled: var("LEADER_ELECTION_ENABLED")
+                    .map(|v| v == "true")
+                    .unwrap_or(false),
+                lease_seconds: var("LEADER_LEASE_SECONDS")
+                    .unwrap_or_else(|_| "30".to_string())
+                    .parse()
+                    .unwrap_or(30),
+                // Usually the server already there for invalidations
+                redis_url: var("LEADER_REDIS_URL")
+                    .or_else(|_| var("INVALIDATION_REDIS_URL"))
+                    .ok()
+                    .filter(|url| !url.trim().is_empty()),
+            },
+            feature_flags: FeatureFlagsConfig {
+                file_path: var("FEATURE_FLAGS_FILE").ok().filter(|path| !path.trim().is_empty()),
+            },
+            self_test: SelfTestConfig {
+                on_startup: var("STARTUP_SELF_TEST")
+                    .map(|v| v == "true")
+                    .unwrap_or(false),
+                ping_upstream: var("SELF_TEST_PING_UPSTREAM")
+                    .map(|v| v == "true")
+                    .unwrap_or(false),
+            },
+            translation: TranslationConfig {
+                provider: match var("TRANSLATION_PROVIDER").as_deref() {
+                    Ok("deepl") => TranslatorKind::DeepL,
+                    Ok("google") => TranslatorKind::Google,
+                    _ => TranslatorKind::Llm,
+                },
+                api_key: var("TRANSLATION_API_KEY").unwrap_or_default(),
+                base_url: var("TRANSLATION_BASE_URL").ok().filter(|url| !url.trim().is_empty()),
+            },
+            unset_variables: Vec::new(),
+        };
+
+        Config {
+            unset_variables: unset.into_inner().into_iter().collect(),
+            ..config
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profile_file_overrides_base_file() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(
+            dir.path().join("config.base.toml"),
+            "LAYERS_TEST_PORT = 3000\nLAYERS_TEST_HOST = \"0.0.0.0\"\nLAYERS_TEST_LANGUAGES = [\"en\", \"de\"]\n",
+        ).unwrap();
+        fs::write(dir.path().join("config.prod.toml"), "LAYERS_TEST_PORT = 8080\n").unwrap();
+        fs::write(dir.path().join("config.staging.toml"), "LAYERS_TEST_PORT = 9090\n").unwrap();
+
+        let layers = ConfigLayers::from_files(ConfigProfile::Prod, dir.path()).unwrap();
+        assert_eq!(layers.var("LAYERS_TEST_PORT").unwrap(), "8080");
+        assert_eq!(layers.var("LAYERS_TEST_HOST").unwrap(), "0.0.0.0");
+        assert_eq!(layers.var("LAYERS_TEST_LANGUAGES").unwrap(), "en,de");
+        assert!(layers.var("LAYERS_TEST_UNSET").is_err());
+
+        // Without its own file a profile gets the base layer only
+        let layers = ConfigLayers::from_files(ConfigProfile::Dev, dir.path()).unwrap();
+        assert_eq!(layers.var("LAYERS_TEST_PORT").unwrap(), "3000");
+
+        fs::write(dir.path().join("config.dev.toml"), "[server]\nport = 1\n").unwrap();
+        assert!(ConfigLayers::from_files(ConfigProfile::Dev, dir.path()).is_err());
+    }
+}
@@ -0,0 +1,116 @@
+use anyhow::Result;
+use chrono::{Datelike, NaiveDate, Utc};
+use std::sync::Arc;
+
+use crate::languages;
+use crate::models::DailySaying;
+use crate::preset::Preset;
+use crate::warmup;
+use crate::AppState;
+
+// Presets with a daily saying: the configured ones, or every public preset
+pub fn presets(state: &AppState) -> Vec<Preset> {
+    let configured = &state.config.daily_saying.presets;
+    if configured.is_empty() {
+        return warmup::public_presets(state);
+    }
+
+    configured
+        .iter()
+        .filter_map(|preset_id| {
+            let preset = state.presets.get_preset_by_id(preset_id);
+            if preset.is_none() {
+                tracing::warn!("Skipping unknown daily saying preset: {}", preset_id);
+            }
+            preset
+        })
+        .collect()
+}
+
+// The preset featured when a request doesn't name one; it rotates through the presets day by day
+pub fn featured_preset_id(preset_ids: &[String], date: NaiveDate) -> Option<&str> {
+    if preset_ids.is_empty() {
+        return None;
+    }
+    let index = date.num_days_from_ce().unsigned_abs() as usize % preset_ids.len();
+    Some(preset_ids[index].as_str())
+}
+
+// Generate every (preset, language) pair that has no saying for `date` yet, returning how many
+// were stored. Failed pairs are retried on the next check.
+pub async fn generate_due(state: &AppState, date: NaiveDate) -> Result<usize> {
+    let mut generated = 0;
+    for language_id in &state.config.daily_saying.languages {
+        if languages::find_language(language_id).is_none() {
+            tracing::warn!("Skipping unknown daily saying language: {}", language_id);
+            continue;
+        }
+
+        for preset in presets(state) {
+            let current = state.storage.get_daily_saying(&preset.id, language_id).await?;
+            if current.is_some_and(|daily| daily.date == date) {
+                continue;
+            }
+
+            match warmup::generate(state, &preset, language_id).await {
+                Ok(saying) => {
+                    state.storage.save_daily_saying(&DailySaying {
+                        date,
+                        preset_id: preset.id.clone(),
+                        language_id: language_id.clone(),
+                        saying: saying.as_ref().clone(),
+                    }).await?;
+                    generated += 1;
+                }
+                Err(e) => {
+                    tracing::warn!("Daily saying failed for preset {} in {}: {:#}", preset.id, language_id, e);
+                }
+            }
+        }
+    }
+
+    if generated > 0 {
+        state.daily_published.notify_waiters();
+    }
+    Ok(generated)
+}
+
+// Keep today's sayings generated; the first check runs at startup
+pub fn spawn_task(state: Arc<AppState>) {
+    if !state.config.daily_saying.enabled {
+        return;
+    }
+
+    let period = std::time::Duration::from_secs(state.config.daily_saying.check_seconds.max(1));
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(period);
+        loop {
+            ticker.tick().await;
+            if !state.leader.is_leader() {
+                continue;
+            }
+            match generate_due(&state, Utc::now().date_naive()).await {
+                Ok(0) => {}
+                Ok(generated) => tracing::info!("Generated {} daily sayings", generated),
+                Err(e) => tracing::error!("Failed to generate daily sayings: {:#}", e),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_featured_preset_rotates_daily() {
+        let preset_ids = vec!["a".to_string(), "b".to_string(), "c".to_string()];
+        let day = NaiveDate::from_ymd_opt(2024, 3, 1).unwrap();
+
+        let first = featured_preset_id(&preset_ids, day).unwrap();
+        assert_eq!(featured_preset_id(&preset_ids, day), Some(first));
+        assert_ne!(featured_preset_id(&preset_ids, day.succ_opt().unwrap()), Some(first));
+        assert_eq!(featured_preset_id(&[], day), None);
+    }
+}
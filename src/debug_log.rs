@@ -0,0 +1,391 @@
+use axum::{
+    body::{Body, Bytes},
+    extract::{Request, State},
+    http::header,
+    middleware::Next,
+    response::Response,
+};
+use chrono::{DateTime, Utc};
+use futures_util::{stream, StreamExt};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use crate::config::{Config, DebugLogConfig};
+use crate::AppState;
+
+const REDACTED: &str = "[redacted]";
+
+// What axum's extractors accept by default. Bodies are buffered for the log up to this much
+// on top of DEBUG_LOG_MAX_BODY_BYTES; larger ones pass through unread and unlogged.
+const BODY_LIMIT: usize = 2 * 1024 * 1024;
+
+// JSON fields that identify a person or grant access; any `*_token` field counts too
+const SENSITIVE_FIELDS: &[&str] = &[
+    "user_id", "ip", "email", "address", "chat_id", "webhook_url", "token", "api_key", "authorization",
+];
+
+// Path segments followed by a user ID or a secret token
+const SENSITIVE_SEGMENTS: &[&str] = &["users", "share", "shared", "unsubscribe"];
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DebugEntryKind {
+    // A request to this service and our response
+    Request,
+    // A call to the LLM provider and its response
+    Upstream,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DebugEntry {
+    pub at: DateTime<Utc>,
+    pub kind: DebugEntryKind,
+    // Method and path of a request, or the upstream URL
+    pub target: String,
+    // None when the upstream call failed before a response
+    pub status: Option<u16>,
+    pub duration_ms: u64,
+    pub request_body: Option<String>,
+    pub response_body: Option<String>,
+}
+
+// Recent request and upstream payloads for troubleshooting (DEBUG_LOG_ENABLED). Everything
+// is scrubbed before it is kept: configured secrets, identifying JSON fields, user IDs and
+// tokens in paths, and email addresses anywhere.
+pub struct DebugLog {
+    config: DebugLogConfig,
+    secrets: Vec<String>,
+    entries: Mutex<VecDeque<DebugEntry>>,
+    file: Option<Mutex<File>>,
+}
+
+// Secrets stay out of debug output
+impl std::fmt::Debug for DebugLog {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DebugLog")
+            .field("enabled", &self.config.enabled)
+            .field("entries", &self.entries.lock().unwrap().len())
+            .finish()
+    }
+}
+
+impl DebugLog {
+    pub fn new(config: &Config) -> Self {
+        Self::with_secrets(config.debug_log.clone(), secrets(config))
+    }
+
+    // Scrubbing only the given secrets rather than every configured credential
+    pub fn with_secrets(debug_log: DebugLogConfig, mut secrets: Vec<String>) -> Self {
+        // Longest first, so a secret containing another is replaced whole
+        secrets.sort_by_key(|secret| std::cmp::Reverse(secret.len()));
+        let file = debug_log.file_path.as_ref()
+            .filter(|_| debug_log.enabled)
+            .and_then(|path| match OpenOptions::new().create(true).append(true).open(path) {
+                Ok(file) => Some(Mutex::new(file)),
+                Err(e) => {
+                    tracing::warn!("Failed to open debug log file {}: {}", path, e);
+                    None
+                }
+            });
+
+        Self {
+            config: debug_log,
+            secrets,
+            entries: Mutex::new(VecDeque::new()),
+            file,
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.config.enabled
+    }
+
+    pub fn record(
+        &self,
+        kind: DebugEntryKind,
+        target: &str,
+        status: Option<u16>,
+        started: Instant,
+        request_body: &[u8],
+        response_body: &[u8],
+    ) {
+        if !self.enabled() {
+            return;
+        }
+
+        let entry = DebugEntry {
+            at: Utc::now(),
+            kind,
+            target: self.scrub_text(&scrub_target(target)),
+            status,
+            duration_ms: started.elapsed().as_millis() as u64,
+            request_body: self.scrub_body(request_body),
+            response_body: self.scrub_body(response_body),
+        };
+
+        if let Some(file) = &self.file {
+            let line = serde_json::to_string(&entry).unwrap_or_default();
+            if let Err(e) = writeln!(file.lock().unwrap(), "{}", line) {
+                tracing::warn!("Failed to write debug log entry: {}", e);
+            }
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        entries.push_front(entry);
+        entries.truncate(self.config.capacity);
+    }
+
+    // Newest first
+    pub fn recent(&self, limit: usize) -> Vec<DebugEntry> {
+        self.entries.lock().unwrap().iter().take(limit).cloned().collect()
+    }
+
+    fn scrub_body(&self, body: &[u8]) -> Option<String> {
+        if body.is_empty() {
+            return None;
+        }
+
+        let text = match serde_json::from_slice::<Value>(body) {
+            Ok(mut value) => {
+                scrub_value(&mut value);
+                value.to_string()
+            }
+            Err(_) => String::from_utf8_lossy(body).into_owned(),
+        };
+        let mut text = self.scrub_text(&text);
+
+        if text.len() > self.config.max_body_bytes {
+            let mut end = self.config.max_body_bytes;
+            while !text.is_char_boundary(end) {
+                end -= 1;
+            }
+            text.truncate(end);
+            text.push_str("...[truncated]");
+        }
+        Some(text)
+    }
+
+    fn scrub_text(&self, text: &str) -> String {
+        let text = self.secrets.iter().fold(text.to_string(), |text, secret| text.replace(secret.as_str(), REDACTED));
+        mask_emails(&text)
+    }
+}
+
+// Every configured credential, wherever it might be echoed
+fn secrets(config: &Config) -> Vec<String> {
+    [
+        Some(&config.openrouter.api_key),
+        Some(&config.translation.api_key),
+        config.server.admin_token.as_ref(),
+        config.notifications.telegram_bot_token.as_ref(),
+        config.notifications.email.smtp_password.as_ref(),
+    ]
+    .into_iter()
+    .flatten()
+    .chain(config.access.tokens.keys())
+    .chain(&config.rate_limit.exempt_tokens)
+    .filter(|secret| !secret.is_empty())
+    .cloned()
+    .collect()
+}
+
+fn is_sensitive_field(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    SENSITIVE_FIELDS.contains(&name.as_str()) || name.ends_with("_token")
+}
+
+fn scrub_value(value: &mut Value) {
+    match value {
+        Value::Object(fields) => {
+            for (name, field) in fields.iter_mut() {
+                if is_sensitive_field(name) && !field.is_null() {
+                    *field = Value::String(REDACTED.to_string());
+                } else {
+                    scrub_value(field);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(scrub_value),
+        _ => {}
+    }
+}
+
+// Redact user IDs and tokens in the path and sensitive query parameters
+fn scrub_target(target: &str) -> String {
+    let (path, query) = match target.split_once('?') {
+        Some((path, query)) => (path, Some(query)),
+        None => (target, None),
+    };
+
+    let segments: Vec<&str> = path.split('/').collect();
+    let mut scrubbed: Vec<&str> = Vec::with_capacity(segments.len());
+    for (i, segment) in segments.iter().enumerate() {
+        let follows_sensitive = i > 0 && SENSITIVE_SEGMENTS.contains(&segments[i - 1]);
+        scrubbed.push(if follows_sensitive && !segment.is_empty() { REDACTED } else { segment });
+    }
+    let mut target = scrubbed.join("/");
+
+    if let Some(query) = query {
+        let pairs: Vec<String> = query
+            .split('&')
+            .map(|pair| match pair.split_once('=') {
+                Some((name, _)) if is_sensitive_field(name) => format!("{}={}", name, REDACTED),
+                _ => pair.to_string(),
+            })
+            .collect();
+        target.push('?');
+        target.push_str(&pairs.join("&"));
+    }
+    target
+}
+
+fn is_email_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '%' | '+' | '-')
+}
+
+// Replace anything shaped like local@domain.tld
+fn mask_emails(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut out = String::with_capacity(text.len());
+    let mut i = 0;
+    while i < chars.len() {
+        if chars[i] != '@' {
+            out.push(chars[i]);
+            i += 1;
+            continue;
+        }
+
+        let local = out.chars().rev().take_while(|c| is_email_char(*c)).count();
+        let domain = chars[i + 1..].iter().take_while(|c| is_email_char(**c)).count();
+        let domain_text: String = chars[i + 1..i + 1 + domain].iter().collect();
+        let domain_text = domain_text.trim_end_matches('.');
+        if local == 0 || !domain_text.contains('.') {
+            out.push('@');
+            i += 1;
+            continue;
+        }
+
+        for _ in 0..local {
+            out.pop();
+        }
+        out.push_str(REDACTED);
+        i += 1 + domain_text.chars().count();
+    }
+    out
+}
+
+// Records requests and our responses; admin routes are left out since they carry the admin
+// token and would log the debug log itself
+pub async fn middleware(State(state): State<Arc<AppState>>, request: Request, next: Next) -> Response {
+    if !state.debug_log.enabled() || request.uri().path().starts_with("/admin") {
+        return next.run(request).await;
+    }
+
+    let started = Instant::now();
+    let target = format!("{} {}", request.method(), request.uri());
+    let limit = state.debug_log.config.max_body_bytes.saturating_add(BODY_LIMIT);
+    let (parts, body) = request.into_parts();
+    let (request_body, body) = match buffer(body, limit).await {
+        Ok(request_body) => (request_body.clone(), Body::from(request_body)),
+        Err(body) => (Bytes::new(), body),
+    };
+    let response = next.run(Request::from_parts(parts, body)).await;
+
+    // Images and other binary bodies are passed through without being read
+    let textual = response.headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|content_type| content_type.starts_with("application/json") || content_type.starts_with("text/"));
+    let status = response.status().as_u16();
+    if !textual {
+        state.debug_log.record(DebugEntryKind::Request, &target, Some(status), started, &request_body, &[]);
+        return response;
+    }
+
+    let (parts, body) = response.into_parts();
+    match buffer(body, limit).await {
+        Ok(response_body) => {
+            state.debug_log.record(DebugEntryKind::Request, &target, Some(status), started, &request_body, &response_body);
+            Response::from_parts(parts, Body::from(response_body))
+        }
+        Err(body) => {
+            state.debug_log.record(DebugEntryKind::Request, &target, Some(status), started, &request_body, &[]);
+            Response::from_parts(parts, body)
+        }
+    }
+}
+
+// Read a body of at most `limit` bytes. A longer one, or one that fails partway, comes back
+// as a body yielding the same bytes (and error), so it still reaches its destination.
+async fn buffer(body: Body, limit: usize) -> Result<Bytes, Body> {
+    let mut chunks = body.into_data_stream();
+    let mut buffered = Vec::new();
+    while let Some(chunk) = chunks.next().await {
+        let read = match chunk {
+            Ok(chunk) => {
+                buffered.extend_from_slice(&chunk);
+                if buffered.len() <= limit {
+                    continue;
+                }
+                vec![Ok(Bytes::from(buffered))]
+            }
+            Err(e) => vec![Ok(Bytes::from(buffered)), Err(e)],
+        };
+        return Err(Body::from_stream(stream::iter(read).chain(chunks)));
+    }
+    Ok(Bytes::from(buffered))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_only_bodies_within_the_limit_are_buffered() {
+        let body = "x".repeat(100);
+        assert_eq!(buffer(Body::from(body.clone()), 100).await.ok().unwrap(), body.as_bytes());
+
+        // Too long to log, but passed on whole
+        let chunks = stream::iter(["x".repeat(60), "y".repeat(60)].map(Ok::<_, std::io::Error>));
+        let passed = buffer(Body::from_stream(chunks), 100).await.err().unwrap();
+        let passed = axum::body::to_bytes(passed, usize::MAX).await.unwrap();
+        assert_eq!(passed, format!("{}{}", "x".repeat(60), "y".repeat(60)).as_bytes());
+    }
+
+    #[test]
+    fn test_payloads_are_scrubbed() {
+        let mut config = Config::from_env_with_provider(crate::config::ProviderType::Mock);
+        config.openrouter.api_key = "sk-or-secret".to_string();
+        config.debug_log = DebugLogConfig { enabled: true, capacity: 2, file_path: None, max_body_bytes: 120 };
+        let log = DebugLog::new(&config);
+
+        log.record(DebugEntryKind::Request, "GET /presets", Some(200), Instant::now(), &[], &[]);
+        log.record(
+            DebugEntryKind::Request,
+            "GET /users/alice/status?user_id=alice&limit=5",
+            Some(200),
+            Instant::now(),
+            br#"{"user_id":"alice","prompt":"mail me at alice@example.com.","headers":{"owner_token":"t"}}"#,
+            b"Bearer sk-or-secret rejected",
+        );
+        log.record(DebugEntryKind::Upstream, "/chat/completions", None, Instant::now(), &[], &"x".repeat(200).into_bytes());
+
+        // Only the newest entries are kept, long bodies are cut
+        let entries = log.recent(10);
+        assert_eq!(entries.len(), 2);
+        assert!(entries[0].response_body.as_ref().unwrap().ends_with("...[truncated]"));
+
+        let entry = &entries[1];
+        assert_eq!(entry.target, "GET /users/[redacted]/status?user_id=[redacted]&limit=5");
+        assert_eq!(
+            entry.request_body.as_deref(),
+            Some(r#"{"headers":{"owner_token":"[redacted]"},"prompt":"mail me at [redacted].","user_id":"[redacted]"}"#)
+        );
+        assert_eq!(entry.response_body.as_deref(), Some("Bearer [redacted] rejected"));
+    }
+}
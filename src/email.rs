@@ -0,0 +1,182 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, NaiveDate, Utc};
+use lettre::message::{Mailbox, MultiPart};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Address, AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use maud::{html, Markup, DOCTYPE};
+use serde::{Deserialize, Serialize};
+
+use crate::config::EmailConfig;
+use crate::models::Saying;
+
+// A user's email delivery settings, stored in their preferences
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmailPreferences {
+    pub address: String,
+    // Send the daily saying by email
+    pub daily: bool,
+    // Secret used by the unsubscribe link, so it works without logging in
+    pub unsubscribe_token: String,
+    pub updated_at: DateTime<Utc>,
+    #[serde(default)]
+    pub last_attempt_on: Option<NaiveDate>,
+    #[serde(default)]
+    pub last_error: Option<String>,
+}
+
+impl EmailPreferences {
+    pub fn new(address: String, daily: bool) -> Self {
+        Self {
+            address,
+            daily,
+            unsubscribe_token: uuid::Uuid::new_v4().simple().to_string(),
+            updated_at: Utc::now(),
+            last_attempt_on: None,
+            last_error: None,
+        }
+    }
+}
+
+pub fn validate_address(address: &str) -> Result<(), String> {
+    address.parse::<Address>()
+        .map(|_| ())
+        .map_err(|e| format!("Invalid email address: {}", e))
+}
+
+pub struct Mailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+    public_url: String,
+}
+
+impl Mailer {
+    // Email is only available when an SMTP host is configured
+    pub fn from_config(config: &EmailConfig) -> Result<Option<Self>> {
+        let Some(host) = config.smtp_host.as_deref() else {
+            return Ok(None);
+        };
+
+        let mut builder = if config.smtp_starttls {
+            AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)
+                .context("Failed to set up SMTP transport")?
+        } else {
+            // Plain SMTP, for local relays and test mail catchers
+            AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host)
+        };
+
+        builder = builder.port(config.smtp_port);
+        if let (Some(username), Some(password)) = (&config.smtp_username, &config.smtp_password) {
+            builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+        }
+
+        let from = config.from.parse().context("Invalid SMTP_FROM address")?;
+
+        Ok(Some(Self {
+            transport: builder.build(),
+            from,
+            public_url: config.public_url.trim_end_matches('/').to_string(),
+        }))
+    }
+
+    pub fn unsubscribe_url(&self, token: &str) -> String {
+        format!("{}/unsubscribe/{}", self.public_url, token)
+    }
+
+    pub async fn send_daily(&self, preferences: &EmailPreferences, saying: &Saying) -> Result<()> {
+        let unsubscribe_url = self.unsubscribe_url(&preferences.unsubscribe_token);
+
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(preferences.address.parse().context("Invalid recipient address")?)
+            .subject("Your saying of the day")
+            .multipart(MultiPart::alternative_plain_html(
+                render_daily_text(saying, &unsubscribe_url),
+                render_daily_html(saying, &unsubscribe_url).into_string(),
+            ))
+            .context("Failed to build email")?;
+
+        self.transport.send(message).await.context("Failed to send email")?;
+        Ok(())
+    }
+}
+
+fn render_daily_text(saying: &Saying, unsubscribe_url: &str) -> String {
+    format!(
+        "{}\n\n--\nYou are receiving this because you subscribed to daily sayings.\nUnsubscribe: {}\n",
+        saying.content, unsubscribe_url
+    )
+}
+
+fn render_daily_html(saying: &Saying, unsubscribe_url: &str) -> Markup {
+    html! {
+        (DOCTYPE)
+        html {
+            head {
+                meta charset="utf-8";
+                meta name="viewport" content="width=device-width, initial-scale=1";
+            }
+            body style="font-family: Georgia, serif; background: #f7f5f0; padding: 2rem; color: #222;" {
+                p style="font-size: 0.9rem; color: #777;" { "Your saying of the day" }
+                blockquote style="font-size: 1.4rem; line-height: 1.5; margin: 1.5rem 0;" { (saying.content) }
+                hr style="border: none; border-top: 1px solid #ddd;";
+                p style="font-size: 0.8rem; color: #777;" {
+                    "You are receiving this because you subscribed to daily sayings. "
+                    a href=(unsubscribe_url) { "Unsubscribe" }
+                }
+            }
+        }
+    }
+}
+
+// Shown when following an unsubscribe link; the form posts back to the same URL
+pub fn render_unsubscribe_confirmation(address: &str) -> Markup {
+    html! {
+        (DOCTYPE)
+        html {
+            head {
+                meta charset="utf-8";
+                title { "Unsubscribe" }
+            }
+            body style="font-family: sans-serif; margin: 2rem;" {
+                h1 { "Unsubscribe" }
+                p { "Stop sending daily sayings to " (address) "?" }
+                form method="post" {
+                    button type="submit" { "Unsubscribe" }
+                }
+            }
+        }
+    }
+}
+
+// Shown after confirming an unsubscribe
+pub fn render_unsubscribed(address: &str) -> Markup {
+    html! {
+        (DOCTYPE)
+        html {
+            head {
+                meta charset="utf-8";
+                title { "Unsubscribed" }
+            }
+            body style="font-family: sans-serif; margin: 2rem;" {
+                h1 { "Unsubscribed" }
+                p { (address) " will no longer receive daily sayings." }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::SayingSource;
+
+    #[test]
+    fn test_daily_email_escapes_content_and_links_unsubscribe() {
+        let saying = Saying::new("<b>Be kind</b>".to_string(), "prompt".to_string(), SayingSource::LLM);
+        let html = render_daily_html(&saying, "https://example.com/unsubscribe/abc").into_string();
+
+        assert!(html.contains("&lt;b&gt;Be kind&lt;/b&gt;"));
+        assert!(html.contains("href=\"https://example.com/unsubscribe/abc\""));
+        assert!(render_daily_text(&saying, "https://example.com/unsubscribe/abc").contains("Unsubscribe: https://example.com/unsubscribe/abc"));
+    }
+}
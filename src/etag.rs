@@ -0,0 +1,113 @@
+use axum::{
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+// Catalog data only changes on deploy, so clients may reuse it for a while before revalidating
+const CATALOG_CACHE_CONTROL: &str = "public, max-age=300";
+// Catalog responses shaped by the user they are requested for (e.g. by tier) must stay out of
+// shared caches, and depend on who is asking
+const USER_CACHE_CONTROL: &str = "private, max-age=300";
+// Rendered images never change for their URL
+const IMAGE_CACHE_CONTROL: &str = "public, max-age=86400";
+
+// Strong ETag for a piece of static data. The build version is mixed in so a deploy that
+// changes the response format also changes the tag.
+pub fn compute(data: &[u8]) -> String {
+    let mut hasher = DefaultHasher::new();
+    env!("CARGO_PKG_VERSION").hash(&mut hasher);
+    data.hash(&mut hasher);
+    format!("\"{:016x}\"", hasher.finish())
+}
+
+// Whether the client's If-None-Match already names this tag (weak comparison, as RFC 9110 asks)
+fn not_modified(headers: &HeaderMap, etag: &str) -> bool {
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+// JSON response with ETag and Cache-Control, or an empty 304 when the client is up to date.
+// `etag` must identify everything the body depends on apart from the request URL.
+pub fn json_response<T: Serialize>(headers: &HeaderMap, etag: &str, body: T) -> Response {
+    with_validators(json_body(headers, etag, body), etag, CATALOG_CACHE_CONTROL)
+}
+
+// Like json_response, for a body that depends on the user it is requested for: only the
+// client may cache it, and only for the same credentials
+pub fn user_json_response<T: Serialize>(headers: &HeaderMap, etag: &str, body: T) -> Response {
+    let mut response = with_validators(json_body(headers, etag, body), etag, USER_CACHE_CONTROL);
+    response.headers_mut().insert(header::VARY, HeaderValue::from_static("authorization"));
+    response
+}
+
+fn json_body<T: Serialize>(headers: &HeaderMap, etag: &str, body: T) -> Response {
+    if not_modified(headers, etag) {
+        StatusCode::NOT_MODIFIED.into_response()
+    } else {
+        Json(body).into_response()
+    }
+}
+
+// Binary body (e.g. a PNG) with ETag and a long Cache-Control, or an empty 304
+pub fn binary_response(headers: &HeaderMap, etag: &str, content_type: &'static str, body: Vec<u8>) -> Response {
+    let response = if not_modified(headers, etag) {
+        StatusCode::NOT_MODIFIED.into_response()
+    } else {
+        ([(header::CONTENT_TYPE, content_type)], body).into_response()
+    };
+
+    with_validators(response, etag, IMAGE_CACHE_CONTROL)
+}
+
+fn with_validators(mut response: Response, etag: &str, cache_control: &'static str) -> Response {
+    let response_headers = response.headers_mut();
+    if let Ok(value) = HeaderValue::from_str(etag) {
+        response_headers.insert(header::ETAG, value);
+    }
+    response_headers.insert(header::CACHE_CONTROL, HeaderValue::from_static(cache_control));
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_if_none_match() {
+        let etag = compute(b"presets");
+        let mut headers = HeaderMap::new();
+        assert!(!not_modified(&headers, &etag));
+
+        headers.insert(header::IF_NONE_MATCH, HeaderValue::from_str(&format!("\"other\", W/{}", etag)).unwrap());
+        assert!(not_modified(&headers, &etag));
+
+        headers.insert(header::IF_NONE_MATCH, HeaderValue::from_static("\"other\""));
+        assert!(!not_modified(&headers, &etag));
+
+        headers.insert(header::IF_NONE_MATCH, HeaderValue::from_static("*"));
+        assert!(not_modified(&headers, &etag));
+    }
+
+    #[test]
+    fn test_user_responses_stay_private() {
+        let etag = compute(b"presets");
+        let headers = HeaderMap::new();
+
+        let response = json_response(&headers, &etag, "catalog");
+        assert_eq!(response.headers()[header::CACHE_CONTROL], CATALOG_CACHE_CONTROL);
+        assert!(response.headers().get(header::VARY).is_none());
+
+        let response = user_json_response(&headers, &etag, "catalog");
+        assert_eq!(response.headers()[header::CACHE_CONTROL], USER_CACHE_CONTROL);
+        assert_eq!(response.headers()[header::VARY], "authorization");
+    }
+}
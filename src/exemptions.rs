@@ -0,0 +1,126 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+use crate::config::RateLimitConfig;
+use crate::storage::Storage;
+use crate::user_hash::{self, UserIdHasher};
+
+// A user ID, or every request made with an API token (e.g. a smoke-test service account)
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExemptSubject {
+    User(String),
+    Token(String),
+}
+
+impl ExemptSubject {
+    // Storage key, e.g. `user:dashboard` or `token:abc123`
+    pub fn key(&self) -> String {
+        match self {
+            ExemptSubject::User(user_id) => format!("user:{}", user_id),
+            ExemptSubject::Token(token) => format!("token:{}", token),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Exemption {
+    pub subject: ExemptSubject,
+    #[serde(default)]
+    pub reason: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+// Callers that bypass rate limits: the configured ones plus those managed under /admin/exemptions
+#[derive(Debug, Default)]
+pub struct ExemptionList {
+    // Keys from RATE_LIMIT_EXEMPT_USERS and RATE_LIMIT_EXEMPT_TOKENS
+    configured: HashSet<String>,
+    exemptions: DashMap<String, Exemption>,
+    // Storage's; user exemptions are stored under hashed IDs when it has one
+    user_ids: Option<UserIdHasher>,
+}
+
+impl ExemptionList {
+    pub fn new(config: &RateLimitConfig, user_ids: Option<UserIdHasher>) -> Self {
+        let users = config.exempt_users.iter()
+            .map(|user_id| ExemptSubject::User(user_hash::user_key(user_ids.as_ref(), user_id)).key());
+        let tokens = config.exempt_tokens.iter().map(|token| ExemptSubject::Token(token.clone()).key());
+
+        Self {
+            configured: users.chain(tokens).collect(),
+            exemptions: DashMap::new(),
+            user_ids,
+        }
+    }
+
+    // Replace the in-memory list with what is in storage
+    pub async fn load(&self, storage: &Storage) -> Result<()> {
+        let exemptions = storage.list_exemptions().await?;
+        self.exemptions.clear();
+        for exemption in exemptions {
+            self.exemptions.insert(exemption.subject.key(), exemption);
+        }
+
+        tracing::info!("Loaded {} rate-limit exemptions", self.exemptions.len());
+        Ok(())
+    }
+
+    // Persist an exemption (replacing any existing one for the same subject) and apply it immediately
+    pub async fn add(&self, storage: &Storage, exemption: Exemption) -> Result<()> {
+        storage.save_exemption(&exemption).await?;
+        self.exemptions.insert(exemption.subject.key(), exemption);
+        Ok(())
+    }
+
+    pub async fn remove(&self, storage: &Storage, subject: &ExemptSubject) -> Result<Option<Exemption>> {
+        storage.delete_exemption(&subject.key()).await?;
+        Ok(self.exemptions.remove(&subject.key()).map(|(_, exemption)| exemption))
+    }
+
+    // Stored exemptions only; configured ones are not listed so tokens stay out of responses
+    pub fn list(&self) -> Vec<Exemption> {
+        let mut exemptions: Vec<Exemption> = self.exemptions.iter().map(|entry| entry.value().clone()).collect();
+        exemptions.sort_by_key(|exemption| exemption.created_at);
+        exemptions
+    }
+
+    // The user ID may be plain or hashed, like the owners of stored records
+    pub fn is_exempt(&self, user_id: &str, token: Option<&str>) -> bool {
+        let user = ExemptSubject::User(user_hash::user_key(self.user_ids.as_ref(), user_id)).key();
+        let token = token.map(|token| ExemptSubject::Token(token.to_string()).key());
+
+        std::iter::once(user)
+            .chain(token)
+            .any(|key| self.configured.contains(&key) || self.exemptions.contains_key(&key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_configured_and_stored_exemptions() {
+        let list = ExemptionList {
+            configured: HashSet::from(["user:dashboard".to_string(), "token:smoke".to_string()]),
+            exemptions: DashMap::new(),
+            user_ids: None,
+        };
+        list.exemptions.insert("user:qa".to_string(), Exemption {
+            subject: ExemptSubject::User("qa".to_string()),
+            reason: None,
+            created_at: Utc::now(),
+        });
+
+        assert!(list.is_exempt("dashboard", None));
+        assert!(list.is_exempt("qa", None));
+        // A service-account token exempts whichever user it acts as
+        assert!(list.is_exempt("alice", Some("smoke")));
+        assert!(!list.is_exempt("alice", Some("other")));
+        assert!(!list.is_exempt("alice", None));
+    }
+}
@@ -0,0 +1,207 @@
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use crate::models::fnv1a;
+use crate::storage::Storage;
+use crate::user_hash::{self, UserIdHasher};
+
+// Who gets an experimental behaviour
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FlagRule {
+    // Share of users (0-100) who get it, picked by a stable hash of the flag name and user ID,
+    // so raising the percentage only ever adds users
+    #[serde(default)]
+    pub percentage: u8,
+    // Users who get it whatever the percentage, e.g. testers. Overrides keep them hashed when
+    // storage hashes user IDs.
+    #[serde(default)]
+    pub users: Vec<String>,
+    // Tenants whose users all get it, matched on the `<tenant>:` prefix of user IDs
+    #[serde(default)]
+    pub tenants: Vec<String>,
+}
+
+impl FlagRule {
+    pub fn validate(&self) -> Result<()> {
+        if self.percentage > 100 {
+            return Err(anyhow!("Percentage must be between 0 and 100, got {}", self.percentage));
+        }
+        Ok(())
+    }
+
+    // For a user ID from a request; the tenant and percentage are decided on the plain ID
+    fn applies_to(&self, name: &str, user_id: &str, user_ids: Option<&UserIdHasher>) -> bool {
+        let tenant = user_id.split_once(':').map(|(tenant, _)| tenant);
+        let user_key = user_hash::user_key(user_ids, user_id);
+        self.users.iter().any(|user| user_hash::user_key(user_ids, user) == user_key)
+            || tenant.is_some_and(|tenant| self.tenants.iter().any(|listed| listed == tenant))
+            || bucket(name, user_id) < self.percentage
+    }
+}
+
+// A rule set under /admin/flags, taking precedence over the file until it is deleted
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FlagOverride {
+    pub name: String,
+    #[serde(flatten)]
+    pub rule: FlagRule,
+    pub updated_at: DateTime<Utc>,
+}
+
+// A flag's effective rule, as listed by GET /admin/flags
+#[derive(Debug, Clone, Serialize)]
+pub struct FlagState {
+    pub name: String,
+    #[serde(flatten)]
+    pub rule: FlagRule,
+    pub overridden: bool,
+}
+
+// Feature flags from FEATURE_FLAGS_FILE plus the overrides managed under /admin/flags.
+// Checking a flag is two map lookups and a hash, cheap enough for every status request.
+#[derive(Debug, Default)]
+pub struct FeatureFlags {
+    configured: HashMap<String, FlagRule>,
+    overrides: DashMap<String, FlagOverride>,
+    // Storage's, to match the hashed users of overrides
+    user_ids: Option<UserIdHasher>,
+}
+
+impl FeatureFlags {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read feature flags file: {:?}", path.as_ref()))?;
+
+        let configured: HashMap<String, FlagRule> = serde_yaml::from_str(&content)
+            .with_context(|| format!("Failed to parse YAML in feature flags file: {:?}", path.as_ref()))?;
+
+        for (name, rule) in &configured {
+            rule.validate().with_context(|| format!("Invalid feature flag {} in {:?}", name, path.as_ref()))?;
+        }
+
+        tracing::info!("Loaded {} feature flags from {:?}", configured.len(), path.as_ref());
+        Ok(Self { configured, overrides: DashMap::new(), user_ids: None })
+    }
+
+    pub fn with_user_ids(mut self, user_ids: Option<UserIdHasher>) -> Self {
+        self.user_ids = user_ids;
+        self
+    }
+
+    // Replace the in-memory overrides with what is in storage
+    pub async fn load(&self, storage: &Storage) -> Result<()> {
+        let overrides = storage.list_flag_overrides().await?;
+        self.overrides.clear();
+        for flag in overrides {
+            self.overrides.insert(flag.name.clone(), flag);
+        }
+
+        tracing::info!("Loaded {} feature flag overrides", self.overrides.len());
+        Ok(())
+    }
+
+    // Persist an override (replacing any existing one for the flag) and apply it immediately
+    pub async fn set(&self, storage: &Storage, flag: FlagOverride) -> Result<()> {
+        storage.save_flag_override(&flag).await?;
+        self.overrides.insert(flag.name.clone(), flag);
+        Ok(())
+    }
+
+    // Drop the override, so the flag goes back to its configured rule (or off)
+    pub async fn remove(&self, storage: &Storage, name: &str) -> Result<Option<FlagOverride>> {
+        storage.delete_flag_override(name).await?;
+        Ok(self.overrides.remove(name).map(|(_, flag)| flag))
+    }
+
+    // Every configured or overridden flag, by name
+    pub fn list(&self) -> Vec<FlagState> {
+        let mut states: Vec<FlagState> = self.configured.iter()
+            .filter(|(name, _)| !self.overrides.contains_key(*name))
+            .map(|(name, rule)| FlagState { name: name.clone(), rule: rule.clone(), overridden: false })
+            .chain(self.overrides.iter().map(|entry| FlagState {
+                name: entry.key().clone(),
+                rule: entry.value().rule.clone(),
+                overridden: true,
+            }))
+            .collect();
+        states.sort_by(|a, b| a.name.cmp(&b.name));
+        states
+    }
+
+    // Unknown flags are off
+    fn is_enabled(&self, name: &str, user_id: &str) -> bool {
+        match self.overrides.get(name) {
+            Some(flag) => flag.rule.applies_to(name, user_id, self.user_ids.as_ref()),
+            None => self.configured.get(name).is_some_and(|rule| rule.applies_to(name, user_id, self.user_ids.as_ref())),
+        }
+    }
+
+    // Names of the flags on for the user. Nothing server-side is gated on a flag yet; they
+    // are for clients that gate behaviour of their own.
+    pub fn enabled_for(&self, user_id: &str) -> Vec<String> {
+        self.list()
+            .into_iter()
+            .map(|flag| flag.name)
+            .filter(|name| self.is_enabled(name, user_id))
+            .collect()
+    }
+}
+
+// 0-99, the same on every instance and across releases (FNV-1a, not std's hasher)
+fn bucket(name: &str, user_id: &str) -> u8 {
+    let bytes = name.bytes().chain(std::iter::once(0)).chain(user_id.bytes());
+    (fnv1a(bytes) % 100) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percentage_rollout_and_overrides() {
+        let flags = FeatureFlags {
+            configured: serde_yaml::from_str("{half: {percentage: 50}, testers: {users: [alice], tenants: [acme]}, everyone: {percentage: 100}}").unwrap(),
+            overrides: DashMap::new(),
+            user_ids: None,
+        };
+
+        assert!(flags.is_enabled("testers", "alice"));
+        assert!(!flags.is_enabled("testers", "bob"));
+        assert!(flags.is_enabled("testers", "acme:bob"));
+        assert!(!flags.is_enabled("testers", "globex:bob"));
+        assert!(flags.is_enabled("everyone", "bob"));
+        assert!(!flags.is_enabled("unknown", "alice"));
+
+        let enabled = (0..1000).filter(|i| flags.is_enabled("half", &format!("user-{}", i))).count();
+        assert!((400..600).contains(&enabled), "{} of 1000 users enabled", enabled);
+
+        // An override replaces the configured rule entirely
+        flags.overrides.insert("everyone".to_string(), FlagOverride {
+            name: "everyone".to_string(),
+            rule: FlagRule::default(),
+            updated_at: Utc::now(),
+        });
+        assert!(!flags.is_enabled("everyone", "bob"));
+        let alice = flags.enabled_for("alice");
+        assert!(alice.contains(&"testers".to_string()) && !alice.contains(&"everyone".to_string()));
+        assert!(flags.list().iter().any(|flag| flag.name == "everyone" && flag.overridden));
+
+        // Users of an override set with hashed user IDs still match by their plain ID
+        let hasher = UserIdHasher::new("pepper");
+        let flags = FeatureFlags::default().with_user_ids(Some(hasher.clone()));
+        flags.overrides.insert("testers".to_string(), FlagOverride {
+            name: "testers".to_string(),
+            rule: FlagRule { users: vec![hasher.hash("alice")], ..FlagRule::default() },
+            updated_at: Utc::now(),
+        });
+        assert_eq!(flags.enabled_for("alice"), vec!["testers".to_string()]);
+        assert!(flags.enabled_for("bob").is_empty());
+
+        assert!(FlagRule { percentage: 101, ..FlagRule::default() }.validate().is_err());
+    }
+}
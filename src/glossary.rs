@@ -0,0 +1,91 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+// A key term and its preferred translation in each language, e.g. a product or preset name
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GlossaryEntry {
+    pub term: String,
+    // Language ID -> preferred translation
+    pub translations: HashMap<String, String>,
+}
+
+// Deployment-wide terminology, injected into translation prompts
+#[derive(Debug, Clone, Default)]
+pub struct Glossary {
+    entries: Vec<GlossaryEntry>,
+}
+
+impl Glossary {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read glossary file: {:?}", path.as_ref()))?;
+
+        let entries: Vec<GlossaryEntry> = serde_yaml::from_str(&content)
+            .with_context(|| format!("Failed to parse YAML in glossary file: {:?}", path.as_ref()))?;
+
+        if entries.iter().any(|entry| entry.term.trim().is_empty()) {
+            return Err(anyhow::anyhow!("Glossary entry without a term in {:?}", path.as_ref()));
+        }
+
+        tracing::info!("Loaded {} glossary terms from {:?}", entries.len(), path.as_ref());
+        Ok(Self { entries })
+    }
+
+    // Terms with a translation into the language, as (term, translation)
+    fn terms<'a>(&'a self, language_id: &'a str) -> impl Iterator<Item = (&'a str, &'a str)> {
+        self.entries.iter().filter_map(move |entry| {
+            entry.translations.get(language_id).map(|translation| (entry.term.as_str(), translation.as_str()))
+        })
+    }
+
+    // Prompt lines telling the model which translations to use; empty without any for the language
+    pub fn instructions(&self, language_id: &str) -> String {
+        let rules: Vec<String> = self.terms(language_id)
+            .map(|(term, translation)| format!("- \"{}\" -> \"{}\"", term, translation))
+            .collect();
+
+        if rules.is_empty() {
+            return String::new();
+        }
+        format!("Always translate these terms exactly as given:\n{}", rules.join("\n"))
+    }
+
+    // Terms used in the English original whose preferred translation is missing from the translation
+    pub fn violations(&self, original: &str, translation: &str, language_id: &str) -> Vec<String> {
+        let original = original.to_lowercase();
+        let translation = translation.to_lowercase();
+
+        self.terms(language_id)
+            .filter(|(term, preferred)| {
+                original.contains(&term.to_lowercase()) && !translation.contains(&preferred.to_lowercase())
+            })
+            .map(|(term, _)| term.to_string())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_instructions_and_violations() {
+        let glossary = Glossary {
+            entries: serde_yaml::from_str(
+                "[{term: Ape Oracle, translations: {ja: 猿の神託, fr: Oracle du Singe}}, {term: banana, translations: {fr: banane}}]",
+            ).unwrap(),
+        };
+
+        assert!(glossary.instructions("ja").contains("\"Ape Oracle\" -> \"猿の神託\""));
+        assert!(glossary.instructions("de").is_empty());
+
+        let original = "The Ape Oracle asks for a banana.";
+        assert_eq!(glossary.violations(original, "L'Oracle du Singe demande une banane.", "fr"), Vec::<String>::new());
+        assert_eq!(glossary.violations(original, "L'Oracle du Singe demande un fruit.", "fr"), vec!["banana"]);
+        // Terms not in the original don't need to appear
+        assert!(glossary.violations("Nothing here.", "Rien ici.", "fr").is_empty());
+    }
+}
@@ -0,0 +1,206 @@
+use chrono::{DateTime, Utc};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tonic::{metadata::MetadataValue, Request, Response, Status};
+
+use crate::access::Caller;
+use crate::handlers::{self, ApiError, GenerationRequest, SayingOutcome};
+use crate::languages::TranslationMode;
+use crate::models::{self, SayingSource};
+use crate::streaks;
+use crate::AppState;
+
+pub mod proto {
+    tonic::include_proto!("promptwrapper.v1");
+}
+
+use proto::saying_service_server::{SayingService, SayingServiceServer};
+
+// gRPC front end over the same AppState as the HTTP API
+pub struct GrpcService {
+    state: Arc<AppState>,
+}
+
+impl From<ApiError> for Status {
+    fn from(error: ApiError) -> Self {
+        let code = error.code();
+        let mut status = match error {
+            ApiError::AccessDenied(msg) => Status::permission_denied(msg),
+            ApiError::RateLimited { message, .. } => Status::resource_exhausted(message),
+            ApiError::NotFound(msg) => Status::not_found(msg),
+            ApiError::PresetNotFound(preset_id) => Status::not_found(format!("No preset with ID: {}", preset_id)),
+            ApiError::BadRequest(msg) => Status::invalid_argument(msg),
+            ApiError::UnknownLanguage(language_id) => Status::invalid_argument(format!("Unknown language: {}", language_id)),
+            ApiError::PromptTooLong(msg) => Status::invalid_argument(msg),
+            ApiError::InternalError(msg) => Status::internal(msg),
+            ApiError::OpenRouterError(err) => Status::unavailable(ApiError::upstream_failure_message(&err)),
+            ApiError::UpstreamAuth(msg) => Status::internal(msg),
+            ApiError::UpstreamQuota(msg) => Status::unavailable(msg),
+            ApiError::UpstreamRateLimited { message, .. } => Status::unavailable(message),
+            ApiError::UpstreamBadRequest(msg) => Status::invalid_argument(msg),
+            ApiError::ReadOnly => Status::unavailable("The service is read-only"),
+            ApiError::Timeout => Status::deadline_exceeded("The request took too long"),
+        };
+        status.metadata_mut().insert("x-error-code", MetadataValue::from_static(code.as_str()));
+        status
+    }
+}
+
+fn timestamp(at: DateTime<Utc>) -> prost_types::Timestamp {
+    prost_types::Timestamp {
+        seconds: at.timestamp(),
+        nanos: at.timestamp_subsec_nanos() as i32,
+    }
+}
+
+fn to_proto(saying: &models::Saying, source: SayingSource) -> proto::Saying {
+    let source = match source {
+        SayingSource::LLM => proto::SayingSource::Llm,
+        SayingSource::Cache => proto::SayingSource::Cache,
+        SayingSource::Database => proto::SayingSource::Database,
+    };
+
+    proto::Saying {
+        id: saying.id.clone(),
+        content: saying.content.clone(),
+        created_at: Some(timestamp(saying.created_at)),
+        source: source as i32,
+        preset_id: saying.preset_id.clone(),
+        language_id: saying.language_id.clone(),
+    }
+}
+
+// Same bearer token and client address the HTTP API uses for access checks
+fn caller<T>(state: &AppState, request: &Request<T>) -> Caller {
+    Caller::from_headers(&request.metadata().clone().into_headers(), request.remote_addr(), &state.config.access.trusted_proxies)
+}
+
+// Proto3 strings can't be absent, so an empty user ID means none was given
+fn resolve_user_id(state: &AppState, caller: &Caller, user_id: String) -> Result<String, ApiError> {
+    handlers::resolve_user_id(state, caller, Some(user_id).filter(|user_id| !user_id.is_empty()))
+}
+
+#[tonic::async_trait]
+impl SayingService for GrpcService {
+    async fn generate_saying(
+        &self,
+        request: Request<proto::GenerateSayingRequest>,
+    ) -> Result<Response<proto::GenerateSayingResponse>, Status> {
+        let caller = caller(&self.state, &request);
+        let request = request.into_inner();
+        let user_id = resolve_user_id(&self.state, &caller, request.user_id)?;
+        let (language_id, detected_language_id) =
+            handlers::request_language(&self.state, request.language_id, request.prompt.as_deref());
+        let translation_mode = match request.translation_mode.as_deref() {
+            None | Some("") => None,
+            Some("bilingual") => Some(TranslationMode::Bilingual),
+            Some("native_only") => Some(TranslationMode::NativeOnly),
+            Some("english_only") => Some(TranslationMode::EnglishOnly),
+            Some(other) => return Err(Status::invalid_argument(format!("Unknown translation mode: {}", other))),
+        };
+
+        // There is no way to hand back an issued token here, so only token callers claim users
+        handlers::check_owner(&self.state, &caller, &user_id, caller.token.is_some()).await?;
+        let request = GenerationRequest {
+            prompt: request.prompt,
+            preset_id: request.preset_id,
+            language_id,
+            detected_language_id,
+            translation_mode,
+            tags: Vec::new(),
+        };
+        // gRPC has no way to hand back a job, so over-quota requests are never queued here
+        let outcome = handlers::generate_saying(&self.state, &caller, &user_id, request, false).await?;
+
+        let response = match outcome {
+            SayingOutcome::Generated(saying, details) => proto::GenerateSayingResponse {
+                saying: Some(to_proto(&saying, saying.source.clone())),
+                from_cache: false,
+                prompt_tokens: Some(details.prompt_tokens as u32),
+                truncated: details.truncated,
+            },
+            SayingOutcome::Cached(saying) => proto::GenerateSayingResponse {
+                saying: Some(to_proto(&saying, SayingSource::Cache)),
+                from_cache: true,
+                prompt_tokens: None,
+                truncated: false,
+            },
+            SayingOutcome::Queued { .. } => return Err(Status::internal("Unexpected queued generation")),
+        };
+
+        Ok(Response::new(response))
+    }
+
+    async fn get_history(
+        &self,
+        request: Request<proto::GetHistoryRequest>,
+    ) -> Result<Response<proto::GetHistoryResponse>, Status> {
+        let caller = caller(&self.state, &request);
+        let request = request.into_inner();
+        let user_id = resolve_user_id(&self.state, &caller, request.user_id)?;
+        handlers::is_user_allowed(&self.state, &caller, &user_id)?;
+        handlers::check_read_limit(&self.state, &caller, &user_id)?;
+        handlers::check_owner(&self.state, &caller, &user_id, false).await?;
+
+        let limit = request.limit.unwrap_or(10) as usize;
+        let sayings = self.state.storage.get_sayings(&user_id, limit).await
+            .map_err(|e| Status::internal(format!("Failed to get sayings: {}", e)))?;
+
+        Ok(Response::new(proto::GetHistoryResponse {
+            sayings: sayings.iter().map(|s| to_proto(s, s.source.clone())).collect(),
+        }))
+    }
+
+    async fn get_status(
+        &self,
+        request: Request<proto::GetStatusRequest>,
+    ) -> Result<Response<proto::GetStatusResponse>, Status> {
+        let caller = caller(&self.state, &request);
+        let request = request.into_inner();
+        let user_id = resolve_user_id(&self.state, &caller, request.user_id)?;
+        handlers::is_user_allowed(&self.state, &caller, &user_id)?;
+        handlers::check_read_limit(&self.state, &caller, &user_id)?;
+        handlers::check_owner(&self.state, &caller, &user_id, false).await?;
+
+        if let Some(minutes) = request.tz_offset {
+            self.state.rate_limiter.set_tz_offset(&user_id, minutes);
+        }
+
+        let latest = self.state.storage.get_sayings(&user_id, 1).await
+            .map_err(|e| Status::internal(format!("Failed to get sayings: {}", e)))?;
+        let streak = self.state.storage.get_user_stats(&user_id).await
+            .map_err(|e| Status::internal(format!("Failed to get user stats: {}", e)))?
+            .streak(streaks::offset_from_minutes(request.tz_offset.unwrap_or(0)), self.state.clock.now());
+
+        let limit_info = self.state.rate_limiter.get_limit_info(&user_id).await;
+        let remaining_requests = limit_info.as_ref()
+            .map(|info| info.available())
+            .unwrap_or(self.state.config.rate_limit.max_requests);
+
+        Ok(Response::new(proto::GetStatusResponse {
+            can_query: remaining_requests > 0 && !self.state.config.server.read_only,
+            remaining_requests,
+            reset_at: limit_info.map(|info| timestamp(info.reset_at)),
+            last_saying: latest.first().map(|s| to_proto(s, s.source.clone())),
+            current_streak: streak.current,
+            longest_streak: streak.longest,
+            user_id,
+        }))
+    }
+}
+
+// Serve the gRPC interface next to the HTTP server
+pub fn spawn_server(state: Arc<AppState>) -> anyhow::Result<()> {
+    let addr = format!("{}:{}", state.config.server.host, state.config.server.grpc_port)
+        .parse::<SocketAddr>()?;
+    let service = SayingServiceServer::new(GrpcService { state });
+
+    tracing::info!("gRPC listening on {}", addr);
+    tokio::spawn(async move {
+        if let Err(e) = tonic::transport::Server::builder().add_service(service).serve(addr).await {
+            tracing::error!("gRPC server failed: {}", e);
+        }
+    });
+
+    Ok(())
+}
@@ -1,70 +1,347 @@
 use axum::{
     extract::{Json, Path, Query, State},
-    response::{IntoResponse, Response},
-    http::StatusCode,
+    response::{Html, IntoResponse, Response},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
 };
 use serde::{Deserialize, Serialize};
 use serde_json::json;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDate, Utc};
+use std::cmp::Reverse;
+use std::collections::{hash_map::RandomState, HashMap, HashSet};
+use std::hash::BuildHasher;
+use std::net::IpAddr;
 use std::sync::Arc;
-use rand::{self, seq::SliceRandom};
+use rand::{self, seq::SliceRandom, Rng};
 use thiserror::Error;
+use lazy_static::lazy_static;
 
-use crate::models::{Saying, SayingSource};
+use crate::models::{normalize_tag, prompt_search_key, Collection, Conversation, FreeformPromptEntry, MessageRole, PromptHistoryEntry, PromptOutcome, Saying, SayingFeedback, ShadowOutput, SayingShare, SayingSource, SayingTranslation, SayingVersion, UserPreferences, UserStats, MAX_TAG_CHARS};
 use crate::preset::Preset;
-use crate::config::TEST_USER_ID;
+use crate::openrouter::{GenerationOptions, UpstreamError};
+use crate::config::{AccessPolicyKind, DefaultUserMode, PromptOverflow, RateLimitMode, DEFAULT_USER_ID};
 use crate::AppState;
-use crate::languages::{Language, get_all_languages, get_language_by_id};
+use crate::user_hash;
+use crate::rate_limiter::RateLimitCheck;
+use crate::access::{Access, Caller};
+use crate::languages::{get_all_languages, resolve_language, Language, TranslationMode};
+use crate::streaks::{self, Streak};
+use crate::achievements::{self, AchievementKind};
+use crate::leaderboard::LeaderboardEntry;
+use crate::metrics::Metrics;
+use crate::notifier::{NotificationChannel, NotificationTarget};
+use crate::email::{self, EmailPreferences};
+use crate::tokens;
+use crate::etag;
+use crate::card;
+use crate::daily;
+use crate::admin;
+use crate::queue::{GenerationJob, JobStatus};
+
+// Machine-readable error identifiers, sent as `code` in every error body (and as the
+// `x-error-code` gRPC metadata). Clients branch on these, so existing codes never change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    AccessDenied,
+    RateLimited,
+    NotFound,
+    PresetNotFound,
+    BadRequest,
+    UnknownLanguage,
+    PromptTooLong,
+    InternalError,
+    // The LLM call failed in a way that may pass on retry
+    UpstreamError,
+    UpstreamTimeout,
+    // The model kept returning empty or unusable output
+    UpstreamUnusableOutput,
+    UpstreamAuth,
+    UpstreamQuota,
+    UpstreamRateLimited,
+    UpstreamBadRequest,
+    // Writes are switched off (READ_ONLY)
+    ReadOnly,
+    // The request ran past REQUEST_TIMEOUT_SECONDS
+    Timeout,
+}
+
+impl ErrorCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::AccessDenied => "ACCESS_DENIED",
+            ErrorCode::RateLimited => "RATE_LIMITED",
+            ErrorCode::NotFound => "NOT_FOUND",
+            ErrorCode::PresetNotFound => "PRESET_NOT_FOUND",
+            ErrorCode::BadRequest => "BAD_REQUEST",
+            ErrorCode::UnknownLanguage => "UNKNOWN_LANGUAGE",
+            ErrorCode::PromptTooLong => "PROMPT_TOO_LONG",
+            ErrorCode::InternalError => "INTERNAL_ERROR",
+            ErrorCode::UpstreamError => "UPSTREAM_ERROR",
+            ErrorCode::UpstreamTimeout => "UPSTREAM_TIMEOUT",
+            ErrorCode::UpstreamUnusableOutput => "UPSTREAM_UNUSABLE_OUTPUT",
+            ErrorCode::UpstreamAuth => "UPSTREAM_AUTH",
+            ErrorCode::UpstreamQuota => "UPSTREAM_QUOTA",
+            ErrorCode::UpstreamRateLimited => "UPSTREAM_RATE_LIMITED",
+            ErrorCode::UpstreamBadRequest => "UPSTREAM_BAD_REQUEST",
+            ErrorCode::ReadOnly => "READ_ONLY",
+            ErrorCode::Timeout => "TIMEOUT",
+        }
+    }
+}
 
 #[derive(Debug, Error)]
 pub enum ApiError {
     #[error("Access denied: {0}")]
     AccessDenied(String),
     
-    #[error("Rate limit exceeded: {0}")]
-    RateLimited(String),
+    #[error("Rate limit exceeded: {message}")]
+    RateLimited { message: String, limit: RateLimitState },
     
     #[error("Not found: {0}")]
     NotFound(String),
     
+    // Holds the preset ID
+    #[error("Preset not found: {0}")]
+    PresetNotFound(String),
+    
     #[error("Bad request: {0}")]
     BadRequest(String),
     
+    // Holds the language ID
+    #[error("Unknown language: {0}")]
+    UnknownLanguage(String),
+    
+    #[error("Prompt too long: {0}")]
+    PromptTooLong(String),
+    
     #[error("Internal server error: {0}")]
     InternalError(String),
     
     #[error("OpenRouter API error: {0}")]
     OpenRouterError(#[from] anyhow::Error),
+    
+    #[error("Upstream authentication failed: {0}")]
+    UpstreamAuth(String),
+    
+    #[error("Upstream quota exhausted: {0}")]
+    UpstreamQuota(String),
+    
+    #[error("Upstream rate limited: {message}")]
+    UpstreamRateLimited { message: String, retry_after: Option<u64> },
+    
+    #[error("Upstream rejected the request: {0}")]
+    UpstreamBadRequest(String),
+    
+    #[error("Service is read-only")]
+    ReadOnly,
+    
+    #[error("Request timed out")]
+    Timeout,
+}
+
+impl ApiError {
+    // Classify a failed LLM call by what the upstream told us
+    pub fn from_upstream(err: anyhow::Error) -> Self {
+        let Some(upstream) = err.downcast_ref::<UpstreamError>() else {
+            return ApiError::OpenRouterError(err);
+        };
+        
+        match upstream {
+            // Our credentials are wrong; the details are for operators, not clients
+            UpstreamError::Auth { .. } => ApiError::UpstreamAuth("The language model provider rejected this service's credentials".to_string()),
+            UpstreamError::Quota(_) => ApiError::UpstreamQuota("The language model provider account is out of credits".to_string()),
+            UpstreamError::RateLimited { retry_after, .. } => ApiError::UpstreamRateLimited {
+                message: "The language model provider is rate limiting requests, try again later".to_string(),
+                retry_after: *retry_after,
+            },
+            // The provider's explanation can name models or the account, so it stays in the log
+            UpstreamError::BadRequest(body) => {
+                tracing::warn!("The language model provider rejected a request: {}", body);
+                ApiError::UpstreamBadRequest("The language model provider rejected the request".to_string())
+            }
+            UpstreamError::Status { .. } | UpstreamError::Degenerate { .. } | UpstreamError::Timeout(_) => ApiError::OpenRouterError(err),
+        }
+    }
+    
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            ApiError::AccessDenied(_) => ErrorCode::AccessDenied,
+            ApiError::RateLimited { .. } => ErrorCode::RateLimited,
+            ApiError::NotFound(_) => ErrorCode::NotFound,
+            ApiError::PresetNotFound(_) => ErrorCode::PresetNotFound,
+            ApiError::BadRequest(_) => ErrorCode::BadRequest,
+            ApiError::UnknownLanguage(_) => ErrorCode::UnknownLanguage,
+            ApiError::PromptTooLong(_) => ErrorCode::PromptTooLong,
+            ApiError::InternalError(_) => ErrorCode::InternalError,
+            ApiError::OpenRouterError(err) => match err.downcast_ref::<UpstreamError>() {
+                Some(UpstreamError::Timeout(_)) => ErrorCode::UpstreamTimeout,
+                Some(UpstreamError::Degenerate { .. }) => ErrorCode::UpstreamUnusableOutput,
+                _ => ErrorCode::UpstreamError,
+            },
+            ApiError::UpstreamAuth(_) => ErrorCode::UpstreamAuth,
+            ApiError::UpstreamQuota(_) => ErrorCode::UpstreamQuota,
+            ApiError::UpstreamRateLimited { .. } => ErrorCode::UpstreamRateLimited,
+            ApiError::UpstreamBadRequest(_) => ErrorCode::UpstreamBadRequest,
+            ApiError::ReadOnly => ErrorCode::ReadOnly,
+            ApiError::Timeout => ErrorCode::Timeout,
+        }
+    }
+    
+    // What clients are told about a failed upstream call. The provider's response body can
+    // name models or the account, so the details only go to the log.
+    pub fn upstream_failure_message(err: &anyhow::Error) -> String {
+        tracing::error!("Language model call failed: {:#}", err);
+        match err.downcast_ref::<UpstreamError>() {
+            Some(UpstreamError::Timeout(_)) => "The language model provider timed out, try again later",
+            Some(UpstreamError::Degenerate { .. }) => "The language model provider returned no usable output, try again",
+            _ => "The language model provider returned an error, try again later",
+        }.to_string()
+    }
+    
+    // Whether the same request may succeed if the client simply tries again later
+    fn retryable(&self) -> bool {
+        matches!(
+            self,
+            ApiError::RateLimited { .. } | ApiError::UpstreamRateLimited { .. } | ApiError::OpenRouterError(_) | ApiError::ReadOnly | ApiError::Timeout
+        )
+    }
 }
 
 impl IntoResponse for ApiError {
     fn into_response(self) -> Response {
         let (status, error_message) = match &self {
-            ApiError::AccessDenied(msg) => (StatusCode::FORBIDDEN, msg),
-            ApiError::RateLimited(msg) => (StatusCode::TOO_MANY_REQUESTS, msg),
-            ApiError::NotFound(msg) => (StatusCode::NOT_FOUND, msg),
-            ApiError::BadRequest(msg) => (StatusCode::BAD_REQUEST, msg),
-            ApiError::InternalError(msg) => (StatusCode::INTERNAL_SERVER_ERROR, msg),
-            ApiError::OpenRouterError(err) => (StatusCode::INTERNAL_SERVER_ERROR, &err.to_string()),
+            ApiError::AccessDenied(msg) => (StatusCode::FORBIDDEN, msg.clone()),
+            ApiError::RateLimited { message, .. } => (StatusCode::TOO_MANY_REQUESTS, message.clone()),
+            ApiError::NotFound(msg) => (StatusCode::NOT_FOUND, msg.clone()),
+            ApiError::PresetNotFound(preset_id) => (StatusCode::NOT_FOUND, format!("No preset with ID: {}", preset_id)),
+            ApiError::BadRequest(msg) => (StatusCode::BAD_REQUEST, msg.clone()),
+            ApiError::UnknownLanguage(language_id) => (StatusCode::BAD_REQUEST, format!("Unknown language: {}", language_id)),
+            ApiError::PromptTooLong(msg) => (StatusCode::BAD_REQUEST, msg.clone()),
+            ApiError::InternalError(msg) => (StatusCode::INTERNAL_SERVER_ERROR, msg.clone()),
+            ApiError::OpenRouterError(err) => (StatusCode::INTERNAL_SERVER_ERROR, ApiError::upstream_failure_message(err)),
+            ApiError::UpstreamAuth(msg) => (StatusCode::BAD_GATEWAY, msg.clone()),
+            ApiError::UpstreamQuota(msg) => (StatusCode::SERVICE_UNAVAILABLE, msg.clone()),
+            ApiError::UpstreamRateLimited { message, .. } => (StatusCode::SERVICE_UNAVAILABLE, message.clone()),
+            ApiError::UpstreamBadRequest(msg) => (StatusCode::BAD_REQUEST, msg.clone()),
+            ApiError::ReadOnly => (StatusCode::SERVICE_UNAVAILABLE, "The service is read-only; only stored sayings can be read".to_string()),
+            ApiError::Timeout => (StatusCode::SERVICE_UNAVAILABLE, "The request took too long to complete, try again later".to_string()),
         };
 
         tracing::error!("{}: {}", status, error_message);
         
-        let body = Json(json!({
-            "error": self.to_string(),
+        // The upstream error's own text is only logged, see upstream_failure_message
+        let error = match &self {
+            ApiError::OpenRouterError(_) => "OpenRouter API error".to_string(),
+            _ => self.to_string(),
+        };
+        let mut body = json!({
+            "error": error,
+            "code": self.code().as_str(),
             "message": error_message,
-        }));
+            "retryable": self.retryable(),
+        });
+        
+        // Rate-limit state goes next to the message so clients can render a countdown
+        if let (ApiError::RateLimited { limit, .. }, Some(fields)) = (&self, body.as_object_mut()) {
+            if let Ok(serde_json::Value::Object(limit)) = serde_json::to_value(limit) {
+                fields.extend(limit);
+            }
+        }
+        let body = Json(body);
 
-        (status, body).into_response()
+        match self {
+            ApiError::UpstreamRateLimited { retry_after: Some(seconds), .. }
+            | ApiError::RateLimited { limit: RateLimitState { retry_after_seconds: seconds, .. }, .. } => {
+                (status, [(header::RETRY_AFTER, seconds.to_string())], body).into_response()
+            }
+            _ => (status, body).into_response(),
+        }
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct SayingResponse {
-    pub id: String,
-    pub content: String,
+// Turns a panicking handler into an ordinary 500 instead of a dropped connection. The panic
+// message goes to the log, not to the client.
+pub fn panic_response(metrics: &Metrics, panic: Box<dyn std::any::Any + Send + 'static>) -> Response {
+    Metrics::incr(&metrics.handler_panics);
+    let message = panic.downcast_ref::<String>().map(String::as_str)
+        .or_else(|| panic.downcast_ref::<&str>().copied())
+        .unwrap_or("unknown panic");
+    tracing::error!("Handler panicked: {}", message);
+
+    ApiError::InternalError("The request could not be completed".to_string()).into_response()
+}
+
+// Errors from the tower middleware around the handlers. Only the request timeout produces
+// one; the connection would otherwise be dropped without a response.
+pub fn middleware_error(metrics: &Metrics, err: tower::BoxError) -> Response {
+    if err.is::<tower::timeout::error::Elapsed>() {
+        Metrics::incr(&metrics.request_timeouts);
+        return ApiError::Timeout.into_response();
+    }
+    ApiError::InternalError(format!("Unhandled middleware error: {}", err)).into_response()
+}
+
+// Where a rate-limited user stands, included in 429 bodies
+#[derive(Debug, Clone, Serialize)]
+pub struct RateLimitState {
+    // Requests left in the current window; above zero when only the burst limit applies
+    pub remaining: u32,
+    pub reset_at: DateTime<Utc>,
+    pub retry_after_seconds: u64,
+    // Always false; a cached answer is a 200, not an error
+    pub served_from_cache: bool,
+}
+
+impl RateLimitState {
+    async fn for_user(state: &AppState, user_id: &str, retry_after: Option<u64>) -> Self {
+        let now = state.clock.now();
+        let reset_at = state.rate_limiter.current_reset_at(user_id).await;
+        let remaining = state.rate_limiter.get_limit_info(user_id).await
+            .map(|info| info.available())
+            .unwrap_or(0);
+
+        Self {
+            remaining,
+            reset_at,
+            retry_after_seconds: retry_after.unwrap_or_else(|| (reset_at - now).num_seconds().max(0) as u64),
+            served_from_cache: false,
+        }
+    }
+}
+
+// The rate limiter can't take on another user without dropping someone's window
+async fn limiter_full(state: &AppState, user_id: &str, retry_after: u64) -> ApiError {
+    ApiError::RateLimited {
+        message: format!("Too many users are active right now, try again in {} seconds", retry_after),
+        limit: RateLimitState::for_user(state, user_id, Some(retry_after)).await,
+    }
+}
+
+// Borrows from the stored saying; handlers serialize it before the saying is dropped
+#[derive(Debug, Serialize)]
+pub struct SayingResponse<'a> {
+    pub id: &'a str,
+    pub content: &'a str,
     pub created_at: DateTime<Utc>,
-    pub source: String,
+    pub source: SayingSource,
+    // Only present on freshly generated sayings
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<GenerationDetails>,
+    // Every text of an edited saying, oldest first; absent until it is edited
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    pub versions: &'a [SayingVersion],
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub pinned: bool,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    pub tags: &'a [String],
+}
+
+// How the prompt for a generated saying was sent upstream
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct GenerationDetails {
+    // Estimated with a generic tokenizer, the model's own count may differ slightly
+    pub prompt_tokens: usize,
+    // Whether the prompt or the system prompt was cut to fit the token budget
+    pub truncated: bool,
 }
 
 #[derive(Debug, Deserialize)]
@@ -73,16 +350,36 @@ pub struct SayingRequest {
     pub user_id: Option<String>,
     pub preset_id: Option<String>,
     pub language_id: Option<String>,
+    pub translation_mode: Option<TranslationMode>,
+    // Attached to the saying when it's generated; sayings served from the cache aren't stored
+    pub tags: Option<Vec<String>>,
+}
+
+// Most users one POST /users/status may ask about
+const MAX_BULK_STATUS_USERS: usize = 100;
+
+#[derive(Debug, Deserialize)]
+pub struct BulkStatusRequest {
+    pub user_ids: Vec<String>,
 }
 
 #[derive(Debug, Serialize)]
-pub struct UserStatusResponse {
+pub struct UserStatusResponse<'a> {
     pub user_id: String,
     pub can_query: bool,
     pub remaining_requests: u32,
     pub reset_at: Option<DateTime<Utc>>,
-    pub last_saying: Option<SayingResponse>,
+    pub last_saying: Option<SayingResponse<'a>>,
     pub selected_preset: Option<PresetResponse>,
+    pub streak: Streak,
+    // Sayings stored in the user's history
+    pub total_sayings: u64,
+    // Requests answered from stored sayings in the current window
+    pub cache_served: u32,
+    // Tokens used by the sayings generated in the current window
+    pub tokens_used: u64,
+    // Feature flags on for this user
+    pub features: Vec<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -96,10 +393,100 @@ pub struct PresetResponse {
     pub instruction_text: String,
 }
 
+// Carries a newly issued history token back to the client (see check_owner)
+const OWNER_TOKEN_HEADER: &str = "x-owner-token";
+
+// Fields a `fields` selector on GET /presets may pick
+const PRESET_FIELDS: [&str; 7] = ["id", "name", "description", "tags", "button_text", "loading_text", "instruction_text"];
+
+#[derive(Debug, Deserialize)]
+pub struct PresetsQuery {
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
+    // Comma-separated, presets must carry all of them
+    pub tags: Option<String>,
+    // Comma-separated subset of PRESET_FIELDS to include
+    pub fields: Option<String>,
+    // `popular` orders by the number of sayings generated; default is catalog order
+    pub sort: Option<String>,
+    // Include presets restricted to this user's tier
+    pub user_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PresetQuery {
+    pub user_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PinPresetRequest {
+    pub preset_id: String,
+}
+
+// Longest text an edit may set, in characters
+const MAX_EDIT_CHARS: usize = 2000;
+// Most versions a saying keeps, the generated one included
+const MAX_SAYING_VERSIONS: usize = 20;
+
+// Most tags one saying may carry
+const MAX_SAYING_TAGS: usize = 10;
+
+// Any field may be left out to keep it as it is; `tags` replaces the whole set
+#[derive(Debug, Deserialize)]
+pub struct EditSayingRequest {
+    pub content: Option<String>,
+    pub pinned: Option<bool>,
+    pub tags: Option<Vec<String>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FeedbackRequest {
+    // 1 (poor) to 5 (great)
+    pub score: u8,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct StatusQuery {
     pub user_id: Option<String>,
     pub language_id: Option<String>,
+    // Overrides the preset's translation mode for this request
+    pub translation_mode: Option<TranslationMode>,
+    // The user's UTC offset in minutes, for calendar-day rate-limit windows
+    pub tz_offset: Option<i32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UserStatusQuery {
+    // The user's UTC offset in minutes, used to bucket activity into local days
+    pub tz_offset: Option<i32>,
+}
+
+// Long-poll length when the client doesn't ask for one, and the most it may ask for
+const DEFAULT_WAIT_SECONDS: u64 = 30;
+const MAX_WAIT_SECONDS: u64 = 60;
+
+#[derive(Debug, Deserialize)]
+pub struct StatusWaitQuery {
+    pub tz_offset: Option<i32>,
+    // Seconds to wait at most, 1-60 (default 30)
+    pub timeout: Option<u64>,
+}
+
+// Why a status wait returned
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WaitEvent {
+    // The user could already generate, so there was nothing to wait for
+    Ready,
+    WindowReset,
+    DailySaying,
+    Timeout,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StatusWaitResponse<'a> {
+    pub event: WaitEvent,
+    pub status: UserStatusResponse<'a>,
 }
 
 // Convert Preset to PresetResponse
@@ -118,357 +505,2774 @@ impl From<Preset> for PresetResponse {
 }
 
 // Convert from our internal Saying model to the API response
-impl From<Saying> for SayingResponse {
-    fn from(saying: Saying) -> Self {
+impl<'a> From<&'a Saying> for SayingResponse<'a> {
+    fn from(saying: &'a Saying) -> Self {
         Self {
-            id: saying.id,
-            content: saying.content,
+            id: &saying.id,
+            content: &saying.content,
             created_at: saying.created_at,
-            source: String::from(saying.source),
+            source: saying.source.clone(),
+            details: None,
+            versions: &saying.versions,
+            pinned: saying.pinned,
+            tags: &saying.tags,
         }
     }
 }
 
-// Function to validate if a user is allowed to access the API
-fn is_user_allowed(user_id: &str) -> Result<(), ApiError> {
-    // In debug mode, allow the test user (but still follow normal workflow)
-    #[cfg(debug_assertions)]
-    if user_id == TEST_USER_ID {
-        tracing::debug!("Test user accessing API in debug mode (follows normal workflow)");
-        return Ok(());
+// The user a request acts as: the one it names, otherwise per DEFAULT_USER_MODE
+pub(crate) fn resolve_user_id(state: &AppState, caller: &Caller, user_id: Option<String>) -> Result<String, ApiError> {
+    if let Some(user_id) = user_id {
+        check_user_id_not_hashed(state, &user_id)?;
+        return Ok(user_id);
     }
+    
+    match state.config.access.default_user {
+        DefaultUserMode::Shared => Ok(DEFAULT_USER_ID.to_string()),
+        DefaultUserMode::Reject => Err(ApiError::BadRequest("A user_id is required".to_string())),
+        DefaultUserMode::Ephemeral => Ok(ephemeral_user_id(caller.ip)),
+    }
+}
+
+lazy_static! {
+    // Seeded per process, so ephemeral IDs can't be derived from an address by anyone else
+    static ref EPHEMERAL_ID_SEED: RandomState = RandomState::new();
+}
 
-    // In release mode, block the test user
-    #[cfg(not(debug_assertions))]
-    if user_id == TEST_USER_ID {
-        tracing::warn!("Blocked test user access attempt in release mode");
-        return Err(ApiError::AccessDenied("This user ID is not allowed in production".to_string()));
+// Stable per client address, not per connection, so reconnecting from another port doesn't
+// bring a fresh quota. Without a known peer (in-process calls) every request is new.
+fn ephemeral_user_id(ip: Option<IpAddr>) -> String {
+    match ip {
+        Some(ip) => format!("anon-{:016x}", EPHEMERAL_ID_SEED.hash_one(ip)),
+        None => format!("anon-{}", uuid::Uuid::new_v4().simple()),
     }
+}
 
-    // Regular users are always allowed
+// With STORAGE_USER_ID_SALT, user IDs in the hashed form reach stored records directly, so
+// requests can't name them
+fn check_user_id_not_hashed(state: &AppState, user_id: &str) -> Result<(), ApiError> {
+    if state.storage.hashes_user_ids() && user_id.starts_with(user_hash::HASHED_PREFIX) {
+        return Err(ApiError::BadRequest(format!("User IDs starting with {} are reserved", user_hash::HASHED_PREFIX)));
+    }
     Ok(())
 }
 
+// Run the configured access policy for a caller acting as `user_id`. Cached-only
+// access only matters when generating, everywhere else it counts as allowed.
+pub(crate) fn is_user_allowed(state: &AppState, caller: &Caller, user_id: &str) -> Result<(), ApiError> {
+    check_user_id_not_hashed(state, user_id)?;
+    is_owner_allowed(state, caller, user_id)
+}
+
+// Whether the owner of a stored record, whose ID may be hashed, is a sandbox user
+pub(crate) fn is_sandbox_owner(state: &AppState, user_id: &str) -> bool {
+    state.config.access.sandbox_users.iter().any(|sandbox| state.storage.same_user(sandbox, user_id))
+}
+
+// As is_user_allowed, for the owner of a stored record, whose ID is hashed when storage
+// hashes user IDs
+pub(crate) fn is_owner_allowed(state: &AppState, caller: &Caller, user_id: &str) -> Result<(), ApiError> {
+    match state.access.check(caller, user_id) {
+        Access::Deny(reason) => Err(ApiError::AccessDenied(reason)),
+        Access::Allow | Access::CachedOnly => Ok(()),
+    }
+}
+
+// With PROTECT_USER_HISTORY, a user ID belongs to the bearer token that first generated for
+// it. Reads of its history need that token, and so does generating, since cooldown can serve
+// the last saying. With `claim`, an unowned user is bound to the caller's token, or to a new
+// one that is returned so the client can send it from then on. Users who already had history
+// when the setting was turned on are only bound by an operator (POST /admin/owners), so
+// nobody can take over someone else's ID by generating for it first.
+pub(crate) async fn check_owner(state: &AppState, caller: &Caller, user_id: &str, claim: bool) -> Result<Option<String>, ApiError> {
+    if !state.config.access.protect_history {
+        return Ok(None);
+    }
+    
+    let owner = state.storage.get_user_owner(user_id).await
+        .map_err(|e| ApiError::InternalError(format!("Failed to get user owner: {}", e)))?;
+    let denied = || ApiError::AccessDenied("This user's history needs the token it was created with".to_string());
+    
+    match owner {
+        Some(owner) if caller.token.as_ref() == Some(&owner) => Ok(None),
+        Some(_) => Err(denied()),
+        None if has_history(state, user_id).await? => Err(ApiError::AccessDenied(
+            "This user's history predates PROTECT_USER_HISTORY; an operator has to bind it to a token".to_string()
+        )),
+        None if !claim => Ok(None),
+        None => {
+            let issued = caller.token.is_none().then(|| uuid::Uuid::new_v4().simple().to_string());
+            let token = caller.token.as_ref().or(issued.as_ref()).cloned().unwrap_or_default();
+            let owner = state.storage.claim_user_owner(user_id, &token).await
+                .map_err(|e| ApiError::InternalError(format!("Failed to claim user: {}", e)))?;
+            
+            // Someone else got there first
+            if owner != token {
+                return Err(denied());
+            }
+            Ok(issued)
+        }
+    }
+}
+
+// Whether anything was stored for the user, so claiming it would hand over someone's history
+async fn has_history(state: &AppState, user_id: &str) -> Result<bool, ApiError> {
+    let stats = state.storage.get_user_stats(user_id).await
+        .map_err(|e| ApiError::InternalError(format!("Failed to get user stats: {}", e)))?;
+    if stats.sayings > 0 {
+        return Ok(true);
+    }
+    
+    let conversations = state.storage.get_conversations(user_id).await
+        .map_err(|e| ApiError::InternalError(format!("Failed to get conversations: {}", e)))?;
+    Ok(!conversations.is_empty())
+}
+
+// Hand a token issued by check_owner to the client, on errors too: the user is bound to it
+// either way, and a client that never saw it would be locked out
+fn with_owner_token(mut response: Response, issued_token: Option<String>) -> Response {
+    if let Some(value) = issued_token.and_then(|token| HeaderValue::from_str(&token).ok()) {
+        response.headers_mut().insert(OWNER_TOKEN_HEADER, value);
+    }
+    response
+}
+
+// Limit reads of user history and status per client (API token, else address, else user),
+// so guessing user IDs from one client runs into the same quota. Exempt callers skip it.
+pub(crate) fn check_read_limit(state: &AppState, caller: &Caller, user_id: &str) -> Result<(), ApiError> {
+    if state.exemptions.is_exempt(user_id, caller.token.as_deref()) {
+        return Ok(());
+    }
+    
+    let key = match (&caller.token, caller.ip) {
+        (Some(token), _) => format!("token:{}", token),
+        (None, Some(ip)) => format!("ip:{}", ip),
+        (None, None) => format!("user:{}", user_id),
+    };
+    
+    state.read_limiter.check(&key).map_err(|reset_at| {
+        Metrics::incr(&state.metrics.rate_limited);
+        ApiError::RateLimited {
+            message: "Too many read requests, slow down".to_string(),
+            limit: RateLimitState {
+                remaining: 0,
+                reset_at,
+                retry_after_seconds: (reset_at - Utc::now()).num_seconds().max(1) as u64,
+                served_from_cache: false,
+            },
+        }
+    })
+}
+
 // GET /sayings - Get all sayings (with optional limit)
 pub async fn get_sayings(
     Query(params): Query<SayingsQuery>,
     State(state): State<Arc<AppState>>,
-) -> Result<Json<Vec<SayingResponse>>, ApiError> {
-    let user_id = params.user_id.unwrap_or_else(|| "default_user".to_string());
+    caller: Caller,
+) -> Result<Response, ApiError> {
+    let user_id = resolve_user_id(&state, &caller, params.user_id)?;
     
     // Check if user is allowed
-    is_user_allowed(&user_id)?;
+    is_user_allowed(&state, &caller, &user_id)?;
+    check_read_limit(&state, &caller, &user_id)?;
+    check_owner(&state, &caller, &user_id, false).await?;
     
     let limit = params.limit.unwrap_or(10);
     
-    let sayings = state.storage.get_sayings(&user_id, limit).await
-        .map_err(|e| ApiError::InternalError(format!("Failed to get sayings: {}", e)))?;
+    // Filtering has to see the whole history before the limit applies
+    let fetch = if params.pinned.is_some() { usize::MAX } else { limit };
+    let sayings = match params.tag.as_deref() {
+        Some(tag) => {
+            let tag = normalize_tag(tag).ok_or_else(|| ApiError::BadRequest(format!("Invalid tag: {}", tag)))?;
+            state.storage.get_sayings_by_tag(&user_id, &tag, fetch).await
+        }
+        None => state.storage.get_sayings(&user_id, fetch).await,
+    }.map_err(|e| ApiError::InternalError(format!("Failed to get sayings: {}", e)))?;
     
-    let response = sayings.into_iter()
-        .map(SayingResponse::from)
+    let response = sayings.iter()
+        .filter(|saying| params.pinned.is_none_or(|pinned| saying.pinned == pinned))
+        .take(limit)
+        .map(|saying| SayingResponse::from(saying.as_ref()))
         .collect::<Vec<_>>();
     
-    Ok(Json(response))
+    Ok(Json(response).into_response())
 }
 
 // GET /sayings/latest - Get the latest saying for a user
 pub async fn get_latest_saying(
     Query(params): Query<StatusQuery>,
     State(state): State<Arc<AppState>>,
-) -> Result<Json<SayingResponse>, ApiError> {
-    let user_id = params.user_id.unwrap_or_else(|| "default_user".to_string());
+    caller: Caller,
+) -> Result<Response, ApiError> {
+    let user_id = resolve_user_id(&state, &caller, params.user_id)?;
     
     // Check if user is allowed
-    is_user_allowed(&user_id)?;
+    is_user_allowed(&state, &caller, &user_id)?;
+    check_read_limit(&state, &caller, &user_id)?;
+    check_owner(&state, &caller, &user_id, false).await?;
     
     let saying = state.storage.get_last_saying(&user_id).await
         .map_err(|e| ApiError::InternalError(format!("Failed to get saying: {}", e)))?
         .ok_or_else(|| ApiError::NotFound("User has no saved sayings".to_string()))?;
     
-    Ok(Json(SayingResponse::from(saying)))
+    Ok(Json(SayingResponse::from(saying.as_ref())).into_response())
 }
 
-// POST /sayings - Create a new saying
-pub async fn create_saying(
-    Query(params): Query<StatusQuery>,
+#[derive(Debug, Deserialize)]
+pub struct DailySayingQuery {
+    pub preset_id: Option<String>,
+    pub language_id: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DailySayingResponse<'a> {
+    pub date: NaiveDate,
+    pub preset_id: &'a str,
+    pub language_id: &'a str,
+    #[serde(flatten)]
+    pub saying: SayingResponse<'a>,
+}
+
+// GET /sayings/daily - The saying shared by everyone today, without touching any user's quota
+pub async fn get_daily_saying(
+    Query(params): Query<DailySayingQuery>,
     State(state): State<Arc<AppState>>,
-    Json(payload): Json<SayingRequest>,
-) -> Result<impl IntoResponse, ApiError> {
-    let user_id = params.user_id.or(payload.user_id).unwrap_or_else(|| "default_user".to_string());
+    caller: Caller,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    check_read_limit(&state, &caller, DEFAULT_USER_ID)?;
     
-    // Get the language ID from the query or the request body, defaulting to English
-    let language_id = params.language_id
-        .or(payload.language_id.clone())
-        .unwrap_or_else(|| crate::languages::DEFAULT_LANGUAGE_ID.to_string());
+    let language_id = params.language_id.unwrap_or_else(|| crate::languages::DEFAULT_LANGUAGE_ID.to_string());
+    if crate::languages::find_language(&language_id).is_none() {
+        return Err(ApiError::UnknownLanguage(language_id));
+    }
     
-    // First check if user is in cooldown period (rate limited)
-    let is_rate_limited = match state.rate_limiter.get_limit_info(&user_id).await {
-        Some(info) => info.remaining_requests == 0,
-        None => false, // No rate limit info yet, not limited
+    let preset_ids: Vec<String> = daily::presets(&state).into_iter().map(|preset| preset.id).collect();
+    let today = Utc::now().date_naive();
+    let preset_id = match params.preset_id {
+        Some(preset_id) if preset_ids.contains(&preset_id) => preset_id,
+        Some(preset_id) => return Err(ApiError::NotFound(format!("No daily saying for preset: {}", preset_id))),
+        None => daily::featured_preset_id(&preset_ids, today)
+            .ok_or_else(|| ApiError::NotFound("No presets have daily sayings".to_string()))?
+            .to_string(),
     };
+    
+    // Until today's is generated the previous one keeps being served
+    let daily = state.storage.get_daily_saying(&preset_id, &language_id).await
+        .map_err(|e| ApiError::InternalError(format!("Failed to get daily saying: {}", e)))?
+        .ok_or_else(|| ApiError::NotFound(format!("No daily saying yet for preset {} in {}", preset_id, language_id)))?;
+    
+    let response = DailySayingResponse {
+        date: daily.date,
+        preset_id: &daily.preset_id,
+        language_id: &daily.language_id,
+        saying: SayingResponse::from(&daily.saying),
+    };
+    Ok(etag::json_response(&headers, &etag::compute(daily.saying.id.as_bytes()), response))
+}
 
-    // If user is rate limited, try to return a cached saying randomly
-    if is_rate_limited {
-        tracing::info!("User {} is in cooldown period, attempting to return cached saying", user_id);
-        
-        // First try to get their own last saying
-        let mut potential_saying = state.storage.get_last_saying(&user_id).await.ok().flatten();
-        
-        // If no personal saying is available, try to get any cached sayings from the system
-        if potential_saying.is_none() {
-            match state.storage.get_any_cached_sayings(5).await { // Fetch up to 5
-                Ok(sayings) if !sayings.is_empty() => {
-                    // Select one randomly
-                    potential_saying = sayings.choose(&mut rand::thread_rng()).cloned();
-                    if potential_saying.is_some() {
-                        tracing::debug!("Returning randomly selected cached saying from system during cooldown");
-                    } else {
-                        tracing::warn!("Failed to select a random saying from the fetched list for user {}", user_id);
-                    }
-                }
-                Ok(_) => {
-                    tracing::warn!("No cached sayings available for rate-limited user {}", user_id);
-                }
-                Err(err) => {
-                    tracing::error!("Error fetching cached sayings for rate-limited user {}: {}", user_id, err);
-                    // Fall through to return rate limit error
-                }
-            }
-        } else {
-            tracing::debug!("Returning user's last saying during cooldown period");
-        }
+// GET /sayings/:saying_id - Get a single saying by its ID
+pub async fn get_saying(
+    Path(saying_id): Path<String>,
+    State(state): State<Arc<AppState>>,
+    caller: Caller,
+) -> Result<Response, ApiError> {
+    let (user_id, saying) = state.storage.get_saying_by_id(&saying_id).await
+        .map_err(|e| ApiError::InternalError(format!("Failed to get saying: {}", e)))?
+        .ok_or_else(|| ApiError::NotFound(format!("No saying with ID: {}", saying_id)))?;
+    
+    // Check if the owner is allowed
+    is_owner_allowed(&state, &caller, &user_id)?;
+    check_read_limit(&state, &caller, &user_id)?;
+    check_owner(&state, &caller, &user_id, false).await?;
+    
+    Ok(Json(SayingResponse::from(saying.as_ref())).into_response())
+}
 
-        // If we found a saying (either last or random cached), return it
-        if let Some(saying) = potential_saying {
-             // Ensure the source is marked as cache
-             let cached_saying = Saying {
-                source: SayingSource::Cache,
-                ..saying
-             };
-            return Ok((StatusCode::OK, Json(SayingResponse::from(cached_saying))));
-        } else {
-            // If absolutely no saying could be returned, enforce rate limit
-            tracing::warn!("Rate limit exceeded for user {} and no cached saying found.", user_id);
-            return Err(ApiError::RateLimited("You have exceeded the rate limit and no cached saying was available.".to_string()));
-        }
+// PATCH /sayings/:saying_id - Replace a saying's text with the owner's edit, keeping the
+// earlier versions, and/or pin it
+pub async fn edit_saying(
+    Path(saying_id): Path<String>,
+    State(state): State<Arc<AppState>>,
+    caller: Caller,
+    Json(payload): Json<EditSayingRequest>,
+) -> Result<Response, ApiError> {
+    let content = payload.content.as_deref().map(str::trim);
+    if content.is_none() && payload.pinned.is_none() && payload.tags.is_none() {
+        return Err(ApiError::BadRequest("Nothing to change; set content, pinned or tags".to_string()));
     }
-
-    // Access check (moved after initial rate limit check)
-    is_user_allowed(&user_id)?;
+    if content.is_some_and(str::is_empty) {
+        return Err(ApiError::BadRequest("Content must not be empty".to_string()));
+    }
+    if content.is_some_and(|content| content.chars().count() > MAX_EDIT_CHARS) {
+        return Err(ApiError::BadRequest(format!("Content must be at most {} characters", MAX_EDIT_CHARS)));
+    }
+    let tags = payload.tags.map(parse_tags).transpose()?;
     
-    // Resolve prompt selection regardless of rate limiting
-    let (system_prompt, user_prompt, preset_id) = match (payload.prompt.clone(), payload.preset_id.clone()) {
-        // User provided their own prompt
-        (Some(prompt), _) => {
-            ("You are a helpful assistant.".to_string(), prompt, None)
-        },
-        
-        // User specified a preset
-        (None, Some(preset_id)) => {
-            let preset = state.presets.get_preset_by_id(&preset_id)
-                .ok_or_else(|| ApiError::BadRequest(format!("Preset not found: {}", preset_id)))?;
-            
-            let prompt = state.presets.random_user_prompt(&preset_id)
-                .map_err(|e| ApiError::BadRequest(format!("Failed to get prompt from preset: {}", e)))?;
-            
-            (preset.system_prompt, prompt, Some(preset_id))
-        },
-        
-        // No prompt or preset specified, try to use the selected preset for the user
-        (None, None) => {
-            // Get or initialize rate limit info for the user
-            let rate_limit_info = match state.rate_limiter.get_limit_info(&user_id).await {
-                Some(info) => info,
-                None => {
-                    // User has no rate limit info, initialize it first
-                    state.rate_limiter.reset(&user_id).await
-                        .map_err(|e| ApiError::InternalError(format!("Failed to initialize rate limit: {}", e)))?;
-                    
-                    // Now get the newly initialized rate limit info
-                    state.rate_limiter.get_limit_info(&user_id).await
-                        .ok_or_else(|| ApiError::InternalError("Failed to get rate limit info after initialization".to_string()))?
-                }
-            };
-            
-            // Get or select a preset for the user
-            let preset = state.presets.get_or_select_preset(&user_id, rate_limit_info.reset_at)
-                .map_err(|e| ApiError::InternalError(format!("Failed to select preset: {}", e)))?;
-            
-            let prompt = state.presets.random_user_prompt(&preset.id)
-                .map_err(|e| ApiError::InternalError(format!("Failed to get prompt from preset: {}", e)))?;
-            
-            (preset.system_prompt, prompt, Some(preset.id))
-        }
-    };
+    let (user_id, saying) = state.storage.get_saying_by_id(&saying_id).await
+        .map_err(|e| ApiError::InternalError(format!("Failed to get saying: {}", e)))?
+        .ok_or_else(|| ApiError::NotFound(format!("No saying with ID: {}", saying_id)))?;
+    
+    // Only the owner edits their sayings
+    is_owner_allowed(&state, &caller, &user_id)?;
+    check_owner(&state, &caller, &user_id, false).await?;
+    
+    if content.is_some() && saying.versions.len() >= MAX_SAYING_VERSIONS {
+        return Err(ApiError::BadRequest(format!("A saying keeps at most {} versions", MAX_SAYING_VERSIONS)));
+    }
+    
+    let mut edited = saying.as_ref().clone();
+    if let Some(content) = content {
+        edited.edit(content.to_string());
+    }
+    if let Some(pinned) = payload.pinned {
+        edited.pinned = pinned;
+    }
+    if let Some(tags) = tags {
+        edited.tags = tags;
+    }
+    let edited = Arc::new(edited);
+    let replaced = state.storage.replace_saying(&user_id, edited.clone()).await
+        .map_err(|e| ApiError::InternalError(format!("Failed to save saying: {}", e)))?;
+    if !replaced {
+        return Err(ApiError::NotFound(format!("No saying with ID: {}", saying_id)));
+    }
+    
+    Ok(Json(SayingResponse::from(edited.as_ref())).into_response())
+}
 
-    // Append translation instructions to system_prompt if language is not English
-    let system_prompt_with_language = if language_id != crate::languages::DEFAULT_LANGUAGE_ID {
-        let translation_prompt = crate::languages::get_translation_prompt(&language_id);
-        if !translation_prompt.is_empty() {
-            format!("{}\n\n{}", system_prompt, translation_prompt)
-        } else {
-            system_prompt
+// Normalize tags from a request, dropping repeats; any invalid tag rejects the request
+fn parse_tags(tags: Vec<String>) -> Result<Vec<String>, ApiError> {
+    let mut parsed: Vec<String> = Vec::new();
+    for tag in &tags {
+        let normalized = normalize_tag(tag).ok_or_else(|| ApiError::BadRequest(format!(
+            "Invalid tag: {} (letters, digits, '-' and '_' only, at most {} characters)", tag, MAX_TAG_CHARS
+        )))?;
+        if !parsed.contains(&normalized) {
+            parsed.push(normalized);
         }
-    } else {
-        system_prompt
+    }
+    if parsed.len() > MAX_SAYING_TAGS {
+        return Err(ApiError::BadRequest(format!("A saying may have at most {} tags", MAX_SAYING_TAGS)));
+    }
+    Ok(parsed)
+}
+
+// POST /sayings/:saying_id/feedback - Rate a saying, replacing any earlier rating
+pub async fn create_feedback(
+    Path(saying_id): Path<String>,
+    State(state): State<Arc<AppState>>,
+    caller: Caller,
+    Json(payload): Json<FeedbackRequest>,
+) -> Result<Response, ApiError> {
+    if !(1..=5).contains(&payload.score) {
+        return Err(ApiError::BadRequest("Score must be between 1 and 5".to_string()));
+    }
+    
+    let (user_id, saying) = state.storage.get_saying_by_id(&saying_id).await
+        .map_err(|e| ApiError::InternalError(format!("Failed to get saying: {}", e)))?
+        .ok_or_else(|| ApiError::NotFound(format!("No saying with ID: {}", saying_id)))?;
+    
+    // Only the owner rates their sayings
+    is_owner_allowed(&state, &caller, &user_id)?;
+    
+    let feedback = SayingFeedback {
+        saying_id,
+        user_id,
+        preset_id: saying.preset_id.clone(),
+        prompt: saying.prompt.clone(),
+        score: payload.score,
+        created_at: Utc::now(),
     };
+    state.storage.save_feedback(&feedback).await
+        .map_err(|e| ApiError::InternalError(format!("Failed to save feedback: {}", e)))?;
+    
+    Ok(Json(feedback).into_response())
+}
 
-    tracing::info!("Processing request for user '{}' with prompt: {} and preset: {:?} in language: {}", 
-                   user_id, user_prompt, preset_id, language_id);
+#[derive(Debug, Deserialize)]
+pub struct TranslateRequest {
+    pub language_id: String,
+}
 
-    // Check rate limit before proceeding with LLM
-    let can_proceed = state.rate_limiter.check(&user_id).await
-        .map_err(|e| ApiError::InternalError(format!("Failed to check rate limit: {}", e)))?;
+// POST /sayings/:saying_id/translate - Translate a stored saying, reusing an earlier translation
+pub async fn translate_saying(
+    Path(saying_id): Path<String>,
+    State(state): State<Arc<AppState>>,
+    caller: Caller,
+    Json(payload): Json<TranslateRequest>,
+) -> Result<Response, ApiError> {
+    let language = crate::languages::find_language(&payload.language_id)
+        .ok_or_else(|| ApiError::UnknownLanguage(payload.language_id.clone()))?;
     
-    if !can_proceed {
-        // This should technically not be reached if the logic above is correct, but kept as safeguard
-        tracing::warn!("Rate limit check failed unexpectedly after initial check for user {}", user_id);
-        return Err(ApiError::RateLimited("You have exceeded the rate limit for this endpoint".to_string()));
-    }
+    let (user_id, saying) = state.storage.get_saying_by_id(&saying_id).await
+        .map_err(|e| ApiError::InternalError(format!("Failed to get saying: {}", e)))?
+        .ok_or_else(|| ApiError::NotFound(format!("No saying with ID: {}", saying_id)))?;
     
-    // Rate limit allows proceeding, fetch directly from LLM
-    tracing::info!("Rate limit permits, querying LLM for prompt: {} for user {}", user_prompt, user_id);
-    let saying = fetch_from_llm(&state, &system_prompt_with_language, &user_prompt, preset_id).await?;
+    // Translating reads the saying, so it takes the same checks as GET /sayings/:saying_id
+    is_owner_allowed(&state, &caller, &user_id)?;
+    check_read_limit(&state, &caller, &user_id)?;
+    check_owner(&state, &caller, &user_id, false).await?;
     
-    // Store the saying for this user
-    if let Err(e) = state.storage.save_saying(&user_id, saying.clone()).await {
-        tracing::error!("Failed to save saying for user {}: {}", user_id, e);
-        // Continue even if saving fails
-    } else {
-        tracing::info!("Successfully saved saying for user: {}", user_id);
+    if let Some(translation) = state.storage.get_translation(&saying_id, &language.id).await
+        .map_err(|e| ApiError::InternalError(format!("Failed to get translation: {}", e)))?
+    {
+        return Ok(Json(translation).into_response());
     }
     
-    // Return the new saying
-    let response = SayingResponse::from(saying);
-    tracing::info!("Returning new saying with ID: {}", response.id);
+    // Translate from the English original; a bilingual saying already carries it
+    let original = crate::languages::english_original(&saying.content);
+    let content = if language.id == crate::languages::DEFAULT_LANGUAGE_ID {
+        original
+    } else {
+        tracing::info!("Translating saying {} into {}", saying_id, language.id);
+        translate_text(&state, &original, &language, is_sandbox_owner(&state, &user_id)).await?
+    };
     
-    Ok((StatusCode::CREATED, Json(response)))
-}
+    let translation = SayingTranslation {
+        saying_id,
+        language_id: language.id,
+        content,
+        created_at: Utc::now(),
+    };
+    state.storage.save_translation(&translation).await
+        .map_err(|e| ApiError::InternalError(format!("Failed to save translation: {}", e)))?;
+    
+    Ok(Json(translation).into_response())
+}
+
+// POST /sayings - Create a new saying
+pub async fn create_saying(
+    Query(params): Query<StatusQuery>,
+    State(state): State<Arc<AppState>>,
+    caller: Caller,
+    Json(payload): Json<SayingRequest>,
+) -> Result<Response, ApiError> {
+    let user_id = resolve_user_id(&state, &caller, params.user_id.or(payload.user_id))?;
+    
+    // Get the language ID from the query or the request body, else from the prompt
+    let (language_id, detected_language_id) =
+        request_language(&state, params.language_id.or(payload.language_id), payload.prompt.as_deref());
+    let translation_mode = params.translation_mode.or(payload.translation_mode);
+    
+    if let Some(minutes) = params.tz_offset {
+        state.rate_limiter.set_tz_offset(&user_id, minutes);
+    }
+    
+    let tags = payload.tags.map(parse_tags).transpose()?.unwrap_or_default();
+    let issued_token = check_owner(&state, &caller, &user_id, true).await?;
+    
+    let request = GenerationRequest {
+        prompt: payload.prompt,
+        preset_id: payload.preset_id,
+        language_id,
+        detected_language_id,
+        translation_mode,
+        tags,
+    };
+    
+    let response = match generate_saying(&state, &caller, &user_id, request, true).await {
+        Ok(outcome) => saying_outcome_response(outcome),
+        Err(e) => e.into_response(),
+    };
+    Ok(with_owner_token(response, issued_token))
+}
+
+fn saying_outcome_response(outcome: SayingOutcome) -> Response {
+    match outcome {
+        SayingOutcome::Cached(saying) => {
+            // Ensure the source is marked as cache
+            let response = SayingResponse {
+                source: SayingSource::Cache,
+                ..SayingResponse::from(saying.as_ref())
+            };
+            (StatusCode::OK, Json(response)).into_response()
+        }
+        SayingOutcome::Generated(saying, details) => {
+            let response = SayingResponse {
+                details: Some(details),
+                ..SayingResponse::from(saying.as_ref())
+            };
+            tracing::info!("Returning new saying with ID: {}", response.id);
+            (StatusCode::CREATED, Json(response)).into_response()
+        }
+        SayingOutcome::Queued { job_id, position } => {
+            let status_url = format!("/jobs/{}", job_id);
+            let mut response = (StatusCode::ACCEPTED, Json(QueuedResponse {
+                job_id,
+                status: JobStatus::Queued,
+                status_url: status_url.clone(),
+                position,
+            })).into_response();
+            if let Ok(location) = HeaderValue::from_str(&status_url) {
+                response.headers_mut().insert(header::LOCATION, location);
+            }
+            response
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct EstimateResponse {
+    pub user_id: String,
+    pub preset_id: Option<String>,
+    pub language_id: String,
+    pub translation_mode: TranslationMode,
+    pub model: String,
+    // The prompts as they would be sent, constraint instructions included
+    pub system_prompt: String,
+    pub user_prompt: String,
+    pub prompt_tokens: usize,
+    // The preset's length limit, otherwise ESTIMATE_COMPLETION_TOKENS
+    pub max_completion_tokens: u32,
+    pub truncated: bool,
+    // For one upstream call; None when MODEL_PRICES has no price for the model
+    pub estimated_cost_usd: Option<f64>,
+    pub quota: EstimateQuota,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EstimateQuota {
+    // Whether the request would reach the LLM rather than the cache or the queue
+    pub allowed: bool,
+    pub exempt: bool,
+    pub remaining_requests: u32,
+    pub reset_at: Option<DateTime<Utc>>,
+}
+
+// POST /sayings/estimate - Show the prompt and cost of a generation request without making it
+pub async fn estimate_saying(
+    Query(params): Query<StatusQuery>,
+    State(state): State<Arc<AppState>>,
+    caller: Caller,
+    Json(payload): Json<SayingRequest>,
+) -> Result<Json<EstimateResponse>, ApiError> {
+    let user_id = resolve_user_id(&state, &caller, params.user_id.or(payload.user_id))?;
+    let (language_id, _) = request_language(&state, params.language_id.or(payload.language_id), payload.prompt.as_deref());
+    let translation_mode = params.translation_mode.or(payload.translation_mode);
+    
+    if crate::languages::find_language(&language_id).is_none() {
+        return Err(ApiError::UnknownLanguage(language_id));
+    }
+    
+    let access = state.access.check(&caller, &user_id);
+    if let Access::Deny(reason) = access {
+        return Err(ApiError::AccessDenied(reason));
+    }
+    
+    // Same prompt selection as generation, minus storing a new preset pick
+    let (system_prompt, user_prompt, preset) = match (payload.prompt, payload.preset_id) {
+        (Some(prompt), _) => (None, prompt, None),
+        (None, Some(preset_id)) => {
+            let preset = state.presets.get_preset_by_id(&preset_id)
+                .ok_or_else(|| ApiError::PresetNotFound(preset_id.clone()))?;
+            if !preset.allows_tier(state.config.access.tier(&user_id)) {
+                return Err(ApiError::AccessDenied(format!("Preset {} requires tier {}", preset_id, preset.min_tier)));
+            }
+            check_preset_rating(&state, &preset)?;
+            let prompt = state.presets.random_user_prompt(&preset_id)
+                .map_err(|e| ApiError::BadRequest(format!("Failed to get prompt from preset: {}", e)))?;
+            (Some(preset.system_prompt.clone()), prompt, Some(preset))
+        }
+        (None, None) => {
+            let preset = state.presets.preview_preset(&state.storage, &user_id, state.config.presets.no_repeat).await
+                .map_err(|e| ApiError::InternalError(format!("Failed to select preset: {}", e)))?;
+            let prompt = state.presets.random_user_prompt(&preset.id)
+                .map_err(|e| ApiError::InternalError(format!("Failed to get prompt from preset: {}", e)))?;
+            (Some(preset.system_prompt.clone()), prompt, Some(preset))
+        }
+    };
+    
+    let preset_id = preset.as_ref().map(|preset| preset.id.clone());
+    let rendered = render_prompt(&state, system_prompt, user_prompt, preset_id.as_deref(), language_id, translation_mode)?;
+    
+    // Constraint instructions are appended by the client, as in get_saying_with_options
+    let constraints = preset.map(|preset| preset.constraints).filter(|constraints| !constraints.is_empty());
+    let system_prompt = match &constraints {
+        Some(constraints) => format!("{}\n\n{}", rendered.system_prompt.trim_end(), constraints.instructions()),
+        None => rendered.system_prompt,
+    };
+    let prompt_tokens = tokens::estimate_chat(&system_prompt, &rendered.user_prompt);
+    let translated = rendered.translation_mode == TranslationMode::Bilingual
+        && rendered.prompt_language != crate::languages::DEFAULT_LANGUAGE_ID;
+    let max_completion_tokens = constraints
+        .and_then(|constraints| constraints.max_tokens(translated))
+        .unwrap_or(state.config.openrouter.estimate_completion_tokens);
+    
+    let model = state.openrouter.current_model().to_string();
+    let estimated_cost_usd = state.config.openrouter.prices.get(&model)
+        .map(|price| tokens::cost(*price, prompt_tokens, max_completion_tokens as usize));
+    
+    // A window that has ended starts over on the next request
+    let exempt = state.exemptions.is_exempt(&user_id, caller.token.as_deref());
+    let (remaining_requests, reset_at) = match state.rate_limiter.get_limit_info(&user_id).await {
+        Some(info) if info.reset_at > state.clock.now() => (info.available(), Some(info.reset_at)),
+        _ => (state.config.rate_limit.max_requests, None),
+    };
+    let allowed = !matches!(access, Access::CachedOnly) && (exempt || state.rate_limiter.has_capacity(&user_id));
+    
+    Ok(Json(EstimateResponse {
+        user_id,
+        preset_id,
+        language_id: rendered.language_id,
+        translation_mode: rendered.translation_mode,
+        model,
+        system_prompt,
+        user_prompt: rendered.user_prompt,
+        prompt_tokens,
+        max_completion_tokens,
+        truncated: rendered.details.truncated,
+        estimated_cost_usd,
+        quota: EstimateQuota { allowed, exempt, remaining_requests, reset_at },
+    }))
+}
+
+// A freshly generated saying, a stored one served while the user is in cooldown, or a job
+// that generates it once the user has capacity again
+#[derive(Debug, Clone)]
+pub enum SayingOutcome {
+    Generated(Arc<Saying>, GenerationDetails),
+    Cached(Arc<Saying>),
+    Queued { job_id: String, position: usize },
+}
+
+// What the caller asked to generate, kept as is for queued requests
+#[derive(Debug, Clone)]
+pub struct GenerationRequest {
+    pub prompt: Option<String>,
+    pub preset_id: Option<String>,
+    pub language_id: String,
+    // Set when `language_id` was detected from the prompt rather than requested
+    pub detected_language_id: Option<String>,
+    pub translation_mode: Option<TranslationMode>,
+    pub tags: Vec<String>,
+}
+
+// The language a request names, else the one its typed prompt is written in (with
+// LANGUAGE_DETECTION_ENABLED and a reliable guess), else English. The second value is the
+// detected language, if that's where the first came from.
+pub fn request_language(state: &AppState, language_id: Option<String>, prompt: Option<&str>) -> (String, Option<String>) {
+    if let Some(language_id) = language_id {
+        return (language_id, None);
+    }
+    
+    let detected = prompt
+        .filter(|_| state.config.languages.detect_prompt_language)
+        .and_then(crate::languages::detect_language);
+    match detected {
+        Some(language_id) => (language_id.to_string(), Some(language_id.to_string())),
+        None => (crate::languages::DEFAULT_LANGUAGE_ID.to_string(), None),
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct QueuedResponse {
+    pub job_id: String,
+    pub status: JobStatus,
+    pub status_url: String,
+    // Jobs ahead of this one in the queue
+    pub position: usize,
+}
+
+// Entries kept in each user's prompt history
+const PROMPT_HISTORY_SIZE: usize = 100;
+
+// Generation flow shared by the HTTP and gRPC front ends: cooldown fallback, prompt
+// selection, rate limiting, the LLM call and persistence. With `queue` set and
+// RATE_LIMIT_MODE=queue, over-quota requests become jobs instead of being rejected.
+// Typed prompts end up in the user's prompt history whatever the outcome.
+pub async fn generate_saying(
+    state: &Arc<AppState>,
+    caller: &Caller,
+    user_id: &str,
+    request: GenerationRequest,
+    queue: bool,
+) -> Result<SayingOutcome, ApiError> {
+    let submitted = request.prompt.clone()
+        .filter(|prompt| !prompt.trim().is_empty())
+        .map(|prompt| (prompt, request.language_id.clone()));
+    
+    let result = run_generation(state, caller, user_id, request, queue).await;
+    if let Some((prompt, language_id)) = submitted {
+        record_submitted_prompt(state, user_id, prompt, language_id, &result).await;
+    }
+    result
+}
+
+// Add a typed prompt to the user's prompt history. Queued requests are recorded once their job
+// runs; callers turned away by access checks and read-only instances record nothing.
+async fn record_submitted_prompt(state: &AppState, user_id: &str, prompt: String, language_id: String, result: &Result<SayingOutcome, ApiError>) {
+    if state.config.server.read_only {
+        return;
+    }
+    
+    let (outcome, saying_id, error_code) = match result {
+        Ok(SayingOutcome::Generated(saying, _)) => (PromptOutcome::Generated, Some(saying.id.clone()), None),
+        Ok(SayingOutcome::Cached(saying)) => (PromptOutcome::Cached, Some(saying.id.clone()), None),
+        Ok(SayingOutcome::Queued { .. }) | Err(ApiError::AccessDenied(_)) => return,
+        Err(e) => (PromptOutcome::Failed, None, Some(e.code().as_str().to_string())),
+    };
+    let entry = PromptHistoryEntry {
+        saying_id,
+        error_code,
+        ..PromptHistoryEntry::new(prompt, language_id, outcome)
+    };
+    if let Err(e) = state.storage.push_prompt_history(user_id, entry, PROMPT_HISTORY_SIZE).await {
+        tracing::error!("Failed to record prompt history for user {}: {}", user_id, e);
+    }
+}
+
+async fn run_generation(
+    state: &Arc<AppState>,
+    caller: &Caller,
+    user_id: &str,
+    request: GenerationRequest,
+    queue: bool,
+) -> Result<SayingOutcome, ApiError> {
+    if state.config.server.read_only {
+        return Err(ApiError::ReadOnly);
+    }
+    let queued_request = (queue && state.config.rate_limit.mode == RateLimitMode::Queue).then(|| request.clone());
+    let GenerationRequest { prompt, preset_id, language_id, detected_language_id, translation_mode, tags } = request;
+
+    if crate::languages::find_language(&language_id).is_none() {
+        return Err(ApiError::UnknownLanguage(language_id));
+    }
+
+    // Access comes first; cached-only callers (e.g. shadow-banned) never reach the LLM
+    match state.access.check(caller, user_id) {
+        Access::Deny(reason) => return Err(ApiError::AccessDenied(reason)),
+        Access::CachedOnly => {
+            tracing::info!("User {} ({:?}) has cached-only access, serving a cached saying", user_id, caller.ip);
+            return serve_cached(state, user_id).await;
+        }
+        Access::Allow => {}
+    }
+
+    // Exempt users and service accounts skip both the cooldown and the limiter below
+    let exempt = state.exemptions.is_exempt(user_id, caller.token.as_deref());
+    if exempt {
+        tracing::info!("User {} is exempt from rate limits", user_id);
+        Metrics::incr(&state.metrics.exempt_requests);
+    }
+
+    // First check if user is in cooldown period (rate limited); a window that has ended
+    // starts over on the next check
+    let is_rate_limited = match state.rate_limiter.get_limit_info(user_id).await {
+        Some(info) => !exempt && info.available() == 0 && info.reset_at > state.clock.now(),
+        None => false, // No rate limit info yet, not limited
+    };
+
+    // If user is rate limited, queue the request or try to return a cached saying randomly
+    if is_rate_limited {
+        if let Some(outcome) = enqueue(state, caller, user_id, &queued_request) {
+            return Ok(outcome);
+        }
+        tracing::info!("User {} is in cooldown period, attempting to return cached saying", user_id);
+        return serve_cached(state, user_id).await;
+    }
+
+    // What the user typed, indexed for suggestions once a saying is generated from it
+    let typed_prompt = prompt.clone().filter(|prompt| !prompt.trim().is_empty());
+    
+    // Resolve prompt selection regardless of rate limiting
+    let (system_prompt, user_prompt, preset_id) = match (prompt, preset_id) {
+        // User provided their own prompt
+        (Some(prompt), _) => {
+            (None, prompt, None)
+        },
+        
+        // User specified a preset
+        (None, Some(preset_id)) => {
+            let preset = state.presets.get_preset_by_id(&preset_id)
+                .ok_or_else(|| ApiError::PresetNotFound(preset_id.clone()))?;
+            
+            if !preset.allows_tier(state.config.access.tier(user_id)) {
+                return Err(ApiError::AccessDenied(format!("Preset {} requires tier {}", preset_id, preset.min_tier)));
+            }
+            check_preset_rating(state, &preset)?;
+            
+            let prompt = state.presets.random_user_prompt(&preset_id)
+                .map_err(|e| ApiError::BadRequest(format!("Failed to get prompt from preset: {}", e)))?;
+            
+            (Some(preset.system_prompt), prompt, Some(preset_id))
+        },
+        
+        // No prompt or preset specified, try to use the selected preset for the user
+        (None, None) => {
+            // Get or initialize rate limit info for the user
+            let rate_limit_info = match state.rate_limiter.get_limit_info(user_id).await {
+                Some(info) => info,
+                None => {
+                    // User has no rate limit info, initialize it first
+                    state.rate_limiter.reset(user_id).await
+                        .map_err(|e| ApiError::InternalError(format!("Failed to initialize rate limit: {}", e)))?;
+                    
+                    // Now get the newly initialized rate limit info
+                    state.rate_limiter.get_limit_info(user_id).await
+                        .ok_or_else(|| ApiError::InternalError("Failed to get rate limit info after initialization".to_string()))?
+                }
+            };
+            
+            // Get or select a preset for the user
+            let preset = state.presets.get_or_select_preset(&state.storage, user_id, rate_limit_info.reset_at, state.config.presets.no_repeat).await
+                .map_err(|e| ApiError::InternalError(format!("Failed to select preset: {}", e)))?;
+            
+            let prompt = state.presets.random_user_prompt(&preset.id)
+                .map_err(|e| ApiError::InternalError(format!("Failed to get prompt from preset: {}", e)))?;
+            
+            (Some(preset.system_prompt), prompt, Some(preset.id))
+        }
+    };
+
+    let RenderedPrompt { system_prompt: system_prompt_with_language, user_prompt, language_id, prompt_language, translation_mode, details } =
+        render_prompt(state, system_prompt, user_prompt, preset_id.as_deref(), language_id, translation_mode)?;
+
+    // Freeform prompts are kept for abuse review
+    if preset_id.is_none() && state.config.freeform.audit_log {
+        let entry = FreeformPromptEntry::new(user_id.to_string(), user_prompt.clone());
+        if let Err(e) = state.storage.log_freeform_prompt(&entry, state.config.freeform.audit_log_max_entries).await {
+            tracing::error!("Failed to log freeform prompt for user {}: {}", user_id, e);
+        }
+    }
+
+    tracing::info!("Processing request for user '{}' with prompt: {} and preset: {:?} in language: {}", 
+                   user_id, user_prompt, preset_id, language_id);
+
+    // Check rate limit before proceeding with LLM
+    let check = if exempt {
+        RateLimitCheck::Allowed
+    } else {
+        state.rate_limiter.check(user_id).await
+            .map_err(|e| ApiError::InternalError(format!("Failed to check rate limit: {}", e)))?
+    };
+    
+    match check {
+        RateLimitCheck::Allowed => {}
+        RateLimitCheck::Exhausted => {
+            // This should technically not be reached if the logic above is correct, but kept as safeguard
+            tracing::warn!("Rate limit check failed unexpectedly after initial check for user {}", user_id);
+            if let Some(outcome) = enqueue(state, caller, user_id, &queued_request) {
+                return Ok(outcome);
+            }
+            Metrics::incr(&state.metrics.rate_limited);
+            return Err(ApiError::RateLimited {
+                message: "You have exceeded the rate limit for this endpoint".to_string(),
+                limit: RateLimitState::for_user(state, user_id, None).await,
+            });
+        }
+        RateLimitCheck::Burst { retry_after } => {
+            tracing::info!("User {} hit the burst limit, retry in {}s", user_id, retry_after);
+            if let Some(outcome) = enqueue(state, caller, user_id, &queued_request) {
+                return Ok(outcome);
+            }
+            Metrics::incr(&state.metrics.rate_limited);
+            return Err(ApiError::RateLimited {
+                message: format!("Too many requests in a short time, try again in {} seconds", retry_after),
+                limit: RateLimitState::for_user(state, user_id, Some(retry_after)).await,
+            });
+        }
+        RateLimitCheck::Full { retry_after } => {
+            tracing::warn!("Rate limiter is full, refusing new user {}", user_id);
+            Metrics::incr(&state.metrics.rate_limited);
+            return Err(limiter_full(state, user_id, retry_after).await);
+        }
+    }
+    
+    // Rate limit allows proceeding, fetch directly from LLM
+    tracing::info!("Rate limit permits, querying LLM for prompt: {} for user {}", user_prompt, user_id);
+    let translated = translation_mode == TranslationMode::Bilingual && prompt_language != crate::languages::DEFAULT_LANGUAGE_ID;
+    let tier = state.config.access.tier(user_id);
+    let sandbox = state.config.access.is_sandbox(user_id);
+    let saying = fetch_from_llm(state, &system_prompt_with_language, &user_prompt, preset_id, translated, tier, sandbox).await?;
+    // Sandbox users never cause upstream calls, not even in the background
+    if !sandbox {
+        spawn_shadow_call(state, &system_prompt_with_language, &user_prompt, translated, &saying);
+    }
+    let content = if prompt_language == language_id {
+        let content = crate::languages::parse_response(saying.content, &language_id, translation_mode);
+        // Only the bilingual format carries the English original to check the translation against
+        if translated {
+            let translation = crate::languages::parse_response(content.clone(), &language_id, TranslationMode::NativeOnly);
+            check_glossary(state, &crate::languages::english_original(&content), &translation, &language_id);
+        }
+        content
+    } else {
+        let language = crate::languages::get_language_by_id(&language_id);
+        let translation = translate_text(state, &saying.content, &language, sandbox).await?;
+        crate::languages::compose(saying.content, translation, translation_mode)
+    };
+    let prompt_hash = crate::models::prompt_hash(&system_prompt_with_language, &user_prompt, &language_id, saying.model.as_deref().unwrap_or_default());
+    let saying = Arc::new(Saying {
+        content,
+        language_id: Some(language_id),
+        detected_language_id,
+        prompt_hash: Some(prompt_hash),
+        tags,
+        ..saying
+    });
+    Metrics::incr(&state.metrics.sayings_generated);
+    
+    // Store the saying for this user
+    if let Err(e) = state.storage.save_saying(user_id, saying.clone()).await {
+        tracing::error!("Failed to save saying for user {}: {}", user_id, e);
+        // Continue even if saving fails
+    } else {
+        tracing::info!("Successfully saved saying for user: {}", user_id);
+        if !sandbox {
+            crate::quality::spawn_scoring(state, saying.clone(), &system_prompt_with_language);
+        }
+    }
+    
+    if let Some(prompt) = &typed_prompt {
+        if let Err(e) = state.storage.record_prompt(user_id, prompt, saying.created_at).await {
+            tracing::error!("Failed to index prompt for user {}: {}", user_id, e);
+        }
+    }
+    
+    if let Some(preset_id) = &saying.preset_id {
+        if let Err(e) = state.storage.record_preset_use(preset_id, saying.created_at).await {
+            tracing::error!("Failed to record usage of preset {}: {}", preset_id, e);
+        }
+    }
+    
+    Ok(SayingOutcome::Generated(saying, details))
+}
+
+// Put an over-quota request in the generation queue; None when queueing is off or the queue
+// (or the user's share of it) is full
+fn enqueue(state: &AppState, caller: &Caller, user_id: &str, request: &Option<GenerationRequest>) -> Option<SayingOutcome> {
+    let request = request.clone()?;
+    match state.queue.push(user_id, caller.clone(), request) {
+        Some((job_id, position)) => {
+            tracing::info!("User {} is over the rate limit, queued job {} at position {}", user_id, job_id, position);
+            Metrics::incr(&state.metrics.queued_requests);
+            Some(SayingOutcome::Queued { job_id, position })
+        }
+        None => {
+            tracing::warn!("Generation queue is full for user {}, rejecting over-quota request", user_id);
+            None
+        }
+    }
+}
+
+// Answer from stored sayings instead of the LLM: the user's own last saying, or a random one
+async fn serve_cached(state: &AppState, user_id: &str) -> Result<SayingOutcome, ApiError> {
+    // First try to get their own last saying
+    let mut potential_saying = state.storage.get_last_saying(user_id).await.ok().flatten();
+    
+    // If no personal saying is available, try to get any cached sayings from the system
+    if potential_saying.is_none() {
+        match state.storage.get_any_cached_sayings(5).await { // Fetch up to 5
+            Ok(sayings) if !sayings.is_empty() => {
+                // Select one randomly
+                potential_saying = sayings.choose(&mut rand::thread_rng()).cloned();
+                if potential_saying.is_some() {
+                    tracing::debug!("Returning randomly selected cached saying from system during cooldown");
+                } else {
+                    tracing::warn!("Failed to select a random saying from the fetched list for user {}", user_id);
+                }
+            }
+            Ok(_) => {
+                tracing::warn!("No cached sayings available for rate-limited user {}", user_id);
+            }
+            Err(err) => {
+                tracing::error!("Error fetching cached sayings for rate-limited user {}: {}", user_id, err);
+                // Fall through to return rate limit error
+            }
+        }
+    } else {
+        tracing::debug!("Returning user's last saying during cooldown period");
+    }
+
+    // If we found a saying (either last or random cached), return it
+    if let Some(saying) = potential_saying {
+        Metrics::incr(&state.metrics.cache_served);
+        state.rate_limiter.record_cache_served(user_id);
+        Ok(SayingOutcome::Cached(saying))
+    } else {
+        // If absolutely no saying could be returned, enforce rate limit
+        tracing::warn!("Rate limit exceeded for user {} and no cached saying found.", user_id);
+        Metrics::incr(&state.metrics.rate_limited);
+        Err(ApiError::RateLimited {
+            message: "You have exceeded the rate limit and no cached saying was available.".to_string(),
+            limit: RateLimitState::for_user(state, user_id, None).await,
+        })
+    }
+}
+
+// Translate English text with the configured translator, checking the result against the glossary
+async fn translate_text(state: &AppState, english: &str, language: &Language, sandbox: bool) -> Result<String, ApiError> {
+    if sandbox {
+        return Ok(format!("A mock {} translation of: {}", language.name, english));
+    }
+
+    let translation = state.translator.translate(english, language).await
+        .map_err(|e| {
+            tracing::error!("Translation into {} with {} failed: {}", language.id, state.translator.name(), e);
+            Metrics::incr(&state.metrics.upstream_errors);
+            if e.is::<UpstreamError>() {
+                ApiError::from_upstream(e)
+            } else {
+                ApiError::InternalError(format!("Failed to translate saying: {}", e))
+            }
+        })?;
+
+    check_glossary(state, english, &translation, &language.id);
+    Ok(translation)
+}
+
+// Report glossary terms a translation didn't use, when validation is on. The translation is
+// still served; misses are logged and counted for review.
+fn check_glossary(state: &AppState, original: &str, translation: &str, language_id: &str) {
+    if !state.config.languages.validate_glossary {
+        return;
+    }
+
+    let missed = state.glossary.violations(original, translation, language_id);
+    if !missed.is_empty() {
+        tracing::warn!("Translation into {} missed glossary terms: {}", language_id, missed.join(", "));
+        Metrics::incr(&state.metrics.glossary_violations);
+    }
+}
+
+// The prompt as sent upstream, with translation instructions appended and token budgets enforced
+struct RenderedPrompt {
+    system_prompt: String,
+    user_prompt: String,
+    // Language the saying is stored in
+    language_id: String,
+    // Language the model is asked to write in
+    prompt_language: String,
+    translation_mode: TranslationMode,
+    details: GenerationDetails,
+}
+
+fn render_prompt(
+    state: &AppState,
+    system_prompt: Option<String>,
+    user_prompt: String,
+    preset_id: Option<&str>,
+    language_id: String,
+    translation_mode: Option<TranslationMode>,
+) -> Result<RenderedPrompt, ApiError> {
+    // The request's translation mode wins over the preset's
+    let translation_mode = translation_mode
+        .or_else(|| preset_id
+            .and_then(|id| state.presets.get_preset_by_id(id))
+            .and_then(|preset| preset.translation_mode))
+        .unwrap_or_default();
+    // English-only sayings are stored as English
+    let language_id = if translation_mode == TranslationMode::EnglishOnly {
+        crate::languages::DEFAULT_LANGUAGE_ID.to_string()
+    } else {
+        language_id
+    };
+    // Freeform prompts (no preset system prompt) get the operator's one for the saying's language
+    let system_prompt = system_prompt
+        .unwrap_or_else(|| state.config.freeform.system_prompt_for(&language_id).to_string());
+
+    // With a translation service the model only writes English, which is translated afterwards
+    let prompt_language = if state.translator.translates_in_prompt() {
+        language_id.clone()
+    } else {
+        crate::languages::DEFAULT_LANGUAGE_ID.to_string()
+    };
+
+    // Append the persona, then translation instructions if the language is not English
+    let with_instructions = |system_prompt: String| crate::languages::with_translation_prompt(
+        state.config.branding.apply(system_prompt),
+        &prompt_language,
+        translation_mode,
+        &state.glossary,
+    );
+
+    // Enforce the token budgets before the request costs the user anything. The system prompt
+    // is cut before the instructions are appended, so they are never what gets dropped.
+    let limits = &state.config.prompt_limits;
+    let (user_prompt, user_truncated) = fit_token_budget(user_prompt, limits.max_user_tokens, limits.overflow, "Prompt")?;
+    let full_system_prompt = with_instructions(system_prompt.clone());
+    let instruction_tokens = tokens::count(&full_system_prompt).saturating_sub(tokens::count(&system_prompt));
+    // Instructions that fill the whole budget would leave nothing of the prompt itself
+    let mut budget = limits.max_system_tokens.checked_sub(instruction_tokens)
+        .filter(|budget| *budget > 0)
+        .ok_or_else(|| instructions_too_long(instruction_tokens, limits.max_system_tokens))?;
+    let (system_prompt, system_truncated) = loop {
+        let (base, truncated) = fit_token_budget(system_prompt.clone(), budget, limits.overflow, "System prompt")?;
+        let assembled = if truncated { with_instructions(base) } else { full_system_prompt.clone() };
+
+        // Tokens can merge differently where the parts meet, so check what is actually sent
+        let total = tokens::count(&assembled);
+        if total <= limits.max_system_tokens {
+            break (assembled, truncated);
+        }
+        budget = budget.saturating_sub(total - limits.max_system_tokens);
+        if budget == 0 {
+            return Err(instructions_too_long(instruction_tokens, limits.max_system_tokens));
+        }
+    };
+    let details = GenerationDetails {
+        prompt_tokens: tokens::estimate_chat(&system_prompt, &user_prompt),
+        truncated: user_truncated || system_truncated,
+    };
+
+    Ok(RenderedPrompt { system_prompt, user_prompt, language_id, prompt_language, translation_mode, details })
+}
+
+fn instructions_too_long(instruction_tokens: usize, max_tokens: usize) -> ApiError {
+    ApiError::PromptTooLong(format!(
+        "System prompt instructions alone are about {} tokens, leaving no room within the limit of {}",
+        instruction_tokens, max_tokens
+    ))
+}
+
+// Reject or truncate text over its token budget; the flag tells whether it was cut
+fn fit_token_budget(text: String, max_tokens: usize, overflow: PromptOverflow, what: &str) -> Result<(String, bool), ApiError> {
+    let Some(truncated) = tokens::truncate(&text, max_tokens) else {
+        return Ok((text, false));
+    };
+
+    match overflow {
+        PromptOverflow::Reject => Err(ApiError::PromptTooLong(format!(
+            "{} is too long: about {} tokens, the limit is {}",
+            what, tokens::count(&text), max_tokens
+        ))),
+        PromptOverflow::Truncate => {
+            tracing::warn!("{} truncated to {} tokens", what, max_tokens);
+            Ok((truncated, true))
+        }
+    }
+}
+
+// Helper function to fetch from LLM
+async fn fetch_from_llm(
+    state: &Arc<AppState>,
+    system_prompt: &str,
+    user_prompt: &str,
+    preset_id: Option<String>,
+    translated: bool,
+    tier: u32,
+    sandbox: bool,
+) -> Result<Saying, ApiError> {
+    let options = GenerationOptions {
+        mock: sandbox,
+        ..generation_options(state, preset_id.as_deref(), translated, tier)
+    };
+    let saying = state.openrouter.get_saying_with_options(system_prompt, user_prompt, &options).await
+        .map_err(|e| {
+            tracing::error!("OpenRouter API error: {}", e);
+            Metrics::incr(&state.metrics.upstream_errors);
+            ApiError::from_upstream(e)
+        })?;
+    
+    // Set preset_id if available
+    let saying_with_preset = Saying {
+        preset_id,
+        ..saying
+    };
+    
+    Ok(saying_with_preset)
+}
+
+// Presets may pin upstream providers (e.g. for compliance) and constrain the answer
+fn generation_options(state: &AppState, preset_id: Option<&str>, translated: bool, tier: u32) -> GenerationOptions {
+    let preset = preset_id.and_then(|id| state.presets.get_preset_by_id(id));
+    GenerationOptions {
+        provider: preset.as_ref().and_then(|preset| preset.provider.clone()),
+        constraints: preset.map(|preset| preset.constraints),
+        translated,
+        priority: tier,
+        model: None,
+        mock: false,
+    }
+}
+
+// For SHADOW_SAMPLE_PERCENT of generations, ask SHADOW_MODEL the same thing in the background
+// and store its answer next to the one the user got. The user never waits for or sees it.
+fn spawn_shadow_call(state: &Arc<AppState>, system_prompt: &str, user_prompt: &str, translated: bool, primary: &Saying) {
+    let shadow = &state.config.openrouter.shadow;
+    let Some(model) = shadow.model.clone() else {
+        return;
+    };
+    if rand::thread_rng().gen_range(0..100) >= shadow.sample_percent {
+        return;
+    }
+
+    // Background work doesn't jump the line, so it waits as tier 0
+    let options = GenerationOptions {
+        model: Some(model.clone()),
+        ..generation_options(state, primary.preset_id.as_deref(), translated, 0)
+    };
+    let mut output = ShadowOutput {
+        id: uuid::Uuid::new_v4().to_string(),
+        saying_id: primary.id.clone(),
+        preset_id: primary.preset_id.clone(),
+        prompt: user_prompt.to_string(),
+        primary_model: primary.model.clone(),
+        primary_content: primary.content.clone(),
+        shadow_model: model,
+        shadow_content: None,
+        shadow_error: None,
+        shadow_latency_ms: 0,
+        created_at: Utc::now(),
+    };
+    let state = state.clone();
+    let system_prompt = system_prompt.to_string();
+
+    tokio::spawn(async move {
+        let started = std::time::Instant::now();
+        match state.openrouter.get_saying_with_options(&system_prompt, &output.prompt, &options).await {
+            Ok(saying) => {
+                // Regenerations of unusable output may have gone to the fallback model
+                output.shadow_model = saying.model.unwrap_or(output.shadow_model);
+                output.shadow_content = Some(saying.content);
+            }
+            Err(e) => output.shadow_error = Some(e.to_string()),
+        }
+        output.shadow_latency_ms = started.elapsed().as_millis() as u64;
+
+        if let Err(e) = state.storage.save_shadow_output(&output).await {
+            tracing::error!("Failed to save shadow output for saying {}: {}", output.saying_id, e);
+        }
+    });
+}
+
+// GET /users/:user_id/status - Get user status
+pub async fn get_user_status(
+    Path(user_id): Path<String>,
+    Query(params): Query<UserStatusQuery>,
+    State(state): State<Arc<AppState>>,
+    caller: Caller,
+) -> Result<Response, ApiError> {
+    // Check if user is allowed
+    is_user_allowed(&state, &caller, &user_id)?;
+    check_read_limit(&state, &caller, &user_id)?;
+    check_owner(&state, &caller, &user_id, false).await?;
+    
+    if let Some(minutes) = params.tz_offset {
+        state.rate_limiter.set_tz_offset(&user_id, minutes);
+    }
+    
+    let latest = state.storage.get_sayings(&user_id, 1).await
+        .map_err(|e| ApiError::InternalError(format!("Failed to get sayings: {}", e)))?;
+    let response = user_status(&state, &user_id, &latest, params.tz_offset).await?;
+    
+    Ok(Json(response).into_response())
+}
+
+// GET /users/:user_id/status/wait - Hold the request until the user can generate again or a
+// daily saying is published (or the timeout passes), then return their status
+pub async fn wait_for_user_status(
+    Path(user_id): Path<String>,
+    Query(params): Query<StatusWaitQuery>,
+    State(state): State<Arc<AppState>>,
+    caller: Caller,
+) -> Result<Response, ApiError> {
+    is_user_allowed(&state, &caller, &user_id)?;
+    check_read_limit(&state, &caller, &user_id)?;
+    check_owner(&state, &caller, &user_id, false).await?;
+    
+    if let Some(minutes) = params.tz_offset {
+        state.rate_limiter.set_tz_offset(&user_id, minutes);
+    }
+    let timeout = std::time::Duration::from_secs(params.timeout.unwrap_or(DEFAULT_WAIT_SECONDS).clamp(1, MAX_WAIT_SECONDS));
+    
+    // Registered before the quota is checked, so a saying published in between still counts
+    let published = state.daily_published.notified();
+    tokio::pin!(published);
+    published.as_mut().enable();
+    
+    let blocked_until = state.rate_limiter.get_limit_info(&user_id).await
+        .filter(|info| info.available() == 0)
+        .map(|info| info.reset_at)
+        .filter(|reset_at| *reset_at > state.clock.now());
+    let event = match blocked_until {
+        None => WaitEvent::Ready,
+        Some(reset_at) => {
+            let until_reset = (reset_at - state.clock.now()).to_std().unwrap_or_default();
+            let wait = async {
+                tokio::select! {
+                    _ = tokio::time::sleep(until_reset) => WaitEvent::WindowReset,
+                    _ = &mut published => WaitEvent::DailySaying,
+                }
+            };
+            tokio::time::timeout(timeout, wait).await.unwrap_or(WaitEvent::Timeout)
+        }
+    };
+    
+    let latest = state.storage.get_sayings(&user_id, 1).await
+        .map_err(|e| ApiError::InternalError(format!("Failed to get sayings: {}", e)))?;
+    let status = user_status(&state, &user_id, &latest, params.tz_offset).await?;
+    
+    Ok(Json(StatusWaitResponse { event, status }).into_response())
+}
+
+// POST /users/status - Get the status of several users in one call (operators only)
+pub async fn bulk_user_status(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    caller: Caller,
+    Json(payload): Json<BulkStatusRequest>,
+) -> Result<Response, ApiError> {
+    require_operator(&state, &headers, &caller)?;
+    
+    let mut user_ids = payload.user_ids;
+    let mut seen = HashSet::new();
+    user_ids.retain(|user_id| seen.insert(user_id.clone()));
+    if user_ids.is_empty() || user_ids.len() > MAX_BULK_STATUS_USERS {
+        return Err(ApiError::BadRequest(format!("Provide between 1 and {} user IDs", MAX_BULK_STATUS_USERS)));
+    }
+    
+    // Every latest saying is loaded before any status is built, since the statuses borrow from them
+    let mut latest = Vec::with_capacity(user_ids.len());
+    for user_id in user_ids {
+        let sayings = state.storage.get_sayings(&user_id, 1).await
+            .map_err(|e| ApiError::InternalError(format!("Failed to get sayings: {}", e)))?;
+        latest.push((user_id, sayings));
+    }
+    
+    let mut statuses = Vec::with_capacity(latest.len());
+    for (user_id, sayings) in &latest {
+        statuses.push(user_status(&state, user_id, sayings, None).await?);
+    }
+    
+    Ok(Json(json!({ "statuses": statuses })).into_response())
+}
+
+// The admin token, or under ACCESS_POLICY=token a service-account token that may act as any user (`*`)
+fn require_operator(state: &AppState, headers: &HeaderMap, caller: &Caller) -> Result<(), ApiError> {
+    let service_account = state.config.access.policy == AccessPolicyKind::Token
+        && caller.token.as_ref()
+            .and_then(|token| state.config.access.tokens.get(token))
+            .is_some_and(|user_id| user_id == "*");
+    if service_account {
+        return Ok(());
+    }
+    
+    if state.config.server.admin_token.is_none() {
+        return Err(ApiError::AccessDenied("The admin token or a service-account token is required".to_string()));
+    }
+    admin::require_admin(state, headers, None)
+}
+
+// What /users/:user_id/status reports, given the user's newest saying if they have one.
+// Streaks come from the stats aggregate and are counted in the given timezone, UTC without one.
+async fn user_status<'a>(
+    state: &AppState,
+    user_id: &str,
+    latest: &'a [Arc<Saying>],
+    tz_offset: Option<i32>,
+) -> Result<UserStatusResponse<'a>, ApiError> {
+    let stats = state.storage.get_user_stats(user_id).await
+        .map_err(|e| ApiError::InternalError(format!("Failed to get user stats: {}", e)))?;
+    let streak = stats.streak(streaks::offset_from_minutes(tz_offset.unwrap_or(0)), state.clock.now());
+    let total_sayings = stats.sayings;
+    
+    // Check rate limit for the user; a window that has ended starts over on the next request
+    let rate_limit_info = match state.rate_limiter.get_limit_info(user_id).await.filter(|info| info.reset_at > state.clock.now()) {
+        Some(info) => info,
+        None => {
+            // User has no running window, return default values
+            // Show a pinned preset, otherwise try to get a default one
+            let selected_preset = state.presets.current_selection(user_id)
+                .map(Ok)
+                .unwrap_or_else(|| state.presets.get_default_preset())
+                .map(|preset| Some(PresetResponse::from(preset)))
+                .unwrap_or_else(|e| {
+                    tracing::error!("Failed to get default preset: {}", e);
+                    None
+                });
+            
+            return Ok(UserStatusResponse {
+                user_id: user_id.to_string(),
+                can_query: !state.config.server.read_only,
+                remaining_requests: state.config.rate_limit.max_requests,
+                reset_at: None,
+                last_saying: None,
+                selected_preset,
+                streak,
+                total_sayings,
+                cache_served: 0,
+                tokens_used: 0,
+                features: state.flags.enabled_for(user_id),
+            });
+        }
+    };
+    
+    let last_saying = latest.first();
+    
+    let since = state.rate_limiter.window_start(&rate_limit_info);
+    let tokens_used = state.storage.tokens_used_since(user_id, since).await
+        .map_err(|e| ApiError::InternalError(format!("Failed to count tokens used: {}", e)))?;
+    
+    // Get or select a preset for the user if they can query; a read-only instance only previews it
+    let selected_preset = if rate_limit_info.available() > 0 {
+        let no_repeat = state.config.presets.no_repeat;
+        let preset = if state.config.server.read_only {
+            state.presets.preview_preset(&state.storage, user_id, no_repeat).await
+        } else {
+            state.presets.get_or_select_preset(&state.storage, user_id, rate_limit_info.reset_at, no_repeat).await
+        };
+        preset
+            .map(|preset| Some(PresetResponse::from(preset)))
+            .unwrap_or_else(|e| {
+                tracing::error!("Failed to select preset: {}", e);
+                None
+            })
+    } else {
+        None
+    };
+    
+    Ok(UserStatusResponse {
+        user_id: user_id.to_string(),
+        can_query: rate_limit_info.available() > 0 && !state.config.server.read_only,
+        remaining_requests: rate_limit_info.available(),
+        reset_at: Some(rate_limit_info.reset_at),
+        last_saying: last_saying.map(|saying| SayingResponse::from(saying.as_ref())),
+        selected_preset,
+        streak,
+        total_sayings,
+        cache_served: rate_limit_info.cache_served,
+        tokens_used,
+        features: state.flags.enabled_for(user_id),
+    })
+}
+
+// GET /presets - Get all available presets
+pub async fn get_presets(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<PresetsQuery>,
+    headers: HeaderMap,
+    caller: Caller,
+) -> Result<Response, ApiError> {
+    let tier = caller_tier(&state, &caller, query.user_id.as_deref())?;
+    let tags = comma_list(query.tags.as_deref());
+    let fields = comma_list(query.fields.as_deref());
+    if let Some(unknown) = fields.iter().find(|field| !PRESET_FIELDS.contains(field)) {
+        return Err(ApiError::BadRequest(format!("Unknown preset field: {}", unknown)));
+    }
+    
+    let (offset, limit) = (query.offset.unwrap_or(0), query.limit.unwrap_or(usize::MAX));
+    let popular = match query.sort.as_deref() {
+        None => false,
+        Some("popular") => true,
+        Some(other) => return Err(ApiError::BadRequest(format!("Unknown sort order: {}", other))),
+    };
+    
+    let (total, presets) = if popular {
+        let sayings: HashMap<String, u64> = state.storage.list_preset_stats().await
+            .map_err(|e| ApiError::InternalError(format!("Failed to get preset stats: {}", e)))?
+            .into_iter()
+            .map(|stats| (stats.preset_id, stats.sayings))
+            .collect();
+        let (total, mut presets) = state.presets.find_presets(&tags, tier, 0, usize::MAX);
+        presets.sort_by_key(|preset| Reverse(sayings.get(&preset.id).copied().unwrap_or(0)));
+        (total, presets.into_iter().skip(offset).take(limit).collect())
+    } else {
+        state.presets.find_presets(&tags, tier, offset, limit)
+    };
+    
+    let response = presets.into_iter()
+        .map(|preset| {
+            let mut value = serde_json::to_value(PresetResponse::from(preset))
+                .map_err(|e| ApiError::InternalError(format!("Failed to serialize preset: {}", e)))?;
+            if let (false, Some(object)) = (fields.is_empty(), value.as_object_mut()) {
+                object.retain(|key, _| fields.contains(&key.as_str()));
+            }
+            Ok(value)
+        })
+        .collect::<Result<Vec<_>, ApiError>>()?;
+    
+    // Otherwise the body depends only on the catalog, the query string and the user's tier
+    let mut response = if popular {
+        Json(response).into_response()
+    } else {
+        let etag = etag::compute(format!("{}:{}", state.presets.etag(), tier).as_bytes());
+        // A user's tier shapes the list, so it mustn't be cached for anyone else
+        match query.user_id {
+            Some(_) => etag::user_json_response(&headers, &etag, response),
+            None => etag::json_response(&headers, &etag, response),
+        }
+    };
+    response.headers_mut().insert("x-total-count", total.into());
+    Ok(response)
+}
+
+// Tier of the user a preset request is made for; anonymous requests are tier 0
+fn caller_tier(state: &AppState, caller: &Caller, user_id: Option<&str>) -> Result<u32, ApiError> {
+    match user_id {
+        Some(user_id) => {
+            is_user_allowed(state, caller, user_id)?;
+            Ok(state.config.access.tier(user_id))
+        }
+        None => Ok(0),
+    }
+}
+
+// Presets rated above PRESETS_MAX_RATING are refused even by ID
+fn check_preset_rating(state: &AppState, preset: &Preset) -> Result<(), ApiError> {
+    if state.presets.allows_rating(preset) {
+        return Ok(());
+    }
+    Err(ApiError::AccessDenied(format!(
+        "Preset {} is rated {}, above this deployment's {} limit",
+        preset.id,
+        preset.rating.as_str(),
+        state.presets.max_rating().as_str(),
+    )))
+}
+
+// Split a comma-separated query parameter, ignoring blanks
+fn comma_list(value: Option<&str>) -> Vec<&str> {
+    value
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+// GET /presets/:preset_id - Get a specific preset
+pub async fn get_preset(
+    Path(preset_id): Path<String>,
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<PresetQuery>,
+    headers: HeaderMap,
+    caller: Caller,
+) -> Result<Response, ApiError> {
+    // Hidden presets are served here, tiered ones only to users who may use them
+    let tier = caller_tier(&state, &caller, query.user_id.as_deref())?;
+    let preset = state.presets.get_preset_by_id(&preset_id)
+        .filter(|preset| preset.allows_tier(tier) && state.presets.allows_rating(preset))
+        .ok_or_else(|| ApiError::PresetNotFound(preset_id.clone()))?;
+    
+    let response = PresetResponse::from(preset);
+    Ok(match query.user_id {
+        Some(_) => etag::user_json_response(&headers, &state.presets.etag(), response),
+        None => etag::json_response(&headers, &state.presets.etag(), response),
+    })
+}
+
+// POST /users/:user_id/preset - Pin the user's preset for the current window
+pub async fn pin_preset(
+    Path(user_id): Path<String>,
+    State(state): State<Arc<AppState>>,
+    caller: Caller,
+    Json(payload): Json<PinPresetRequest>,
+) -> Result<Response, ApiError> {
+    // Check if user is allowed
+    is_user_allowed(&state, &caller, &user_id)?;
+    
+    // Hidden presets may be pinned by ID, tiered ones only by users who may use them
+    let tier = state.config.access.tier(&user_id);
+    let preset = state.presets.get_preset_by_id(&payload.preset_id)
+        .filter(|preset| preset.allows_tier(tier) && state.presets.allows_rating(preset))
+        .ok_or_else(|| ApiError::PresetNotFound(payload.preset_id.clone()))?;
+    
+    let reset_at = state.rate_limiter.current_reset_at(&user_id).await;
+    state.presets.pin_preset(&state.storage, &user_id, preset.clone(), reset_at, state.config.presets.no_repeat).await
+        .map_err(|e| ApiError::InternalError(format!("Failed to pin preset: {}", e)))?;
+    
+    Ok(Json(PresetResponse::from(preset)).into_response())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SayingsQuery {
+    pub user_id: Option<String>,
+    pub limit: Option<usize>,
+    // Only list pinned (true) or unpinned (false) sayings
+    pub pinned: Option<bool>,
+    // Only list sayings with this tag
+    pub tag: Option<String>,
+}
+
+// Most suggestions one GET /prompts/suggest returns
+const MAX_PROMPT_SUGGESTIONS: usize = 50;
+
+#[derive(Debug, Deserialize)]
+pub struct PromptSuggestQuery {
+    pub user_id: Option<String>,
+    // What the user has typed so far
+    pub q: Option<String>,
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PromptSuggestion {
+    pub prompt: String,
+    // "history" for the user's own earlier prompts, "preset" for preset prompts
+    pub source: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preset_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+// GET /prompts/suggest - Prompts starting with what the user has typed: their own earlier
+// prompts (most recent first), then the prompts of presets they can use
+pub async fn suggest_prompts(
+    Query(params): Query<PromptSuggestQuery>,
+    State(state): State<Arc<AppState>>,
+    caller: Caller,
+) -> Result<Response, ApiError> {
+    let user_id = resolve_user_id(&state, &caller, params.user_id)?;
+    
+    is_user_allowed(&state, &caller, &user_id)?;
+    check_read_limit(&state, &caller, &user_id)?;
+    check_owner(&state, &caller, &user_id, false).await?;
+    
+    let limit = params.limit.unwrap_or(10).min(MAX_PROMPT_SUGGESTIONS);
+    let prefix = prompt_search_key(params.q.as_deref().unwrap_or_default());
+    
+    let history = state.storage.suggest_prompts(&user_id, &prefix, limit).await
+        .map_err(|e| ApiError::InternalError(format!("Failed to get prompt suggestions: {}", e)))?;
+    let mut seen: HashSet<String> = history.iter().map(|entry| prompt_search_key(&entry.prompt)).collect();
+    let mut suggestions: Vec<PromptSuggestion> = history.into_iter()
+        .map(|entry| PromptSuggestion {
+            prompt: entry.prompt,
+            source: "history",
+            preset_id: None,
+            last_used_at: Some(entry.last_used_at),
+        })
+        .collect();
+    
+    let tier = state.config.access.tier(&user_id);
+    for (preset_id, prompt) in state.presets.suggest_user_prompts(&prefix, tier, usize::MAX) {
+        if suggestions.len() >= limit {
+            break;
+        }
+        if seen.insert(prompt_search_key(&prompt)) {
+            suggestions.push(PromptSuggestion { prompt, source: "preset", preset_id: Some(preset_id), last_used_at: None });
+        }
+    }
+    
+    Ok(Json(suggestions).into_response())
+}
+
+// GET /languages - Get all available languages
+pub async fn get_languages(headers: HeaderMap) -> Response {
+    let languages = get_all_languages();
+    etag::json_response(&headers, crate::languages::etag(), languages)
+}
+
+// GET /languages/:language_id - Get a specific language by ID
+pub async fn get_language(
+    Path(language_id): Path<String>,
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    let language = resolve_language(&language_id, &state.config.languages.fallbacks);
+    Ok(etag::json_response(&headers, crate::languages::etag(), language))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateCollectionRequest {
+    pub user_id: Option<String>,
+    pub name: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CollectionItemRequest {
+    pub user_id: Option<String>,
+    pub saying_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CollectionOwnerRequest {
+    pub user_id: Option<String>,
+}
+
+// Who and what made a saying, for crediting it outside the service
+#[derive(Debug, Serialize)]
+pub struct Attribution {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preset: Option<String>,
+    // Left out of public documents unless ATTRIBUTION_SHOW_MODEL is set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    pub generated_at: DateTime<Utc>,
+}
+
+impl Attribution {
+    // One-line credit such as "Stoic Sage - 2024-05-01 - openai/gpt-4o"
+    pub fn line(&self) -> String {
+        let date = self.generated_at.format("%Y-%m-%d").to_string();
+        [self.preset.as_deref(), Some(date.as_str()), self.model.as_deref()]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>()
+            .join(" - ")
+    }
+}
+
+// Credits for a saying; `public` hides the model unless the deployment opts in
+fn attribution(state: &AppState, saying: &Saying, public: bool) -> Attribution {
+    let show_model = !public || state.config.attribution.show_model;
+    Attribution {
+        preset: saying.preset_id.as_deref()
+            .and_then(|preset_id| state.presets.get_preset_by_id(preset_id))
+            .map(|preset| preset.name),
+        model: saying.model.clone().filter(|_| show_model),
+        generated_at: saying.created_at,
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SayingExport<'a> {
+    #[serde(flatten)]
+    pub saying: SayingResponse<'a>,
+    pub attribution: Attribution,
+}
+
+// A collection together with the sayings it references
+#[derive(Debug, Serialize)]
+pub struct CollectionExportResponse<'a> {
+    pub id: &'a str,
+    pub name: &'a str,
+    pub created_at: DateTime<Utc>,
+    pub sayings: Vec<SayingExport<'a>>,
+}
+
+// Load a collection and make sure the caller owns it
+async fn get_owned_collection(state: &AppState, collection_id: &str, user_id: &str) -> Result<Collection, ApiError> {
+    let collection = state.storage.get_collection(collection_id).await
+        .map_err(|e| ApiError::InternalError(format!("Failed to get collection: {}", e)))?
+        .ok_or_else(|| ApiError::NotFound(format!("No collection with ID: {}", collection_id)))?;
+    
+    if !state.storage.same_user(&collection.user_id, user_id) {
+        return Err(ApiError::AccessDenied("Collection belongs to another user".to_string()));
+    }
+    
+    Ok(Collection { user_id: user_id.to_string(), ..collection })
+}
+
+// Resolve the sayings of a collection and serialize them as one export document.
+// `public` is set for shared links, which follow the model attribution setting.
+async fn export_collection_response(state: &AppState, collection: &Collection, public: bool) -> Result<Response, ApiError> {
+    let mut sayings = Vec::with_capacity(collection.saying_ids.len());
+    for saying_id in &collection.saying_ids {
+        // Sayings that no longer exist are skipped rather than failing the export
+        if let Some((_, saying)) = state.storage.get_saying_by_id(saying_id).await
+            .map_err(|e| ApiError::InternalError(format!("Failed to get saying: {}", e)))? {
+            sayings.push(saying);
+        }
+    }
+    
+    let response = CollectionExportResponse {
+        id: &collection.id,
+        name: &collection.name,
+        created_at: collection.created_at,
+        sayings: sayings.iter()
+            .map(|saying| SayingExport {
+                saying: SayingResponse::from(saying.as_ref()),
+                attribution: attribution(state, saying, public),
+            })
+            .collect(),
+    };
+    
+    Ok(Json(response).into_response())
+}
+
+// POST /collections - Create a new, empty collection
+pub async fn create_collection(
+    State(state): State<Arc<AppState>>,
+    caller: Caller,
+    Json(payload): Json<CreateCollectionRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    let user_id = resolve_user_id(&state, &caller, payload.user_id)?;
+    
+    // Check if user is allowed
+    is_user_allowed(&state, &caller, &user_id)?;
+    
+    let name = payload.name.trim();
+    if name.is_empty() {
+        return Err(ApiError::BadRequest("Collection name must not be empty".to_string()));
+    }
+    
+    let collection = Collection {
+        id: uuid::Uuid::new_v4().to_string(),
+        user_id,
+        name: name.to_string(),
+        saying_ids: Vec::new(),
+        created_at: Utc::now(),
+        share_token: None,
+    };
+    
+    let collection = state.storage.save_collection(collection).await
+        .map_err(|e| ApiError::InternalError(format!("Failed to save collection: {}", e)))?;
+    
+    Ok((StatusCode::CREATED, Json(collection)))
+}
+
+// GET /collections - List a user's collections
+pub async fn get_collections(
+    Query(params): Query<StatusQuery>,
+    State(state): State<Arc<AppState>>,
+    caller: Caller,
+) -> Result<Json<Vec<Collection>>, ApiError> {
+    let user_id = resolve_user_id(&state, &caller, params.user_id)?;
+    
+    // Check if user is allowed
+    is_user_allowed(&state, &caller, &user_id)?;
+    check_owner(&state, &caller, &user_id, false).await?;
+    
+    let collections = state.storage.get_collections(&user_id).await
+        .map_err(|e| ApiError::InternalError(format!("Failed to get collections: {}", e)))?;
+    
+    Ok(Json(collections))
+}
+
+// GET /collections/:collection_id - Get one of the user's collections
+pub async fn get_collection(
+    Path(collection_id): Path<String>,
+    Query(params): Query<StatusQuery>,
+    State(state): State<Arc<AppState>>,
+    caller: Caller,
+) -> Result<Json<Collection>, ApiError> {
+    let user_id = resolve_user_id(&state, &caller, params.user_id)?;
+    
+    // Check if user is allowed
+    is_user_allowed(&state, &caller, &user_id)?;
+    check_owner(&state, &caller, &user_id, false).await?;
+    
+    let collection = get_owned_collection(&state, &collection_id, &user_id).await?;
+    
+    // The share link is only handed out by POST /collections/:collection_id/share
+    Ok(Json(Collection { share_token: None, ..collection }))
+}
+
+// POST /collections/:collection_id/sayings - Add one of the user's sayings to a collection
+pub async fn add_saying_to_collection(
+    Path(collection_id): Path<String>,
+    State(state): State<Arc<AppState>>,
+    caller: Caller,
+    Json(payload): Json<CollectionItemRequest>,
+) -> Result<Json<Collection>, ApiError> {
+    let user_id = resolve_user_id(&state, &caller, payload.user_id)?;
+    
+    // Check if user is allowed
+    is_user_allowed(&state, &caller, &user_id)?;
+    
+    let mut collection = get_owned_collection(&state, &collection_id, &user_id).await?;
+    
+    let (owner_id, _) = state.storage.get_saying_by_id(&payload.saying_id).await
+        .map_err(|e| ApiError::InternalError(format!("Failed to get saying: {}", e)))?
+        .ok_or_else(|| ApiError::NotFound(format!("No saying with ID: {}", payload.saying_id)))?;
+    
+    if !state.storage.same_user(&owner_id, &user_id) {
+        return Err(ApiError::AccessDenied("Saying belongs to another user".to_string()));
+    }
+    
+    if !collection.saying_ids.contains(&payload.saying_id) {
+        collection.saying_ids.push(payload.saying_id);
+    }
+    
+    let collection = state.storage.save_collection(collection).await
+        .map_err(|e| ApiError::InternalError(format!("Failed to save collection: {}", e)))?;
+    
+    Ok(Json(collection))
+}
+
+// GET /collections/:collection_id/export - Export a collection with its sayings
+pub async fn export_collection(
+    Path(collection_id): Path<String>,
+    Query(params): Query<StatusQuery>,
+    State(state): State<Arc<AppState>>,
+    caller: Caller,
+) -> Result<Response, ApiError> {
+    let user_id = resolve_user_id(&state, &caller, params.user_id)?;
+    
+    // Check if user is allowed
+    is_user_allowed(&state, &caller, &user_id)?;
+    check_owner(&state, &caller, &user_id, false).await?;
+    
+    let collection = get_owned_collection(&state, &collection_id, &user_id).await?;
+    
+    export_collection_response(&state, &collection, false).await
+}
+
+// POST /collections/:collection_id/share - Create a share token for a collection
+pub async fn share_collection(
+    Path(collection_id): Path<String>,
+    State(state): State<Arc<AppState>>,
+    caller: Caller,
+    Json(payload): Json<CollectionOwnerRequest>,
+) -> Result<Json<Collection>, ApiError> {
+    let user_id = resolve_user_id(&state, &caller, payload.user_id)?;
+    
+    // Check if user is allowed
+    is_user_allowed(&state, &caller, &user_id)?;
+    check_owner(&state, &caller, &user_id, false).await?;
+    
+    let mut collection = get_owned_collection(&state, &collection_id, &user_id).await?;
+    
+    // Sharing twice keeps the existing link valid
+    if collection.share_token.is_none() {
+        collection.share_token = Some(uuid::Uuid::new_v4().simple().to_string());
+    }
+    
+    let collection = state.storage.save_collection(collection).await
+        .map_err(|e| ApiError::InternalError(format!("Failed to save collection: {}", e)))?;
+    
+    Ok(Json(collection))
+}
+
+// GET /collections/shared/:token - Read a shared collection without knowing its owner
+pub async fn get_shared_collection(
+    Path(token): Path<String>,
+    State(state): State<Arc<AppState>>,
+) -> Result<Response, ApiError> {
+    let collection = state.storage.find_collection_by_share_token(&token).await
+        .map_err(|e| ApiError::InternalError(format!("Failed to get collection: {}", e)))?
+        .ok_or_else(|| ApiError::NotFound("No shared collection for this token".to_string()))?;
+    
+    export_collection_response(&state, &collection, true).await
+}
+
+#[derive(Debug, Serialize)]
+pub struct SayingShareResponse {
+    pub token: String,
+    pub saying_id: String,
+    pub created_at: DateTime<Utc>,
+    pub url: String,
+    // Open Graph image for link previews
+    pub card_url: String,
+}
+
+impl From<SayingShare> for SayingShareResponse {
+    fn from(share: SayingShare) -> Self {
+        Self {
+            url: format!("/share/{}", share.token),
+            card_url: format!("/share/{}/card.png", share.token),
+            token: share.token,
+            saying_id: share.saying_id,
+            created_at: share.created_at,
+        }
+    }
+}
+
+// POST /sayings/:saying_id/share - Get a public link to a saying (creating it if needed)
+pub async fn share_saying(
+    Path(saying_id): Path<String>,
+    State(state): State<Arc<AppState>>,
+    caller: Caller,
+) -> Result<Json<SayingShareResponse>, ApiError> {
+    let (user_id, _) = state.storage.get_saying_by_id(&saying_id).await
+        .map_err(|e| ApiError::InternalError(format!("Failed to get saying: {}", e)))?
+        .ok_or_else(|| ApiError::NotFound(format!("No saying with ID: {}", saying_id)))?;
+    
+    // Only the owner shares their sayings
+    is_owner_allowed(&state, &caller, &user_id)?;
+    check_owner(&state, &caller, &user_id, false).await?;
+    
+    // Sharing twice keeps the existing link valid
+    if let Some(share) = state.storage.get_saying_share(&saying_id).await
+        .map_err(|e| ApiError::InternalError(format!("Failed to get share: {}", e)))?
+    {
+        return Ok(Json(share.into()));
+    }
+    
+    let share = SayingShare {
+        token: uuid::Uuid::new_v4().simple().to_string(),
+        saying_id,
+        user_id,
+        created_at: Utc::now(),
+    };
+    state.storage.save_saying_share(&share).await
+        .map_err(|e| ApiError::InternalError(format!("Failed to save share: {}", e)))?;
+    
+    Ok(Json(share.into()))
+}
+
+// The saying behind a share token
+async fn shared_saying(state: &AppState, token: &str) -> Result<Arc<Saying>, ApiError> {
+    let share = state.storage.find_saying_share_by_token(token).await
+        .map_err(|e| ApiError::InternalError(format!("Failed to get share: {}", e)))?
+        .ok_or_else(|| ApiError::NotFound("No shared saying for this token".to_string()))?;
+    
+    let (_, saying) = state.storage.get_saying_by_id(&share.saying_id).await
+        .map_err(|e| ApiError::InternalError(format!("Failed to get saying: {}", e)))?
+        .ok_or_else(|| ApiError::NotFound("The shared saying no longer exists".to_string()))?;
+    
+    Ok(saying)
+}
+
+// GET /share/:token - Read a shared saying without knowing its owner
+pub async fn get_shared_saying(
+    Path(token): Path<String>,
+    State(state): State<Arc<AppState>>,
+) -> Result<Response, ApiError> {
+    let saying = shared_saying(&state, &token).await?;
+    Ok(Json(SayingResponse::from(saying.as_ref())).into_response())
+}
+
+// GET /share/:token/card.png - Open Graph image of a shared saying, rendered once and kept
+pub async fn get_share_card(
+    Path(token): Path<String>,
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    let cached = state.storage.get_share_card(&token).await
+        .map_err(|e| ApiError::InternalError(format!("Failed to get share card: {}", e)))?;
+    
+    let png = match cached {
+        Some(png) => png,
+        None => {
+            let saying = shared_saying(&state, &token).await?;
+            // The bitmap font has no CJK glyphs; such sayings show their English original
+            let text = if card::can_render(&saying.content) {
+                saying.content.clone()
+            } else {
+                crate::languages::english_original(&saying.content)
+            };
+            let footer = format!("prompt-wrapper - {}", attribution(&state, &saying, true).line());
+            
+            let png = card::render(&text, &footer)
+                .map_err(|e| ApiError::InternalError(format!("Failed to render share card: {}", e)))?;
+            if !state.config.server.read_only {
+                state.storage.save_share_card(&token, &png).await
+                    .map_err(|e| ApiError::InternalError(format!("Failed to save share card: {}", e)))?;
+            }
+            png
+        }
+    };
+    
+    Ok(etag::binary_response(&headers, &etag::compute(token.as_bytes()), "image/png", png))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PromptHistoryQuery {
+    pub limit: Option<usize>,
+}
+
+// GET /users/:user_id/prompts - Prompts the user submitted, newest first, failed ones included
+pub async fn get_prompt_history(
+    Path(user_id): Path<String>,
+    Query(params): Query<PromptHistoryQuery>,
+    State(state): State<Arc<AppState>>,
+    caller: Caller,
+) -> Result<Json<Vec<PromptHistoryEntry>>, ApiError> {
+    is_user_allowed(&state, &caller, &user_id)?;
+    check_read_limit(&state, &caller, &user_id)?;
+    check_owner(&state, &caller, &user_id, false).await?;
+    
+    let history = state.storage.get_prompt_history(&user_id, params.limit.unwrap_or(20)).await
+        .map_err(|e| ApiError::InternalError(format!("Failed to get prompt history: {}", e)))?;
+    
+    Ok(Json(history))
+}
 
-// Helper function to fetch from LLM
-async fn fetch_from_llm(
-    state: &Arc<AppState>,
-    system_prompt: &str,
-    user_prompt: &str,
-    preset_id: Option<String>
-) -> Result<Saying, ApiError> {
-    let saying = state.openrouter.get_saying_with_system(system_prompt, user_prompt).await
+// One conversation in a listing, without its messages
+#[derive(Debug, Serialize)]
+pub struct ConversationSummary {
+    pub id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preset_id: Option<String>,
+    pub message_count: usize,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl From<&Conversation> for ConversationSummary {
+    fn from(conversation: &Conversation) -> Self {
+        Self {
+            id: conversation.id.clone(),
+            preset_id: conversation.preset_id.clone(),
+            message_count: conversation.messages.len(),
+            created_at: conversation.created_at,
+            updated_at: conversation.updated_at,
+        }
+    }
+}
+
+// GET /users/:user_id/conversations - The user's conversations, most recently active first
+pub async fn get_conversations(
+    Path(user_id): Path<String>,
+    State(state): State<Arc<AppState>>,
+    caller: Caller,
+) -> Result<Json<Vec<ConversationSummary>>, ApiError> {
+    is_user_allowed(&state, &caller, &user_id)?;
+    check_read_limit(&state, &caller, &user_id)?;
+    check_owner(&state, &caller, &user_id, false).await?;
+    
+    let conversations = state.storage.get_conversations(&user_id).await
+        .map_err(|e| ApiError::InternalError(format!("Failed to get conversations: {}", e)))?;
+    
+    Ok(Json(conversations.iter().map(ConversationSummary::from).collect()))
+}
+
+// GET /users/:user_id/conversations/:conversation_id - One conversation with all its messages
+pub async fn get_conversation(
+    Path((user_id, conversation_id)): Path<(String, String)>,
+    State(state): State<Arc<AppState>>,
+    caller: Caller,
+) -> Result<Json<Conversation>, ApiError> {
+    is_user_allowed(&state, &caller, &user_id)?;
+    check_read_limit(&state, &caller, &user_id)?;
+    check_owner(&state, &caller, &user_id, false).await?;
+    
+    let conversation = state.storage.get_conversation(&user_id, &conversation_id).await
+        .map_err(|e| ApiError::InternalError(format!("Failed to get conversation: {}", e)))?
+        .ok_or_else(|| ApiError::NotFound(format!("No conversation with ID: {}", conversation_id)))?;
+    
+    Ok(Json(conversation))
+}
+
+// DELETE /users/:user_id/conversations/:conversation_id - Delete a conversation for good
+pub async fn delete_conversation(
+    Path((user_id, conversation_id)): Path<(String, String)>,
+    State(state): State<Arc<AppState>>,
+    caller: Caller,
+) -> Result<StatusCode, ApiError> {
+    is_user_allowed(&state, &caller, &user_id)?;
+    check_owner(&state, &caller, &user_id, false).await?;
+    
+    let deleted = state.storage.delete_conversation(&user_id, &conversation_id).await
+        .map_err(|e| ApiError::InternalError(format!("Failed to delete conversation: {}", e)))?;
+    if !deleted {
+        return Err(ApiError::NotFound(format!("No conversation with ID: {}", conversation_id)));
+    }
+    
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChatRequest {
+    pub user_id: Option<String>,
+    // Conversation to continue; a new one is started when unset
+    pub conversation_id: Option<String>,
+    // Preset whose system prompt a new conversation runs under; the freeform one when unset
+    pub preset_id: Option<String>,
+    pub message: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChatResponse {
+    pub conversation_id: String,
+    pub reply: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    pub message_count: usize,
+    // Leading messages the model now only sees as a summary
+    pub summarized_messages: usize,
+}
+
+// POST /chat - Send a message in a conversation and get the model's reply
+pub async fn chat(
+    State(state): State<Arc<AppState>>,
+    caller: Caller,
+    Json(payload): Json<ChatRequest>,
+) -> Result<Response, ApiError> {
+    if state.config.server.read_only {
+        return Err(ApiError::ReadOnly);
+    }
+    let user_id = resolve_user_id(&state, &caller, payload.user_id)?;
+    
+    // There is no cached reply to fall back on, so cached-only callers can't chat
+    match state.access.check(&caller, &user_id) {
+        Access::Deny(reason) => return Err(ApiError::AccessDenied(reason)),
+        Access::CachedOnly => return Err(ApiError::AccessDenied("Chat is not available to this user".to_string())),
+        Access::Allow => {}
+    }
+    let issued_token = check_owner(&state, &caller, &user_id, true).await?;
+    
+    let response = chat_turn(&state, &caller, user_id, payload).await.unwrap_or_else(IntoResponse::into_response);
+    Ok(with_owner_token(response, issued_token))
+}
+
+// The rest of POST /chat, once the caller may act as the user
+async fn chat_turn(state: &AppState, caller: &Caller, user_id: String, payload: ChatRequest) -> Result<Response, ApiError> {
+    if payload.message.trim().is_empty() {
+        return Err(ApiError::BadRequest("A message is required".to_string()));
+    }
+    let limits = &state.config.prompt_limits;
+    let (message, _) = fit_token_budget(payload.message, limits.max_user_tokens, limits.overflow, "Message")?;
+    
+    // Held until the reply is saved, so a concurrent message waits and then sees this turn
+    let _turn = match &payload.conversation_id {
+        Some(conversation_id) => Some(state.chat_turns.lock(&user_id, conversation_id).await),
+        None => None,
+    };
+    let mut conversation = match payload.conversation_id {
+        Some(conversation_id) => state.storage.get_conversation(&user_id, &conversation_id).await
+            .map_err(|e| ApiError::InternalError(format!("Failed to get conversation: {}", e)))?
+            .ok_or_else(|| ApiError::NotFound(format!("No conversation with ID: {}", conversation_id)))?,
+        None => Conversation::new(user_id.clone(), payload.preset_id),
+    };
+    
+    let tier = state.config.access.tier(&user_id);
+    let system_prompt = match conversation.preset_id.as_deref() {
+        Some(preset_id) => {
+            let preset = state.presets.get_preset_by_id(preset_id)
+                .ok_or_else(|| ApiError::PresetNotFound(preset_id.to_string()))?;
+            if !preset.allows_tier(tier) {
+                return Err(ApiError::AccessDenied(format!("Preset {} requires tier {}", preset_id, preset.min_tier)));
+            }
+            check_preset_rating(state, &preset)?;
+            preset.system_prompt
+        }
+        None => state.config.freeform.system_prompt_for(crate::languages::DEFAULT_LANGUAGE_ID).to_string(),
+    };
+    let system_prompt = state.config.branding.apply(system_prompt);
+    
+    // Every message costs a request, like generating a saying
+    if state.exemptions.is_exempt(&user_id, caller.token.as_deref()) {
+        Metrics::incr(&state.metrics.exempt_requests);
+    } else {
+        let check = state.rate_limiter.check(&user_id).await
+            .map_err(|e| ApiError::InternalError(format!("Failed to check rate limit: {}", e)))?;
+        match check {
+            RateLimitCheck::Allowed => {}
+            RateLimitCheck::Exhausted => {
+                Metrics::incr(&state.metrics.rate_limited);
+                return Err(ApiError::RateLimited {
+                    message: "You have exceeded the rate limit for this endpoint".to_string(),
+                    limit: RateLimitState::for_user(state, &user_id, None).await,
+                });
+            }
+            RateLimitCheck::Burst { retry_after } => {
+                Metrics::incr(&state.metrics.rate_limited);
+                return Err(ApiError::RateLimited {
+                    message: format!("Too many requests in a short time, try again in {} seconds", retry_after),
+                    limit: RateLimitState::for_user(state, &user_id, Some(retry_after)).await,
+                });
+            }
+            RateLimitCheck::Full { retry_after } => {
+                Metrics::incr(&state.metrics.rate_limited);
+                return Err(limiter_full(state, &user_id, retry_after).await);
+            }
+        }
+    }
+    
+    conversation.push(MessageRole::User, message);
+    let messages = crate::chat::prepare_context(state, &mut conversation, &system_prompt, tier).await;
+    let options = GenerationOptions {
+        mock: state.config.access.is_sandbox(&user_id),
+        ..generation_options(state, conversation.preset_id.as_deref(), false, tier)
+    };
+    let reply = state.openrouter.get_reply(&messages, &options).await
         .map_err(|e| {
             tracing::error!("OpenRouter API error: {}", e);
-            ApiError::OpenRouterError(e)
+            Metrics::incr(&state.metrics.upstream_errors);
+            ApiError::from_upstream(e)
         })?;
+    conversation.push(MessageRole::Assistant, reply.content.clone());
     
-    // Set preset_id if available
-    let saying_with_preset = Saying {
-        preset_id,
-        ..saying
+    state.storage.save_conversation(&conversation).await
+        .map_err(|e| ApiError::InternalError(format!("Failed to save conversation: {}", e)))?;
+    
+    Ok(Json(ChatResponse {
+        conversation_id: conversation.id.clone(),
+        reply: reply.content,
+        model: reply.model,
+        message_count: conversation.messages.len(),
+        summarized_messages: conversation.summary.as_ref().map_or(0, |summary| summary.covers),
+    }).into_response())
+}
+
+// GET /users/:user_id/stats - Counts and token totals over the user's generation history
+pub async fn get_user_stats(
+    Path(user_id): Path<String>,
+    State(state): State<Arc<AppState>>,
+    caller: Caller,
+) -> Result<Json<UserStats>, ApiError> {
+    // Check if user is allowed
+    is_user_allowed(&state, &caller, &user_id)?;
+    check_read_limit(&state, &caller, &user_id)?;
+    check_owner(&state, &caller, &user_id, false).await?;
+    
+    let stats = state.storage.get_user_stats(&user_id).await
+        .map_err(|e| ApiError::InternalError(format!("Failed to get user stats: {}", e)))?;
+    
+    // Activity and first uses are kept for streaks and achievements, which have their own endpoints
+    Ok(Json(UserStats {
+        active_days: Default::default(),
+        preset_first_used: Default::default(),
+        language_first_used: Default::default(),
+        ..stats
+    }))
+}
+
+#[derive(Debug, Serialize)]
+pub struct JobResponse<'a> {
+    pub id: &'a str,
+    pub status: JobStatus,
+    pub created_at: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<DateTime<Utc>>,
+    // Jobs ahead of this one while it waits
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub position: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub saying: Option<SayingResponse<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<&'a str>,
+}
+
+// GET /jobs/:job_id - Progress and result of a queued generation request
+pub async fn get_job(
+    Path(job_id): Path<String>,
+    State(state): State<Arc<AppState>>,
+    caller: Caller,
+) -> Result<Response, ApiError> {
+    let job: GenerationJob = state.queue.get(&job_id)
+        .ok_or_else(|| ApiError::NotFound(format!("Job not found: {}", job_id)))?;
+    
+    // Check if user is allowed
+    is_user_allowed(&state, &caller, &job.user_id)?;
+    
+    let saying = match &job.outcome {
+        Some(SayingOutcome::Generated(saying, details)) => Some(SayingResponse {
+            details: Some(*details),
+            ..SayingResponse::from(saying.as_ref())
+        }),
+        Some(SayingOutcome::Cached(saying)) => Some(SayingResponse {
+            source: SayingSource::Cache,
+            ..SayingResponse::from(saying.as_ref())
+        }),
+        Some(SayingOutcome::Queued { .. }) | None => None,
+    };
+    let response = JobResponse {
+        id: &job.id,
+        status: job.status,
+        created_at: job.created_at,
+        finished_at: job.finished_at,
+        position: state.queue.position(&job.id),
+        saying,
+        error: job.error.as_deref(),
     };
     
-    Ok(saying_with_preset)
+    Ok(Json(response).into_response())
 }
 
-// GET /users/:user_id/status - Get user status
-pub async fn get_user_status(
+#[derive(Debug, Serialize)]
+pub struct AchievementResponse {
+    pub id: AchievementKind,
+    pub name: &'static str,
+    pub description: &'static str,
+    pub awarded_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AchievementsResponse {
+    pub user_id: String,
+    pub earned: Vec<AchievementResponse>,
+    pub locked: Vec<AchievementResponse>,
+}
+
+// GET /users/:user_id/achievements - Badges the user has earned so far
+pub async fn get_user_achievements(
     Path(user_id): Path<String>,
+    Query(params): Query<UserStatusQuery>,
     State(state): State<Arc<AppState>>,
-) -> Result<Json<UserStatusResponse>, ApiError> {
+    caller: Caller,
+) -> Result<Json<AchievementsResponse>, ApiError> {
     // Check if user is allowed
-    is_user_allowed(&user_id)?;
+    is_user_allowed(&state, &caller, &user_id)?;
+    check_read_limit(&state, &caller, &user_id)?;
+    check_owner(&state, &caller, &user_id, false).await?;
     
-    // Check rate limit for the user
-    let rate_limit_info = match state.rate_limiter.get_limit_info(&user_id).await {
-        Some(info) => info,
-        None => {
-            // User has no rate limit info yet, return default values
-            // Try to get a default preset
-            let selected_preset = state.presets.get_default_preset()
-                .map(|preset| Some(PresetResponse::from(preset)))
-                .unwrap_or_else(|e| {
-                    tracing::error!("Failed to get default preset: {}", e);
-                    None
-                });
-            
-            let response = UserStatusResponse {
-                user_id: user_id.clone(),
-                can_query: true,
-                remaining_requests: state.config.rate_limit.max_requests,
-                reset_at: None,
-                last_saying: None,
-                selected_preset,
-            };
-            
-            return Ok(Json(response));
+    let stats = state.storage.get_user_stats(&user_id).await
+        .map_err(|e| ApiError::InternalError(format!("Failed to get user stats: {}", e)))?;
+    let stored = state.storage.get_achievements(&user_id).await
+        .map_err(|e| ApiError::InternalError(format!("Failed to get achievements: {}", e)))?;
+    
+    // Badges stay awarded even if the history that earned them is later removed
+    let (earned, changed) = achievements::merge(
+        stored,
+        achievements::evaluate(&stats, streaks::offset_from_minutes(params.tz_offset.unwrap_or(0))),
+    );
+    if changed && !state.config.server.read_only {
+        if let Err(e) = state.storage.save_achievements(&user_id, &earned).await {
+            tracing::error!("Failed to save achievements for user {}: {}", user_id, e);
         }
-    };
+    }
     
-    // Get the last saying for this user from storage
-    let last_saying = state.storage.get_last_saying(&user_id).await
-        .ok()
-        .and_then(|result| result.map(SayingResponse::from));
+    let locked = AchievementKind::ALL.iter()
+        .filter(|kind| !earned.iter().any(|a| a.kind == **kind))
+        .map(|kind| AchievementResponse {
+            id: *kind,
+            name: kind.name(),
+            description: kind.description(),
+            awarded_at: None,
+        })
+        .collect();
     
-    // Get or select a preset for the user if they can query
-    let selected_preset = if rate_limit_info.remaining_requests > 0 {
-        state.presets.get_or_select_preset(&user_id, rate_limit_info.reset_at)
-            .map(|preset| Some(PresetResponse::from(preset)))
-            .unwrap_or_else(|e| {
-                tracing::error!("Failed to select preset: {}", e);
-                None
-            })
-    } else {
-        None
+    let earned = earned.into_iter()
+        .map(|a| AchievementResponse {
+            id: a.kind,
+            name: a.kind.name(),
+            description: a.kind.description(),
+            awarded_at: Some(a.awarded_at),
+        })
+        .collect();
+    
+    Ok(Json(AchievementsResponse { user_id, earned, locked }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LeaderboardQuery {
+    // Ranking to return: "sayings" (default) or "streak"
+    pub by: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LeaderboardResponse<'a> {
+    pub by: &'a str,
+    pub window_days: i64,
+    pub generated_at: Option<DateTime<Utc>>,
+    pub entries: &'a [LeaderboardEntry],
+}
+
+// GET /leaderboard - Anonymized top users, served from the last background aggregation
+pub async fn get_leaderboard(
+    Query(params): Query<LeaderboardQuery>,
+    State(state): State<Arc<AppState>>,
+) -> Result<Response, ApiError> {
+    if !state.leaderboard.enabled() {
+        return Err(ApiError::NotFound("Leaderboard is not enabled".to_string()));
+    }
+    
+    let snapshot = state.leaderboard.snapshot();
+    let (by, entries) = match params.by.as_deref() {
+        None | Some("sayings") => ("sayings", &snapshot.by_sayings),
+        Some("streak") => ("streak", &snapshot.by_streak),
+        Some(other) => return Err(ApiError::BadRequest(format!("Unknown leaderboard ranking: {}", other))),
     };
     
-    let response = UserStatusResponse {
-        user_id: user_id.clone(),
-        can_query: rate_limit_info.remaining_requests > 0,
-        remaining_requests: rate_limit_info.remaining_requests,
-        reset_at: Some(rate_limit_info.reset_at),
-        last_saying,
-        selected_preset,
+    let response = LeaderboardResponse {
+        by,
+        window_days: snapshot.window_days,
+        generated_at: snapshot.generated_at,
+        entries,
     };
     
-    Ok(Json(response))
+    Ok(Json(response).into_response())
 }
 
-// GET /presets - Get all available presets
-pub async fn get_presets(
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LeaderboardOptIn {
+    pub opt_in: bool,
+}
+
+// GET /users/:user_id/leaderboard - Whether the user is ranked on the leaderboard
+pub async fn get_leaderboard_opt_in(
+    Path(user_id): Path<String>,
     State(state): State<Arc<AppState>>,
-) -> Json<Vec<PresetResponse>> {
-    let presets = state.presets.get_all_presets();
-    let response = presets.into_iter()
-        .map(PresetResponse::from)
-        .collect::<Vec<_>>();
+    caller: Caller,
+) -> Result<Json<LeaderboardOptIn>, ApiError> {
+    if !state.leaderboard.enabled() {
+        return Err(ApiError::NotFound("Leaderboard is not enabled".to_string()));
+    }
+    is_user_allowed(&state, &caller, &user_id)?;
+    check_owner(&state, &caller, &user_id, false).await?;
     
-    Json(response)
+    let preferences = state.storage.get_preferences(&user_id).await
+        .map_err(|e| ApiError::InternalError(format!("Failed to get preferences: {}", e)))?;
+    
+    Ok(Json(LeaderboardOptIn { opt_in: preferences.leaderboard }))
 }
 
-// GET /presets/:preset_id - Get a specific preset
-pub async fn get_preset(
-    Path(preset_id): Path<String>,
+// PUT /users/:user_id/leaderboard - Opt in to or out of the leaderboard; takes effect on its next refresh
+pub async fn update_leaderboard_opt_in(
+    Path(user_id): Path<String>,
     State(state): State<Arc<AppState>>,
-) -> Result<Json<PresetResponse>, ApiError> {
-    let preset = state.presets.get_preset_by_id(&preset_id)
-        .ok_or_else(|| ApiError::NotFound(format!("No preset with ID: {}", preset_id)))?;
+    caller: Caller,
+    Json(payload): Json<LeaderboardOptIn>,
+) -> Result<Json<LeaderboardOptIn>, ApiError> {
+    if !state.leaderboard.enabled() {
+        return Err(ApiError::NotFound("Leaderboard is not enabled".to_string()));
+    }
+    is_user_allowed(&state, &caller, &user_id)?;
+    check_owner(&state, &caller, &user_id, false).await?;
+    
+    let mut preferences = state.storage.get_preferences(&user_id).await
+        .map_err(|e| ApiError::InternalError(format!("Failed to get preferences: {}", e)))?;
+    preferences.leaderboard = payload.opt_in;
+    
+    state.storage.save_preferences(&user_id, &preferences).await
+        .map_err(|e| ApiError::InternalError(format!("Failed to save preferences: {}", e)))?;
+    
+    Ok(Json(payload))
+}
+
+// GET /users/:user_id/notifications - List the user's registered notification channels
+pub async fn get_notifications(
+    Path(user_id): Path<String>,
+    State(state): State<Arc<AppState>>,
+    caller: Caller,
+) -> Result<Json<Vec<NotificationTarget>>, ApiError> {
+    if !state.notifier.enabled() {
+        return Err(ApiError::NotFound("Notifications are not enabled".to_string()));
+    }
+    is_user_allowed(&state, &caller, &user_id)?;
+    check_owner(&state, &caller, &user_id, false).await?;
+    
+    let preferences = state.storage.get_preferences(&user_id).await
+        .map_err(|e| ApiError::InternalError(format!("Failed to get preferences: {}", e)))?;
+    
+    Ok(Json(preferences.notifications))
+}
+
+// POST /users/:user_id/notifications - Register a channel for the daily saying
+pub async fn create_notification(
+    Path(user_id): Path<String>,
+    State(state): State<Arc<AppState>>,
+    caller: Caller,
+    Json(channel): Json<NotificationChannel>,
+) -> Result<Response, ApiError> {
+    if !state.notifier.enabled() {
+        return Err(ApiError::NotFound("Notifications are not enabled".to_string()));
+    }
+    is_user_allowed(&state, &caller, &user_id)?;
+    
+    channel.validate(state.notifier.config()).map_err(ApiError::BadRequest)?;
+    
+    let mut preferences = state.storage.get_preferences(&user_id).await
+        .map_err(|e| ApiError::InternalError(format!("Failed to get preferences: {}", e)))?;
+    
+    let target = NotificationTarget::new(channel);
+    preferences.notifications.push(target.clone());
+    
+    state.storage.save_preferences(&user_id, &preferences).await
+        .map_err(|e| ApiError::InternalError(format!("Failed to save preferences: {}", e)))?;
     
-    Ok(Json(PresetResponse::from(preset)))
+    Ok((StatusCode::CREATED, Json(target)).into_response())
+}
+
+// DELETE /users/:user_id/notifications/:target_id - Stop sending to a channel
+pub async fn delete_notification(
+    Path((user_id, target_id)): Path<(String, String)>,
+    State(state): State<Arc<AppState>>,
+    caller: Caller,
+) -> Result<StatusCode, ApiError> {
+    if !state.notifier.enabled() {
+        return Err(ApiError::NotFound("Notifications are not enabled".to_string()));
+    }
+    is_user_allowed(&state, &caller, &user_id)?;
+    
+    let mut preferences = state.storage.get_preferences(&user_id).await
+        .map_err(|e| ApiError::InternalError(format!("Failed to get preferences: {}", e)))?;
+    
+    let before = preferences.notifications.len();
+    preferences.notifications.retain(|target| target.id != target_id);
+    if preferences.notifications.len() == before {
+        return Err(ApiError::NotFound(format!("Notification channel not found: {}", target_id)));
+    }
+    
+    state.storage.save_preferences(&user_id, &preferences).await
+        .map_err(|e| ApiError::InternalError(format!("Failed to save preferences: {}", e)))?;
+    
+    Ok(StatusCode::NO_CONTENT)
 }
 
 #[derive(Debug, Deserialize)]
-pub struct SayingsQuery {
-    pub user_id: Option<String>,
-    pub limit: Option<usize>,
+pub struct EmailPreferencesRequest {
+    pub address: String,
+    // Subscribe to the daily saying (default: true)
+    pub daily: Option<bool>,
 }
 
-// GET /languages - Get all available languages
-pub async fn get_languages() -> Json<Vec<Language>> {
-    let languages = get_all_languages();
-    Json(languages)
+// GET /users/:user_id/email - Get the user's email preferences
+pub async fn get_email_preferences(
+    Path(user_id): Path<String>,
+    State(state): State<Arc<AppState>>,
+    caller: Caller,
+) -> Result<Json<EmailPreferences>, ApiError> {
+    if !state.notifier.email_enabled() {
+        return Err(ApiError::NotFound("Email delivery is not enabled".to_string()));
+    }
+    is_user_allowed(&state, &caller, &user_id)?;
+    check_owner(&state, &caller, &user_id, false).await?;
+    
+    let preferences = state.storage.get_preferences(&user_id).await
+        .map_err(|e| ApiError::InternalError(format!("Failed to get preferences: {}", e)))?;
+    
+    preferences.email
+        .map(Json)
+        .ok_or_else(|| ApiError::NotFound(format!("No email preferences for user: {}", user_id)))
 }
 
-// GET /languages/:language_id - Get a specific language by ID
-pub async fn get_language(
-    Path(language_id): Path<String>,
-) -> Result<Json<Language>, ApiError> {
-    let language = get_language_by_id(&language_id);
-    Ok(Json(language))
-} 
\ No newline at end of file
+// PUT /users/:user_id/email - Set the address and whether to email the daily saying
+pub async fn update_email_preferences(
+    Path(user_id): Path<String>,
+    State(state): State<Arc<AppState>>,
+    caller: Caller,
+    Json(payload): Json<EmailPreferencesRequest>,
+) -> Result<Json<EmailPreferences>, ApiError> {
+    if !state.notifier.email_enabled() {
+        return Err(ApiError::NotFound("Email delivery is not enabled".to_string()));
+    }
+    is_user_allowed(&state, &caller, &user_id)?;
+    
+    email::validate_address(&payload.address).map_err(ApiError::BadRequest)?;
+    let daily = payload.daily.unwrap_or(true);
+    
+    let mut preferences = state.storage.get_preferences(&user_id).await
+        .map_err(|e| ApiError::InternalError(format!("Failed to get preferences: {}", e)))?;
+    
+    // Keep the unsubscribe token (and links already sent) while the address stays the same
+    let email = match preferences.email.take() {
+        Some(existing) if existing.address == payload.address => EmailPreferences {
+            daily,
+            updated_at: Utc::now(),
+            ..existing
+        },
+        _ => EmailPreferences::new(payload.address, daily),
+    };
+    preferences.email = Some(email.clone());
+    
+    state.storage.save_preferences(&user_id, &preferences).await
+        .map_err(|e| ApiError::InternalError(format!("Failed to save preferences: {}", e)))?;
+    
+    Ok(Json(email))
+}
+
+// DELETE /users/:user_id/email - Forget the user's email address
+pub async fn delete_email_preferences(
+    Path(user_id): Path<String>,
+    State(state): State<Arc<AppState>>,
+    caller: Caller,
+) -> Result<StatusCode, ApiError> {
+    if !state.notifier.email_enabled() {
+        return Err(ApiError::NotFound("Email delivery is not enabled".to_string()));
+    }
+    is_user_allowed(&state, &caller, &user_id)?;
+    
+    let mut preferences = state.storage.get_preferences(&user_id).await
+        .map_err(|e| ApiError::InternalError(format!("Failed to get preferences: {}", e)))?;
+    
+    if preferences.email.take().is_none() {
+        return Err(ApiError::NotFound(format!("No email preferences for user: {}", user_id)));
+    }
+    
+    state.storage.save_preferences(&user_id, &preferences).await
+        .map_err(|e| ApiError::InternalError(format!("Failed to save preferences: {}", e)))?;
+    
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// The user and preferences an unsubscribe link belongs to
+async fn find_unsubscribe(state: &AppState, token: &str) -> Result<(String, UserPreferences), ApiError> {
+    let unknown = || ApiError::NotFound("Unknown unsubscribe link".to_string());
+    
+    let user_id = state.storage.find_unsubscribe_token(token).await
+        .map_err(|e| ApiError::InternalError(format!("Failed to look up unsubscribe link: {}", e)))?
+        .ok_or_else(unknown)?;
+    let preferences = state.storage.get_preferences(&user_id).await
+        .map_err(|e| ApiError::InternalError(format!("Failed to get preferences: {}", e)))?;
+    
+    // The address may have changed since the index was read
+    if !preferences.email.as_ref().is_some_and(|email| email.unsubscribe_token == token) {
+        return Err(unknown());
+    }
+    Ok((user_id, preferences))
+}
+
+// GET /unsubscribe/:token - Confirmation page linked from every email. Mail scanners follow
+// links, so nothing changes until the page's form is submitted.
+pub async fn unsubscribe_page(
+    Path(token): Path<String>,
+    State(state): State<Arc<AppState>>,
+) -> Result<Response, ApiError> {
+    let (_, preferences) = find_unsubscribe(&state, &token).await?;
+    let email = preferences.email.as_ref().expect("matched on email preferences");
+    
+    Ok(Html(email::render_unsubscribe_confirmation(&email.address).into_string()).into_response())
+}
+
+// POST /unsubscribe/:token - Turn off daily emails for the address the link was sent to
+pub async fn unsubscribe(
+    Path(token): Path<String>,
+    State(state): State<Arc<AppState>>,
+) -> Result<Response, ApiError> {
+    let (user_id, mut preferences) = find_unsubscribe(&state, &token).await?;
+    
+    let email = preferences.email.as_mut().expect("matched on email preferences");
+    email.daily = false;
+    email.updated_at = Utc::now();
+    let page = email::render_unsubscribed(&email.address);
+    
+    state.storage.save_preferences(&user_id, &preferences).await
+        .map_err(|e| ApiError::InternalError(format!("Failed to save preferences: {}", e)))?;
+    
+    Ok(Html(page.into_string()).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, ProviderType, StorageType};
+
+    fn state(default_user: DefaultUserMode) -> Arc<AppState> {
+        let mut config = Config::from_env_with_provider(ProviderType::Mock);
+        config.storage.type_ = StorageType::Memory;
+        config.access.default_user = default_user;
+        crate::build_app_state(config).unwrap()
+    }
+
+    fn caller(peer: Option<&str>) -> Caller {
+        Caller::from_headers(&HeaderMap::new(), peer.map(|peer| peer.parse().unwrap()), &[])
+    }
+
+    #[tokio::test]
+    async fn test_resolve_user_id_per_default_user_mode() {
+        let named = || Some("alice".to_string());
+        let peer = caller(Some("203.0.113.7:50000"));
+
+        let shared = state(DefaultUserMode::Shared);
+        assert_eq!(resolve_user_id(&shared, &peer, named()).unwrap(), "alice");
+        assert_eq!(resolve_user_id(&shared, &peer, None).unwrap(), DEFAULT_USER_ID);
+
+        let reject = state(DefaultUserMode::Reject);
+        assert_eq!(resolve_user_id(&reject, &peer, named()).unwrap(), "alice");
+        assert!(matches!(resolve_user_id(&reject, &peer, None), Err(ApiError::BadRequest(_))));
+
+        let ephemeral = state(DefaultUserMode::Ephemeral);
+        assert_eq!(resolve_user_id(&ephemeral, &peer, named()).unwrap(), "alice");
+        let anonymous = resolve_user_id(&ephemeral, &peer, None).unwrap();
+        assert!(anonymous.starts_with("anon-"));
+        // Reconnecting from another port is still the same user, another address isn't
+        assert_eq!(resolve_user_id(&ephemeral, &caller(Some("203.0.113.7:50001")), None).unwrap(), anonymous);
+        assert_ne!(resolve_user_id(&ephemeral, &caller(Some("198.51.100.1:50000")), None).unwrap(), anonymous);
+        // Without a peer every request is its own user
+        let unknown = caller(None);
+        assert_ne!(resolve_user_id(&ephemeral, &unknown, None).unwrap(), resolve_user_id(&ephemeral, &unknown, None).unwrap());
+    }
+
+    async fn body(response: Response) -> String {
+        String::from_utf8(axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap().to_vec()).unwrap()
+    }
+
+    async fn daily(state: &AppState) -> bool {
+        state.storage.get_preferences("alice").await.unwrap().email.unwrap().daily
+    }
+
+    #[tokio::test]
+    async fn test_unsubscribe_link_only_unsubscribes_on_post() {
+        let state = state(DefaultUserMode::Shared);
+        let subscribe = |address: &str| UserPreferences {
+            email: Some(EmailPreferences::new(address.to_string(), true)),
+            ..Default::default()
+        };
+
+        let preferences = subscribe("alice@example.com");
+        let token = preferences.email.as_ref().unwrap().unsubscribe_token.clone();
+        state.storage.save_preferences("alice", &preferences).await.unwrap();
+
+        // Following the link only asks for confirmation
+        let page = unsubscribe_page(Path(token.clone()), State(state.clone())).await.unwrap();
+        assert!(body(page).await.contains("alice@example.com"));
+        assert!(daily(&state).await);
+
+        unsubscribe(Path(token.clone()), State(state.clone())).await.unwrap();
+        assert!(!daily(&state).await);
+
+        // A new address gets a new token and links to the old one stop working
+        let preferences = subscribe("alice@example.org");
+        let new_token = preferences.email.as_ref().unwrap().unsubscribe_token.clone();
+        state.storage.save_preferences("alice", &preferences).await.unwrap();
+        assert!(matches!(unsubscribe_page(Path(token), State(state.clone())).await, Err(ApiError::NotFound(_))));
+        assert!(unsubscribe_page(Path(new_token), State(state.clone())).await.is_ok());
+    }
+}
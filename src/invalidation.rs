@@ -0,0 +1,208 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use futures_util::StreamExt;
+use redis::aio::MultiplexedConnection;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+use crate::config::InvalidationConfig;
+use crate::models::PresetSelectionRecord;
+use crate::preset::Preset;
+use crate::AppState;
+
+// Wait before resubscribing after the Redis connection drops
+const RECONNECT_DELAY_SECS: u64 = 5;
+
+// A change one instance made that the others must apply to their own caches
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Invalidation {
+    // Global cache entries were removed, as by DELETE /admin/cache
+    Cache { preset_id: Option<String>, prompt: Option<String> },
+    // A preset's cache entries older than `before` left the fallback pool (cache refresh)
+    StaleCache { preset_id: String, before: DateTime<Utc> },
+    // A user's preset for the current window was picked or pinned
+    PresetSelection { user_id: String, selection: PresetSelectionRecord },
+    // A preset was imported into the catalog (POST /admin/presets/import). Boxed, since a
+    // preset is far larger than the other variants.
+    PresetImport { preset: Box<Preset> },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Envelope {
+    // Publishing instance, which skips its own messages
+    origin: String,
+    #[serde(flatten)]
+    invalidation: Invalidation,
+}
+
+// Broadcasts invalidations over Redis pub/sub (INVALIDATION_REDIS_URL) so instances with
+// their own in-memory caches stay consistent. Without a URL every call is a no-op.
+pub struct InvalidationBus {
+    client: Option<redis::Client>,
+    channel: String,
+    instance_id: String,
+    // Opened on first publish and dropped after an error, so the next publish reconnects
+    connection: Mutex<Option<MultiplexedConnection>>,
+}
+
+// The Redis URL may carry a password
+impl std::fmt::Debug for InvalidationBus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("InvalidationBus")
+            .field("enabled", &self.enabled())
+            .field("channel", &self.channel)
+            .field("instance_id", &self.instance_id)
+            .finish()
+    }
+}
+
+impl InvalidationBus {
+    pub fn new(config: &InvalidationConfig) -> Result<Self> {
+        let client = config.redis_url.as_deref()
+            .map(redis::Client::open)
+            .transpose()
+            .context("Invalid INVALIDATION_REDIS_URL")?;
+
+        Ok(Self {
+            client,
+            channel: config.channel.clone(),
+            instance_id: uuid::Uuid::new_v4().to_string(),
+            connection: Mutex::new(None),
+        })
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.client.is_some()
+    }
+
+    // Failures are logged, not returned: the local change already happened, and the other
+    // instances catch up when their entries expire
+    pub async fn publish(&self, invalidation: Invalidation) {
+        let Some(client) = &self.client else {
+            return;
+        };
+
+        let payload = match serde_json::to_string(&Envelope { origin: self.instance_id.clone(), invalidation }) {
+            Ok(payload) => payload,
+            Err(e) => {
+                tracing::error!("Failed to serialize invalidation: {}", e);
+                return;
+            }
+        };
+
+        let mut connection = self.connection.lock().await;
+        if connection.is_none() {
+            match client.get_multiplexed_tokio_connection().await {
+                Ok(opened) => *connection = Some(opened),
+                Err(e) => {
+                    tracing::warn!("Failed to connect to Redis for invalidations: {}", e);
+                    return;
+                }
+            }
+        }
+
+        if let Some(open) = connection.as_mut() {
+            let published: redis::RedisResult<i64> = redis::cmd("PUBLISH").arg(&self.channel).arg(payload).query_async(open).await;
+            if let Err(e) = published {
+                tracing::warn!("Failed to publish invalidation: {}", e);
+                *connection = None;
+            }
+        }
+    }
+
+    // The invalidation in a message, unless this instance sent it
+    fn decode(&self, payload: &str) -> Option<Invalidation> {
+        match serde_json::from_str::<Envelope>(payload) {
+            Ok(envelope) if envelope.origin == self.instance_id => None,
+            Ok(envelope) => Some(envelope.invalidation),
+            Err(e) => {
+                tracing::warn!("Ignoring malformed invalidation message: {}", e);
+                None
+            }
+        }
+    }
+}
+
+// Apply another instance's change to this instance's caches
+async fn apply(state: &AppState, invalidation: Invalidation) -> Result<()> {
+    match invalidation {
+        Invalidation::Cache { preset_id, prompt } => {
+            let removed = state.storage.invalidate_cache(preset_id.as_deref(), prompt.as_deref()).await?;
+            tracing::debug!("Remote invalidation removed {} cache entries", removed);
+        }
+        Invalidation::StaleCache { preset_id, before } => {
+            state.storage.mark_cache_stale(&preset_id, before).await?;
+        }
+        Invalidation::PresetSelection { user_id, selection } => {
+            state.presets.apply_selection(&state.storage, &user_id, selection).await?;
+        }
+        // Saved here too, so the preset is still there after this instance restarts
+        Invalidation::PresetImport { preset } => {
+            state.storage.save_imported_preset(&preset).await?;
+            state.presets.apply_import(*preset)?;
+        }
+    }
+    Ok(())
+}
+
+// Listen for other instances' invalidations, resubscribing whenever the connection drops
+pub fn spawn_subscriber(state: Arc<AppState>) {
+    let Some(client) = state.invalidations.client.clone() else {
+        return;
+    };
+
+    tokio::spawn(async move {
+        loop {
+            if let Err(e) = subscribe(&state, &client).await {
+                tracing::warn!("Invalidation subscription failed: {:#}", e);
+            }
+            tokio::time::sleep(Duration::from_secs(RECONNECT_DELAY_SECS)).await;
+        }
+    });
+}
+
+async fn subscribe(state: &AppState, client: &redis::Client) -> Result<()> {
+    let mut pubsub = client.get_async_connection().await.context("Failed to connect to Redis")?.into_pubsub();
+    pubsub.subscribe(&state.invalidations.channel).await.context("Failed to subscribe")?;
+    tracing::info!("Subscribed to invalidations on {}", state.invalidations.channel);
+
+    let mut messages = pubsub.on_message();
+    while let Some(message) = messages.next().await {
+        let payload: String = match message.get_payload() {
+            Ok(payload) => payload,
+            Err(e) => {
+                tracing::warn!("Ignoring unreadable invalidation message: {}", e);
+                continue;
+            }
+        };
+
+        if let Some(invalidation) = state.invalidations.decode(&payload) {
+            if let Err(e) = apply(state, invalidation).await {
+                tracing::error!("Failed to apply invalidation: {:#}", e);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_own_messages_are_skipped() {
+        let config = InvalidationConfig { redis_url: None, channel: "test".to_string() };
+        let (local, remote) = (InvalidationBus::new(&config).unwrap(), InvalidationBus::new(&config).unwrap());
+        let invalidation = Invalidation::Cache { preset_id: Some("oracle".to_string()), prompt: None };
+
+        let payload = serde_json::to_string(&Envelope { origin: local.instance_id.clone(), invalidation: invalidation.clone() }).unwrap();
+        assert!(payload.contains(r#""kind":"cache""#));
+        assert_eq!(local.decode(&payload), None);
+        assert_eq!(remote.decode(&payload), Some(invalidation));
+        assert_eq!(remote.decode("not json"), None);
+    }
+}
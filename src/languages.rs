@@ -1,30 +1,65 @@
 use lazy_static::lazy_static;
 use std::collections::HashMap;
 use serde::{Deserialize, Serialize};
+use whatlang::Lang;
+
+use crate::glossary::Glossary;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TextDirection {
+    Ltr,
+    Rtl,
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Language {
     pub id: String,
     pub name: String,
     pub native_name: String,
+    // Layout direction of text in this language, for frontends setting `dir`
+    pub direction: TextDirection,
+    // ISO 15924 script code, e.g. `Arab` or `Hant`
+    pub script: String,
+    // BCP 47 locale tag, e.g. `zh-Hant-TW`, for `lang` attributes and Intl formatting
+    pub locale: String,
+}
+
+fn language(id: &str, name: &str, native_name: &str, direction: TextDirection, script: &str, locale: &str) -> Language {
+    Language {
+        id: id.to_string(),
+        name: name.to_string(),
+        native_name: native_name.to_string(),
+        direction,
+        script: script.to_string(),
+        locale: locale.to_string(),
+    }
 }
 
 lazy_static! {
-    static ref LANGUAGES: Vec<Language> = vec![
-        Language { id: "en".to_string(), name: "English".to_string(), native_name: "English".to_string() },
-        Language { id: "es".to_string(), name: "Spanish".to_string(), native_name: "Español".to_string() },
-        Language { id: "fr".to_string(), name: "French".to_string(), native_name: "Français".to_string() },
-        Language { id: "de".to_string(), name: "German".to_string(), native_name: "Deutsch".to_string() },
-        Language { id: "it".to_string(), name: "Italian".to_string(), native_name: "Italiano".to_string() },
-        Language { id: "pt".to_string(), name: "Portuguese".to_string(), native_name: "Português".to_string() },
-        Language { id: "ru".to_string(), name: "Russian".to_string(), native_name: "Русский".to_string() },
-        Language { id: "zh-TW".to_string(), name: "Traditional Chinese".to_string(), native_name: "正體中文".to_string() },
-        Language { id: "zh-CN".to_string(), name: "Simplified Chinese".to_string(), native_name: "简体中文".to_string() },
-        Language { id: "ja".to_string(), name: "Japanese".to_string(), native_name: "日本語".to_string() },
-        Language { id: "ko".to_string(), name: "Korean".to_string(), native_name: "한국어".to_string() },
-        Language { id: "ar".to_string(), name: "Arabic".to_string(), native_name: "العربية".to_string() },
-        Language { id: "hi".to_string(), name: "Hindi".to_string(), native_name: "हिन्दी".to_string() },
-    ];
+    static ref LANGUAGES: Vec<Language> = {
+        use TextDirection::{Ltr, Rtl};
+        vec![
+            language("en", "English", "English", Ltr, "Latn", "en-US"),
+            language("es", "Spanish", "Español", Ltr, "Latn", "es-ES"),
+            language("fr", "French", "Français", Ltr, "Latn", "fr-FR"),
+            language("de", "German", "Deutsch", Ltr, "Latn", "de-DE"),
+            language("it", "Italian", "Italiano", Ltr, "Latn", "it-IT"),
+            language("pt", "Portuguese", "Português", Ltr, "Latn", "pt-PT"),
+            language("ru", "Russian", "Русский", Ltr, "Cyrl", "ru-RU"),
+            language("zh-TW", "Traditional Chinese", "正體中文", Ltr, "Hant", "zh-Hant-TW"),
+            language("zh-CN", "Simplified Chinese", "简体中文", Ltr, "Hans", "zh-Hans-CN"),
+            language("ja", "Japanese", "日本語", Ltr, "Jpan", "ja-JP"),
+            language("ko", "Korean", "한국어", Ltr, "Kore", "ko-KR"),
+            language("ar", "Arabic", "العربية", Rtl, "Arab", "ar"),
+            language("he", "Hebrew", "עברית", Rtl, "Hebr", "he-IL"),
+            language("hi", "Hindi", "हिन्दी", Ltr, "Deva", "hi-IN"),
+        ]
+    };
+
+    static ref LANGUAGES_ETAG: String = crate::etag::compute(
+        &serde_json::to_vec(&*LANGUAGES).expect("languages serialize"),
+    );
 
     static ref LANGUAGE_MAP: HashMap<String, Language> = {
         let mut map = HashMap::new();
@@ -41,17 +76,94 @@ pub fn get_all_languages() -> Vec<Language> {
     LANGUAGES.clone()
 }
 
+// Validator for the language endpoints; the list is compiled in
+pub fn etag() -> &'static str {
+    &LANGUAGES_ETAG
+}
+
 pub fn get_language_by_id(id: &str) -> Language {
     LANGUAGE_MAP.get(id).cloned().unwrap_or_else(|| LANGUAGES[0].clone())
 }
 
-pub fn get_translation_prompt(language_id: &str) -> String {
-    if language_id == "en" {
+// Exact lookup, for write paths where an unknown ID is a client error
+pub fn find_language(id: &str) -> Option<Language> {
+    LANGUAGE_MAP.get(id).cloned()
+}
+
+// Follow the configured fallback chain (e.g. zh-HK -> zh-TW) to the first supported
+// language, ending at the default language when the chain runs out or loops
+pub fn resolve_language(id: &str, fallbacks: &HashMap<String, String>) -> Language {
+    let mut current = id;
+    let mut seen = Vec::new();
+    loop {
+        if let Some(language) = find_language(current) {
+            return language;
+        }
+        seen.push(current);
+        match fallbacks.get(current) {
+            Some(next) if !seen.contains(&next.as_str()) => current = next,
+            _ => return get_language_by_id(DEFAULT_LANGUAGE_ID),
+        }
+    }
+}
+
+// Common characters that only occur in Traditional Chinese, telling zh-TW from zh-CN text
+const TRADITIONAL_ONLY: &str = "這個們來說時會對學國為與麼裡後還讓經過點開關嗎見";
+
+// The supported language a prompt is written in, if it can be told reliably. Short or mixed
+// text often can't, and then the caller keeps its default.
+pub fn detect_language(text: &str) -> Option<&'static str> {
+    let info = whatlang::detect(text).filter(|info| info.is_reliable())?;
+    let id = match info.lang() {
+        Lang::Eng => "en",
+        Lang::Spa => "es",
+        Lang::Fra => "fr",
+        Lang::Deu => "de",
+        Lang::Ita => "it",
+        Lang::Por => "pt",
+        Lang::Rus => "ru",
+        Lang::Cmn if text.chars().any(|c| TRADITIONAL_ONLY.contains(c)) => "zh-TW",
+        Lang::Cmn => "zh-CN",
+        Lang::Jpn => "ja",
+        Lang::Kor => "ko",
+        Lang::Ara => "ar",
+        Lang::Heb => "he",
+        Lang::Hin => "hi",
+        _ => return None,
+    };
+    Some(id)
+}
+
+// How a saying requested in a language other than English is laid out
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TranslationMode {
+    // The English original as a blockquote, followed by the translation
+    #[default]
+    Bilingual,
+    // Only the requested language
+    NativeOnly,
+    // Only English, whatever language was requested
+    EnglishOnly,
+}
+
+pub fn get_translation_prompt(language_id: &str, mode: TranslationMode) -> String {
+    if language_id == "en" || mode == TranslationMode::EnglishOnly {
         return String::new();
     }
     
     let language = get_language_by_id(language_id);
     
+    if mode == TranslationMode::NativeOnly {
+        return format!(
+            r#"
+Regardless of the instructions above, you MUST write your response entirely in {} ({}).
+Do not include an English version, and do not include any notes about the language or translation.
+"#,
+            language.name, language.native_name
+        );
+    }
+    
     format!(
         r#"
 Regardless of the instructions above, you MUST format your responses as follows:
@@ -70,4 +182,146 @@ If you're unsure about any specialized terms, use the most appropriate translati
 "#,
         language.name, language.native_name, language.name
     )
-} 
\ No newline at end of file
+}
+
+// System prompt for translating an existing saying, as opposed to generating one
+pub fn translate_only_prompt(language: &Language, glossary: &Glossary) -> String {
+    let prompt = format!(
+        "Translate the user's message into {} ({}). Reply with the translation only: no quotes, \
+        notes or explanations, and keep the tone and line breaks of the original.",
+        language.name, language.native_name
+    );
+    append_glossary(prompt, glossary, &language.id)
+}
+
+fn append_glossary(prompt: String, glossary: &Glossary, language_id: &str) -> String {
+    let instructions = glossary.instructions(language_id);
+    if instructions.is_empty() {
+        prompt
+    } else {
+        format!("{}\n\n{}", prompt, instructions)
+    }
+}
+
+// The English text of a saying; bilingual sayings carry it as a leading blockquote
+pub fn english_original(content: &str) -> String {
+    let quoted: Vec<&str> = content.trim_start()
+        .lines()
+        .map_while(|line| line.trim_start().strip_prefix('>'))
+        .map(str::trim)
+        .collect();
+
+    if quoted.is_empty() {
+        content.trim().to_string()
+    } else {
+        quoted.join("\n").trim().to_string()
+    }
+}
+
+// The part of a generated response to keep for the mode. Models sometimes answer in the
+// bilingual format anyway; in native-only mode the English blockquote is dropped.
+pub fn parse_response(content: String, language_id: &str, mode: TranslationMode) -> String {
+    if language_id == DEFAULT_LANGUAGE_ID || mode != TranslationMode::NativeOnly {
+        return content;
+    }
+
+    let native = content.trim_start()
+        .lines()
+        .skip_while(|line| line.trim_start().starts_with('>'))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let native = native.trim();
+    if native.is_empty() {
+        content
+    } else {
+        native.to_string()
+    }
+}
+
+// Lay out an English saying and its separately made translation for the mode
+pub fn compose(english: String, translation: String, mode: TranslationMode) -> String {
+    match mode {
+        TranslationMode::Bilingual => {
+            let quoted: Vec<String> = english.lines().map(|line| format!("> {}", line)).collect();
+            format!("{}\n\n{}", quoted.join("\n"), translation)
+        }
+        TranslationMode::NativeOnly => translation,
+        TranslationMode::EnglishOnly => english,
+    }
+}
+
+// Append the translation instructions to a system prompt, if the language needs any
+pub fn with_translation_prompt(system_prompt: String, language_id: &str, mode: TranslationMode, glossary: &Glossary) -> String {
+    let translation_prompt = get_translation_prompt(language_id, mode);
+    if translation_prompt.is_empty() {
+        system_prompt
+    } else {
+        append_glossary(format!("{}\n\n{}", system_prompt, translation_prompt), glossary, language_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_language_follows_fallbacks() {
+        let fallbacks = HashMap::from([
+            ("zh-HK".to_string(), "zh-MO".to_string()),
+            ("zh-MO".to_string(), "zh-TW".to_string()),
+            ("xx".to_string(), "yy".to_string()),
+            ("yy".to_string(), "xx".to_string()),
+        ]);
+
+        assert_eq!(resolve_language("zh-HK", &fallbacks).id, "zh-TW");
+        assert_eq!(resolve_language("ja", &fallbacks).id, "ja");
+        assert_eq!(resolve_language("tlh", &fallbacks).id, DEFAULT_LANGUAGE_ID);
+        // A cycle ends at the default instead of spinning
+        assert_eq!(resolve_language("xx", &fallbacks).id, DEFAULT_LANGUAGE_ID);
+        assert!(find_language("zh-HK").is_none());
+    }
+
+    #[test]
+    fn test_english_original() {
+        assert_eq!(english_original("> Stay hungry.\n> Stay foolish.\n\n保持飢餓。"), "Stay hungry.\nStay foolish.");
+        assert_eq!(english_original("Plain English saying"), "Plain English saying");
+    }
+
+    #[test]
+    fn test_parse_response_by_mode() {
+        let bilingual = "> Stay hungry.\n\n保持飢餓。".to_string();
+
+        assert_eq!(parse_response(bilingual.clone(), "zh-TW", TranslationMode::NativeOnly), "保持飢餓。");
+        assert_eq!(parse_response(bilingual.clone(), "zh-TW", TranslationMode::Bilingual), bilingual);
+        assert_eq!(parse_response("保持飢餓。".to_string(), "zh-TW", TranslationMode::NativeOnly), "保持飢餓。");
+        assert!(get_translation_prompt("zh-TW", TranslationMode::EnglishOnly).is_empty());
+    }
+
+    #[test]
+    fn test_compose_round_trips_through_parsing() {
+        let composed = compose("Stay hungry.\nStay foolish.".to_string(), "保持飢餓。".to_string(), TranslationMode::Bilingual);
+
+        assert_eq!(composed, "> Stay hungry.\n> Stay foolish.\n\n保持飢餓。");
+        assert_eq!(english_original(&composed), "Stay hungry.\nStay foolish.");
+        assert_eq!(parse_response(composed, "zh-TW", TranslationMode::NativeOnly), "保持飢餓。");
+    }
+
+    #[test]
+    fn test_detect_language() {
+        assert_eq!(detect_language("今日はとても良い天気ですね。散歩に行きましょう。"), Some("ja"));
+        assert_eq!(detect_language("Quelle est la meilleure façon d'apprendre une nouvelle langue ?"), Some("fr"));
+        assert_eq!(detect_language("我們來說說這個時代的學問"), Some("zh-TW"));
+        assert_eq!(detect_language("我们来说说这个时代的学问"), Some("zh-CN"));
+        assert_eq!(detect_language("ok"), None);
+    }
+
+    #[test]
+    fn test_rtl_languages() {
+        let rtl: Vec<String> = get_all_languages()
+            .into_iter()
+            .filter(|language| language.direction == TextDirection::Rtl)
+            .map(|language| language.id)
+            .collect();
+        assert_eq!(rtl, vec!["ar", "he"]);
+    }
+}
@@ -0,0 +1,156 @@
+use anyhow::{anyhow, Context, Result};
+use redis::aio::MultiplexedConnection;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+use crate::config::LeaderElectionConfig;
+use crate::AppState;
+
+// The one lease covering every scheduled job
+const SCHEDULER_LEASE_KEY: &str = "prompt-wrapper:leader:scheduler";
+
+// Take the lease if it's free, or extend it if we already hold it, in one step. A GET then
+// PEXPIRE from the client could extend a lease another instance took in between.
+const ACQUIRE_SCRIPT: &str = r"
+local holder = redis.call('GET', KEYS[1])
+if holder == false then
+    redis.call('SET', KEYS[1], ARGV[1], 'PX', ARGV[2])
+    return 1
+elseif holder == ARGV[1] then
+    redis.call('PEXPIRE', KEYS[1], ARGV[2])
+    return 1
+end
+return 0
+";
+
+// Decides which of several instances runs the scheduled jobs (daily sayings, notifications,
+// cache warm-up and refresh). The leader holds a lease in Redis (LEADER_REDIS_URL) and
+// renews it at a third of its length; when it stops, another instance takes over once the
+// lease lapses. Memory and Sled storage belong to one instance, so they can't hold it.
+// Without LEADER_ELECTION_ENABLED every instance considers itself the leader.
+pub struct LeaderElection {
+    config: LeaderElectionConfig,
+    client: Option<redis::Client>,
+    instance_id: String,
+    leader: AtomicBool,
+    // Opened on first renewal and dropped after an error, so the next renewal reconnects
+    connection: Mutex<Option<MultiplexedConnection>>,
+}
+
+// The Redis URL may carry a password
+impl std::fmt::Debug for LeaderElection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LeaderElection")
+            .field("enabled", &self.config.enabled)
+            .field("instance_id", &self.instance_id)
+            .field("leader", &self.is_leader())
+            .finish()
+    }
+}
+
+impl LeaderElection {
+    pub fn new(config: LeaderElectionConfig) -> Result<Self> {
+        let client = match (config.enabled, config.redis_url.as_deref()) {
+            (false, _) => None,
+            (true, Some(url)) => Some(redis::Client::open(url).context("Invalid LEADER_REDIS_URL")?),
+            (true, None) => {
+                return Err(anyhow!(
+                    "LEADER_ELECTION_ENABLED needs a Redis server the instances share (LEADER_REDIS_URL or INVALIDATION_REDIS_URL)"
+                ))
+            }
+        };
+
+        Ok(Self {
+            leader: AtomicBool::new(!config.enabled),
+            config,
+            client,
+            instance_id: uuid::Uuid::new_v4().to_string(),
+            connection: Mutex::new(None),
+        })
+    }
+
+    // Cheap enough to check on every job tick
+    pub fn is_leader(&self) -> bool {
+        self.leader.load(Ordering::Relaxed)
+    }
+
+    // Take or renew the lease and record the outcome. A Redis error counts as lost
+    // leadership, so jobs pause rather than risk running twice.
+    pub async fn renew(&self) -> bool {
+        let Some(client) = &self.client else {
+            return true;
+        };
+
+        let leader = match self.acquire(client).await {
+            Ok(acquired) => acquired,
+            Err(e) => {
+                tracing::error!("Failed to renew scheduler lease: {:#}", e);
+                false
+            }
+        };
+
+        match (self.leader.swap(leader, Ordering::Relaxed), leader) {
+            (false, true) => tracing::info!("Became scheduler leader ({})", self.instance_id),
+            (true, false) => tracing::warn!("Lost scheduler leadership ({})", self.instance_id),
+            _ => {}
+        }
+        leader
+    }
+
+    async fn acquire(&self, client: &redis::Client) -> Result<bool> {
+        // Only put back once it has worked
+        let mut connection = self.connection.lock().await;
+        let mut open = match connection.take() {
+            Some(open) => open,
+            None => client.get_multiplexed_tokio_connection().await.context("Failed to connect to Redis")?,
+        };
+
+        let acquired: i64 = redis::Script::new(ACQUIRE_SCRIPT)
+            .key(SCHEDULER_LEASE_KEY)
+            .arg(&self.instance_id)
+            .arg(self.config.lease_seconds.saturating_mul(1000))
+            .invoke_async(&mut open)
+            .await
+            .context("Lease script failed")?;
+        *connection = Some(open);
+        Ok(acquired == 1)
+    }
+}
+
+pub fn spawn_task(state: Arc<AppState>) {
+    if !state.config.leader_election.enabled {
+        return;
+    }
+
+    let period = std::time::Duration::from_secs((state.config.leader_election.lease_seconds / 3).max(1));
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(period);
+        loop {
+            ticker.tick().await;
+            state.leader.renew().await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(enabled: bool, redis_url: Option<&str>) -> LeaderElectionConfig {
+        LeaderElectionConfig { enabled, lease_seconds: 30, redis_url: redis_url.map(str::to_string) }
+    }
+
+    #[tokio::test]
+    async fn test_election_needs_a_shared_redis() {
+        // Storage is per instance, so there's nothing to elect with
+        assert!(LeaderElection::new(config(true, None)).is_err());
+        assert!(LeaderElection::new(config(true, Some("redis://127.0.0.1:6379"))).is_ok());
+
+        // Off, every instance leads
+        let election = LeaderElection::new(config(false, None)).unwrap();
+        assert!(election.is_leader());
+        assert!(election.renew().await);
+    }
+}
@@ -0,0 +1,181 @@
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use serde::Serialize;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, RwLock};
+
+use crate::config::LeaderboardConfig;
+use crate::models::UserStats;
+use crate::streaks;
+use crate::AppState;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LeaderboardEntry {
+    pub rank: usize,
+    // Anonymized, stable for the lifetime of the process
+    pub user: String,
+    pub value: u32,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct LeaderboardSnapshot {
+    pub generated_at: Option<DateTime<Utc>>,
+    pub window_days: i64,
+    pub by_sayings: Vec<LeaderboardEntry>,
+    pub by_streak: Vec<LeaderboardEntry>,
+}
+
+// Rankings are computed periodically in the background and served from memory,
+// so requests never scan storage
+pub struct Leaderboard {
+    config: LeaderboardConfig,
+    salt: u64,
+    snapshot: RwLock<Arc<LeaderboardSnapshot>>,
+}
+
+impl Leaderboard {
+    pub fn new(config: LeaderboardConfig) -> Self {
+        let snapshot = LeaderboardSnapshot {
+            window_days: config.window_days,
+            ..Default::default()
+        };
+
+        Self {
+            config,
+            salt: rand::random(),
+            snapshot: RwLock::new(Arc::new(snapshot)),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.config.enabled
+    }
+
+    pub fn snapshot(&self) -> Arc<LeaderboardSnapshot> {
+        self.snapshot.read().unwrap().clone()
+    }
+
+    fn anonymize(&self, user_id: &str) -> String {
+        let mut hasher = DefaultHasher::new();
+        self.salt.hash(&mut hasher);
+        user_id.hash(&mut hasher);
+        format!("user-{:08x}", hasher.finish() as u32)
+    }
+
+    // Read the stats of users who opted in once and rebuild both rankings. The window is
+    // counted in whole UTC days, today included.
+    pub async fn refresh(&self, state: &AppState) -> anyhow::Result<()> {
+        let now = state.clock.now();
+        let window_start = (now - Duration::days((self.config.window_days - 1).max(0))).date_naive();
+        let mut by_sayings = Vec::new();
+        let mut by_streak = Vec::new();
+
+        for (user_id, preferences) in state.storage.list_preferences().await? {
+            if !preferences.leaderboard {
+                continue;
+            }
+
+            let stats = state.storage.get_user_stats(&user_id).await?;
+            let Some((sayings, streak)) = score(&stats, window_start, now) else {
+                continue;
+            };
+            let user = self.anonymize(&user_id);
+
+            by_sayings.push((user.clone(), sayings));
+            if streak > 0 {
+                by_streak.push((user, streak));
+            }
+        }
+
+        let snapshot = LeaderboardSnapshot {
+            generated_at: Some(now),
+            window_days: self.config.window_days,
+            by_sayings: rank(by_sayings, self.config.size),
+            by_streak: rank(by_streak, self.config.size),
+        };
+
+        *self.snapshot.write().unwrap() = Arc::new(snapshot);
+        Ok(())
+    }
+}
+
+// Sayings in the window and the current streak counted over them, or None without any
+fn score(stats: &UserStats, window_start: NaiveDate, now: DateTime<Utc>) -> Option<(u32, u32)> {
+    let in_window: Vec<_> = stats.active_days.range(window_start..).map(|(_, day)| day).collect();
+
+    if in_window.is_empty() {
+        return None;
+    }
+
+    let sayings = in_window.iter().map(|day| day.sayings).sum::<u64>();
+    let active = in_window.iter().flat_map(|day| [day.first_at, day.last_at]);
+    let streak = streaks::compute_streak(active, streaks::offset_from_minutes(0), now);
+    Some((u32::try_from(sayings).unwrap_or(u32::MAX), streak.current))
+}
+
+fn rank(mut scores: Vec<(String, u32)>, size: usize) -> Vec<LeaderboardEntry> {
+    scores.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+    scores.into_iter()
+        .take(size)
+        .enumerate()
+        .map(|(i, (user, value))| LeaderboardEntry { rank: i + 1, user, value })
+        .collect()
+}
+
+// Periodically rebuild the leaderboard in the background
+pub fn spawn_refresh_task(state: Arc<AppState>) {
+    if !state.leaderboard.enabled() {
+        return;
+    }
+
+    let period = std::time::Duration::from_secs(state.config.leaderboard.refresh_seconds.max(1));
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(period);
+        loop {
+            ticker.tick().await;
+            if let Err(e) = state.leaderboard.refresh(&state).await {
+                tracing::error!("Failed to refresh leaderboard: {}", e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Saying, SayingSource};
+
+    fn history(ages_in_days: &[i64], now: DateTime<Utc>) -> UserStats {
+        let sayings: Vec<Saying> = ages_in_days.iter()
+            .map(|&age| Saying {
+                created_at: now - Duration::days(age),
+                ..Saying::new("c".to_string(), "p".to_string(), SayingSource::LLM)
+            })
+            .collect();
+        UserStats::from_sayings("user", &sayings)
+    }
+
+    #[test]
+    fn test_only_sayings_in_the_window_score() {
+        let now = Utc::now();
+        let window_start = (now - Duration::days(6)).date_naive();
+
+        // Today twice, yesterday and the day before, plus two before the window
+        assert_eq!(score(&history(&[0, 0, 1, 2, 10, 11], now), window_start, now), Some((4, 3)));
+        // Active in the window, but not lately enough to have a streak running
+        assert_eq!(score(&history(&[4, 5], now), window_start, now), Some((2, 0)));
+        assert_eq!(score(&history(&[10], now), window_start, now), None);
+    }
+
+    #[test]
+    fn test_rankings_are_ordered_and_capped() {
+        let scores = vec![("user-b".to_string(), 3), ("user-c".to_string(), 5), ("user-a".to_string(), 3)];
+
+        let ranked = rank(scores, 2);
+        let users: Vec<_> = ranked.iter().map(|entry| (entry.rank, entry.user.as_str(), entry.value)).collect();
+        // Ties are broken by the anonymized name, so the order is stable between refreshes
+        assert_eq!(users, [(1, "user-c", 5), (2, "user-a", 3)]);
+    }
+}
@@ -0,0 +1,451 @@
+use axum::{
+    error_handling::HandleErrorLayer,
+    http::HeaderName,
+    middleware,
+    routing::{delete, get, post, put},
+    Extension, Router,
+};
+use dotenv::dotenv;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::Notify;
+use tower::limit::GlobalConcurrencyLimitLayer;
+use tower::timeout::TimeoutLayer;
+use tower::ServiceBuilder;
+use tower_http::catch_panic::CatchPanicLayer;
+use tower_http::cors::{Any, CorsLayer};
+use tower_http::trace::TraceLayer;
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+
+pub mod access;
+pub mod achievements;
+pub mod admin;
+pub mod audit;
+#[cfg(feature = "sqlite-migration")]
+pub mod backend_migration;
+pub mod bans;
+pub mod bench;
+pub mod card;
+pub mod chat;
+pub mod cli;
+pub mod clock;
+pub mod compression;
+pub mod concurrency;
+pub mod config;
+pub mod daily;
+pub mod debug_log;
+pub mod email;
+pub mod etag;
+pub mod exemptions;
+pub mod flags;
+pub mod glossary;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod handlers;
+pub mod http_client;
+pub mod invalidation;
+pub mod leader;
+pub mod leaderboard;
+pub mod metrics;
+pub mod migrations;
+pub mod models;
+pub mod notifier;
+pub mod openrouter;
+pub mod preset;
+pub mod prompt_rotation;
+pub mod quality;
+pub mod queue;
+pub mod rate_limiter;
+pub mod read_only;
+pub mod routing;
+pub mod self_test;
+pub mod signing;
+pub mod storage;
+pub mod streaks;
+pub mod tokens;
+pub mod translator;
+pub mod user_hash;
+pub mod warmup;
+pub mod languages;
+
+use crate::access::{AccessPolicy, TrustedProxies};
+use crate::audit::AuditLog;
+use crate::bans::BanList;
+use crate::chat::TurnLocks;
+use crate::cli::Command;
+use crate::clock::Clock;
+use crate::exemptions::ExemptionList;
+use crate::flags::FeatureFlags;
+use crate::glossary::Glossary;
+use crate::debug_log::DebugLog;
+use crate::invalidation::InvalidationBus;
+use crate::leader::LeaderElection;
+use crate::config::{Config, HttpClientConfig, ProviderType, StorageType};
+use crate::leaderboard::Leaderboard;
+use crate::metrics::Metrics;
+use crate::notifier::Notifier;
+use crate::openrouter::OpenRouterClient;
+use crate::preset::Presets;
+use crate::queue::GenerationQueue;
+use crate::rate_limiter::{RateLimiter, ReadLimiter};
+use crate::signing::PresetKeys;
+use crate::storage::Storage;
+use crate::translator::Translator;
+
+// Application state that will be shared between handlers
+pub struct AppState {
+    pub config: Config,
+    pub openrouter: OpenRouterClient,
+    pub rate_limiter: RateLimiter,
+    pub read_limiter: ReadLimiter,
+    pub storage: Storage,
+    pub presets: Presets,
+    // Signs preset exports and checks signed imports
+    pub preset_keys: PresetKeys,
+    pub glossary: Glossary,
+    pub leaderboard: Leaderboard,
+    pub metrics: Arc<Metrics>,
+    pub notifier: Notifier,
+    pub bans: Arc<BanList>,
+    pub exemptions: ExemptionList,
+    pub flags: FeatureFlags,
+    // Decides who may act as which user
+    pub access: Box<dyn AccessPolicy>,
+    // Translates sayings, in the generation prompt or through a translation service
+    pub translator: Box<dyn Translator>,
+    // Over-quota generation requests waiting for capacity (RATE_LIMIT_MODE=queue)
+    pub queue: GenerationQueue,
+    // Scrubbed request and upstream payloads (DEBUG_LOG_ENABLED)
+    pub debug_log: Arc<DebugLog>,
+    pub invalidations: Arc<InvalidationBus>,
+    pub leader: LeaderElection,
+    // Wakes status long-polls whenever daily sayings are generated
+    pub daily_published: Notify,
+    // Time as seen by the rate limiter, preset selections and the audit log
+    pub clock: Arc<dyn Clock>,
+    // Hash-chained record of admin actions
+    pub audit: AuditLog,
+    // Serializes the turns of each chat conversation
+    pub chat_turns: TurnLocks,
+}
+
+// Initialize the sandbox users (SANDBOX_USERS) with a fresh quota
+async fn initialize_sandbox_users(app_state: &Arc<AppState>) -> anyhow::Result<()> {
+    for user_id in &app_state.config.access.sandbox_users {
+        tracing::info!("Initializing sandbox user with ID: {}", user_id);
+        
+        // Note: We use reset() which gives the user their full quota, but follows normal rules
+        app_state.rate_limiter.reset(user_id).await?;
+    }
+    
+    // Don't pre-populate any sayings or select presets - the mock provider generates them
+    // through the regular workflow
+    Ok(())
+}
+
+// Everything the binary does: read the command line, then serve or run the subcommand
+pub async fn run() -> anyhow::Result<()> {
+    // Load environment variables from .env file
+    dotenv().ok();
+
+    // Initialize tracing
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::EnvFilter::new(
+            std::env::var("RUST_LOG").unwrap_or_else(|_| "info".into()),
+        ))
+        .with(tracing_subscriber::fmt::layer())
+        .init();
+
+    match cli::parse_args(std::env::args().skip(1))? {
+        Command::Serve => serve(Config::from_env()).await,
+        Command::Bench(args) => bench::run(args).await,
+        // Migrations only touch storage, so don't insist on an OpenRouter key
+        #[cfg(feature = "sqlite-migration")]
+        Command::MigrateBackend(args) => {
+            let config = Config::from_env_with_provider(ProviderType::Mock);
+            backend_migration::run_cli(&config.storage.connection_string, args).await
+        }
+        Command::SelfTest => self_test::run_cli(&Config::from_env()).await,
+        Command::Migrate { dry_run, hash_user_ids } => {
+            migrations::run_cli(&Config::from_env_with_provider(ProviderType::Mock), dry_run, hash_user_ids)
+        }
+    }
+}
+
+// Build the shared application state from config
+pub fn build_app_state(config: Config) -> anyhow::Result<Arc<AppState>> {
+    // Ensure data directory exists for Sled if needed
+    if let StorageType::Sled = config.storage.type_ {
+        let path = Path::new(&config.storage.connection_string);
+        if let Some(parent) = path.parent() {
+            if !parent.exists() {
+                tracing::info!("Creating data directory: {:?}", parent);
+                fs::create_dir_all(parent)?;
+            }
+        }
+    }
+
+    // Load presets
+    let presets_path = &config.presets.file_path;
+    let invalidations = Arc::new(InvalidationBus::new(&config.invalidation)?);
+    let clock = clock::system();
+    let presets = Presets::from_file(presets_path)?
+        .with_invalidations(invalidations.clone())
+        .with_max_rating(config.presets.max_rating)
+        .with_clock(clock.clone())
+        .with_rng_seed(config.presets.rng_seed);
+    if let Some(seed) = config.presets.rng_seed {
+        tracing::info!("Picking presets and prompts with PRESETS_RNG_SEED {}", seed);
+    }
+    let preset_keys = PresetKeys::from_config(&config.presets)?;
+    if let Some(public_key) = preset_keys.public_key() {
+        tracing::info!("Signing preset exports with public key {}", public_key);
+    }
+    let glossary = match &config.languages.glossary_path {
+        Some(path) => Glossary::from_file(path)?,
+        None => Glossary::default(),
+    };
+    let flags = match &config.feature_flags.file_path {
+        Some(path) => FeatureFlags::from_file(path)?,
+        None => FeatureFlags::default(),
+    };
+
+    // Initialize services
+    let http_client = http_client::build(&config.http_client)?;
+    // OpenRouter may need its own way out, e.g. where openrouter.ai is blocked
+    let openrouter_http_client = match &config.openrouter.proxy_url {
+        Some(proxy_url) => http_client::build(&HttpClientConfig {
+            proxy_url: Some(proxy_url.clone()),
+            ..config.http_client.clone()
+        })?,
+        None => http_client.clone(),
+    };
+    let metrics = Arc::new(Metrics::new());
+    let debug_log = Arc::new(DebugLog::new(&config));
+    let openrouter_client = OpenRouterClient::new(config.openrouter.clone(), openrouter_http_client, metrics.clone(), debug_log.clone());
+    let rate_limiter = RateLimiter::new(config.rate_limit.clone()).with_clock(clock.clone());
+    let read_limiter = ReadLimiter::new(config.read_rate_limit.clone()).with_clock(clock.clone());
+    let queue = GenerationQueue::new(config.rate_limit.queue_size, config.rate_limit.queue_per_user);
+    let storage = Storage::new(config.storage.clone())?;
+    let flags = flags.with_user_ids(storage.user_id_hasher());
+    let exemptions = ExemptionList::new(&config.rate_limit, storage.user_id_hasher());
+    let leaderboard = Leaderboard::new(config.leaderboard.clone());
+    let translator = translator::from_config(&config.translation, openrouter_client.clone(), http_client.clone(), glossary.clone());
+    let notifier = Notifier::new(config.notifications.clone(), http_client)?;
+    let bans = Arc::new(BanList::new(storage.user_id_hasher()));
+    let access = access::from_config(&config.access, bans.clone(), storage.user_id_hasher());
+    let leader = LeaderElection::new(config.leader_election.clone())?;
+    
+    // Create and share application state
+    Ok(Arc::new(AppState {
+        config,
+        openrouter: openrouter_client,
+        rate_limiter,
+        read_limiter,
+        storage,
+        presets,
+        preset_keys,
+        glossary,
+        leaderboard,
+        metrics,
+        notifier,
+        bans,
+        exemptions,
+        flags,
+        access,
+        translator,
+        queue,
+        debug_log,
+        invalidations,
+        leader,
+        daily_published: Notify::new(),
+        clock,
+        audit: AuditLog::default(),
+        chat_turns: TurnLocks::default(),
+    }))
+}
+
+// Build the HTTP router on top of the application state
+pub fn build_router(app_state: Arc<AppState>) -> Router {
+    // Set up CORS
+    let cors = CorsLayer::new()
+        .allow_origin(Any)
+        .allow_methods(Any)
+        .allow_headers(Any)
+        // Browser clients need to read the issued history token and list totals
+        .expose_headers([HeaderName::from_static("x-owner-token"), HeaderName::from_static("x-total-count")]);
+
+    let trusted_proxies = TrustedProxies(Arc::new(app_state.config.access.trusted_proxies.clone()));
+    let metrics = app_state.metrics.clone();
+    let catch_panic = CatchPanicLayer::custom(move |panic| handlers::panic_response(&metrics, panic));
+
+    // Overrunning requests get a 503 TIMEOUT body instead of a dropped connection. The limit
+    // is shared by every route, so MAX_CONCURRENT_REQUESTS caps the whole service.
+    let server = &app_state.config.server;
+    let metrics = app_state.metrics.clone();
+    let limits = ServiceBuilder::new()
+        .layer(HandleErrorLayer::new(move |err: tower::BoxError| {
+            let metrics = metrics.clone();
+            async move { handlers::middleware_error(&metrics, err) }
+        }))
+        .option_layer((server.request_timeout_seconds > 0)
+            .then(|| TimeoutLayer::new(Duration::from_secs(server.request_timeout_seconds))))
+        // Both branches of the timeout's option_layer must fail with the same error type
+        .map_err(tower::BoxError::from)
+        .option_layer((server.max_concurrent_requests > 0)
+            .then(|| GlobalConcurrencyLimitLayer::new(server.max_concurrent_requests)));
+
+    // Long polls are meant to sit idle, so they neither time out nor hold a request slot
+    let long_polls = Router::new()
+        .route("/users/:user_id/status/wait", get(handlers::wait_for_user_status));
+
+    // Define routes
+    Router::new()
+        // Sayings resource
+        .route("/sayings", get(handlers::get_sayings).post(handlers::create_saying))
+        .route("/sayings/estimate", post(handlers::estimate_saying))
+        .route("/chat", post(handlers::chat))
+        .route("/sayings/latest", get(handlers::get_latest_saying))
+        .route("/sayings/daily", get(handlers::get_daily_saying))
+        .route("/sayings/:saying_id", get(handlers::get_saying).patch(handlers::edit_saying))
+        .route("/sayings/:saying_id/feedback", post(handlers::create_feedback))
+        .route("/sayings/:saying_id/translate", post(handlers::translate_saying))
+        .route("/sayings/:saying_id/share", post(handlers::share_saying))
+        .route("/share/:token", get(handlers::get_shared_saying))
+        .route("/share/:token/card.png", get(handlers::get_share_card))
+        
+        // Prompt autocomplete
+        .route("/prompts/suggest", get(handlers::suggest_prompts))
+        
+        // Queued generation requests
+        .route("/jobs/:job_id", get(handlers::get_job))
+        
+        // Collections resource
+        .route("/collections", get(handlers::get_collections).post(handlers::create_collection))
+        .route("/collections/shared/:token", get(handlers::get_shared_collection))
+        .route("/collections/:collection_id", get(handlers::get_collection))
+        .route("/collections/:collection_id/sayings", post(handlers::add_saying_to_collection))
+        .route("/collections/:collection_id/export", get(handlers::export_collection))
+        .route("/collections/:collection_id/share", post(handlers::share_collection))
+        
+        // User status resource
+        .route("/users/status", post(handlers::bulk_user_status))
+        .route("/users/:user_id/status", get(handlers::get_user_status))
+        .route("/users/:user_id/preset", post(handlers::pin_preset))
+        .route("/users/:user_id/achievements", get(handlers::get_user_achievements))
+        .route("/users/:user_id/stats", get(handlers::get_user_stats))
+        .route("/users/:user_id/prompts", get(handlers::get_prompt_history))
+        .route("/users/:user_id/conversations", get(handlers::get_conversations))
+        .route("/users/:user_id/conversations/:conversation_id", get(handlers::get_conversation).delete(handlers::delete_conversation))
+        .route("/users/:user_id/notifications", get(handlers::get_notifications).post(handlers::create_notification))
+        .route("/users/:user_id/notifications/:target_id", delete(handlers::delete_notification))
+        .route("/users/:user_id/email", get(handlers::get_email_preferences).put(handlers::update_email_preferences).delete(handlers::delete_email_preferences))
+        .route("/unsubscribe/:token", get(handlers::unsubscribe_page).post(handlers::unsubscribe))
+        
+        // Leaderboard resource
+        .route("/leaderboard", get(handlers::get_leaderboard))
+        .route("/users/:user_id/leaderboard", get(handlers::get_leaderboard_opt_in).put(handlers::update_leaderboard_opt_in))
+        
+        // Presets resource
+        .route("/presets", get(handlers::get_presets))
+        .route("/presets/:preset_id", get(handlers::get_preset))
+        
+        // Languages resource
+        .route("/languages", get(handlers::get_languages))
+        .route("/languages/:language_id", get(handlers::get_language))
+        
+        // Operator dashboard
+        .route("/admin", get(admin::dashboard))
+        .route("/admin/audit", get(admin::audit_log))
+        .route("/admin/freeform-prompts", get(admin::freeform_prompts))
+        .route("/admin/presets", get(admin::presets))
+        .route("/admin/presets/import", post(admin::import_preset))
+        .route("/admin/presets/:preset_id/export", get(admin::export_preset))
+        .route("/admin/cache", get(admin::list_cache).delete(admin::invalidate_cache))
+        .route("/admin/bans", get(admin::list_bans).post(admin::create_ban))
+        .route("/admin/bans/:kind/:subject", delete(admin::delete_ban))
+        .route("/admin/credits", post(admin::grant_credits))
+        .route("/admin/owners", post(admin::set_owner))
+        .route("/admin/exemptions", get(admin::list_exemptions).post(admin::create_exemption))
+        .route("/admin/exemptions/:kind/:subject", delete(admin::delete_exemption))
+        .route("/admin/flags", get(admin::list_flags))
+        .route("/admin/flags/:name", put(admin::set_flag).delete(admin::delete_flag))
+        .route("/admin/debug/recent", get(admin::debug_recent))
+        .route("/admin/canary", get(admin::canary))
+        .route("/admin/shadow", get(admin::shadow_outputs))
+        .route("/admin/quality", get(admin::quality))
+        .route("/admin/prompts/underperforming", get(admin::underperforming_prompts))
+        .route_layer(limits)
+        .merge(long_polls)
+        
+        // Inside the debug log, so a panicked request is recorded with its 500
+        .layer(catch_panic)
+        .layer(middleware::from_fn_with_state(app_state.clone(), read_only::middleware))
+        .layer(middleware::from_fn_with_state(app_state.clone(), debug_log::middleware))
+        .layer(cors)
+        .layer(TraceLayer::new_for_http())
+        // Outermost, so every extractor of the caller sees it
+        .layer(Extension(trusted_proxies))
+        .with_state(app_state)
+}
+
+async fn serve(config: Config) -> anyhow::Result<()> {
+    // Always reported; only strict mode (STARTUP_SELF_TEST) refuses to start on a failure
+    let report = self_test::run(&config).await;
+    report.log();
+    if !report.passed() {
+        if config.self_test.on_startup {
+            anyhow::bail!("Startup checks failed");
+        }
+        tracing::warn!("Startup checks failed; starting anyway as STARTUP_SELF_TEST is off");
+    }
+
+    let app_state = build_app_state(config.clone())?;
+    app_state.bans.load(&app_state.storage).await?;
+    app_state.exemptions.load(&app_state.storage).await?;
+    app_state.flags.load(&app_state.storage).await?;
+    app_state.presets.load_imported(&app_state.storage).await?;
+    
+    if !app_state.config.server.read_only {
+        if let Err(e) = initialize_sandbox_users(&app_state).await {
+            tracing::warn!("Failed to initialize sandbox users: {}", e);
+        }
+    }
+
+    // Background jobs; the ones that generate or send sayings are left to writable instances,
+    // and among several instances to the leader. Settle leadership before the first run.
+    app_state.leader.renew().await;
+    leader::spawn_task(app_state.clone());
+    leaderboard::spawn_refresh_task(app_state.clone());
+    prompt_rotation::spawn_task(app_state.clone());
+    if app_state.config.server.read_only {
+        tracing::info!("Read-only mode: writes are rejected and scheduled generation is off");
+    } else {
+        notifier::spawn_daily_task(app_state.clone());
+        warmup::spawn_task(app_state.clone());
+        warmup::spawn_refresh_task(app_state.clone());
+        daily::spawn_task(app_state.clone());
+    }
+    rate_limiter::spawn_cleanup_task(app_state.clone());
+    queue::spawn_task(app_state.clone());
+    invalidation::spawn_subscriber(app_state.clone());
+    #[cfg(feature = "grpc")]
+    grpc::spawn_server(app_state.clone())?;
+
+    let app = build_router(app_state);
+
+    // Start server
+    let addr = format!("{}:{}", config.server.host, config.server.port)
+        .parse::<SocketAddr>()
+        .expect("Invalid socket address");
+    tracing::info!("listening on {}", addr);
+    let listener = TcpListener::bind(addr).await?;
+    // Peer addresses are needed for IP bans
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await?;
+
+    Ok(())
+}
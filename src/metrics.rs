@@ -0,0 +1,89 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// Process-wide counters, cheap enough to bump on every request
+#[derive(Debug)]
+pub struct Metrics {
+    pub started_at: DateTime<Utc>,
+    pub sayings_generated: AtomicU64,
+    pub cache_served: AtomicU64,
+    pub rate_limited: AtomicU64,
+    // Requests from exempt users and tokens, which skip the rate limiter
+    pub exempt_requests: AtomicU64,
+    pub upstream_errors: AtomicU64,
+    // Empty or unusable model outputs that had to be regenerated
+    pub degenerate_responses: AtomicU64,
+    // Translations that missed a preferred glossary term (with GLOSSARY_VALIDATE)
+    pub glossary_violations: AtomicU64,
+    // Over-quota requests queued instead of rejected (RATE_LIMIT_MODE=queue)
+    pub queued_requests: AtomicU64,
+    // Times the primary model was judged degraded and traffic moved to the fallback
+    pub model_failovers: AtomicU64,
+    // Handlers that panicked and were answered with a 500
+    pub handler_panics: AtomicU64,
+    // Requests cut off at REQUEST_TIMEOUT_SECONDS
+    pub request_timeouts: AtomicU64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MetricsSnapshot {
+    pub started_at: DateTime<Utc>,
+    pub sayings_generated: u64,
+    pub cache_served: u64,
+    pub rate_limited: u64,
+    pub exempt_requests: u64,
+    pub upstream_errors: u64,
+    pub degenerate_responses: u64,
+    pub glossary_violations: u64,
+    pub queued_requests: u64,
+    pub model_failovers: u64,
+    pub handler_panics: u64,
+    pub request_timeouts: u64,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self {
+            started_at: Utc::now(),
+            sayings_generated: AtomicU64::new(0),
+            cache_served: AtomicU64::new(0),
+            rate_limited: AtomicU64::new(0),
+            exempt_requests: AtomicU64::new(0),
+            upstream_errors: AtomicU64::new(0),
+            degenerate_responses: AtomicU64::new(0),
+            glossary_violations: AtomicU64::new(0),
+            queued_requests: AtomicU64::new(0),
+            model_failovers: AtomicU64::new(0),
+            handler_panics: AtomicU64::new(0),
+            request_timeouts: AtomicU64::new(0),
+        }
+    }
+
+    pub fn incr(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            started_at: self.started_at,
+            sayings_generated: self.sayings_generated.load(Ordering::Relaxed),
+            cache_served: self.cache_served.load(Ordering::Relaxed),
+            rate_limited: self.rate_limited.load(Ordering::Relaxed),
+            exempt_requests: self.exempt_requests.load(Ordering::Relaxed),
+            upstream_errors: self.upstream_errors.load(Ordering::Relaxed),
+            degenerate_responses: self.degenerate_responses.load(Ordering::Relaxed),
+            glossary_violations: self.glossary_violations.load(Ordering::Relaxed),
+            queued_requests: self.queued_requests.load(Ordering::Relaxed),
+            model_failovers: self.model_failovers.load(Ordering::Relaxed),
+            handler_panics: self.handler_panics.load(Ordering::Relaxed),
+            request_timeouts: self.request_timeouts.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
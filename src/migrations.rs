@@ -0,0 +1,454 @@
+use anyhow::{anyhow, Context, Result};
+use serde_json::Value;
+
+use crate::compression;
+use crate::config::{Config, StorageType};
+use crate::models;
+use crate::storage;
+use crate::user_hash::UserIdHasher;
+
+// Bump this and append to MIGRATIONS whenever the persisted layout changes
+pub const CURRENT_SCHEMA_VERSION: u32 = 6;
+
+// Tree names shared with the Sled storage backend
+pub const SAYING_INDEX_TREE: &str = "saying_index";
+pub const CONTENT_INDEX_TREE: &str = "content_index";
+
+pub const META_TREE: &str = "meta";
+const SCHEMA_VERSION_KEY: &[u8] = b"schema_version";
+
+struct Migration {
+    // Schema version the data is at once this migration has run
+    version: u32,
+    description: &'static str,
+    // Returns the number of records changed (or that would change, in a dry run)
+    run: fn(&sled::Db, bool) -> Result<usize>,
+}
+
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "Normalize legacy saying records and start tracking the schema version",
+        run: migrate_v1_normalize_sayings,
+    },
+    Migration {
+        version: 2,
+        description: "Build the saying ID -> user ID index",
+        run: migrate_v2_build_saying_index,
+    },
+    Migration {
+        version: 3,
+        description: "Build the content hash -> global cache key index",
+        run: migrate_v3_build_content_index,
+    },
+    Migration {
+        version: 4,
+        description: "Key the global cache on the prompt hash",
+        run: migrate_v4_rekey_global_cache,
+    },
+    Migration {
+        version: 5,
+        description: "Recount user stats from each history, recording active days for streaks",
+        run: migrate_v5_rebuild_user_stats,
+    },
+    Migration {
+        version: 6,
+        description: "Build the unsubscribe token -> user ID index",
+        run: migrate_v6_index_unsubscribe_tokens,
+    },
+];
+
+#[derive(Debug)]
+pub struct MigrationStep {
+    pub version: u32,
+    pub description: &'static str,
+    pub records_changed: usize,
+}
+
+#[derive(Debug)]
+pub struct MigrationReport {
+    pub from_version: u32,
+    pub to_version: u32,
+    pub dry_run: bool,
+    pub steps: Vec<MigrationStep>,
+}
+
+// Read the stored schema version. Databases written before versioning existed report 0,
+// while a brand new database is considered to already be at the current version.
+pub fn schema_version(db: &sled::Db) -> Result<u32> {
+    let meta = db.open_tree(META_TREE).context("Failed to open meta tree")?;
+
+    match meta.get(SCHEMA_VERSION_KEY).context("Failed to read schema version")? {
+        Some(ivec) => {
+            let bytes: [u8; 4] = ivec
+                .as_ref()
+                .try_into()
+                .map_err(|_| anyhow!("Corrupt schema version record"))?;
+            Ok(u32::from_be_bytes(bytes))
+        }
+        None if is_empty(db)? => Ok(CURRENT_SCHEMA_VERSION),
+        None => Ok(0),
+    }
+}
+
+fn set_schema_version(db: &sled::Db, version: u32) -> Result<()> {
+    let meta = db.open_tree(META_TREE).context("Failed to open meta tree")?;
+    meta.insert(SCHEMA_VERSION_KEY, &version.to_be_bytes())
+        .context("Failed to write schema version")?;
+    db.flush().context("Failed to flush schema version")?;
+    Ok(())
+}
+
+fn is_empty(db: &sled::Db) -> Result<bool> {
+    let global_tree = db.open_tree("global_cache").context("Failed to open global cache tree")?;
+    Ok(db.is_empty() && global_tree.is_empty())
+}
+
+// Bring the database up to CURRENT_SCHEMA_VERSION, one version at a time.
+// In a dry run nothing is written and the reported counts are what each step would change.
+pub fn run_migrations(db: &sled::Db, dry_run: bool) -> Result<MigrationReport> {
+    let from_version = schema_version(db)?;
+
+    if from_version > CURRENT_SCHEMA_VERSION {
+        return Err(anyhow!(
+            "Database schema version {} is newer than this build supports ({})",
+            from_version, CURRENT_SCHEMA_VERSION
+        ));
+    }
+
+    let mut steps = Vec::new();
+
+    for migration in MIGRATIONS.iter().filter(|m| m.version > from_version) {
+        let records_changed = (migration.run)(db, dry_run)
+            .with_context(|| format!("Migration to schema version {} failed", migration.version))?;
+
+        if !dry_run {
+            set_schema_version(db, migration.version)?;
+        }
+
+        steps.push(MigrationStep {
+            version: migration.version,
+            description: migration.description,
+            records_changed,
+        });
+    }
+
+    // Stamp fresh databases so they are not mistaken for legacy ones later
+    if !dry_run && steps.is_empty() {
+        set_schema_version(db, from_version)?;
+    }
+
+    Ok(MigrationReport {
+        from_version,
+        to_version: CURRENT_SCHEMA_VERSION,
+        dry_run,
+        steps,
+    })
+}
+
+// v0 -> v1: fill in fields that older builds didn't write so every record
+// deserializes with the current Saying model
+fn migrate_v1_normalize_sayings(db: &sled::Db, dry_run: bool) -> Result<usize> {
+    let mut changed = 0;
+
+    for result in db.iter() {
+        let (key, ivec) = result.context("Failed to iterate Sled database")?;
+
+        // Skip internal keys
+        if key.starts_with(b"__") {
+            continue;
+        }
+
+        let mut sayings: Vec<Value> = serde_json::from_slice(&compression::decode(&ivec)?)
+            .context("Failed to parse user sayings record")?;
+
+        let mut record_changed = false;
+        for saying in sayings.iter_mut() {
+            record_changed |= normalize_saying_v1(saying);
+        }
+
+        if record_changed {
+            changed += 1;
+            if !dry_run {
+                db.insert(key, serde_json::to_vec(&sayings)?)
+                    .context("Failed to rewrite user sayings record")?;
+            }
+        }
+    }
+
+    let global_tree = db.open_tree("global_cache").context("Failed to open global cache tree")?;
+    for result in global_tree.iter() {
+        let (key, ivec) = result.context("Failed to iterate global cache")?;
+
+        let mut saying: Value = serde_json::from_slice(&compression::decode(&ivec)?)
+            .context("Failed to parse global cache entry")?;
+
+        if normalize_saying_v1(&mut saying) {
+            changed += 1;
+            if !dry_run {
+                global_tree.insert(key, serde_json::to_vec(&saying)?)
+                    .context("Failed to rewrite global cache entry")?;
+            }
+        }
+    }
+
+    Ok(changed)
+}
+
+fn normalize_saying_v1(saying: &mut Value) -> bool {
+    let Some(fields) = saying.as_object_mut() else {
+        return false;
+    };
+
+    let mut changed = false;
+    if !fields.contains_key("preset_id") {
+        fields.insert("preset_id".to_string(), Value::Null);
+        changed = true;
+    }
+    if !fields.contains_key("source") {
+        fields.insert("source".to_string(), Value::String("database".to_string()));
+        changed = true;
+    }
+
+    changed
+}
+
+// v1 -> v2: index every stored saying by ID
+fn migrate_v2_build_saying_index(db: &sled::Db, dry_run: bool) -> Result<usize> {
+    let index_tree = db.open_tree(SAYING_INDEX_TREE).context("Failed to open saying index tree")?;
+    let mut indexed = 0;
+
+    for result in db.iter() {
+        let (key, ivec) = result.context("Failed to iterate Sled database")?;
+
+        // Skip internal keys
+        if key.starts_with(b"__") {
+            continue;
+        }
+
+        let sayings: Vec<Value> = serde_json::from_slice(&compression::decode(&ivec)?)
+            .context("Failed to parse user sayings record")?;
+
+        for id in sayings.iter().filter_map(|s| s.get("id").and_then(Value::as_str)) {
+            indexed += 1;
+            if !dry_run {
+                index_tree.insert(id.as_bytes(), key.as_ref())
+                    .context("Failed to write saying index entry")?;
+            }
+        }
+    }
+
+    Ok(indexed)
+}
+
+// Content index entries are keyed `<content hash>/<cache key JSON>`, so all cache keys
+// holding the same quote sit next to each other
+pub fn content_index_key(content_hash: &str, cache_key: &[u8]) -> Vec<u8> {
+    let mut key = Vec::with_capacity(content_hash.len() + 1 + cache_key.len());
+    key.extend_from_slice(content_hash.as_bytes());
+    key.push(b'/');
+    key.extend_from_slice(cache_key);
+    key
+}
+
+// v2 -> v3: index every global cache entry by the hash of its content
+fn migrate_v3_build_content_index(db: &sled::Db, dry_run: bool) -> Result<usize> {
+    let global_tree = db.open_tree("global_cache").context("Failed to open global cache tree")?;
+    let index_tree = db.open_tree(CONTENT_INDEX_TREE).context("Failed to open content index tree")?;
+    let mut indexed = 0;
+
+    for result in global_tree.iter() {
+        let (key, ivec) = result.context("Failed to iterate global cache")?;
+
+        let saying: Value = serde_json::from_slice(&compression::decode(&ivec)?)
+            .context("Failed to parse global cache entry")?;
+        let Some(content) = saying.get("content").and_then(Value::as_str) else {
+            continue;
+        };
+
+        indexed += 1;
+        if !dry_run {
+            index_tree.insert(content_index_key(&models::content_hash(content), &key), key.as_ref())
+                .context("Failed to write content index entry")?;
+        }
+    }
+
+    Ok(indexed)
+}
+
+// v3 -> v4: global cache keys gain the hash of the prompts, language and model. Entries keep
+// their preset and prompt; the system prompt they were generated with wasn't stored, so
+// they are keyed as `CacheKey::from_saying` does for older sayings.
+fn migrate_v4_rekey_global_cache(db: &sled::Db, dry_run: bool) -> Result<usize> {
+    let global_tree = db.open_tree("global_cache").context("Failed to open global cache tree")?;
+    let index_tree = db.open_tree(CONTENT_INDEX_TREE).context("Failed to open content index tree")?;
+    let mut rekeyed = 0;
+
+    for result in global_tree.iter() {
+        let (key, ivec) = result.context("Failed to iterate global cache")?;
+
+        let saying: models::Saying = serde_json::from_slice(&compression::decode(&ivec)?)
+            .context("Failed to parse global cache entry")?;
+        let new_key = serde_json::to_vec(&models::CacheKey::from_saying(&saying))?;
+        if new_key == key.as_ref() {
+            continue;
+        }
+
+        rekeyed += 1;
+        if !dry_run {
+            let content_hash = saying.content_hash();
+            global_tree.remove(&key).context("Failed to remove old global cache entry")?;
+            global_tree.insert(&new_key, ivec).context("Failed to write global cache entry")?;
+            index_tree.remove(content_index_key(&content_hash, &key))
+                .context("Failed to remove old content index entry")?;
+            index_tree.insert(content_index_key(&content_hash, &new_key), new_key.as_slice())
+                .context("Failed to write content index entry")?;
+        }
+    }
+
+    Ok(rekeyed)
+}
+
+// v4 -> v5: user stats gain the days the user was active on. Aggregates written
+// before then don't have them, so every history is counted again.
+fn migrate_v5_rebuild_user_stats(db: &sled::Db, dry_run: bool) -> Result<usize> {
+    let stats_tree = db.open_tree(storage::USER_STATS_TREE).context("Failed to open user stats tree")?;
+    let mut rebuilt = 0;
+
+    for result in db.iter() {
+        let (key, ivec) = result.context("Failed to iterate Sled database")?;
+
+        // Skip internal keys
+        if key.starts_with(b"__") {
+            continue;
+        }
+
+        let sayings: Vec<models::Saying> = serde_json::from_slice(&compression::decode(&ivec)?)
+            .context("Failed to parse user sayings record")?;
+        let stats = models::UserStats::from_sayings(&String::from_utf8_lossy(&key), &sayings);
+
+        rebuilt += 1;
+        if !dry_run {
+            stats_tree.insert(&key, serde_json::to_vec(&stats)?)
+                .context("Failed to write user stats")?;
+        }
+    }
+
+    Ok(rebuilt)
+}
+
+// v5 -> v6: unsubscribe links are looked up through an index instead of scanning every
+// user's preferences
+fn migrate_v6_index_unsubscribe_tokens(db: &sled::Db, dry_run: bool) -> Result<usize> {
+    let preferences_tree = db.open_tree(storage::PREFERENCES_TREE).context("Failed to open preferences tree")?;
+    let index_tree = db.open_tree(storage::UNSUBSCRIBE_TOKENS_TREE).context("Failed to open unsubscribe tokens tree")?;
+    let mut indexed = 0;
+
+    for result in preferences_tree.iter() {
+        let (key, ivec) = result.context("Failed to iterate preferences")?;
+        let preferences: models::UserPreferences = serde_json::from_slice(&ivec)
+            .context("Failed to parse user preferences")?;
+        let Some(email) = preferences.email else {
+            continue;
+        };
+
+        indexed += 1;
+        if !dry_run {
+            index_tree.insert(email.unsubscribe_token.as_bytes(), key)
+                .context("Failed to write unsubscribe token index entry")?;
+        }
+    }
+
+    Ok(indexed)
+}
+
+// Entry point for `prompt-wrapper --migrate [--dry-run] [--hash-user-ids]`
+pub fn run_cli(config: &Config, dry_run: bool, hash_user_ids: bool) -> Result<()> {
+    if !matches!(config.storage.type_, StorageType::Sled) {
+        println!("Storage type has no persistent schema, nothing to migrate");
+        return Ok(());
+    }
+
+    let db = sled::open(&config.storage.connection_string).context("Failed to open Sled database")?;
+    let report = run_migrations(&db, dry_run)?;
+
+    println!(
+        "Schema version: {} -> {}{}",
+        report.from_version,
+        report.to_version,
+        if report.dry_run { " (dry run, nothing written)" } else { "" }
+    );
+
+    if report.steps.is_empty() {
+        println!("Database is up to date");
+    }
+
+    for step in &report.steps {
+        println!("  v{}: {} ({} records)", step.version, step.description, step.records_changed);
+    }
+
+    if hash_user_ids {
+        let salt = config.storage.user_id_salt.as_deref()
+            .ok_or_else(|| anyhow!("--hash-user-ids needs STORAGE_USER_ID_SALT to be set"))?;
+        let report = storage::hash_user_ids(&db, &UserIdHasher::new(salt), dry_run)?;
+        println!("Hashed user IDs in {} records", report.records_changed);
+        if report.conflicts > 0 {
+            println!("  {} records left under plaintext user IDs whose hashed IDs already hold data", report.conflicts);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_legacy_database_is_migrated() {
+        let temp_dir = tempdir().unwrap();
+        let db = sled::open(temp_dir.path().join("legacy-db")).unwrap();
+
+        // A record written before preset_id existed
+        db.insert(
+            b"legacy_user",
+            br#"[{"id":"1","content":"c","prompt":"p","created_at":"2024-01-01T00:00:00Z","source":"llm"}]"#.to_vec(),
+        ).unwrap();
+        assert_eq!(schema_version(&db).unwrap(), 0);
+
+        let dry_run = run_migrations(&db, true).unwrap();
+        assert_eq!(dry_run.steps[0].records_changed, 1);
+        assert_eq!(schema_version(&db).unwrap(), 0);
+
+        let report = run_migrations(&db, false).unwrap();
+        assert_eq!(report.from_version, 0);
+        assert_eq!(schema_version(&db).unwrap(), CURRENT_SCHEMA_VERSION);
+
+        let stored: Vec<Value> = serde_json::from_slice(&db.get(b"legacy_user").unwrap().unwrap()).unwrap();
+        assert!(stored[0].get("preset_id").unwrap().is_null());
+    }
+
+    #[test]
+    fn test_global_cache_is_rekeyed_on_prompt_hash() {
+        let temp_dir = tempdir().unwrap();
+        let db = sled::open(temp_dir.path().join("v3-db")).unwrap();
+        let global_tree = db.open_tree("global_cache").unwrap();
+        let index_tree = db.open_tree(CONTENT_INDEX_TREE).unwrap();
+
+        let old_key = br#"{"preset_id":"oracle","prompt":"p"}"#;
+        let saying = br#"{"id":"1","content":"c","prompt":"p","created_at":"2024-01-01T00:00:00Z","source":"cache","preset_id":"oracle","language_id":"fr"}"#;
+        global_tree.insert(old_key, saying.to_vec()).unwrap();
+        index_tree.insert(content_index_key(&models::content_hash("c"), old_key), old_key.to_vec()).unwrap();
+        set_schema_version(&db, 3).unwrap();
+
+        assert_eq!(run_migrations(&db, false).unwrap().steps[0].records_changed, 1);
+
+        let saying: models::Saying = serde_json::from_slice(saying).unwrap();
+        let new_key = serde_json::to_vec(&models::CacheKey::from_saying(&saying)).unwrap();
+        assert!(global_tree.get(old_key).unwrap().is_none());
+        assert!(global_tree.get(&new_key).unwrap().is_some());
+        assert_eq!(index_tree.iter().values().next().unwrap().unwrap().as_ref(), new_key.as_slice());
+        assert_eq!(index_tree.len(), 1);
+    }
+}
@@ -1,7 +1,12 @@
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, FixedOffset, NaiveDate, Utc};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::hash::{Hash, Hasher};
 
+use crate::email::EmailPreferences;
+use crate::notifier::NotificationTarget;
+use crate::streaks::{self, Streak};
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Saying {
     pub id: String,
@@ -10,43 +15,177 @@ pub struct Saying {
     pub created_at: DateTime<Utc>,
     pub source: SayingSource,
     pub preset_id: Option<String>, // Track which preset was used, if any
+    // Language the saying was requested in, if any
+    #[serde(default)]
+    pub language_id: Option<String>,
+    // Language detected from the prompt when the request didn't name one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detected_language_id: Option<String>,
+    // Token counts the provider reported for the completion, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<OpenRouterUsage>,
+    // Model that produced the saying, for attribution; unknown for older sayings
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    // Hash of the prompts, language and model the saying was generated from, see `prompt_hash`;
+    // unknown for older sayings
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt_hash: Option<String>,
+    // Global cache entries superseded by a newer one for their preset; kept for exact prompt
+    // matches but no longer handed out as random fallback
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub stale: bool,
+    // Every text the saying has had, oldest (the generated one) first, once its owner edited
+    // it; `content` is the last. Empty while the saying is unedited.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub versions: Vec<SayingVersion>,
+    // Set by the owner to keep the saying through history retention and the per-user cap
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub pinned: bool,
+    // Owner's labels for filtering their history, normalized by `normalize_tag`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+}
+
+// One text of an edited saying
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SayingVersion {
+    pub content: String,
+    pub created_at: DateTime<Utc>,
+    // False for the text the saying was generated (or served) with
+    pub edited: bool,
+}
+
+impl Saying {
+    // A fresh saying with a new ID; optional metadata is filled in by the caller
+    pub fn new(content: String, prompt: String, source: SayingSource) -> Self {
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            content,
+            prompt,
+            created_at: Utc::now(),
+            source,
+            preset_id: None,
+            language_id: None,
+            detected_language_id: None,
+            usage: None,
+            model: None,
+            prompt_hash: None,
+            stale: false,
+            versions: Vec::new(),
+            pinned: false,
+            tags: Vec::new(),
+        }
+    }
+
+    pub fn content_hash(&self) -> String {
+        content_hash(&self.content)
+    }
+
+    // Edited sayings are the user's own text and are never served to anyone else
+    pub fn is_edited(&self) -> bool {
+        !self.versions.is_empty()
+    }
+
+    // Replace the content, keeping the previous texts in `versions`
+    pub fn edit(&mut self, content: String) {
+        if self.versions.is_empty() {
+            self.versions.push(SayingVersion {
+                content: self.content.clone(),
+                created_at: self.created_at,
+                edited: false,
+            });
+        }
+        self.versions.push(SayingVersion { content: content.clone(), created_at: Utc::now(), edited: true });
+        self.content = content;
+    }
+}
+
+// Longest tag, in characters
+pub const MAX_TAG_CHARS: usize = 32;
+
+// Tags are compared lowercased; letters, digits, '-' and '_' only, so they're safe in
+// query strings and index keys
+pub fn normalize_tag(tag: &str) -> Option<String> {
+    let tag = tag.trim().to_lowercase();
+    let valid = !tag.is_empty()
+        && tag.chars().count() <= MAX_TAG_CHARS
+        && tag.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_');
+    valid.then_some(tag)
+}
+
+// Identifies a saying's text regardless of case and whitespace, so the same quote produced
+// by different prompts can be recognized. FNV-1a rather than std's hasher because the
+// result is persisted and must not change between builds.
+pub fn content_hash(content: &str) -> String {
+    let words = content.split_whitespace()
+        .flat_map(|word| word.to_lowercase().into_bytes().into_iter().chain(std::iter::once(b' ')));
+    format!("{:016x}", fnv1a(words))
+}
+
+// Identifies everything that shapes a generation: the rendered system prompt (translation
+// instructions included), the user prompt, the language and the model. Persisted, so FNV-1a
+// like `content_hash`; the parts are NUL-separated so they can't run into each other.
+pub fn prompt_hash(system_prompt: &str, user_prompt: &str, language_id: &str, model: &str) -> String {
+    let parts = [system_prompt, user_prompt, language_id, model]
+        .into_iter()
+        .flat_map(|part| part.bytes().chain(std::iter::once(0)));
+    format!("{:016x}", fnv1a(parts))
 }
 
-// Global cache key for identifying reusable sayings across users
+pub fn fnv1a(bytes: impl Iterator<Item = u8>) -> u64 {
+    bytes.fold(0xcbf2_9ce4_8422_2325, |hash, byte| (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3))
+}
+
+// Global cache key for identifying reusable sayings across users. The preset and prompt are
+// kept alongside the hash for invalidation filters and the admin listing.
 #[derive(Debug, Clone, Serialize, Deserialize, Eq)]
 pub struct CacheKey {
     pub preset_id: Option<String>,
     pub prompt: String,
+    // `prompt_hash` of the generation
+    pub hash: String,
 }
 
+// The prompt is part of the hash
 impl PartialEq for CacheKey {
     fn eq(&self, other: &Self) -> bool {
-        self.preset_id == other.preset_id && self.prompt == other.prompt
+        self.preset_id == other.preset_id && self.hash == other.hash
     }
 }
 
 impl Hash for CacheKey {
     fn hash<H: Hasher>(&self, state: &mut H) {
         self.preset_id.hash(state);
-        self.prompt.hash(state);
+        self.hash.hash(state);
     }
 }
 
 impl CacheKey {
-    pub fn new(preset_id: Option<String>, prompt: String) -> Self {
-        Self { preset_id, prompt }
+    pub fn new(preset_id: Option<String>, system_prompt: &str, prompt: String, language_id: &str, model: &str) -> Self {
+        let hash = prompt_hash(system_prompt, &prompt, language_id, model);
+        Self { preset_id, prompt, hash }
     }
     
-    // Create from a saying
+    // Create from a saying. Sayings from before prompt hashes were recorded are keyed on
+    // what they stored, with an empty system prompt.
     pub fn from_saying(saying: &Saying) -> Self {
+        let hash = saying.prompt_hash.clone().unwrap_or_else(|| prompt_hash(
+            "",
+            &saying.prompt,
+            saying.language_id.as_deref().unwrap_or_default(),
+            saying.model.as_deref().unwrap_or_default(),
+        ));
         Self {
             preset_id: saying.preset_id.clone(),
             prompt: saying.prompt.clone(),
+            hash,
         }
     }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum SayingSource {
     #[serde(rename = "llm")]
     LLM,
@@ -72,11 +211,477 @@ impl From<SayingSource> for String {
     }
 }
 
+// A named, user-owned group of sayings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Collection {
+    pub id: String,
+    pub user_id: String,
+    pub name: String,
+    pub saying_ids: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    // Set once the owner shares the collection; anyone with the token can read it. Only
+    // the owner's share response carries it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub share_token: Option<String>,
+}
+
+// A multi-turn exchange between a user and the model, kept whole for chat and multi-turn presets
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Conversation {
+    pub id: String,
+    pub user_id: String,
+    // Preset whose system prompt the conversation runs under, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preset_id: Option<String>,
+    // Oldest first; the full transcript, even once older turns are summarized
+    pub messages: Vec<ConversationMessage>,
+    // Stands in for the older messages when the context sent to the model would be too long
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary: Option<ConversationContextSummary>,
+    pub created_at: DateTime<Utc>,
+    // Time of the last message
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConversationContextSummary {
+    pub content: String,
+    // Number of leading messages the summary covers
+    pub covers: usize,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConversationMessage {
+    pub role: MessageRole,
+    pub content: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageRole {
+    System,
+    User,
+    Assistant,
+}
+
+impl MessageRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MessageRole::System => "system",
+            MessageRole::User => "user",
+            MessageRole::Assistant => "assistant",
+        }
+    }
+}
+
+impl Conversation {
+    pub fn new(user_id: String, preset_id: Option<String>) -> Self {
+        let now = Utc::now();
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            user_id,
+            preset_id,
+            messages: Vec::new(),
+            summary: None,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    pub fn push(&mut self, role: MessageRole, content: String) {
+        let now = Utc::now();
+        self.messages.push(ConversationMessage { role, content, created_at: now });
+        self.updated_at = now;
+    }
+}
+
+// Per-user settings that outlive a single request
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UserPreferences {
+    #[serde(default)]
+    pub notifications: Vec<NotificationTarget>,
+    #[serde(default)]
+    pub email: Option<EmailPreferences>,
+    // Ranked, anonymized, on the leaderboard; nobody is until they opt in
+    #[serde(default)]
+    pub leaderboard: bool,
+}
+
+// Summary of one global cache entry for the admin API
+#[derive(Debug, Clone, Serialize)]
+pub struct CacheEntryInfo {
+    pub preset_id: Option<String>,
+    pub prompt: String,
+    pub saying_id: String,
+    // Serialized size of the cached saying
+    pub size_bytes: usize,
+    pub created_at: DateTime<Utc>,
+    pub age_seconds: i64,
+    pub stale: bool,
+}
+
+impl CacheEntryInfo {
+    pub fn new(saying: &Saying, size_bytes: usize) -> Self {
+        Self {
+            preset_id: saying.preset_id.clone(),
+            prompt: saying.prompt.clone(),
+            saying_id: saying.id.clone(),
+            size_bytes,
+            created_at: saying.created_at,
+            age_seconds: (Utc::now() - saying.created_at).num_seconds(),
+            stale: saying.stale,
+        }
+    }
+}
+
+// A user's rating of one of their sayings, 1 (poor) to 5 (great). Rating again replaces it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SayingFeedback {
+    pub saying_id: String,
+    pub user_id: String,
+    pub preset_id: Option<String>,
+    pub prompt: String,
+    pub score: u8,
+    pub created_at: DateTime<Utc>,
+}
+
+// A public link to one saying, e.g. for social media; sharing again reuses the token
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SayingShare {
+    pub token: String,
+    pub saying_id: String,
+    pub user_id: String,
+    pub created_at: DateTime<Utc>,
+}
+
+// The saying shared by everyone for one preset and language on a UTC day
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DailySaying {
+    pub date: NaiveDate,
+    pub preset_id: String,
+    pub language_id: String,
+    pub saying: Saying,
+}
+
+// A stored saying translated after the fact, cached per (saying, language)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SayingTranslation {
+    pub saying_id: String,
+    pub language_id: String,
+    pub content: String,
+    pub created_at: DateTime<Utc>,
+}
+
+// A user's preset for one rate-limit window, persisted so it survives restarts
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PresetSelectionRecord {
+    pub preset_id: String,
+    pub selected_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    // Chosen by the user rather than picked at random
+    #[serde(default)]
+    pub pinned: bool,
+}
+
+// How much a preset gets used and how its sayings are rated
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PresetStats {
+    pub preset_id: String,
+    pub sayings: u64,
+    pub feedback_count: u64,
+    pub feedback_total: u64,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+impl PresetStats {
+    pub fn new(preset_id: &str) -> Self {
+        Self {
+            preset_id: preset_id.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn record_use(&mut self, at: DateTime<Utc>) {
+        self.sayings += 1;
+        self.last_used_at = self.last_used_at.max(Some(at));
+    }
+
+    // Count a new rating, or swap in the new score when the saying was already rated
+    pub fn record_feedback(&mut self, score: u8, previous: Option<u8>) {
+        match previous {
+            Some(previous) => self.feedback_total = self.feedback_total - u64::from(previous) + u64::from(score),
+            None => {
+                self.feedback_count += 1;
+                self.feedback_total += u64::from(score);
+            }
+        }
+    }
+
+    pub fn average_feedback(&self) -> Option<f64> {
+        (self.feedback_count > 0).then(|| self.feedback_total as f64 / self.feedback_count as f64)
+    }
+}
+
+// A user's generation history in numbers, kept up to date as sayings are saved
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UserStats {
+    pub user_id: String,
+    pub sayings: u64,
+    // Sayings without a preset or language are only counted in the total
+    pub by_preset: BTreeMap<String, u64>,
+    pub by_language: BTreeMap<String, u64>,
+    pub by_source: BTreeMap<String, u64>,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub total_tokens: u64,
+    pub first_activity_at: Option<DateTime<Utc>>,
+    pub last_activity_at: Option<DateTime<Utc>>,
+    // The first and last saying of each UTC day the user was active on, so streaks can be
+    // counted without reading the history. A UTC day overlaps at most two calendar days
+    // wherever the user is, and both of them are active exactly when these two fall on them.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub active_days: BTreeMap<NaiveDate, DayActivity>,
+    // When each preset and language was first used, so badges can be dated without the history
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub preset_first_used: BTreeMap<String, DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub language_first_used: BTreeMap<String, DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DayActivity {
+    pub first_at: DateTime<Utc>,
+    pub last_at: DateTime<Utc>,
+    pub sayings: u64,
+}
+
+impl UserStats {
+    pub fn new(user_id: &str) -> Self {
+        Self {
+            user_id: user_id.to_string(),
+            ..Default::default()
+        }
+    }
+
+    // Aggregate an existing history, e.g. for users with sayings from before stats were kept
+    pub fn from_sayings<'a>(user_id: &str, sayings: impl IntoIterator<Item = &'a Saying>) -> Self {
+        let mut stats = Self::new(user_id);
+        for saying in sayings {
+            stats.record(saying);
+        }
+        stats
+    }
+
+    pub fn record(&mut self, saying: &Saying) {
+        self.sayings += 1;
+        if let Some(preset_id) = &saying.preset_id {
+            *self.by_preset.entry(preset_id.clone()).or_default() += 1;
+            record_first_use(&mut self.preset_first_used, preset_id, saying.created_at);
+        }
+        if let Some(language_id) = &saying.language_id {
+            *self.by_language.entry(language_id.clone()).or_default() += 1;
+            record_first_use(&mut self.language_first_used, language_id, saying.created_at);
+        }
+        *self.by_source.entry(saying.source.to_string()).or_default() += 1;
+
+        if let Some(usage) = &saying.usage {
+            self.prompt_tokens += u64::from(usage.prompt_tokens.unwrap_or(0));
+            self.completion_tokens += u64::from(usage.completion_tokens.unwrap_or(0));
+            self.total_tokens += u64::from(usage.total_tokens.unwrap_or(0));
+        }
+
+        self.first_activity_at = Some(self.first_activity_at.map_or(saying.created_at, |at| at.min(saying.created_at)));
+        self.last_activity_at = self.last_activity_at.max(Some(saying.created_at));
+        self.active_days
+            .entry(saying.created_at.date_naive())
+            .and_modify(|day| {
+                day.first_at = day.first_at.min(saying.created_at);
+                day.last_at = day.last_at.max(saying.created_at);
+                day.sayings += 1;
+            })
+            .or_insert(DayActivity { first_at: saying.created_at, last_at: saying.created_at, sayings: 1 });
+    }
+
+    // Daily streaks in the user's timezone
+    pub fn streak(&self, offset: FixedOffset, now: DateTime<Utc>) -> Streak {
+        streaks::compute_streak(self.activity_bounds(), offset, now)
+    }
+
+    // The first and last saying of every active UTC day, oldest first
+    pub fn activity_bounds(&self) -> impl Iterator<Item = DateTime<Utc>> + '_ {
+        self.active_days.values().flat_map(|day| [day.first_at, day.last_at])
+    }
+}
+
+fn record_first_use(first_used: &mut BTreeMap<String, DateTime<Utc>>, id: &str, at: DateTime<Utc>) {
+    first_used
+        .entry(id.to_string())
+        .and_modify(|first| *first = (*first).min(at))
+        .or_insert(at);
+}
+
+// An LLM judge's marks for a generated saying, each from 1 (poor) to 5 (excellent)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QualityScore {
+    pub saying_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preset_id: Option<String>,
+    // Model that wrote the saying
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    // User prompt the saying answered
+    pub prompt: String,
+    // How well the saying answers the prompt
+    pub relevance: u8,
+    // How well it keeps to the voice the system prompt asks for
+    pub tone: u8,
+    // How well it keeps to the preset's length and format rules
+    pub length: u8,
+    pub judge_model: Option<String>,
+    pub scored_at: DateTime<Utc>,
+}
+
+impl QualityScore {
+    pub fn overall(&self) -> f64 {
+        f64::from(self.relevance + self.tone + self.length) / 3.0
+    }
+}
+
+// A second model's answer to a generation request, kept next to the answer the user got
+// so the two models can be compared offline
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ShadowOutput {
+    pub id: String,
+    // The saying the user got
+    pub saying_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preset_id: Option<String>,
+    pub prompt: String,
+    pub primary_model: Option<String>,
+    // Raw model outputs, before translations are split off
+    pub primary_content: String,
+    pub shadow_model: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shadow_content: Option<String>,
+    // Why the shadow call failed, when it did
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shadow_error: Option<String>,
+    pub shadow_latency_ms: u64,
+    pub created_at: DateTime<Utc>,
+}
+
+impl ShadowOutput {
+    // Sled key that sorts entries chronologically
+    pub fn storage_key(&self) -> String {
+        format!("{:020}-{}", self.created_at.timestamp_nanos_opt().unwrap_or_default(), self.id)
+    }
+}
+
+// A raw prompt submitted by a user, kept for abuse review
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FreeformPromptEntry {
+    pub id: String,
+    pub user_id: String,
+    pub prompt: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl FreeformPromptEntry {
+    pub fn new(user_id: String, prompt: String) -> Self {
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            user_id,
+            prompt,
+            created_at: Utc::now(),
+        }
+    }
+
+    // Sled key that sorts entries chronologically
+    pub fn storage_key(&self) -> String {
+        format!("{:020}-{}", self.created_at.timestamp_nanos_opt().unwrap_or_default(), self.id)
+    }
+}
+
+// A prompt the user has generated a saying from, indexed for suggestions
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PromptIndexEntry {
+    // As last typed; the index key is `prompt_search_key` of it
+    pub prompt: String,
+    pub uses: u64,
+    pub last_used_at: DateTime<Utc>,
+}
+
+impl PromptIndexEntry {
+    // Count another use of the prompt, starting a new entry for its first
+    pub fn record(entry: Option<Self>, prompt: &str, used_at: DateTime<Utc>) -> Self {
+        let uses = entry.map_or(0, |entry| entry.uses);
+        Self { prompt: prompt.to_string(), uses: uses + 1, last_used_at: used_at }
+    }
+}
+
+// A prompt the user submitted and what became of it, including failures, so it can be retried
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PromptHistoryEntry {
+    pub id: String,
+    pub prompt: String,
+    pub language_id: String,
+    pub created_at: DateTime<Utc>,
+    pub outcome: PromptOutcome,
+    // The saying generated or served for it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub saying_id: Option<String>,
+    // Error code of a failed request, as sent in the error body
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_code: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PromptOutcome {
+    Generated,
+    Cached,
+    Failed,
+}
+
+impl PromptHistoryEntry {
+    pub fn new(prompt: String, language_id: String, outcome: PromptOutcome) -> Self {
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            prompt,
+            language_id,
+            created_at: Utc::now(),
+            outcome,
+            saying_id: None,
+            error_code: None,
+        }
+    }
+}
+
+// Prompts are matched ignoring case and runs of whitespace
+pub fn prompt_search_key(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase()
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RateLimitInfo {
     pub user_id: String,
     pub remaining_requests: u32,
     pub reset_at: DateTime<Utc>,
+    // Extra requests granted by an operator for this window, spent before the base quota
+    #[serde(default)]
+    pub credits: u32,
+    // Requests answered from stored sayings this window instead of being generated
+    #[serde(default)]
+    pub cache_served: u32,
+}
+
+impl RateLimitInfo {
+    // Requests the user can still make in this window, credits included
+    pub fn available(&self) -> u32 {
+        self.remaining_requests.saturating_add(self.credits)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
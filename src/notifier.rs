@@ -0,0 +1,397 @@
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Duration, NaiveDate, Timelike, Utc};
+use futures_util::future::join_all;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use tokio::task::{JoinError, JoinSet};
+
+use crate::config::NotificationsConfig;
+use crate::email::{EmailPreferences, Mailer};
+use crate::handlers;
+use crate::metrics::Metrics;
+use crate::models::Saying;
+use crate::openrouter::GenerationOptions;
+use crate::AppState;
+
+const DISCORD_WEBHOOK_PREFIXES: [&str; 2] = ["https://discord.com/api/webhooks/", "https://discordapp.com/api/webhooks/"];
+const SLACK_WEBHOOK_PREFIX: &str = "https://hooks.slack.com/";
+// Users delivered to at once
+const DELIVERY_CONCURRENCY: usize = 16;
+
+// Where a user's daily saying gets pushed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum NotificationChannel {
+    // Sent through the bot configured with TELEGRAM_BOT_TOKEN
+    Telegram { chat_id: String },
+    Discord { webhook_url: String },
+    Slack { webhook_url: String },
+}
+
+impl NotificationChannel {
+    // Only accept webhooks on the providers' own hosts, so registrations can't point the
+    // server at arbitrary URLs
+    pub fn validate(&self, config: &NotificationsConfig) -> Result<(), String> {
+        match self {
+            NotificationChannel::Telegram { chat_id } => {
+                if config.telegram_bot_token.is_none() {
+                    return Err("Telegram notifications are not configured on this server".to_string());
+                }
+                if chat_id.trim().is_empty() {
+                    return Err("chat_id must not be empty".to_string());
+                }
+            }
+            NotificationChannel::Discord { webhook_url } => {
+                if !DISCORD_WEBHOOK_PREFIXES.iter().any(|prefix| webhook_url.starts_with(prefix)) {
+                    return Err("webhook_url must be a Discord webhook URL".to_string());
+                }
+            }
+            NotificationChannel::Slack { webhook_url } => {
+                if !webhook_url.starts_with(SLACK_WEBHOOK_PREFIX) {
+                    return Err("webhook_url must be a Slack incoming webhook URL".to_string());
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// A registered channel as persisted in the user's preferences
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NotificationTarget {
+    pub id: String,
+    #[serde(flatten)]
+    pub channel: NotificationChannel,
+    pub created_at: DateTime<Utc>,
+    // Day (UTC) of the last delivery attempt; each target gets one attempt per day
+    #[serde(default)]
+    pub last_attempt_on: Option<NaiveDate>,
+    // Why the last attempt failed, cleared on success
+    #[serde(default)]
+    pub last_error: Option<String>,
+}
+
+impl NotificationTarget {
+    pub fn new(channel: NotificationChannel) -> Self {
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            channel,
+            created_at: Utc::now(),
+            last_attempt_on: None,
+            last_error: None,
+        }
+    }
+
+    pub fn is_due(&self, now: DateTime<Utc>, daily_hour: u32) -> bool {
+        is_due(self.last_attempt_on, now, daily_hour)
+    }
+}
+
+// Due once per day, from the configured hour onwards
+fn is_due(last_attempt_on: Option<NaiveDate>, now: DateTime<Utc>, daily_hour: u32) -> bool {
+    now.hour() >= daily_hour && last_attempt_on != Some(now.date_naive())
+}
+
+pub struct Notifier {
+    config: NotificationsConfig,
+    client: Client,
+    mailer: Option<Mailer>,
+}
+
+impl Notifier {
+    pub fn new(config: NotificationsConfig, client: Client) -> Result<Self> {
+        let mailer = Mailer::from_config(&config.email)?;
+
+        Ok(Self {
+            config,
+            client,
+            mailer,
+        })
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.config.enabled
+    }
+
+    // Email needs an SMTP server on top of notifications being enabled
+    pub fn email_enabled(&self) -> bool {
+        self.config.enabled && self.mailer.is_some()
+    }
+
+    pub fn config(&self) -> &NotificationsConfig {
+        &self.config
+    }
+
+    async fn deliver(&self, channel: &NotificationChannel, text: &str) -> Result<()> {
+        let request = match channel {
+            NotificationChannel::Telegram { chat_id } => {
+                let token = self.config.telegram_bot_token.as_deref()
+                    .ok_or_else(|| anyhow!("TELEGRAM_BOT_TOKEN is not set"))?;
+                self.client
+                    .post(format!("https://api.telegram.org/bot{}/sendMessage", token))
+                    .json(&json!({ "chat_id": chat_id, "text": text }))
+            }
+            NotificationChannel::Discord { webhook_url } => {
+                self.client.post(webhook_url).json(&json!({ "content": text }))
+            }
+            NotificationChannel::Slack { webhook_url } => {
+                self.client.post(webhook_url).json(&json!({ "text": text }))
+            }
+        };
+
+        request.send().await
+            .context("Failed to send notification")?
+            .error_for_status()
+            .context("Notification was rejected")?;
+
+        Ok(())
+    }
+
+    pub async fn deliver_with_retry(&self, channel: &NotificationChannel, text: &str) -> Result<()> {
+        with_retry(self.config.max_retries, || self.deliver(channel, text)).await
+    }
+
+    pub async fn email_with_retry(&self, preferences: &EmailPreferences, saying: &Saying) -> Result<()> {
+        let mailer = self.mailer.as_ref().ok_or_else(|| anyhow!("SMTP is not configured"))?;
+        with_retry(self.config.max_retries, || mailer.send_daily(preferences, saying)).await
+    }
+}
+
+// Run `attempt` with exponential backoff between failures (1s, 2s, 4s, ...)
+async fn with_retry<F, Fut>(max_retries: u32, mut attempt: F) -> Result<()>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    let mut retries = 0;
+
+    loop {
+        match attempt().await {
+            Ok(()) => return Ok(()),
+            Err(e) if retries < max_retries => {
+                let backoff = std::time::Duration::from_secs(1 << retries.min(6));
+                tracing::warn!("Delivery attempt {} failed, retrying in {:?}: {:#}", retries + 1, backoff, e);
+                tokio::time::sleep(backoff).await;
+                retries += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// The saying to push today: the user's latest one if it's from today, otherwise a fresh
+// one from their preset of the day
+async fn daily_saying(state: &AppState, user_id: &str) -> Result<Arc<Saying>> {
+    let today = Utc::now().date_naive();
+
+    if let Some(saying) = state.storage.get_last_saying(user_id).await? {
+        if saying.created_at.date_naive() == today {
+            return Ok(saying);
+        }
+    }
+
+    let end_of_day = (today + Duration::days(1)).and_hms_opt(0, 0, 0).unwrap().and_utc();
+    let preset = state.presets.get_or_select_preset(&state.storage, user_id, end_of_day, state.config.presets.no_repeat).await?;
+    let prompt = state.presets.random_user_prompt(&preset.id)?;
+
+    let options = GenerationOptions {
+        provider: preset.provider.clone(),
+        constraints: Some(preset.constraints.clone()),
+        translated: false,
+        // Background work doesn't jump the line
+        priority: 0,
+        model: None,
+        // Users come from storage here, so their IDs may be hashed
+        mock: handlers::is_sandbox_owner(state, user_id),
+    };
+
+    let system_prompt = state.config.branding.apply(preset.system_prompt.clone());
+    let saying = state.openrouter.get_saying_with_options(&system_prompt, &prompt, &options).await
+        .inspect_err(|_| Metrics::incr(&state.metrics.upstream_errors))?;
+    let saying = Arc::new(Saying {
+        preset_id: Some(preset.id.clone()),
+        ..saying
+    });
+    Metrics::incr(&state.metrics.sayings_generated);
+
+    state.storage.save_saying(user_id, saying.clone()).await?;
+    if let Err(e) = state.storage.record_preset_use(&preset.id, saying.created_at).await {
+        tracing::error!("Failed to record usage of preset {}: {}", preset.id, e);
+    }
+    Ok(saying)
+}
+
+// Push today's saying to every registered target (and email subscription) that hasn't had it yet.
+// Each user is delivered to in their own task, so one user's retry backoff doesn't hold up the rest
+pub async fn deliver_due(state: &Arc<AppState>) -> Result<()> {
+    let now = Utc::now();
+    let daily_hour = state.notifier.config.daily_hour;
+    let mut tasks = JoinSet::new();
+
+    for (user_id, preferences) in state.storage.list_preferences().await? {
+        let due: Vec<NotificationTarget> = preferences.notifications.into_iter()
+            .filter(|target| target.is_due(now, daily_hour))
+            .collect();
+
+        let email_due = preferences.email
+            .filter(|email| email.daily && state.notifier.email_enabled())
+            .filter(|email| is_due(email.last_attempt_on, now, daily_hour));
+
+        if due.is_empty() && email_due.is_none() {
+            continue;
+        }
+
+        if tasks.len() >= DELIVERY_CONCURRENCY {
+            log_delivery(tasks.join_next().await);
+        }
+
+        let state = state.clone();
+        tasks.spawn(async move {
+            let outcome = deliver_to_user(&state, &user_id, due, email_due, now).await;
+            (user_id, outcome)
+        });
+    }
+
+    // Wait for the stragglers so the next run doesn't see their targets as still due
+    while !tasks.is_empty() {
+        log_delivery(tasks.join_next().await);
+    }
+
+    Ok(())
+}
+
+fn log_delivery(joined: Option<Result<(String, Result<()>), JoinError>>) {
+    match joined {
+        Some(Ok((user_id, Err(e)))) => tracing::error!("Failed to deliver daily sayings to user {}: {:#}", user_id, e),
+        Some(Err(e)) => tracing::error!("Daily saying delivery task failed: {}", e),
+        Some(Ok((_, Ok(())))) | None => {}
+    }
+}
+
+async fn deliver_to_user(
+    state: &AppState,
+    user_id: &str,
+    due: Vec<NotificationTarget>,
+    email_due: Option<EmailPreferences>,
+    now: DateTime<Utc>,
+) -> Result<()> {
+    // Leave the targets due so the next run tries again
+    let saying = daily_saying(state, user_id).await.context("Failed to get daily saying")?;
+
+    // Targets are independent, so a failing webhook doesn't delay the others
+    let saying = &saying;
+    let deliveries = due.iter().map(|target| async move {
+        let outcome = state.notifier.deliver_with_retry(&target.channel, &saying.content).await;
+        if let Err(e) = &outcome {
+            tracing::error!("Failed to deliver daily saying to user {} target {}: {:#}", user_id, target.id, e);
+        }
+        (target.id.clone(), outcome.err().map(|e| format!("{:#}", e)))
+    });
+    let email = async {
+        let email = email_due.as_ref()?;
+        let outcome = state.notifier.email_with_retry(email, saying).await;
+        if let Err(e) = &outcome {
+            tracing::error!("Failed to email daily saying to user {}: {:#}", user_id, e);
+        }
+        Some((email.unsubscribe_token.clone(), outcome.err().map(|e| format!("{:#}", e))))
+    };
+    let (outcomes, email_outcome) = tokio::join!(join_all(deliveries), email);
+    let mut outcomes: HashMap<_, _> = outcomes.into_iter().collect();
+
+    // Re-read so registrations changed while we were delivering aren't lost
+    let mut preferences = state.storage.get_preferences(user_id).await?;
+    for target in preferences.notifications.iter_mut() {
+        if let Some(error) = outcomes.remove(&target.id) {
+            target.last_attempt_on = Some(now.date_naive());
+            target.last_error = error;
+        }
+    }
+    if let (Some(email), Some((token, error))) = (preferences.email.as_mut(), email_outcome) {
+        // Only record the result against the same subscription we sent to
+        if email.unsubscribe_token == token {
+            email.last_attempt_on = Some(now.date_naive());
+            email.last_error = error;
+        }
+    }
+    state.storage.save_preferences(user_id, &preferences).await
+}
+
+// Periodically check for and send due daily sayings in the background
+pub fn spawn_daily_task(state: Arc<AppState>) {
+    if !state.notifier.enabled() {
+        return;
+    }
+
+    let period = std::time::Duration::from_secs(state.config.notifications.check_seconds.max(1));
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(period);
+        loop {
+            ticker.tick().await;
+            if !state.leader.is_leader() {
+                continue;
+            }
+            if let Err(e) = deliver_due(&state).await {
+                tracing::error!("Failed to deliver daily sayings: {}", e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn config() -> NotificationsConfig {
+        NotificationsConfig {
+            enabled: true,
+            daily_hour: 9,
+            check_seconds: 300,
+            max_retries: 3,
+            telegram_bot_token: None,
+            email: crate::config::EmailConfig {
+                smtp_host: None,
+                smtp_port: 587,
+                smtp_username: None,
+                smtp_password: None,
+                smtp_starttls: true,
+                from: "noreply@localhost".to_string(),
+                public_url: "http://localhost:3000".to_string(),
+            },
+        }
+    }
+
+    #[test]
+    fn test_webhooks_must_point_at_provider() {
+        let discord = NotificationChannel::Discord { webhook_url: "https://discord.com/api/webhooks/1/abc".to_string() };
+        let slack = NotificationChannel::Slack { webhook_url: "https://hooks.slack.com/services/T/B/x".to_string() };
+        let elsewhere = NotificationChannel::Slack { webhook_url: "http://169.254.169.254/latest".to_string() };
+        let telegram = NotificationChannel::Telegram { chat_id: "42".to_string() };
+
+        assert!(discord.validate(&config()).is_ok());
+        assert!(slack.validate(&config()).is_ok());
+        assert!(elsewhere.validate(&config()).is_err());
+        // No bot token configured
+        assert!(telegram.validate(&config()).is_err());
+    }
+
+    #[test]
+    fn test_target_is_due_once_per_day() {
+        let mut target = NotificationTarget::new(NotificationChannel::Telegram { chat_id: "42".to_string() });
+        let morning = Utc.with_ymd_and_hms(2024, 3, 1, 8, 0, 0).unwrap();
+        let later = Utc.with_ymd_and_hms(2024, 3, 1, 10, 0, 0).unwrap();
+
+        assert!(!target.is_due(morning, 9));
+        assert!(target.is_due(later, 9));
+
+        target.last_attempt_on = Some(later.date_naive());
+        assert!(!target.is_due(later, 9));
+        assert!(target.is_due(later + Duration::days(1), 9));
+    }
+}
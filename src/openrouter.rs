@@ -1,21 +1,92 @@
-use anyhow::{Result, Context, anyhow};
+use anyhow::{Result, anyhow};
+use chrono::Utc;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
+use std::sync::Arc;
+use thiserror::Error;
 
-use crate::config::OpenRouterConfig;
+use crate::concurrency::PriorityLimiter;
+use crate::debug_log::{DebugEntryKind, DebugLog};
+use crate::config::{OpenRouterConfig, ProviderPreferences, ProviderType};
+use crate::routing::{Canary, ModelHealth, RoutingEventKind};
+use crate::metrics::Metrics;
 use crate::models::{OpenRouterResponse, Saying, SayingSource};
+use crate::preset::ResponseConstraints;
 
 #[derive(Debug, Clone)]
 pub struct OpenRouterClient {
     config: OpenRouterConfig,
     client: Client,
+    metrics: Arc<Metrics>,
+    // Shared by every clone, so the cap holds across the whole service
+    limiter: Arc<PriorityLimiter>,
+    health: Arc<ModelHealth>,
+    canary: Arc<Canary>,
+    debug_log: Arc<DebugLog>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct Message {
-    role: String,
-    content: String,
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Message {
+    pub role: String,
+    pub content: String,
+}
+
+// Non-success responses from OpenRouter, classified so callers can react to each kind
+#[derive(Debug, Error)]
+pub enum UpstreamError {
+    #[error("OpenRouter rejected the API key ({status}): {body}")]
+    Auth { status: u16, body: String },
+    
+    #[error("OpenRouter account is out of credits: {0}")]
+    Quota(String),
+    
+    #[error("OpenRouter is rate limiting requests: {body}")]
+    RateLimited { retry_after: Option<u64>, body: String },
+    
+    #[error("OpenRouter rejected the request: {0}")]
+    BadRequest(String),
+    
+    #[error("OpenRouter API returned error {status}: {body}")]
+    Status { status: u16, body: String },
+    
+    #[error("OpenRouter returned no usable output after {attempts} attempts")]
+    Degenerate { attempts: u32 },
+    
+    #[error("OpenRouter request timed out: {0}")]
+    Timeout(String),
+}
+
+impl UpstreamError {
+    fn from_status(status: u16, retry_after: Option<u64>, body: String) -> Self {
+        match status {
+            400 => UpstreamError::BadRequest(body),
+            401 | 403 => UpstreamError::Auth { status, body },
+            402 => UpstreamError::Quota(body),
+            429 => UpstreamError::RateLimited { retry_after, body },
+            _ => UpstreamError::Status { status, body },
+        }
+    }
+}
+
+// Longest Retry-After worth waiting out inside a request
+const MAX_RETRY_AFTER_SECS: u64 = 10;
+
+// Per-request settings layered over the client configuration
+#[derive(Debug, Clone, Default)]
+pub struct GenerationOptions {
+    // Overrides the configured provider routing field by field
+    pub provider: Option<ProviderPreferences>,
+    // Length and format rules, enforced with max_tokens and checked after generation
+    pub constraints: Option<ResponseConstraints>,
+    // The system prompt asks for an English blockquote followed by a translation
+    pub translated: bool,
+    // Place in line when upstream calls are at LLM_MAX_CONCURRENT; the user's tier
+    pub priority: u32,
+    // Model for the first attempt, bypassing routing and the canary (shadow calls)
+    pub model: Option<String>,
+    // Answer from the mock provider whatever is configured (SANDBOX_USERS)
+    pub mock: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -25,10 +96,15 @@ pub struct ChatResponse {
 }
 
 impl OpenRouterClient {
-    pub fn new(config: OpenRouterConfig) -> Self {
+    pub fn new(config: OpenRouterConfig, client: Client, metrics: Arc<Metrics>, debug_log: Arc<DebugLog>) -> Self {
         Self {
+            limiter: PriorityLimiter::new(config.max_concurrent),
+            health: Arc::new(ModelHealth::new(config.routing.clone())),
+            canary: Arc::new(Canary::new(config.canary.clone())),
             config,
-            client: Client::new(),
+            client,
+            metrics,
+            debug_log,
         }
     }
 
@@ -41,36 +117,207 @@ impl OpenRouterClient {
     }
 
     pub async fn get_saying_with_system(&self, system_prompt: &str, user_prompt: &str) -> Result<Saying> {
+        self.get_saying_with_options(system_prompt, user_prompt, &GenerationOptions::default()).await
+    }
+
+    // Routing preferences to send: per-request ones over the configured defaults
+    fn provider_preferences(&self, options: &GenerationOptions) -> Option<ProviderPreferences> {
+        let preferences = match &options.provider {
+            Some(overrides) => overrides.merged_over(&self.config.provider_preferences),
+            None => self.config.provider_preferences.clone(),
+        };
+
+        (!preferences.is_empty()).then_some(preferences)
+    }
+
+    pub async fn get_saying_with_options(&self, system_prompt: &str, user_prompt: &str, options: &GenerationOptions) -> Result<Saying> {
+        let Some(constraints) = options.constraints.as_ref().filter(|constraints| !constraints.is_empty()) else {
+            return self.complete_usable(&prompt_messages(system_prompt, user_prompt), None, options).await;
+        };
+
+        let system_prompt = format!("{}\n\n{}", system_prompt.trim_end(), constraints.instructions());
+        let messages = prompt_messages(&system_prompt, user_prompt);
+        let max_tokens = constraints.max_tokens(options.translated);
+        // The rules are about the saying itself, i.e. the English original when it's translated
+        let check = |content: &str| if options.translated {
+            constraints.check(&crate::languages::english_original(content))
+        } else {
+            constraints.check(content)
+        };
+
+        let saying = self.complete_usable(&messages, max_tokens, options).await?;
+        let Err(violation) = check(&saying.content) else {
+            return Ok(saying);
+        };
+
+        // One more try; models usually comply the second time round
+        tracing::warn!("Response broke preset constraints ({}), retrying once", violation);
+        let saying = self.complete_usable(&messages, max_tokens, options).await?;
+        if let Err(violation) = check(&saying.content) {
+            tracing::warn!("Retried response still breaks preset constraints ({}), using it anyway", violation);
+        }
+
+        Ok(saying)
+    }
+
+    // The next assistant turn of a conversation, oldest message first. The saying's prompt is
+    // the latest user message; preset constraints don't apply to chat replies.
+    pub async fn get_reply(&self, messages: &[Message], options: &GenerationOptions) -> Result<Saying> {
+        self.complete_usable(messages, None, options).await
+    }
+
+    // Regenerate empty or unusable output, on the fallback model when one is configured
+    async fn complete_usable(&self, messages: &[Message], max_tokens: Option<u32>, options: &GenerationOptions) -> Result<Saying> {
+        let attempts = self.config.empty_retries + 1;
+
+        for attempt in 0..attempts {
+            let model = if attempt == 0 { options.model.as_deref() } else { self.config.fallback_model.as_deref() };
+            let saying = self.complete(messages, max_tokens, model, options).await?;
+
+            if !is_degenerate(&saying.content, options.translated) {
+                return Ok(saying);
+            }
+
+            Metrics::incr(&self.metrics.degenerate_responses);
+            if let Some(model) = &saying.model {
+                self.canary.record_degenerate(model);
+            }
+            tracing::warn!("Model returned unusable output {:?} (attempt {} of {})", saying.content, attempt + 1, attempts);
+        }
+
+        Err(UpstreamError::Degenerate { attempts }.into())
+    }
+
+    // A single chat completion round trip
+    async fn complete(
+        &self,
+        messages: &[Message],
+        max_tokens: Option<u32>,
+        model: Option<&str>,
+        options: &GenerationOptions,
+    ) -> Result<Saying> {
+        // Held until the response is read, so the mock provider's latency counts too, and
+        // while backing off, so retries don't add to the load on a struggling provider
+        let _permit = self.limiter.acquire(options.priority).await?;
+
+        if options.mock || matches!(self.config.provider, ProviderType::Mock) {
+            return Ok(self.mock_saying(last_user_prompt(messages)).await);
+        }
+
         // Validate API key first
         if self.config.api_key.is_empty() {
             return Err(anyhow!("OpenRouter API key is not configured. Please add it to your .env file."));
         }
 
-        let url = format!("{}/chat/completions", self.config.base_url);
-        
-        let messages = vec![
-            Message {
-                role: "system".to_string(),
-                content: system_prompt.to_string(),
-            },
-            Message {
-                role: "user".to_string(),
-                content: user_prompt.to_string(),
+        let primary = self.primary_model();
+        let model = match model {
+            Some(model) => model,
+            // New requests avoid a degraded primary, apart from the canary's share
+            None => match self.canary.pick() {
+                Some(canary) => canary,
+                None => self.health.route(primary, self.config.fallback_model.as_deref(), Utc::now()),
             },
-        ];
+        };
+
+        // Each attempt counts towards the model's health on its own
+        let mut retries = 0;
+        loop {
+            let started = std::time::Instant::now();
+            let result = self.send(messages, max_tokens, model, options).await;
+            self.record_outcome(primary, model, started, &result);
+
+            let Some(delay) = result.as_ref().err().and_then(|e| self.retry_delay(e, retries)) else {
+                return result;
+            };
+            retries += 1;
+            tracing::warn!("Retrying OpenRouter call in {:?} (retry {} of {})", delay, retries, self.config.upstream_retries);
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    // How long to wait before trying a failed call again, if at all: rate limits and server
+    // errors, with exponential backoff, while retries are left. A Retry-After beyond
+    // MAX_RETRY_AFTER_SECS is passed on to the caller instead of holding the request.
+    fn retry_delay(&self, error: &anyhow::Error, retries: u32) -> Option<std::time::Duration> {
+        if retries >= self.config.upstream_retries {
+            return None;
+        }
+
+        let backoff = std::time::Duration::from_millis(self.config.retry_backoff_ms.saturating_mul(1u64 << retries.min(16)));
+        match error.downcast_ref::<UpstreamError>()? {
+            UpstreamError::RateLimited { retry_after: Some(seconds), .. } => {
+                (*seconds <= MAX_RETRY_AFTER_SECS).then(|| backoff.max(std::time::Duration::from_secs(*seconds)))
+            }
+            UpstreamError::RateLimited { retry_after: None, .. } => Some(backoff),
+            UpstreamError::Status { status, .. } if *status >= 500 => Some(backoff),
+            _ => None,
+        }
+    }
+
+    // Feed an attempt's latency and outcome to routing and the canary
+    fn record_outcome(&self, primary: &str, model: &str, started: std::time::Instant, result: &Result<Saying>) {
+        // Rejections of the key, account or request say nothing about the model's health
+        let counts = match result {
+            Ok(_) => true,
+            Err(e) => !matches!(
+                e.downcast_ref::<UpstreamError>(),
+                Some(UpstreamError::Auth { .. } | UpstreamError::Quota(_) | UpstreamError::BadRequest(_))
+            ),
+        };
+        if counts {
+            let latency_ms = started.elapsed().as_millis() as u64;
+            self.canary.record(model, latency_ms, result.as_ref().ok().map(|saying| saying.content.chars().count()));
+            if let Some(event) = self.health.record(primary, model, latency_ms, result.is_ok(), Utc::now()) {
+                match event.kind {
+                    RoutingEventKind::Degraded => {
+                        Metrics::incr(&self.metrics.model_failovers);
+                        tracing::warn!("Model {} degraded ({}), routing new requests to the fallback model", event.model, event.reason);
+                    }
+                    RoutingEventKind::Recovered => {
+                        tracing::info!("Model {} recovered ({}), routing new requests to it again", event.model, event.reason);
+                    }
+                }
+            }
+        }
+    }
+
+    fn primary_model(&self) -> &str {
+        // Default model to use if none is specified (as in the TypeScript implementation)
+        if self.config.model.is_empty() { "openai/gpt-3.5-turbo" } else { self.config.model.as_str() }
+    }
+
+    // The model a new request would go to right now; unlike routing it never takes a probe slot
+    pub fn current_model(&self) -> &str {
+        match self.config.fallback_model.as_deref() {
+            Some(fallback) if self.config.routing.enabled && self.health.is_degraded() => fallback,
+            _ => self.primary_model(),
+        }
+    }
+
+    // One request to the chat completions endpoint with the given model
+    async fn send(
+        &self,
+        messages: &[Message],
+        max_tokens: Option<u32>,
+        model: &str,
+        options: &GenerationOptions,
+    ) -> Result<Saying> {
+        let url = format!("{}/chat/completions", self.config.base_url);
 
         // Log the request for debugging
         tracing::debug!(
             "Sending request to OpenRouter with model: {} and messages: {:?}",
-            self.config.model,
+            model,
             serde_json::to_string(&messages).unwrap_or_default()
         );
 
-        // Default model to use if none is specified (as in the TypeScript implementation)
-        let model = if self.config.model.is_empty() {
-            "openai/gpt-3.5-turbo".to_string()
-        } else {
-            self.config.model.clone()
+        let body = request_body(model, messages, self.provider_preferences(options), max_tokens);
+        let started = std::time::Instant::now();
+        let debug = |status: Option<u16>, response: &str| {
+            if self.debug_log.enabled() {
+                let request = serde_json::to_vec(&body).unwrap_or_default();
+                self.debug_log.record(DebugEntryKind::Upstream, &url, status, started, &request, response.as_bytes());
+            }
         };
 
         let response_result = self.client
@@ -80,18 +327,21 @@ impl OpenRouterClient {
             // Add headers similar to TypeScript implementation
             .header("HTTP-Referer", "http://localhost:3000")
             .header("X-Title", "AI Chat Tool")
-            .json(&json!({
-                "model": model,
-                "messages": messages,
-            }))
+            .json(&body)
             .send()
             .await;
 
         // Handle request errors
         let response = match response_result {
             Ok(resp) => resp,
+            Err(e) if e.is_timeout() => {
+                tracing::error!("Request to OpenRouter timed out: {}", e);
+                debug(None, &e.to_string());
+                return Err(UpstreamError::Timeout(e.to_string()).into());
+            }
             Err(e) => {
                 tracing::error!("Error sending request to OpenRouter: {}", e);
+                debug(None, &e.to_string());
                 return Err(anyhow!("Failed to connect to OpenRouter: {}", e));
             }
         };
@@ -99,13 +349,28 @@ impl OpenRouterClient {
         // Check status code first
         if !response.status().is_success() {
             let status = response.status();
+            let retry_after = response.headers()
+                .get(reqwest::header::RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse().ok());
             let error_text = response.text().await.unwrap_or_else(|_| "Unable to read error response".to_string());
             tracing::error!("OpenRouter API error: Status {}, Response: {}", status, error_text);
-            return Err(anyhow!("OpenRouter API returned error {}: {}", status, error_text));
+            debug(Some(status.as_u16()), &error_text);
+            return Err(UpstreamError::from_status(status.as_u16(), retry_after, error_text).into());
         }
 
         // Parse the response
-        let response_data = match response.json::<OpenRouterResponse>().await {
+        let status = response.status().as_u16();
+        let response_text = match response.text().await {
+            Ok(text) => text,
+            Err(e) => {
+                tracing::error!("Error reading OpenRouter response: {}", e);
+                debug(Some(status), &e.to_string());
+                return Err(anyhow!("Failed to read OpenRouter response: {}", e));
+            }
+        };
+        debug(Some(status), &response_text);
+        let response_data = match serde_json::from_str::<OpenRouterResponse>(&response_text) {
             Ok(data) => data,
             Err(e) => {
                 tracing::error!("Error parsing OpenRouter response: {}", e);
@@ -120,17 +385,60 @@ impl OpenRouterClient {
             return Err(anyhow!("OpenRouter response contained no choices"));
         };
 
-        // Create a new Saying with default preset_id as None
+        // Create a new Saying, preset_id and language are set by the handler later
         Ok(Saying {
-            id: uuid::Uuid::new_v4().to_string(),
-            content,
-            prompt: user_prompt.to_string(),
-            created_at: chrono::Utc::now(),
-            source: SayingSource::LLM,
-            preset_id: None, // Will be set by the handler later
+            usage: response_data.usage,
+            model: Some(model.to_string()),
+            ..Saying::new(content, last_user_prompt(messages).to_string(), SayingSource::LLM)
         })
     }
 
+    // Checks that OpenRouter is reachable and accepts the key without spending any tokens
+    pub async fn ping(&self) -> Result<()> {
+        if let ProviderType::Mock = self.config.provider {
+            return Ok(());
+        }
+
+        let response = self.client
+            .get(format!("{}/auth/key", self.config.base_url))
+            .header("Authorization", format!("Bearer {}", self.config.api_key))
+            .send()
+            .await
+            .map_err(|e| anyhow!("Failed to connect to OpenRouter: {}", e))?;
+
+        if !response.status().is_success() {
+            let status = response.status().as_u16();
+            let body = response.text().await.unwrap_or_else(|_| "Unable to read error response".to_string());
+            return Err(UpstreamError::from_status(status, None, body).into());
+        }
+        Ok(())
+    }
+
+    pub fn limiter(&self) -> &PriorityLimiter {
+        &self.limiter
+    }
+
+    pub fn health(&self) -> &ModelHealth {
+        &self.health
+    }
+
+    pub fn canary(&self) -> &Canary {
+        &self.canary
+    }
+
+    // Canned response used by the mock provider, no network involved
+    async fn mock_saying(&self, user_prompt: &str) -> Saying {
+        if self.config.mock_latency_ms > 0 {
+            tokio::time::sleep(std::time::Duration::from_millis(self.config.mock_latency_ms)).await;
+        }
+
+        Saying::new(
+            format!("A mock saying in reply to: {}", user_prompt),
+            user_prompt.to_string(),
+            SayingSource::LLM,
+        )
+    }
+
     // New method similar to TypeScript's generateChatResponse
     pub async fn generate_chat_response(&self, messages: Vec<Message>, model_id: Option<String>) -> ChatResponse {
         if self.config.api_key.is_empty() {
@@ -164,10 +472,7 @@ impl OpenRouterClient {
             .header("Content-Type", "application/json")
             .header("HTTP-Referer", "http://localhost:3000")
             .header("X-Title", "AI Chat Tool")
-            .json(&json!({
-                "model": model,
-                "messages": messages,
-            }))
+            .json(&request_body(&model, &messages, self.provider_preferences(&GenerationOptions::default()), None))
             .send()
             .await {
                 Ok(res) => res,
@@ -220,4 +525,370 @@ impl OpenRouterClient {
             error: None,
         }
     }
-}
\ No newline at end of file
+}
+
+// Output that can't be a saying: nothing but whitespace and punctuation, the translation
+// format's `[... here]` placeholders, or only the English blockquote when a translation was due
+fn is_degenerate(content: &str, translated: bool) -> bool {
+    let mut substantive = content.lines()
+        .map(str::trim)
+        .filter(|line| line.chars().any(char::is_alphanumeric))
+        .filter(|line| {
+            let unquoted = line.trim_start_matches('>').trim();
+            !(unquoted.starts_with('[') && unquoted.ends_with(']'))
+        })
+        .peekable();
+
+    match substantive.peek() {
+        None => true,
+        Some(_) if translated => substantive.all(|line| line.starts_with('>')),
+        Some(_) => false,
+    }
+}
+
+// The system + user pair a single saying is generated from
+fn prompt_messages(system_prompt: &str, user_prompt: &str) -> Vec<Message> {
+    vec![
+        Message {
+            role: "system".to_string(),
+            content: system_prompt.to_string(),
+        },
+        Message {
+            role: "user".to_string(),
+            content: user_prompt.to_string(),
+        },
+    ]
+}
+
+// What a completion answers: the latest user message
+fn last_user_prompt(messages: &[Message]) -> &str {
+    messages.iter()
+        .rev()
+        .find(|message| message.role == "user")
+        .map_or("", |message| message.content.as_str())
+}
+
+// Chat completion request body; optional fields are only included when set
+fn request_body(model: &str, messages: &[Message], provider: Option<ProviderPreferences>, max_tokens: Option<u32>) -> serde_json::Value {
+    let mut body = json!({
+        "model": model,
+        "messages": messages,
+    });
+
+    if let Some(provider) = provider {
+        body["provider"] = json!(provider);
+    }
+
+    if let Some(max_tokens) = max_tokens {
+        body["max_tokens"] = json!(max_tokens);
+    }
+
+    body
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::DataCollection;
+    use std::time::Duration;
+    use wiremock::matchers::{body_partial_json, header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[test]
+    fn test_preset_provider_preferences_override_global_ones() {
+        let global = ProviderPreferences {
+            order: Some(vec!["openai".to_string()]),
+            allow_fallbacks: Some(true),
+            data_collection: None,
+        };
+        let preset = ProviderPreferences {
+            order: Some(vec!["azure".to_string()]),
+            data_collection: Some(DataCollection::Deny),
+            ..Default::default()
+        };
+
+        let body = request_body("model", &[], Some(preset.merged_over(&global)), None);
+        assert_eq!(body["provider"], json!({
+            "order": ["azure"],
+            "allow_fallbacks": true,
+            "data_collection": "deny",
+        }));
+
+        // Nothing configured, nothing sent
+        let body = request_body("model", &[], None, None);
+        assert!(body.get("provider").is_none());
+        assert!(body.get("max_tokens").is_none());
+    }
+
+    #[test]
+    fn test_upstream_status_classification() {
+        assert!(matches!(UpstreamError::from_status(400, None, String::new()), UpstreamError::BadRequest(_)));
+        assert!(matches!(UpstreamError::from_status(401, None, String::new()), UpstreamError::Auth { status: 401, .. }));
+        assert!(matches!(UpstreamError::from_status(402, None, String::new()), UpstreamError::Quota(_)));
+        assert!(matches!(
+            UpstreamError::from_status(429, Some(30), String::new()),
+            UpstreamError::RateLimited { retry_after: Some(30), .. }
+        ));
+        assert!(matches!(UpstreamError::from_status(503, None, String::new()), UpstreamError::Status { status: 503, .. }));
+    }
+
+    #[test]
+    fn test_degenerate_output_detection() {
+        assert!(is_degenerate("", false));
+        assert!(is_degenerate("  \n\t ", false));
+        assert!(is_degenerate("...", false));
+        assert!(is_degenerate("> [English original answer here]\n\n[Spanish translation here]", true));
+        // The English header alone, with the translation missing
+        assert!(is_degenerate("> Patience is bitter, but its fruit is sweet.\n", true));
+
+        assert!(!is_degenerate("Patience is bitter, but its fruit is sweet.", false));
+        assert!(!is_degenerate("> Patience is bitter, but its fruit is sweet.\n\nLa paciencia es amarga, pero su fruto es dulce.", true));
+    }
+
+    // Every setting spelled out, so nothing in the environment changes what the tests see
+    fn test_config(base_url: &str, api_key: &str) -> OpenRouterConfig {
+        OpenRouterConfig {
+            provider: ProviderType::OpenRouter,
+            api_key: api_key.to_string(),
+            model: "primary/model".to_string(),
+            base_url: base_url.to_string(),
+            mock_latency_ms: 0,
+            provider_preferences: ProviderPreferences::default(),
+            proxy_url: None,
+            empty_retries: 1,
+            fallback_model: Some("fallback/model".to_string()),
+            upstream_retries: 2,
+            retry_backoff_ms: 10,
+            max_concurrent: 0,
+            routing: crate::config::ModelRoutingConfig {
+                enabled: false,
+                window_seconds: 300,
+                min_samples: 5,
+                max_error_percent: 50,
+                max_latency_ms: 15000,
+                probe_seconds: 30,
+            },
+            canary: crate::config::CanaryConfig { model: None, percent: 0 },
+            shadow: crate::config::ShadowConfig { model: None, sample_percent: 0 },
+            prices: Default::default(),
+            estimate_completion_tokens: 200,
+        }
+    }
+
+    fn test_debug_log() -> Arc<DebugLog> {
+        let config = crate::config::DebugLogConfig { enabled: false, capacity: 0, file_path: None, max_body_bytes: 0 };
+        Arc::new(DebugLog::with_secrets(config, Vec::new()))
+    }
+
+    #[tokio::test]
+    async fn test_mock_option_bypasses_the_configured_provider() {
+        let config = test_config("http://127.0.0.1:1", "");
+        let client = OpenRouterClient::new(config, Client::new(), Arc::new(Metrics::new()), test_debug_log());
+
+        let sandboxed = GenerationOptions { mock: true, ..GenerationOptions::default() };
+        let saying = client.get_saying_with_options("Be wise.", "Hello", &sandboxed).await.unwrap();
+        assert_eq!(saying.prompt, "Hello");
+        // Everyone else still needs the key
+        assert!(client.get_saying_with_options("Be wise.", "Hello", &GenerationOptions::default()).await.is_err());
+    }
+
+    // A client for a wiremock server standing in for OpenRouter, with one regeneration of
+    // unusable output on the fallback model, two quick retries of rate limits and server
+    // errors, and requests cut off after `timeout`
+    fn upstream_client(server: &MockServer, timeout: Duration) -> OpenRouterClient {
+        let http_client = Client::builder().timeout(timeout).build().unwrap();
+        OpenRouterClient::new(test_config(&server.uri(), "test-key"), http_client, Arc::new(Metrics::new()), test_debug_log())
+    }
+
+    fn completion(model: &str, content: &str) -> serde_json::Value {
+        json!({
+            "id": "gen-1",
+            "created": 1700000000,
+            "model": model,
+            "choices": [{"index": 0, "message": {"role": "assistant", "content": content}}],
+            "usage": {"prompt_tokens": 12, "completion_tokens": 8, "total_tokens": 20},
+        })
+    }
+
+    #[tokio::test]
+    async fn test_success_returns_the_completion() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .and(header("authorization", "Bearer test-key"))
+            .and(body_partial_json(json!({"model": "primary/model"})))
+            .respond_with(ResponseTemplate::new(200).set_body_json(completion("primary/model", "Patience is bitter, but its fruit is sweet.")))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let saying = upstream_client(&server, Duration::from_secs(5)).get_saying_with_system("Be wise.", "patience").await.unwrap();
+        assert_eq!(saying.content, "Patience is bitter, but its fruit is sweet.");
+        assert_eq!(saying.prompt, "patience");
+        assert_eq!(saying.model.as_deref(), Some("primary/model"));
+        assert_eq!(saying.usage.unwrap().total_tokens, Some(20));
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_is_reported_with_retry_after_and_not_retried() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "30").set_body_string("slow down"))
+            // Too long to wait out inside the request; the caller gets the delay in the error
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let err = upstream_client(&server, Duration::from_secs(5)).get_saying_with_system("Be wise.", "patience").await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<UpstreamError>(),
+            Some(UpstreamError::RateLimited { retry_after: Some(30), body }) if body == "slow down"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_malformed_json_is_an_error() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("{\"choices\": [oops"))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let err = upstream_client(&server, Duration::from_secs(5)).get_saying_with_system("Be wise.", "patience").await.unwrap_err();
+        assert!(err.downcast_ref::<UpstreamError>().is_none());
+        assert!(err.to_string().contains("Failed to parse OpenRouter response"));
+    }
+
+    #[tokio::test]
+    async fn test_slow_response_times_out() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(completion("primary/model", "Too late.")).set_delay(Duration::from_secs(2)))
+            .mount(&server)
+            .await;
+
+        let err = upstream_client(&server, Duration::from_millis(200)).get_saying_with_system("Be wise.", "patience").await.unwrap_err();
+        assert!(matches!(err.downcast_ref::<UpstreamError>(), Some(UpstreamError::Timeout(_))));
+    }
+
+    #[tokio::test]
+    async fn test_empty_output_is_regenerated_on_the_fallback_model() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(body_partial_json(json!({"model": "primary/model"})))
+            .respond_with(ResponseTemplate::new(200).set_body_json(completion("primary/model", "  ")))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(body_partial_json(json!({"model": "fallback/model"})))
+            .respond_with(ResponseTemplate::new(200).set_body_json(completion("fallback/model", "Still water runs deep.")))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let saying = upstream_client(&server, Duration::from_secs(5)).get_saying_with_system("Be wise.", "water").await.unwrap();
+        assert_eq!(saying.content, "Still water runs deep.");
+        assert_eq!(saying.model.as_deref(), Some("fallback/model"));
+    }
+
+    #[tokio::test]
+    async fn test_output_still_empty_after_the_retries_fails() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(completion("primary/model", "...")))
+            .expect(2)
+            .mount(&server)
+            .await;
+
+        let err = upstream_client(&server, Duration::from_secs(5)).get_saying_with_system("Be wise.", "water").await.unwrap_err();
+        assert!(matches!(err.downcast_ref::<UpstreamError>(), Some(UpstreamError::Degenerate { attempts: 2 })));
+    }
+
+    #[tokio::test]
+    async fn test_translated_response_is_checked_on_the_english_original() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            // Room for the translation on top of the 60 character ceiling of 76
+            .and(body_partial_json(json!({"max_tokens": 168})))
+            .respond_with(ResponseTemplate::new(200).set_body_json(completion(
+                "primary/model",
+                "> Patience is bitter, but its fruit is sweet.\n\nLa paciencia es amarga, pero su fruto es dulce.",
+            )))
+            // Two lines and twice the characters, but the saying itself complies: no retry
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let options = GenerationOptions {
+            constraints: Some(ResponseConstraints {
+                max_response_chars: Some(60),
+                max_response_sentences: None,
+                format: Some(crate::preset::ResponseFormat::OneLiner),
+            }),
+            translated: true,
+            ..GenerationOptions::default()
+        };
+        let saying = upstream_client(&server, Duration::from_secs(5)).get_saying_with_options("Be wise.", "patience", &options).await.unwrap();
+        assert!(saying.content.ends_with("pero su fruto es dulce."));
+    }
+
+    #[tokio::test]
+    async fn test_server_errors_keep_their_status() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(503).set_body_string("upstream unavailable"))
+            // The first call and both retries
+            .expect(3)
+            .mount(&server)
+            .await;
+
+        let err = upstream_client(&server, Duration::from_secs(5)).get_saying_with_system("Be wise.", "water").await.unwrap_err();
+        assert!(matches!(err.downcast_ref::<UpstreamError>(), Some(UpstreamError::Status { status: 503, .. })));
+    }
+
+    #[tokio::test]
+    async fn test_rate_limits_and_server_errors_are_retried_with_backoff() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "0").set_body_string("slow down"))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(502).set_body_string("bad gateway"))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(completion("primary/model", "Still water runs deep.")))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let started = std::time::Instant::now();
+        let saying = upstream_client(&server, Duration::from_secs(5)).get_saying_with_system("Be wise.", "water").await.unwrap();
+        assert_eq!(saying.content, "Still water runs deep.");
+        // 10ms, then 20ms
+        assert!(started.elapsed() >= Duration::from_millis(30));
+    }
+
+    #[tokio::test]
+    async fn test_client_errors_are_not_retried() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(402).set_body_string("out of credits"))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let err = upstream_client(&server, Duration::from_secs(5)).get_saying_with_system("Be wise.", "water").await.unwrap_err();
+        assert!(matches!(err.downcast_ref::<UpstreamError>(), Some(UpstreamError::Quota(_))));
+    }
+}
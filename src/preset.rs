@@ -1,13 +1,24 @@
 use anyhow::{Context, Result};
 use chrono::{DateTime, Utc};
+use rand::rngs::StdRng;
 use rand::seq::SliceRandom;
+use rand::SeedableRng;
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::path::Path;
-use std::sync::{Arc, Mutex};
-use uuid::Uuid;
+use std::sync::{Arc, Mutex, RwLock};
+use dashmap::{mapref::entry::Entry, DashMap};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+use crate::clock::{self, Clock};
+use crate::config::ProviderPreferences;
+use crate::invalidation::{Invalidation, InvalidationBus};
+use crate::languages::TranslationMode;
+use crate::models::{fnv1a, prompt_search_key, PresetSelectionRecord};
+use crate::signing::PresetSignature;
+use crate::storage::Storage;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Preset {
     pub id: String,
     pub name: String,
@@ -18,6 +29,224 @@ pub struct Preset {
     pub instruction_text: String,
     pub system_prompt: String,
     pub user_prompts: Vec<String>,
+    // OpenRouter provider routing for this preset, layered over the global settings
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider: Option<ProviderPreferences>,
+    // Length and shape the answers must have, declared flat in the preset
+    #[serde(flatten)]
+    pub constraints: ResponseConstraints,
+    // Left out of GET /presets and random selection, but usable by ID (e.g. experiments)
+    #[serde(default)]
+    pub hidden: bool,
+    // Only users with at least this tier (see USER_TIERS) may see or use the preset
+    #[serde(default)]
+    pub min_tier: u32,
+    // Audience the preset's sayings are written for, checked against PRESETS_MAX_RATING
+    #[serde(default)]
+    pub rating: ContentRating,
+    // Layout of non-English sayings, unless the request picks one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub translation_mode: Option<TranslationMode>,
+}
+
+impl Preset {
+    // The fields a preset can't work without
+    fn validate(&self) -> Result<()> {
+        if self.id.is_empty() || self.name.is_empty() || self.system_prompt.is_empty() || self.user_prompts.is_empty() {
+            return Err(anyhow::anyhow!("Preset {:?} needs an id, name, system_prompt and user_prompts", self.id));
+        }
+        Ok(())
+    }
+
+    pub fn allows_tier(&self, tier: u32) -> bool {
+        tier >= self.min_tier
+    }
+
+    // Whether the preset shows up in listings and random selection for a user of this tier
+    pub fn is_listed_for(&self, tier: u32) -> bool {
+        !self.hidden && self.allows_tier(tier)
+    }
+}
+
+// Version of the single-preset export format; imports of newer versions are refused
+pub const PRESET_EXPORT_VERSION: u32 = 1;
+
+// One preset packaged to move between deployments, as JSON or YAML
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PresetExport {
+    pub schema_version: u32,
+    // Hash of the preset's canonical JSON, to catch damaged or hand-edited files
+    pub checksum: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exported_at: Option<DateTime<Utc>>,
+    pub preset: Preset,
+    // Publisher's signature over `canonical_preset`, when the exporting deployment has a key
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<PresetSignature>,
+}
+
+impl PresetExport {
+    pub fn new(preset: Preset) -> Self {
+        Self {
+            schema_version: PRESET_EXPORT_VERSION,
+            checksum: checksum(&preset),
+            exported_at: Some(Utc::now()),
+            preset,
+            signature: None,
+        }
+    }
+
+    // The bytes the checksum and signature cover
+    pub fn canonical_preset(&self) -> Vec<u8> {
+        canonical(&self.preset)
+    }
+
+    // The packaged preset, once the version and checksum check out and it is complete
+    pub fn into_preset(self) -> Result<Preset> {
+        if self.schema_version == 0 || self.schema_version > PRESET_EXPORT_VERSION {
+            return Err(anyhow::anyhow!(
+                "Unsupported schema_version {}, this deployment reads up to {}",
+                self.schema_version, PRESET_EXPORT_VERSION
+            ));
+        }
+        if self.checksum != checksum(&self.preset) {
+            return Err(anyhow::anyhow!("Checksum mismatch, the preset was changed after export"));
+        }
+        self.preset.validate()?;
+        Ok(self.preset)
+    }
+}
+
+// Stable across builds and deployments, so FNV-1a like the other persisted hashes
+fn checksum(preset: &Preset) -> String {
+    format!("fnv1a:{:016x}", fnv1a(canonical(preset).into_iter()))
+}
+
+// Field order is fixed by the struct and no field is a map, so this is the same everywhere
+fn canonical(preset: &Preset) -> Vec<u8> {
+    serde_json::to_vec(preset).unwrap_or_default()
+}
+
+// Ordered from mildest to strongest, so a deployment allows every rating up to its maximum
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ContentRating {
+    #[default]
+    AllAges,
+    Teen,
+    Mature,
+}
+
+impl ContentRating {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ContentRating::AllAges => "all-ages",
+            ContentRating::Teen => "teen",
+            ContentRating::Mature => "mature",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ResponseFormat {
+    OneLiner,
+    Haiku,
+    Paragraph,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ResponseConstraints {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_response_chars: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_response_sentences: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<ResponseFormat>,
+}
+
+impl ResponseConstraints {
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+
+    // Instructions appended to the system prompt so the model knows the rules up front
+    pub fn instructions(&self) -> String {
+        let mut rules = Vec::new();
+        match self.format {
+            Some(ResponseFormat::OneLiner) => rules.push("Answer in a single line.".to_string()),
+            Some(ResponseFormat::Haiku) => rules.push("Answer with a haiku: exactly three lines of five, seven and five syllables.".to_string()),
+            Some(ResponseFormat::Paragraph) => rules.push("Answer in a single paragraph.".to_string()),
+            None => {}
+        }
+        if let Some(sentences) = self.max_response_sentences {
+            rules.push(format!("Use at most {} sentence{}.", sentences, if sentences == 1 { "" } else { "s" }));
+        }
+        if let Some(chars) = self.max_response_chars {
+            rules.push(format!("Keep the answer under {} characters.", chars));
+        }
+        rules.join(" ")
+    }
+
+    // Hard ceiling for the completion. Sized for about one token per character (the worst
+    // case across languages), so it never cuts off an answer that would have been valid;
+    // tighter limits are left to `check`. A translated answer carries the saying twice.
+    pub fn max_tokens(&self, translated: bool) -> Option<u32> {
+        // Limits come from preset files, so huge ones saturate instead of wrapping
+        let to_u32 = |limit: usize| u32::try_from(limit).unwrap_or(u32::MAX);
+        let by_chars = self.max_response_chars.map(|chars| to_u32(chars).saturating_add(16));
+        let by_sentences = self.max_response_sentences.map(|sentences| to_u32(sentences).saturating_mul(64));
+        let by_format = self.format.map(|format| match format {
+            ResponseFormat::OneLiner => 96,
+            ResponseFormat::Haiku => 64,
+            ResponseFormat::Paragraph => 384,
+        });
+
+        let ceiling = [by_chars, by_sentences, by_format].into_iter().flatten().min();
+        if translated {
+            ceiling.map(|tokens| tokens.saturating_mul(2).saturating_add(TRANSLATION_FORMAT_TOKENS))
+        } else {
+            ceiling
+        }
+    }
+
+    // Post-generation validation; the error describes the first rule the answer breaks
+    pub fn check(&self, content: &str) -> Result<(), String> {
+        let content = content.trim();
+
+        if let Some(max) = self.max_response_chars {
+            let chars = content.chars().count();
+            if chars > max {
+                return Err(format!("{} characters, limit is {}", chars, max));
+            }
+        }
+
+        if let Some(max) = self.max_response_sentences {
+            let sentences = count_sentences(content);
+            if sentences > max {
+                return Err(format!("{} sentences, limit is {}", sentences, max));
+            }
+        }
+
+        match self.format {
+            Some(ResponseFormat::OneLiner) if content.contains('\n') => Err("not a single line".to_string()),
+            Some(ResponseFormat::Haiku) if content.lines().filter(|line| !line.trim().is_empty()).count() != 3 => {
+                Err("not a three-line haiku".to_string())
+            }
+            Some(ResponseFormat::Paragraph) if content.contains("\n\n") => Err("more than one paragraph".to_string()),
+            _ => Ok(()),
+        }
+    }
+}
+
+// Blockquote markers and the blank line between the English original and its translation
+const TRANSLATION_FORMAT_TOKENS: u32 = 16;
+
+// Runs of terminal punctuation end a sentence, so "..." or "?!" count once
+fn count_sentences(text: &str) -> usize {
+    text.split(['.', '!', '?', '。', '！', '？'])
+        .filter(|part| part.chars().any(char::is_alphanumeric))
+        .count()
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -27,11 +256,51 @@ pub struct PresetSelection {
     pub expires_at: DateTime<Utc>,
 }
 
+// The presets file plus imported presets, replaced whole when a preset is imported
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Catalog {
+    presets: Vec<Preset>,
+    // IDs of the imported presets; the others come from the file and can't be replaced
+    imported: HashSet<String>,
+    // Validator for the preset catalog endpoints
+    #[serde(skip)]
+    etag: String,
+}
+
+impl Catalog {
+    fn check_importable(&self, id: &str) -> Result<()> {
+        if self.presets.iter().any(|p| p.id == id) && !self.imported.contains(id) {
+            return Err(anyhow::anyhow!("Preset {} comes from the presets file and can't be replaced", id));
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Presets {
-    presets: Vec<Preset>,
+    catalog: Arc<RwLock<Arc<Catalog>>>,
     // Map of user_id -> currently selected preset
-    selections: Arc<Mutex<std::collections::HashMap<String, PresetSelection>>>,
+    selections: Arc<DashMap<String, PresetSelection>>,
+    // Tells other instances about new selections, so a user gets the same preset from each
+    #[serde(skip)]
+    invalidations: Option<Arc<InvalidationBus>>,
+    // Presets rated above this are never listed or picked, and refused by ID
+    #[serde(skip)]
+    max_rating: ContentRating,
+    // Preset ID -> user prompt -> selection weight, for prompts picked less often (see
+    // prompt_rotation); unlisted prompts weigh 1
+    #[serde(skip)]
+    prompt_weights: Arc<DashMap<String, HashMap<String, f64>>>,
+    // Decides when selections expire
+    #[serde(skip, default = "clock::system")]
+    clock: Arc<dyn Clock>,
+    // Source of every random pick, shared by clones; see `with_rng_seed`
+    #[serde(skip, default = "entropy_rng")]
+    rng: Arc<Mutex<StdRng>>,
+}
+
+fn entropy_rng() -> Arc<Mutex<StdRng>> {
+    Arc::new(Mutex::new(StdRng::from_entropy()))
 }
 
 impl Presets {
@@ -44,80 +313,562 @@ impl Presets {
         
         // Validate presets
         for preset in &presets {
-            if preset.id.is_empty() || preset.name.is_empty() || preset.system_prompt.is_empty() || preset.user_prompts.is_empty() {
-                return Err(anyhow::anyhow!("Invalid preset in file: {:?}", path.as_ref()));
-            }
+            preset.validate().with_context(|| format!("Invalid preset in file: {:?}", path.as_ref()))?;
         }
         
         tracing::info!("Loaded {} presets from {:?}", presets.len(), path.as_ref());
         
-        Ok(Self {
-            presets,
-            selections: Arc::new(Mutex::new(std::collections::HashMap::new())),
-        })
+        Ok(Self::new(presets, crate::etag::compute(content.as_bytes())))
+    }
+    
+    fn new(presets: Vec<Preset>, etag: String) -> Self {
+        Self {
+            catalog: Arc::new(RwLock::new(Arc::new(Catalog { presets, imported: HashSet::new(), etag }))),
+            selections: Arc::new(DashMap::new()),
+            invalidations: None,
+            max_rating: ContentRating::Mature,
+            prompt_weights: Arc::new(DashMap::new()),
+            clock: clock::system(),
+            rng: entropy_rng(),
+        }
+    }
+    
+    pub fn with_invalidations(self, invalidations: Arc<InvalidationBus>) -> Self {
+        Self { invalidations: Some(invalidations), ..self }
+    }
+    
+    pub fn with_max_rating(self, max_rating: ContentRating) -> Self {
+        Self { max_rating, ..self }
+    }
+    
+    pub fn with_clock(self, clock: Arc<dyn Clock>) -> Self {
+        Self { clock, ..self }
+    }
+    
+    // With a seed, the same sequence of requests gets the same presets and prompts (for one
+    // build; the generator may change between versions of `rand`)
+    pub fn with_rng_seed(self, seed: Option<u64>) -> Self {
+        match seed {
+            Some(seed) => Self { rng: Arc::new(Mutex::new(StdRng::seed_from_u64(seed))), ..self },
+            None => self,
+        }
+    }
+    
+    fn catalog(&self) -> Arc<Catalog> {
+        self.catalog.read().unwrap().clone()
+    }
+    
+    // Add the presets imported earlier, kept in storage
+    pub async fn load_imported(&self, storage: &Storage) -> Result<()> {
+        let presets = storage.list_imported_presets().await?;
+        let count = presets.len();
+        for preset in presets {
+            if let Err(e) = self.apply_import(preset) {
+                tracing::warn!("Skipping stored imported preset: {:#}", e);
+            }
+        }
+        
+        tracing::info!("Loaded {} imported presets", count);
+        Ok(())
+    }
+    
+    // Add a preset from another deployment, or replace the one imported earlier with its ID.
+    // Presets from the file can't be replaced. Other instances pick it up too.
+    pub async fn import(&self, storage: &Storage, preset: Preset) -> Result<()> {
+        self.catalog().check_importable(&preset.id)?;
+        storage.save_imported_preset(&preset).await?;
+        self.apply_import(preset.clone())?;
+        
+        if let Some(invalidations) = &self.invalidations {
+            invalidations.publish(Invalidation::PresetImport { preset: Box::new(preset) }).await;
+        }
+        Ok(())
     }
     
-    pub fn get_or_select_preset(&self, user_id: &str, reset_at: DateTime<Utc>) -> Result<Preset> {
-        let mut selections = self.selections.lock().unwrap();
+    // Put an imported preset in the catalog; storage is left to the caller
+    pub fn apply_import(&self, preset: Preset) -> Result<()> {
+        let mut catalog = self.catalog.write().unwrap();
+        catalog.check_importable(&preset.id)?;
         
-        // Check if user already has a selected preset and if it's still valid
-        if let Some(selection) = selections.get(user_id) {
-            if selection.expires_at > Utc::now() {
-                return Ok(selection.preset.clone());
+        let mut imported = catalog.imported.clone();
+        imported.insert(preset.id.clone());
+        let mut presets = catalog.presets.clone();
+        match presets.iter().position(|p| p.id == preset.id) {
+            Some(index) => presets[index] = preset,
+            None => presets.push(preset),
+        }
+        let etag = crate::etag::compute(&serde_json::to_vec(&presets).unwrap_or_default());
+        
+        *catalog = Arc::new(Catalog { presets, imported, etag });
+        Ok(())
+    }
+    
+    pub fn is_imported(&self, id: &str) -> bool {
+        self.catalog().imported.contains(id)
+    }
+    
+    pub fn max_rating(&self) -> ContentRating {
+        self.max_rating
+    }
+    
+    // Whether this deployment serves the preset's rating at all
+    pub fn allows_rating(&self, preset: &Preset) -> bool {
+        preset.rating <= self.max_rating
+    }
+    
+    fn is_listed(&self, preset: &Preset, tier: u32) -> bool {
+        preset.is_listed_for(tier) && self.allows_rating(preset)
+    }
+    
+    // The user's preset for the current window, picking a new one once it expires. A new pick
+    // avoids the user's last `no_repeat` presets (kept in storage) when there are others to choose.
+    pub async fn get_or_select_preset(&self, storage: &Storage, user_id: &str, reset_at: DateTime<Utc>, no_repeat: usize) -> Result<Preset> {
+        if let Some(preset) = self.current_selection(user_id) {
+            return Ok(preset);
+        }
+        
+        // A selection made before a restart still holds for its window
+        if let Some(record) = storage.get_preset_selection(user_id).await? {
+            if let Some(preset) = self.get_preset_by_id(&record.preset_id).filter(|p| record.expires_at > self.clock.now() && self.allows_rating(p)) {
+                let selection = PresetSelection { preset, selected_at: record.selected_at, expires_at: record.expires_at };
+                return Ok(self.selections.entry(user_id.to_string()).or_insert(selection).preset.clone());
+            }
+        }
+        
+        let mut recent = storage.get_recent_presets(user_id).await?;
+        recent.truncate(no_repeat);
+        let preset = self.random_preset_excluding(&recent)?;
+        
+        {
+            // Hold the entry for this user so concurrent requests agree on one selection
+            let selection = self.selections.entry(user_id.to_string());
+            if let Entry::Occupied(existing) = &selection {
+                if existing.get().expires_at > self.clock.now() {
+                    return Ok(existing.get().preset.clone());
+                }
             }
+            
+            // Store the selection
+            selection.insert(PresetSelection {
+                preset: preset.clone(),
+                selected_at: self.clock.now(),
+                expires_at: reset_at,
+            });
+        }
+        
+        self.record_selection(storage, user_id, &preset, reset_at, false, no_repeat).await?;
+        Ok(preset)
+    }
+    
+    // The preset a request would use, like `get_or_select_preset` but without storing a new
+    // pick, so a fresh window may still land on another preset
+    pub async fn preview_preset(&self, storage: &Storage, user_id: &str, no_repeat: usize) -> Result<Preset> {
+        if let Some(preset) = self.current_selection(user_id) {
+            return Ok(preset);
         }
         
-        // Select a new random preset
-        let preset = self.random_preset()?;
+        if let Some(record) = storage.get_preset_selection(user_id).await? {
+            if let Some(preset) = self.get_preset_by_id(&record.preset_id).filter(|p| record.expires_at > self.clock.now() && self.allows_rating(p)) {
+                return Ok(preset);
+            }
+        }
         
-        // Store the selection
-        selections.insert(user_id.to_string(), PresetSelection {
+        let mut recent = storage.get_recent_presets(user_id).await?;
+        recent.truncate(no_repeat);
+        self.random_preset_excluding(&recent)
+    }
+    
+    // Use `preset` for the user until `reset_at`, replacing any random pick for the window.
+    // Callers check that the user may use the preset.
+    pub async fn pin_preset(&self, storage: &Storage, user_id: &str, preset: Preset, reset_at: DateTime<Utc>, no_repeat: usize) -> Result<()> {
+        self.selections.insert(user_id.to_string(), PresetSelection {
             preset: preset.clone(),
-            selected_at: Utc::now(),
+            selected_at: self.clock.now(),
             expires_at: reset_at,
         });
         
-        Ok(preset)
+        self.record_selection(storage, user_id, &preset, reset_at, true, no_repeat).await
     }
     
+    async fn record_selection(&self, storage: &Storage, user_id: &str, preset: &Preset, reset_at: DateTime<Utc>, pinned: bool, no_repeat: usize) -> Result<()> {
+        let record = PresetSelectionRecord {
+            preset_id: preset.id.clone(),
+            selected_at: self.clock.now(),
+            expires_at: reset_at,
+            pinned,
+        };
+        storage.save_preset_selection(user_id, &record).await?;
+        storage.push_recent_preset(user_id, &preset.id, no_repeat).await?;
+        
+        if let Some(invalidations) = &self.invalidations {
+            invalidations.publish(Invalidation::PresetSelection { user_id: user_id.to_string(), selection: record }).await;
+        }
+        Ok(())
+    }
+    
+    // A selection made on another instance, kept here too so both serve the same preset
+    pub async fn apply_selection(&self, storage: &Storage, user_id: &str, record: PresetSelectionRecord) -> Result<()> {
+        let Some(preset) = self.get_preset_by_id(&record.preset_id) else {
+            return Ok(());
+        };
+        
+        self.selections.insert(user_id.to_string(), PresetSelection {
+            preset,
+            selected_at: record.selected_at,
+            expires_at: record.expires_at,
+        });
+        storage.save_preset_selection(user_id, &record).await
+    }
+    
+    pub fn current_selection(&self, user_id: &str) -> Option<Preset> {
+        self.selections
+            .get(user_id)
+            .filter(|selection| selection.expires_at > self.clock.now())
+            .map(|selection| selection.preset.clone())
+    }
+    
+    // Random selection only picks presets listed for everyone
     pub fn random_preset(&self) -> Result<Preset> {
-        let mut rng = rand::thread_rng();
+        self.random_preset_excluding(&[])
+    }
+    
+    // Prefer presets not in `exclude`, falling back to any listed preset when that leaves none
+    fn random_preset_excluding(&self, exclude: &[String]) -> Result<Preset> {
+        let catalog = self.catalog();
+        let listed: Vec<&Preset> = catalog.presets.iter().filter(|p| self.is_listed(p, 0)).collect();
+        let fresh: Vec<&Preset> = listed.iter().copied().filter(|p| !exclude.contains(&p.id)).collect();
         
-        self.presets
-            .choose(&mut rng)
-            .cloned()
+        if fresh.is_empty() { &listed } else { &fresh }
+            .choose(&mut *self.rng.lock().unwrap())
+            .map(|preset| (*preset).clone())
             .ok_or_else(|| anyhow::anyhow!("No presets available"))
     }
     
     pub fn get_preset_by_id(&self, id: &str) -> Option<Preset> {
-        self.presets.iter().find(|p| p.id == id).cloned()
+        self.catalog().presets.iter().find(|p| p.id == id).cloned()
+    }
+    
+    pub fn etag(&self) -> String {
+        self.catalog().etag.clone()
+    }
+
+    // Presets listed for `tier` that carry all of `tags` (case-insensitive), as one page of
+    // `limit` from `offset`, together with the number of presets matching before paging
+    pub fn find_presets(&self, tags: &[&str], tier: u32, offset: usize, limit: usize) -> (usize, Vec<Preset>) {
+        let catalog = self.catalog();
+        let matching: Vec<&Preset> = catalog.presets
+            .iter()
+            .filter(|preset| self.is_listed(preset, tier))
+            .filter(|preset| tags.iter().all(|tag| preset.tags.iter().any(|t| t.eq_ignore_ascii_case(tag))))
+            .collect();
+        let page = matching.iter().skip(offset).take(limit).map(|preset| (*preset).clone()).collect();
+        
+        (matching.len(), page)
+    }
+
+    // User prompts of the presets listed for `tier` whose search key starts with `prefix`
+    // (see `prompt_search_key`), as (preset ID, prompt) in catalog order
+    pub fn suggest_user_prompts(&self, prefix: &str, tier: u32, limit: usize) -> Vec<(String, String)> {
+        self.catalog()
+            .presets
+            .iter()
+            .filter(|preset| self.is_listed(preset, tier))
+            .flat_map(|preset| preset.user_prompts.iter().map(move |prompt| (preset, prompt)))
+            .filter(|(_, prompt)| prompt_search_key(prompt).starts_with(prefix))
+            .take(limit)
+            .map(|(preset, prompt)| (preset.id.clone(), prompt.clone()))
+            .collect()
+    }
+
+    // Every preset this deployment's rating allows, hidden and tiered ones included
+    // IDs given to more than one preset, whichever one a lookup happens to find first
+    pub fn duplicate_ids(&self) -> Vec<String> {
+        let mut seen = HashSet::new();
+        let mut duplicates: Vec<String> = self.catalog().presets.iter()
+            .filter(|p| !seen.insert(p.id.as_str()))
+            .map(|p| p.id.clone())
+            .collect();
+        duplicates.sort();
+        duplicates.dedup();
+        duplicates
     }
     
     pub fn get_all_presets(&self) -> Vec<Preset> {
-        self.presets.clone()
+        self.catalog().presets.iter().filter(|p| self.allows_rating(p)).cloned().collect()
     }
     
+    // A user prompt of the preset, down-weighted prompts less likely than the rest
     pub fn random_user_prompt(&self, preset_id: &str) -> Result<String> {
         let preset = self.get_preset_by_id(preset_id)
             .ok_or_else(|| anyhow::anyhow!("Preset not found: {}", preset_id))?;
         
-        let mut rng = rand::thread_rng();
+        let mut rng = self.rng.lock().unwrap();
         
+        // Every prompt weighing nothing leaves an even pick
         preset.user_prompts
-            .choose(&mut rng)
+            .choose_weighted(&mut *rng, |prompt| self.prompt_weight(preset_id, prompt))
+            .ok()
+            .or_else(|| preset.user_prompts.choose(&mut *rng))
             .cloned()
             .ok_or_else(|| anyhow::anyhow!("No user prompts available for preset: {}", preset_id))
     }
     
+    // Replace every prompt weight at once
+    pub fn set_prompt_weights(&self, weights: HashMap<String, HashMap<String, f64>>) {
+        self.prompt_weights.retain(|preset_id, _| weights.contains_key(preset_id));
+        for (preset_id, prompts) in weights {
+            self.prompt_weights.insert(preset_id, prompts);
+        }
+    }
+    
+    fn prompt_weight(&self, preset_id: &str, prompt: &str) -> f64 {
+        self.prompt_weights.get(preset_id)
+            .and_then(|weights| weights.get(prompt).copied())
+            .unwrap_or(1.0)
+    }
+    
     pub fn get_default_preset(&self) -> Result<Preset> {
         // First try to find a preset with ID "oracle" (matching the TypeScript default)
-        if let Some(preset) = self.get_preset_by_id("oracle") {
+        if let Some(preset) = self.get_preset_by_id("oracle").filter(|p| self.is_listed(p, 0)) {
             return Ok(preset);
         }
         
-        // If not found, return the first preset
-        self.presets.first()
+        // If not found, return the first preset listed for everyone
+        self.catalog()
+            .presets
+            .iter()
+            .find(|p| self.is_listed(p, 0))
             .cloned()
             .ok_or_else(|| anyhow::anyhow!("No presets available"))
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::config::{StorageConfig, StorageFallbackPolicy, StorageType};
+
+    #[test]
+    fn test_random_preset_avoids_recent_ones() {
+        let preset = |id: &str| -> Preset {
+            serde_yaml::from_str(&format!(
+                "{{id: {id}, name: {id}, description: '', tags: [], button_text: '', loading_text: '', instruction_text: '', system_prompt: s, user_prompts: [p]}}"
+            )).unwrap()
+        };
+        let presets = Presets::new(vec![preset("a"), preset("b"), preset("c")], String::new());
+
+        let recent = vec!["a".to_string(), "b".to_string()];
+        for _ in 0..20 {
+            assert_eq!(presets.random_preset_excluding(&recent).unwrap().id, "c");
+        }
+
+        // With everything excluded any preset is still better than none
+        let all = vec!["a".to_string(), "b".to_string(), "c".to_string()];
+        assert!(presets.random_preset_excluding(&all).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_selection_holds_until_it_expires() {
+        let preset = |id: &str| -> Preset {
+            serde_yaml::from_str(&format!(
+                "{{id: {id}, name: {id}, description: '', tags: [], button_text: '', loading_text: '', instruction_text: '', system_prompt: s, user_prompts: [p]}}"
+            )).unwrap()
+        };
+        let clock = ManualClock::new(Utc::now());
+        let presets = Presets::new(vec![preset("a"), preset("b")], String::new()).with_clock(clock.clone());
+        let storage = Storage::new(StorageConfig {
+            type_: StorageType::Memory,
+            connection_string: "memory".to_string(),
+            dedupe_by_content: false,
+            max_sayings_per_user: 0,
+            retention_days: 0,
+            fallback_policy: StorageFallbackPolicy::Fail,
+            compression_level: 0,
+            user_id_salt: None,
+        }).unwrap();
+
+        let first = presets.get_or_select_preset(&storage, "alice", clock.now() + chrono::Duration::hours(1), 1).await.unwrap();
+        clock.advance(chrono::Duration::minutes(59));
+        assert_eq!(presets.current_selection("alice").unwrap().id, first.id);
+
+        clock.advance(chrono::Duration::minutes(2));
+        assert!(presets.current_selection("alice").is_none());
+        // A new window, and the last preset is avoided
+        let second = presets.get_or_select_preset(&storage, "alice", clock.now() + chrono::Duration::hours(1), 1).await.unwrap();
+        assert_ne!(second.id, first.id);
+    }
+
+    #[test]
+    fn test_seeded_picks_repeat() {
+        let preset = |id: &str| -> Preset {
+            serde_yaml::from_str(&format!(
+                "{{id: {id}, name: {id}, description: '', tags: [], button_text: '', loading_text: '', instruction_text: '', system_prompt: s, user_prompts: [p1, p2, p3, p4]}}"
+            )).unwrap()
+        };
+        let picks = |seed: u64| {
+            let presets = Presets::new(vec![preset("a"), preset("b"), preset("c"), preset("d")], String::new()).with_rng_seed(Some(seed));
+            (0..20)
+                .map(|_| {
+                    let preset = presets.random_preset().unwrap();
+                    (preset.id.clone(), presets.random_user_prompt(&preset.id).unwrap())
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(picks(7), picks(7));
+        assert_ne!(picks(7), picks(8));
+    }
+
+    #[test]
+    fn test_duplicate_ids_are_reported_once() {
+        let preset = |id: &str| -> Preset {
+            serde_yaml::from_str(&format!(
+                "{{id: {id}, name: {id}, description: '', tags: [], button_text: '', loading_text: '', instruction_text: '', system_prompt: s, user_prompts: [p]}}"
+            )).unwrap()
+        };
+        let presets = Presets::new(vec![preset("b"), preset("a"), preset("b"), preset("b"), preset("c")], String::new());
+        assert_eq!(presets.duplicate_ids(), vec!["b".to_string()]);
+    }
+
+    #[test]
+    fn test_find_presets_filters_by_tag_and_pages() {
+        let preset = |id: &str, tags: &[&str]| -> Preset {
+            serde_yaml::from_str(&format!(
+                "{{id: {id}, name: {id}, description: '', tags: [{}], button_text: '', loading_text: '', instruction_text: '', system_prompt: s, user_prompts: [p]}}",
+                tags.join(", ")
+            )).unwrap()
+        };
+        let presets = Presets::new(
+            vec![
+                preset("a", &["wisdom", "short"]),
+                preset("b", &["Wisdom"]),
+                preset("c", &["fortune"]),
+                Preset { hidden: true, ..preset("lab", &["wisdom"]) },
+                Preset { min_tier: 2, ..preset("gold", &["wisdom"]) },
+            ],
+            String::new(),
+        );
+
+        let (total, page) = presets.find_presets(&["wisdom"], 0, 0, 10);
+        assert_eq!(total, 2);
+        assert_eq!(page.iter().map(|p| p.id.as_str()).collect::<Vec<_>>(), ["a", "b"]);
+
+        assert_eq!(presets.find_presets(&["wisdom", "short"], 0, 0, 10).0, 1);
+
+        // Hidden presets are never listed, tiered ones only for high enough tiers
+        let (_, page) = presets.find_presets(&["wisdom"], 2, 0, 10);
+        assert_eq!(page.iter().map(|p| p.id.as_str()).collect::<Vec<_>>(), ["a", "b", "gold"]);
+
+        let (total, page) = presets.find_presets(&[], 0, 1, 1);
+        assert_eq!(total, 3);
+        assert_eq!(page[0].id, "b");
+    }
+
+    #[test]
+    fn test_max_rating_hides_stronger_presets() {
+        let preset = |id: &str, rating: &str| -> Preset {
+            serde_yaml::from_str(&format!(
+                "{{id: {id}, name: {id}, description: '', tags: [], button_text: '', loading_text: '', instruction_text: '', system_prompt: s, user_prompts: [p], rating: {rating}}}"
+            )).unwrap()
+        };
+        let presets = Presets::new(
+            vec![preset("kids", "all-ages"), preset("school", "teen"), preset("noir", "mature")],
+            String::new(),
+        )
+        .with_max_rating(ContentRating::Teen);
+
+        let (_, page) = presets.find_presets(&[], 0, 0, 10);
+        assert_eq!(page.iter().map(|p| p.id.as_str()).collect::<Vec<_>>(), ["kids", "school"]);
+        assert_eq!(presets.get_all_presets().len(), 2);
+        for _ in 0..20 {
+            assert_ne!(presets.random_preset().unwrap().id, "noir");
+        }
+
+        // Still found by ID, so generation can refuse it with a reason
+        let noir = presets.get_preset_by_id("noir").unwrap();
+        assert!(!presets.allows_rating(&noir));
+    }
+
+    #[test]
+    fn test_preset_export_round_trip_and_import() {
+        let preset = |id: &str| -> Preset {
+            serde_yaml::from_str(&format!(
+                "{{id: {id}, name: {id}, description: '', tags: [], button_text: '', loading_text: '', instruction_text: '', system_prompt: s, user_prompts: [p], max_response_chars: 80}}"
+            )).unwrap()
+        };
+
+        // Survives a trip through YAML, checksum included
+        let yaml = serde_yaml::to_string(&PresetExport::new(preset("haiku"))).unwrap();
+        let export: PresetExport = serde_yaml::from_str(&yaml).unwrap();
+        assert_eq!(export.into_preset().unwrap().constraints.max_response_chars, Some(80));
+
+        let mut edited = PresetExport::new(preset("haiku"));
+        edited.preset.system_prompt = "Something else".to_string();
+        assert!(edited.into_preset().is_err());
+        let newer = PresetExport { schema_version: PRESET_EXPORT_VERSION + 1, ..PresetExport::new(preset("haiku")) };
+        assert!(newer.into_preset().is_err());
+
+        let presets = Presets::new(vec![preset("oracle")], String::new());
+        let etag = presets.etag();
+        assert!(presets.apply_import(preset("oracle")).is_err());
+        presets.apply_import(preset("haiku")).unwrap();
+        presets.apply_import(Preset { name: "Haiku".to_string(), ..preset("haiku") }).unwrap();
+        assert_eq!(presets.get_all_presets().len(), 2);
+        assert_eq!(presets.get_preset_by_id("haiku").unwrap().name, "Haiku");
+        assert!(presets.is_imported("haiku") && !presets.is_imported("oracle"));
+        assert_ne!(presets.etag(), etag);
+    }
+
+    #[test]
+    fn test_response_constraints() {
+        let constraints = ResponseConstraints {
+            max_response_chars: Some(40),
+            max_response_sentences: Some(1),
+            format: Some(ResponseFormat::OneLiner),
+        };
+
+        assert!(constraints.check("Patience is a quiet kind of strength...").is_ok());
+        assert!(constraints.check("Breathe. Then begin.").is_err());
+        assert!(constraints.check("Slow down\nand listen").is_err());
+        assert!(constraints.check("The river does not hurry, yet it always arrives.").is_err());
+        assert_eq!(constraints.max_tokens(false), Some(56));
+        assert_eq!(constraints.max_tokens(true), Some(128));
+
+        let huge = ResponseConstraints {
+            max_response_chars: Some(usize::MAX),
+            max_response_sentences: Some(u32::MAX as usize / 2),
+            format: None,
+        };
+        assert_eq!(huge.max_tokens(false), Some(u32::MAX));
+        assert_eq!(huge.max_tokens(true), Some(u32::MAX));
+
+        let haiku = ResponseConstraints {
+            format: Some(ResponseFormat::Haiku),
+            ..Default::default()
+        };
+        assert!(haiku.check("Old silent pond\nA frog jumps into the pond\nSplash! Silence again.\n").is_ok());
+        assert!(haiku.check("Old silent pond, a frog jumps in.").is_err());
+    }
+
+    #[test]
+    fn test_constraints_are_read_from_flat_preset_fields() {
+        let preset: Preset = serde_yaml::from_str(r#"
+id: poet
+name: Poet
+description: Speaks in haiku
+tags: []
+button_text: Ask
+loading_text: Thinking
+instruction_text: Ask anything
+system_prompt: You are a poet.
+user_prompts: ["Tell me about spring"]
+format: haiku
+max_response_chars: 120
+"#).unwrap();
+
+        assert_eq!(preset.constraints.format, Some(ResponseFormat::Haiku));
+        assert_eq!(preset.constraints.max_response_chars, Some(120));
+        assert_eq!(preset.constraints.max_response_sentences, None);
+    }
+}
@@ -0,0 +1,147 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::config::PromptRotationConfig;
+use crate::models::{QualityScore, SayingFeedback};
+use crate::AppState;
+
+// How one preset user prompt has been rated, by users and by the quality judge
+#[derive(Debug, Clone, Serialize)]
+pub struct PromptPerformance {
+    pub preset_id: String,
+    pub prompt: String,
+    pub ratings: usize,
+    // Over all ratings, on the 1-5 scale
+    pub average: f64,
+    // Weight in random selection while rotation is on
+    pub weight: f64,
+}
+
+// Every preset prompt with ratings, worst first. Feedback scores and the judge's overall
+// marks count the same; prompts with fewer than `min_ratings` keep weight 1.
+pub fn assess(feedback: &[SayingFeedback], scores: &[QualityScore], config: &PromptRotationConfig) -> Vec<PromptPerformance> {
+    let mut ratings: HashMap<(&str, &str), Vec<f64>> = HashMap::new();
+    let feedback = feedback.iter()
+        .filter_map(|feedback| Some(((feedback.preset_id.as_deref()?, feedback.prompt.as_str()), f64::from(feedback.score))));
+    let scores = scores.iter()
+        .filter_map(|score| Some(((score.preset_id.as_deref()?, score.prompt.as_str()), score.overall())));
+    for (key, rating) in feedback.chain(scores) {
+        ratings.entry(key).or_default().push(rating);
+    }
+
+    let mut prompts: Vec<PromptPerformance> = ratings
+        .into_iter()
+        .map(|((preset_id, prompt), ratings)| {
+            let average = ratings.iter().sum::<f64>() / ratings.len() as f64;
+            let poor = ratings.len() >= config.min_ratings && average < config.poor_score;
+            PromptPerformance {
+                preset_id: preset_id.to_string(),
+                prompt: prompt.to_string(),
+                ratings: ratings.len(),
+                average,
+                weight: if poor { config.poor_weight } else { 1.0 },
+            }
+        })
+        .collect();
+    prompts.sort_by(|a, b| a.average.total_cmp(&b.average).then_with(|| b.ratings.cmp(&a.ratings)));
+    prompts
+}
+
+// Ratings of the prompts still in the catalog, from storage
+pub async fn current(state: &AppState) -> Result<Vec<PromptPerformance>> {
+    let feedback = state.storage.list_feedback().await?;
+    let scores = state.storage.list_quality_scores().await?;
+
+    let mut prompts = assess(&feedback, &scores, &state.config.prompt_rotation);
+    prompts.retain(|performance| {
+        state.presets.get_preset_by_id(&performance.preset_id)
+            .is_some_and(|preset| preset.user_prompts.contains(&performance.prompt))
+    });
+    Ok(prompts)
+}
+
+// Apply fresh weights to random prompt selection; returns how many prompts are down-weighted
+pub async fn refresh(state: &AppState) -> Result<usize> {
+    let mut weights: HashMap<String, HashMap<String, f64>> = HashMap::new();
+    for performance in current(state).await?.into_iter().filter(|performance| performance.weight < 1.0) {
+        weights.entry(performance.preset_id).or_default().insert(performance.prompt, performance.weight);
+    }
+
+    let down_weighted = weights.values().map(HashMap::len).sum();
+    state.presets.set_prompt_weights(weights);
+    Ok(down_weighted)
+}
+
+// Every instance picks prompts, so each keeps its own weights up to date
+pub fn spawn_task(state: Arc<AppState>) {
+    if !state.config.prompt_rotation.enabled {
+        return;
+    }
+
+    let period = std::time::Duration::from_secs(state.config.prompt_rotation.refresh_seconds.max(1));
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(period);
+        loop {
+            ticker.tick().await;
+            match refresh(&state).await {
+                Ok(down_weighted) => tracing::info!("Prompt rotation: {} user prompts down-weighted", down_weighted),
+                Err(e) => tracing::error!("Failed to refresh prompt weights: {:#}", e),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    #[test]
+    fn test_assess_down_weights_poorly_rated_prompts() {
+        let config = PromptRotationConfig {
+            enabled: true,
+            refresh_seconds: 3600,
+            min_ratings: 3,
+            poor_score: 2.5,
+            poor_weight: 0.2,
+        };
+        let feedback = |prompt: &str, score: u8| SayingFeedback {
+            saying_id: uuid::Uuid::new_v4().to_string(),
+            user_id: "user".to_string(),
+            preset_id: Some("oracle".to_string()),
+            prompt: prompt.to_string(),
+            score,
+            created_at: Utc::now(),
+        };
+        let feedback = vec![
+            feedback("dull", 1),
+            feedback("dull", 2),
+            feedback("good", 5),
+            feedback("good", 4),
+            feedback("good", 5),
+            feedback("new", 1),
+        ];
+        let scores = vec![QualityScore {
+            saying_id: uuid::Uuid::new_v4().to_string(),
+            preset_id: Some("oracle".to_string()),
+            model: None,
+            prompt: "dull".to_string(),
+            relevance: 2,
+            tone: 2,
+            length: 2,
+            judge_model: None,
+            scored_at: Utc::now(),
+        }];
+
+        let prompts = assess(&feedback, &scores, &config);
+        let weight = |prompt: &str| prompts.iter().find(|p| p.prompt == prompt).unwrap().weight;
+        assert_eq!(prompts[0].prompt, "new");
+        assert_eq!(weight("dull"), 0.2);
+        assert_eq!(weight("good"), 1.0);
+        // Too few ratings to judge yet
+        assert_eq!(weight("new"), 1.0);
+    }
+}
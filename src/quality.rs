@@ -0,0 +1,185 @@
+use chrono::Utc;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::models::{QualityScore, Saying};
+use crate::openrouter::GenerationOptions;
+use crate::AppState;
+
+const JUDGE_SYSTEM_PROMPT: &str = "You grade short sayings written by another assistant. \
+Reply with JSON only, in the form {\"relevance\": 1, \"tone\": 1, \"length\": 1}, each mark from 1 (poor) to 5 (excellent). \
+relevance: how well the saying answers the prompt. \
+tone: how well it keeps to the voice the writer's instructions ask for. \
+length: how well it keeps to the length and format rules in those instructions, or suits a short saying when there are none.";
+
+#[derive(Debug, Deserialize)]
+struct Marks {
+    relevance: u8,
+    tone: u8,
+    length: u8,
+}
+
+// Average marks of the sayings of one preset or model
+#[derive(Debug, Clone, Serialize)]
+pub struct QualityRanking {
+    pub key: String,
+    pub scored: usize,
+    pub relevance: f64,
+    pub tone: f64,
+    pub length: f64,
+    pub overall: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RankBy {
+    Preset,
+    Model,
+}
+
+fn judge_prompt(instructions: &str, prompt: &str, saying: &str) -> String {
+    format!("Instructions given to the writer:\n{}\n\nPrompt:\n{}\n\nSaying:\n{}", instructions, prompt, saying)
+}
+
+// The judge's marks, tolerating prose or code fences around the JSON object
+fn parse_marks(response: &str) -> Option<Marks> {
+    let start = response.find('{')?;
+    let end = response.rfind('}')?;
+    let marks: Marks = serde_json::from_str(response.get(start..=end)?).ok()?;
+    [marks.relevance, marks.tone, marks.length]
+        .iter()
+        .all(|mark| (1..=5).contains(mark))
+        .then_some(marks)
+}
+
+// For QUALITY_SAMPLE_PERCENT of generated sayings, have the judge grade the saying against
+// the instructions it was written under, and store the marks. Runs in the background.
+pub fn spawn_scoring(state: &Arc<AppState>, saying: Arc<Saying>, system_prompt: &str) {
+    let config = &state.config.quality;
+    if !config.enabled || rand::thread_rng().gen_range(0..100) >= config.sample_percent {
+        return;
+    }
+
+    // Length rules are sent separately from the preset's system prompt, so the judge gets both
+    let constraints = saying.preset_id.as_deref()
+        .and_then(|id| state.presets.get_preset_by_id(id))
+        .map(|preset| preset.constraints)
+        .filter(|constraints| !constraints.is_empty());
+    let instructions = match constraints {
+        Some(constraints) => format!("{}\n\n{}", system_prompt.trim_end(), constraints.instructions()),
+        None => system_prompt.to_string(),
+    };
+    let options = GenerationOptions {
+        model: config.judge_model.clone(),
+        ..GenerationOptions::default()
+    };
+    let state = state.clone();
+
+    tokio::spawn(async move {
+        let prompt = judge_prompt(&instructions, &saying.prompt, &saying.content);
+        let response = match state.openrouter.get_saying_with_options(JUDGE_SYSTEM_PROMPT, &prompt, &options).await {
+            Ok(response) => response,
+            Err(e) => {
+                tracing::warn!("Quality scoring failed for saying {}: {:#}", saying.id, e);
+                return;
+            }
+        };
+        let Some(marks) = parse_marks(&response.content) else {
+            tracing::warn!("Judge gave no usable marks for saying {}: {:?}", saying.id, response.content);
+            return;
+        };
+
+        let score = QualityScore {
+            saying_id: saying.id.clone(),
+            preset_id: saying.preset_id.clone(),
+            model: saying.model.clone(),
+            prompt: saying.prompt.clone(),
+            relevance: marks.relevance,
+            tone: marks.tone,
+            length: marks.length,
+            judge_model: response.model,
+            scored_at: Utc::now(),
+        };
+        if let Err(e) = state.storage.save_quality_score(&score).await {
+            tracing::error!("Failed to save quality score for saying {}: {}", saying.id, e);
+        }
+    });
+}
+
+// Average marks per preset or model, best overall first. Freeform sayings have no preset
+// and older ones no known model; those are left out.
+pub fn rank(scores: &[QualityScore], by: RankBy) -> Vec<QualityRanking> {
+    let mut groups: HashMap<&str, Vec<&QualityScore>> = HashMap::new();
+    for score in scores {
+        let key = match by {
+            RankBy::Preset => score.preset_id.as_deref(),
+            RankBy::Model => score.model.as_deref(),
+        };
+        if let Some(key) = key {
+            groups.entry(key).or_default().push(score);
+        }
+    }
+
+    let mut rankings: Vec<QualityRanking> = groups
+        .into_iter()
+        .map(|(key, scores)| {
+            let average = |mark: fn(&QualityScore) -> f64| scores.iter().map(|score| mark(score)).sum::<f64>() / scores.len() as f64;
+            QualityRanking {
+                key: key.to_string(),
+                scored: scores.len(),
+                relevance: average(|score| f64::from(score.relevance)),
+                tone: average(|score| f64::from(score.tone)),
+                length: average(|score| f64::from(score.length)),
+                overall: average(QualityScore::overall),
+            }
+        })
+        .collect();
+    rankings.sort_by(|a, b| b.overall.total_cmp(&a.overall).then_with(|| a.key.cmp(&b.key)));
+    rankings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_marks() {
+        let marks = parse_marks("Sure!\n```json\n{\"relevance\": 4, \"tone\": 5, \"length\": 3}\n```").unwrap();
+        assert_eq!((marks.relevance, marks.tone, marks.length), (4, 5, 3));
+
+        assert!(parse_marks("{\"relevance\": 6, \"tone\": 5, \"length\": 3}").is_none());
+        assert!(parse_marks("{\"relevance\": 4}").is_none());
+        assert!(parse_marks("Four out of five").is_none());
+    }
+
+    #[test]
+    fn test_rank_by_preset() {
+        let score = |preset_id: Option<&str>, relevance: u8, tone: u8, length: u8| QualityScore {
+            saying_id: uuid::Uuid::new_v4().to_string(),
+            preset_id: preset_id.map(str::to_string),
+            model: Some("model".to_string()),
+            prompt: "prompt".to_string(),
+            relevance,
+            tone,
+            length,
+            judge_model: None,
+            scored_at: Utc::now(),
+        };
+        let scores = vec![
+            score(Some("oracle"), 3, 3, 3),
+            score(Some("oracle"), 5, 5, 5),
+            score(Some("haiku"), 5, 5, 2),
+            score(None, 1, 1, 1),
+        ];
+
+        let rankings = rank(&scores, RankBy::Preset);
+        assert_eq!(rankings.iter().map(|r| r.key.as_str()).collect::<Vec<_>>(), ["haiku", "oracle"]);
+        assert_eq!(rankings[1].scored, 2);
+        assert_eq!(rankings[1].overall, 4.0);
+
+        let rankings = rank(&scores, RankBy::Model);
+        assert_eq!(rankings.len(), 1);
+        assert_eq!(rankings[0].scored, 4);
+    }
+}
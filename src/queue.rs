@@ -0,0 +1,249 @@
+use chrono::{DateTime, Duration, Utc};
+use dashmap::DashMap;
+use serde::Serialize;
+use std::collections::{HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use crate::access::Caller;
+use crate::handlers::{self, ApiError, GenerationRequest, SayingOutcome};
+use crate::AppState;
+
+// Finished jobs stay around this long for clients to collect their result
+const RETENTION_MINUTES: i64 = 60;
+// How often waiting jobs are checked against their users' rate limits
+const CHECK_SECONDS: u64 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Completed,
+    Failed,
+}
+
+// An over-quota generation request waiting for the user's capacity (RATE_LIMIT_MODE=queue)
+#[derive(Debug, Clone)]
+pub struct GenerationJob {
+    pub id: String,
+    pub user_id: String,
+    pub caller: Caller,
+    pub request: GenerationRequest,
+    pub status: JobStatus,
+    pub created_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub outcome: Option<SayingOutcome>,
+    pub error: Option<String>,
+}
+
+// Jobs live in memory only; a restart drops whatever is still waiting
+pub struct GenerationQueue {
+    max_size: usize,
+    // Waiting jobs per user; 0 for no limit
+    max_per_user: usize,
+    jobs: DashMap<String, GenerationJob>,
+    // IDs of the jobs waiting to run, oldest first
+    pending: Mutex<VecDeque<String>>,
+}
+
+impl GenerationQueue {
+    pub fn new(max_size: usize, max_per_user: usize) -> Self {
+        Self {
+            max_size,
+            max_per_user,
+            jobs: DashMap::new(),
+            pending: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    // Queue a request, returning the job ID and how many jobs are ahead of it; None when the
+    // queue is full or the user already has as many jobs waiting as they may
+    pub fn push(&self, user_id: &str, caller: Caller, request: GenerationRequest) -> Option<(String, usize)> {
+        let mut pending = self.pending.lock().unwrap();
+        if pending.len() >= self.max_size {
+            return None;
+        }
+        if self.max_per_user > 0 {
+            let waiting = pending.iter()
+                .filter(|id| self.jobs.get(*id).is_some_and(|job| job.user_id == user_id))
+                .count();
+            if waiting >= self.max_per_user {
+                return None;
+            }
+        }
+
+        let job = GenerationJob {
+            id: uuid::Uuid::new_v4().to_string(),
+            user_id: user_id.to_string(),
+            caller,
+            request,
+            status: JobStatus::Queued,
+            created_at: Utc::now(),
+            finished_at: None,
+            outcome: None,
+            error: None,
+        };
+        let id = job.id.clone();
+        self.jobs.insert(id.clone(), job);
+        pending.push_back(id.clone());
+        Some((id, pending.len() - 1))
+    }
+
+    pub fn get(&self, job_id: &str) -> Option<GenerationJob> {
+        self.jobs.get(job_id).map(|job| job.clone())
+    }
+
+    // Jobs ahead of this one, while it is still waiting
+    pub fn position(&self, job_id: &str) -> Option<usize> {
+        self.pending.lock().unwrap().iter().position(|id| id == job_id)
+    }
+
+    // Start the oldest waiting job of every user `ready` says has capacity, one per user, and
+    // none for users who already have a job running
+    fn take_ready(&self, ready: impl Fn(&str) -> bool) -> Vec<GenerationJob> {
+        let mut busy: HashSet<String> = self.jobs
+            .iter()
+            .filter(|job| job.status == JobStatus::Running)
+            .map(|job| job.user_id.clone())
+            .collect();
+
+        let mut pending = self.pending.lock().unwrap();
+        let mut taken = Vec::new();
+        pending.retain(|id| {
+            let Some(mut job) = self.jobs.get_mut(id) else {
+                return false;
+            };
+            if busy.contains(&job.user_id) || !ready(&job.user_id) {
+                // Later jobs of a user who has to wait keep waiting too
+                busy.insert(job.user_id.clone());
+                return true;
+            }
+            busy.insert(job.user_id.clone());
+            job.status = JobStatus::Running;
+            taken.push(job.clone());
+            false
+        });
+        taken
+    }
+
+    // Put a job that lost the race for capacity back at the front of the queue
+    fn requeue(&self, job_id: &str) {
+        if let Some(mut job) = self.jobs.get_mut(job_id) {
+            job.status = JobStatus::Queued;
+        }
+        self.pending.lock().unwrap().push_front(job_id.to_string());
+    }
+
+    fn finish(&self, job_id: &str, result: Result<SayingOutcome, String>) {
+        if let Some(mut job) = self.jobs.get_mut(job_id) {
+            job.finished_at = Some(Utc::now());
+            match result {
+                Ok(outcome) => {
+                    job.status = JobStatus::Completed;
+                    job.outcome = Some(outcome);
+                }
+                Err(error) => {
+                    job.status = JobStatus::Failed;
+                    job.error = Some(error);
+                }
+            }
+        }
+    }
+
+    // Forget jobs that finished more than the retention period ago, returning how many
+    fn purge_finished(&self, now: DateTime<Utc>) -> usize {
+        let before = self.jobs.len();
+        let cutoff = now - Duration::minutes(RETENTION_MINUTES);
+        self.jobs.retain(|_, job| job.finished_at.is_none_or(|at| at > cutoff));
+        before.saturating_sub(self.jobs.len())
+    }
+}
+
+// Run waiting jobs as their users' windows reset or bursts pass
+pub fn spawn_task(state: Arc<AppState>) {
+    if state.config.rate_limit.mode != crate::config::RateLimitMode::Queue {
+        return;
+    }
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(std::time::Duration::from_secs(CHECK_SECONDS));
+        loop {
+            ticker.tick().await;
+            let purged = state.queue.purge_finished(Utc::now());
+            if purged > 0 {
+                tracing::debug!("Purged {} finished generation jobs", purged);
+            }
+
+            for job in state.queue.take_ready(|user_id| state.rate_limiter.has_capacity(user_id)) {
+                let state = state.clone();
+                tokio::spawn(async move {
+                    tracing::info!("Running queued job {} for user {}", job.id, job.user_id);
+                    match handlers::generate_saying(&state, &job.caller, &job.user_id, job.request.clone(), false).await {
+                        // Someone else used the capacity first
+                        Err(ApiError::RateLimited { .. }) => state.queue.requeue(&job.id),
+                        Err(e) => {
+                            tracing::warn!("Queued job {} failed: {}", job.id, e);
+                            state.queue.finish(&job.id, Err(e.to_string()));
+                        }
+                        Ok(outcome) => state.queue.finish(&job.id, Ok(outcome)),
+                    }
+                });
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request() -> GenerationRequest {
+        GenerationRequest {
+            prompt: Some("prompt".to_string()),
+            preset_id: None,
+            language_id: "en".to_string(),
+            detected_language_id: None,
+            translation_mode: None,
+            tags: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_queue_is_bounded_and_runs_one_job_per_ready_user() {
+        let queue = GenerationQueue::new(3, 0);
+        let (first, _) = queue.push("alice", Caller::default(), request()).unwrap();
+        let (second, _) = queue.push("alice", Caller::default(), request()).unwrap();
+        let (third, position) = queue.push("bob", Caller::default(), request()).unwrap();
+        assert_eq!(position, 2);
+        assert!(queue.push("carol", Caller::default(), request()).is_none());
+
+        // Bob is still over the limit; only Alice's oldest job starts
+        let taken = queue.take_ready(|user_id| user_id == "alice");
+        assert_eq!(taken.iter().map(|job| job.id.as_str()).collect::<Vec<_>>(), [first.as_str()]);
+        assert_eq!(queue.position(&second), Some(0));
+        assert_eq!(queue.position(&third), Some(1));
+
+        // Alice's next job waits for the running one to finish
+        assert!(queue.take_ready(|user_id| user_id == "alice").is_empty());
+        queue.finish(&first, Err("upstream down".to_string()));
+        assert_eq!(queue.get(&first).unwrap().status, JobStatus::Failed);
+        assert_eq!(queue.take_ready(|_| true).len(), 2);
+
+        assert_eq!(queue.purge_finished(Utc::now() + Duration::minutes(RETENTION_MINUTES + 1)), 1);
+        assert!(queue.get(&first).is_none());
+    }
+
+    #[test]
+    fn test_one_user_cannot_fill_the_queue() {
+        let queue = GenerationQueue::new(10, 2);
+        assert!(queue.push("alice", Caller::default(), request()).is_some());
+        let (second, _) = queue.push("alice", Caller::default(), request()).unwrap();
+        assert!(queue.push("alice", Caller::default(), request()).is_none());
+        assert!(queue.push("bob", Caller::default(), request()).is_some());
+
+        // A running job no longer counts as waiting
+        queue.take_ready(|user_id| user_id == "alice");
+        assert_eq!(queue.position(&second), Some(0));
+        assert!(queue.push("alice", Caller::default(), request()).is_some());
+    }
+}
@@ -1,88 +1,476 @@
 use anyhow::Result;
-use chrono::{DateTime, Duration, Utc};
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use chrono::{DateTime, Duration, FixedOffset, Utc};
+use dashmap::DashMap;
+use std::collections::{HashSet, VecDeque};
+use std::sync::Arc;
 
-use crate::config::RateLimitConfig;
+use crate::clock::{self, Clock};
+use crate::config::{RateLimitConfig, RateLimitWindow, ReadRateLimitConfig};
 use crate::models::RateLimitInfo;
+use crate::streaks;
+use crate::AppState;
+
+// Result of a rate-limit check
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RateLimitCheck {
+    Allowed,
+    // The window quota is used up
+    Exhausted,
+    // Too many requests in a short time; the window quota is untouched
+    Burst { retry_after: u64 },
+    // The limiter tracks as many users as it may; a new user waits for expired windows to be swept
+    Full { retry_after: u64 },
+}
 
 #[derive(Debug, Clone)]
 pub struct RateLimiter {
     config: RateLimitConfig,
     // In a real application, you'd use a persistent store like Redis
-    // This in-memory implementation is just for demonstration
-    store: Arc<Mutex<HashMap<String, RateLimitInfo>>>,
+    // This in-memory implementation is just for demonstration.
+    // DashMap shards the entries so concurrent users don't contend on one lock.
+    store: Arc<DashMap<String, RateLimitInfo>>,
+    // Last UTC offset (in minutes) each user reported, for calendar-day windows
+    tz_offsets: Arc<DashMap<String, i32>>,
+    // Times of each user's allowed requests within the burst period, oldest first
+    bursts: Arc<DashMap<String, VecDeque<DateTime<Utc>>>>,
+    clock: Arc<dyn Clock>,
 }
 
 impl RateLimiter {
     pub fn new(config: RateLimitConfig) -> Self {
         Self {
             config,
-            store: Arc::new(Mutex::new(HashMap::new())),
+            store: Arc::new(DashMap::new()),
+            tz_offsets: Arc::new(DashMap::new()),
+            bursts: Arc::new(DashMap::new()),
+            clock: clock::system(),
+        }
+    }
+    
+    pub fn with_clock(self, clock: Arc<dyn Clock>) -> Self {
+        Self { clock, ..self }
+    }
+    
+    // Remember the user's timezone; it applies from their next window on
+    pub fn set_tz_offset(&self, user_id: &str, minutes: i32) {
+        self.tz_offsets.insert(user_id.to_string(), minutes);
+    }
+    
+    // When a window starting now ends for this user
+    fn window_end(&self, user_id: &str, now: DateTime<Utc>) -> DateTime<Utc> {
+        match self.config.window {
+            RateLimitWindow::Rolling => now + Duration::seconds(self.config.window_seconds as i64),
+            RateLimitWindow::CalendarDay => {
+                let minutes = self.tz_offsets.get(user_id).map(|offset| *offset).unwrap_or(0);
+                next_local_midnight(now, streaks::offset_from_minutes(minutes))
+            }
+        }
+    }
+
+    // A full quota, in a window starting now
+    fn fresh_window(&self, user_id: &str, now: DateTime<Utc>) -> RateLimitInfo {
+        RateLimitInfo {
+            user_id: user_id.to_string(),
+            remaining_requests: self.config.max_requests,
+            reset_at: self.window_end(user_id, now),
+            credits: 0,
+            cache_served: 0,
         }
     }
 
-    pub async fn check(&self, user_id: &str) -> Result<bool> {
-        let mut store = self.store.lock().unwrap();
-        let now = Utc::now();
+    pub async fn check(&self, user_id: &str) -> Result<RateLimitCheck> {
+        let now = self.clock.now();
         
-        if let Some(info) = store.get(user_id) {
-            // Check if the rate limit window has expired
-            if now > info.reset_at {
-                // Reset the rate limit
-                let new_info = RateLimitInfo {
-                    user_id: user_id.to_string(),
-                    remaining_requests: self.config.max_requests - 1,
-                    reset_at: now + Duration::seconds(self.config.window_seconds as i64),
-                };
-                store.insert(user_id.to_string(), new_info);
-                return Ok(true);
-            }
-            
-            // Check if there are remaining requests
-            if info.remaining_requests > 0 {
-                // Update remaining requests
-                let new_info = RateLimitInfo {
-                    user_id: user_id.to_string(),
-                    remaining_requests: info.remaining_requests - 1,
-                    reset_at: info.reset_at,
-                };
-                store.insert(user_id.to_string(), new_info);
-                return Ok(true);
+        // Evicting a live window would hand its user a fresh quota, so once full, new users are
+        // refused until the cleanup task drops windows that have ended
+        let max = self.config.max_entries;
+        if max > 0 && self.store.len() >= max && !self.store.contains_key(user_id) {
+            return Ok(RateLimitCheck::Full { retry_after: self.config.cleanup_interval_seconds.max(1) });
+        }
+        
+        // The entry guard holds the shard lock, so check-and-decrement is atomic per user
+        let mut entry = self.store.entry(user_id.to_string())
+            .or_insert_with(|| self.fresh_window(user_id, now));
+        Ok(self.consume(user_id, &mut entry, now))
+    }
+    
+    fn consume(&self, user_id: &str, info: &mut RateLimitInfo, now: DateTime<Utc>) -> RateLimitCheck {
+        self.roll_window(user_id, info, now);
+        
+        // Check if there are remaining requests
+        if info.available() == 0 {
+            return RateLimitCheck::Exhausted;
+        }
+        
+        // Then the short-term limit, still under the caller's entry guard
+        if self.config.burst_max > 0 {
+            let mut recent = self.bursts.entry(user_id.to_string()).or_default();
+            let period = Duration::seconds(self.config.burst_seconds as i64);
+            if let Some(retry_after) = burst_retry_after(&mut recent, now, period, self.config.burst_max) {
+                return RateLimitCheck::Burst { retry_after };
             }
-            
-            // Rate limit exceeded
-            return Ok(false);
+            recent.push_back(now);
         }
         
-        // First request for this user
-        let new_info = RateLimitInfo {
-            user_id: user_id.to_string(),
-            remaining_requests: self.config.max_requests - 1,
-            reset_at: now + Duration::seconds(self.config.window_seconds as i64),
-        };
-        store.insert(user_id.to_string(), new_info);
+        // Spend granted credits before the base quota; unused ones end with the window either way
+        if info.credits > 0 {
+            info.credits -= 1;
+        } else {
+            info.remaining_requests -= 1;
+        }
+        RateLimitCheck::Allowed
+    }
+    
+    // Start a new window once the current one has ended; unused credits end with it
+    fn roll_window(&self, user_id: &str, info: &mut RateLimitInfo, now: DateTime<Utc>) {
+        if now > info.reset_at {
+            info.remaining_requests = self.config.max_requests;
+            info.credits = 0;
+            info.cache_served = 0;
+            info.reset_at = self.window_end(user_id, now);
+        }
+    }
+    
+    // Give the user extra requests for their current window (starting one if needed). Like
+    // reset, an operator action, so it may go past RATE_LIMIT_MAX_ENTRIES.
+    pub async fn grant_credits(&self, user_id: &str, credits: u32) -> RateLimitInfo {
+        let now = self.clock.now();
         
-        Ok(true)
+        let mut entry = self.store.entry(user_id.to_string())
+            .or_insert_with(|| self.fresh_window(user_id, now));
+        self.roll_window(user_id, &mut entry, now);
+        entry.credits = entry.credits.saturating_add(credits);
+        entry.clone()
     }
     
     pub async fn reset(&self, user_id: &str) -> Result<()> {
-        let mut store = self.store.lock().unwrap();
-        let now = Utc::now();
+        let now = self.clock.now();
         
         // Set up the user with a fresh rate limit
-        let new_info = RateLimitInfo {
-            user_id: user_id.to_string(),
-            remaining_requests: self.config.max_requests,  // Full quota
-            reset_at: now + Duration::seconds(self.config.window_seconds as i64),
-        };
-        
-        store.insert(user_id.to_string(), new_info);
+        self.store.insert(user_id.to_string(), self.fresh_window(user_id, now));
         Ok(())
     }
     
+    // When the user's current window ends, or would end if they made a request now
+    pub async fn current_reset_at(&self, user_id: &str) -> DateTime<Utc> {
+        let now = self.clock.now();
+        self.store
+            .get(user_id)
+            .map(|info| info.reset_at)
+            .filter(|reset_at| *reset_at > now)
+            .unwrap_or_else(|| self.window_end(user_id, now))
+    }
+    
+    // Whether a request from the user would be allowed now, without counting one
+    pub fn has_capacity(&self, user_id: &str) -> bool {
+        let now = self.clock.now();
+        let quota_left = self.store
+            .get(user_id)
+            .is_none_or(|info| now > info.reset_at || info.available() > 0);
+        if !quota_left || self.config.burst_max == 0 {
+            return quota_left;
+        }
+        
+        let period = Duration::seconds(self.config.burst_seconds as i64);
+        self.bursts
+            .get(user_id)
+            .is_none_or(|recent| recent.iter().filter(|at| **at + period > now).count() < self.config.burst_max as usize)
+    }
+    
+    // Count a request answered from stored sayings against the user's current window. Users
+    // without a running window (e.g. shadow-banned before their first request) aren't counted.
+    pub fn record_cache_served(&self, user_id: &str) {
+        let now = self.clock.now();
+        if let Some(mut info) = self.store.get_mut(user_id) {
+            if info.reset_at > now {
+                info.cache_served += 1;
+            }
+        }
+    }
+    
+    // When the window ending at `info.reset_at` began
+    pub fn window_start(&self, info: &RateLimitInfo) -> DateTime<Utc> {
+        match self.config.window {
+            RateLimitWindow::Rolling => info.reset_at - Duration::seconds(self.config.window_seconds as i64),
+            RateLimitWindow::CalendarDay => info.reset_at - Duration::days(1),
+        }
+    }
+    
     pub async fn get_limit_info(&self, user_id: &str) -> Option<RateLimitInfo> {
-        let store = self.store.lock().unwrap();
-        store.get(user_id).cloned()
+        self.store.get(user_id).map(|info| info.value().clone())
+    }
+    
+    // Copy of every tracked user's state, for operator views
+    pub fn snapshot(&self) -> Vec<RateLimitInfo> {
+        self.store.iter().map(|entry| entry.value().clone()).collect()
     }
-}
\ No newline at end of file
+    
+    // Drop users whose window has ended; their next request starts a fresh one anyway.
+    // Returns how many were dropped.
+    pub fn purge_expired(&self, now: DateTime<Utc>) -> usize {
+        let before = self.store.len();
+        self.store.retain(|_, info| info.reset_at > now);
+        
+        let period = Duration::seconds(self.config.burst_seconds as i64);
+        self.bursts.retain(|_, recent| recent.back().is_some_and(|at| *at + period > now));
+        
+        // Collected first so no store lock is held while the offsets are locked
+        let live: HashSet<String> = self.store.iter().map(|entry| entry.key().clone()).collect();
+        self.tz_offsets.retain(|user_id, _| live.contains(user_id));
+        
+        before.saturating_sub(self.store.len())
+    }
+}
+
+// Requests so far in a read window
+#[derive(Debug, Clone)]
+struct ReadWindow {
+    requests: u32,
+    reset_at: DateTime<Utc>,
+}
+
+// Fixed-window counter for the read endpoints. Much cheaper than the generation limiter,
+// and keyed by client so one scraper walking many user IDs shares one quota.
+#[derive(Debug, Clone)]
+pub struct ReadLimiter {
+    config: ReadRateLimitConfig,
+    windows: Arc<DashMap<String, ReadWindow>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl ReadLimiter {
+    pub fn new(config: ReadRateLimitConfig) -> Self {
+        Self {
+            config,
+            windows: Arc::new(DashMap::new()),
+            clock: clock::system(),
+        }
+    }
+    
+    pub fn with_clock(self, clock: Arc<dyn Clock>) -> Self {
+        Self { clock, ..self }
+    }
+    
+    // Counts the read, or says when the client's window ends if it is over quota
+    pub fn check(&self, key: &str) -> std::result::Result<(), DateTime<Utc>> {
+        if self.config.max_requests == 0 {
+            return Ok(());
+        }
+        
+        let now = self.clock.now();
+        let window_end = now + Duration::seconds(self.config.window_seconds as i64);
+        let mut window = self.windows
+            .entry(key.to_string())
+            .or_insert_with(|| ReadWindow { requests: 0, reset_at: window_end });
+        
+        if now >= window.reset_at {
+            *window = ReadWindow { requests: 0, reset_at: window_end };
+        }
+        if window.requests >= self.config.max_requests {
+            return Err(window.reset_at);
+        }
+        
+        window.requests += 1;
+        Ok(())
+    }
+    
+    pub fn purge_expired(&self, now: DateTime<Utc>) -> usize {
+        let before = self.windows.len();
+        self.windows.retain(|_, window| window.reset_at > now);
+        before.saturating_sub(self.windows.len())
+    }
+}
+
+// Sweep expired windows out of memory in the background
+pub fn spawn_cleanup_task(state: Arc<AppState>) {
+    let period = std::time::Duration::from_secs(state.config.rate_limit.cleanup_interval_seconds.max(1));
+    
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(period);
+        loop {
+            ticker.tick().await;
+            let now = state.clock.now();
+            let purged = state.rate_limiter.purge_expired(now) + state.read_limiter.purge_expired(now);
+            if purged > 0 {
+                tracing::debug!("Purged {} expired rate limit entries", purged);
+            }
+        }
+    });
+}
+
+// Drop requests older than `period` and, if `max` remain, how many seconds until the
+// oldest expires
+fn burst_retry_after(recent: &mut VecDeque<DateTime<Utc>>, now: DateTime<Utc>, period: Duration, max: u32) -> Option<u64> {
+    while recent.front().is_some_and(|at| *at + period <= now) {
+        recent.pop_front();
+    }
+    
+    if recent.len() < max as usize {
+        return None;
+    }
+    let wait = recent.front().map(|at| *at + period - now).unwrap_or(period);
+    Some(wait.num_seconds().max(1) as u64)
+}
+
+// The first midnight after `now` in the given timezone
+fn next_local_midnight(now: DateTime<Utc>, offset: FixedOffset) -> DateTime<Utc> {
+    let tomorrow = now.with_timezone(&offset).date_naive() + Duration::days(1);
+    let midnight = tomorrow.and_hms_opt(0, 0, 0).unwrap();
+    (midnight - Duration::seconds(offset.local_minus_utc() as i64)).and_utc()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::config::RateLimitMode;
+    use chrono::TimeZone;
+
+    fn limiter(max_requests: u32, max_entries: usize) -> RateLimiter {
+        RateLimiter::new(RateLimitConfig {
+            max_requests,
+            window_seconds: 3600,
+            window: RateLimitWindow::Rolling,
+            burst_max: 0,
+            burst_seconds: 60,
+            exempt_users: Vec::new(),
+            exempt_tokens: Vec::new(),
+            max_entries,
+            cleanup_interval_seconds: 300,
+            mode: RateLimitMode::Reject,
+            queue_size: 0,
+            queue_per_user: 0,
+        })
+    }
+
+    #[test]
+    fn test_next_local_midnight() {
+        let now = Utc.with_ymd_and_hms(2024, 3, 10, 20, 30, 0).unwrap();
+        
+        assert_eq!(next_local_midnight(now, streaks::offset_from_minutes(0)), Utc.with_ymd_and_hms(2024, 3, 11, 0, 0, 0).unwrap());
+        // 04:30 on the 11th in UTC+8, so the window runs until 00:00 on the 12th there
+        assert_eq!(next_local_midnight(now, streaks::offset_from_minutes(480)), Utc.with_ymd_and_hms(2024, 3, 11, 16, 0, 0).unwrap());
+        // 15:30 on the 10th in UTC-5
+        assert_eq!(next_local_midnight(now, streaks::offset_from_minutes(-300)), Utc.with_ymd_and_hms(2024, 3, 11, 5, 0, 0).unwrap());
+    }
+
+    #[test]
+    fn test_burst_retry_after() {
+        let start = Utc.with_ymd_and_hms(2024, 3, 10, 12, 0, 0).unwrap();
+        let period = Duration::seconds(60);
+        let mut recent = VecDeque::new();
+        
+        for second in 0..3 {
+            let now = start + Duration::seconds(second);
+            assert_eq!(burst_retry_after(&mut recent, now, period, 3), None);
+            recent.push_back(now);
+        }
+        
+        // The fourth request within the minute waits for the first to age out
+        assert_eq!(burst_retry_after(&mut recent, start + Duration::seconds(10), period, 3), Some(50));
+        assert_eq!(burst_retry_after(&mut recent, start + Duration::seconds(60), period, 3), None);
+        assert_eq!(recent.len(), 2);
+    }
+
+    #[test]
+    fn test_purge_expired() {
+        let limiter = limiter(5, 10);
+        let start = Utc.with_ymd_and_hms(2024, 3, 10, 12, 0, 0).unwrap();
+        let track = |user_id: &str, reset_in_minutes: i64| {
+            let info = RateLimitInfo {
+                user_id: user_id.to_string(),
+                remaining_requests: 5,
+                reset_at: start + Duration::minutes(reset_in_minutes),
+                credits: 0,
+                cache_served: 0,
+            };
+            limiter.store.insert(user_id.to_string(), info);
+            limiter.tz_offsets.insert(user_id.to_string(), 60);
+        };
+        
+        track("expired", 10);
+        track("active", 60);
+        assert_eq!(limiter.purge_expired(start + Duration::minutes(30)), 1);
+        assert!(limiter.store.contains_key("active"));
+        assert!(!limiter.tz_offsets.contains_key("expired"));
+    }
+
+    #[tokio::test]
+    async fn test_full_limiter_refuses_new_users_instead_of_evicting() {
+        let clock = ManualClock::new(Utc.with_ymd_and_hms(2024, 3, 10, 12, 0, 0).unwrap());
+        let limiter = limiter(1, 2).with_clock(clock.clone());
+        
+        assert_eq!(limiter.check("alice").await.unwrap(), RateLimitCheck::Allowed);
+        assert_eq!(limiter.check("bob").await.unwrap(), RateLimitCheck::Allowed);
+        assert_eq!(limiter.check("carol").await.unwrap(), RateLimitCheck::Full { retry_after: 300 });
+        // Tracked users keep their spent quota
+        assert_eq!(limiter.check("alice").await.unwrap(), RateLimitCheck::Exhausted);
+        
+        // Once their windows are swept there is room again
+        clock.advance(Duration::hours(2));
+        limiter.purge_expired(clock.now());
+        assert_eq!(limiter.check("carol").await.unwrap(), RateLimitCheck::Allowed);
+    }
+
+    #[test]
+    fn test_credits_spent_first_and_end_with_window() {
+        let limiter = limiter(1, 10);
+        let now = Utc.with_ymd_and_hms(2024, 3, 10, 12, 0, 0).unwrap();
+        let mut info = RateLimitInfo {
+            user_id: "alice".to_string(),
+            remaining_requests: 1,
+            reset_at: now + Duration::hours(1),
+            credits: 2,
+            cache_served: 0,
+        };
+        
+        assert_eq!(limiter.consume("alice", &mut info, now), RateLimitCheck::Allowed);
+        assert_eq!((info.remaining_requests, info.credits), (1, 1));
+        assert_eq!(limiter.consume("alice", &mut info, now), RateLimitCheck::Allowed);
+        assert_eq!(limiter.consume("alice", &mut info, now), RateLimitCheck::Allowed);
+        assert_eq!(limiter.consume("alice", &mut info, now), RateLimitCheck::Exhausted);
+        
+        info.credits = 3;
+        info.cache_served = 4;
+        limiter.roll_window("alice", &mut info, now + Duration::hours(2));
+        assert_eq!((info.remaining_requests, info.credits, info.cache_served), (1, 0, 0));
+        assert_eq!(limiter.window_start(&info), now + Duration::hours(2));
+    }
+
+    #[tokio::test]
+    async fn test_quota_returns_when_the_window_resets() {
+        let clock = ManualClock::new(Utc.with_ymd_and_hms(2024, 3, 10, 12, 0, 0).unwrap());
+        let limiter = limiter(2, 10).with_clock(clock.clone());
+        
+        assert_eq!(limiter.check("alice").await.unwrap(), RateLimitCheck::Allowed);
+        assert_eq!(limiter.check("alice").await.unwrap(), RateLimitCheck::Allowed);
+        assert_eq!(limiter.check("alice").await.unwrap(), RateLimitCheck::Exhausted);
+        assert!(!limiter.has_capacity("alice"));
+        
+        // Still inside the hour-long window
+        clock.advance(Duration::minutes(59));
+        assert_eq!(limiter.check("alice").await.unwrap(), RateLimitCheck::Exhausted);
+        
+        clock.advance(Duration::minutes(2));
+        assert!(limiter.has_capacity("alice"));
+        assert_eq!(limiter.check("alice").await.unwrap(), RateLimitCheck::Allowed);
+        assert_eq!(limiter.current_reset_at("alice").await, clock.now() + Duration::hours(1));
+    }
+
+    #[test]
+    fn test_read_limiter_counts_per_key() {
+        let clock = ManualClock::new(Utc.with_ymd_and_hms(2024, 3, 10, 12, 0, 0).unwrap());
+        let limiter = ReadLimiter::new(ReadRateLimitConfig { max_requests: 2, window_seconds: 60 })
+            .with_clock(clock.clone());
+        
+        assert!(limiter.check("ip:203.0.113.7").is_ok());
+        assert!(limiter.check("ip:203.0.113.7").is_ok());
+        assert_eq!(limiter.check("ip:203.0.113.7").unwrap_err(), clock.now() + Duration::seconds(60));
+        assert!(limiter.check("ip:198.51.100.1").is_ok());
+        
+        // The window ends on the app clock, not the system one
+        clock.advance(Duration::seconds(60));
+        assert!(limiter.check("ip:203.0.113.7").is_ok());
+        assert_eq!(limiter.purge_expired(clock.now() + Duration::seconds(60)), 2);
+    }
+}
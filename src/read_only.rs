@@ -0,0 +1,47 @@
+use axum::{
+    extract::{Request, State},
+    http::Method,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::sync::Arc;
+
+use crate::handlers::ApiError;
+use crate::AppState;
+
+// POSTs that only compute a response and store nothing
+const READ_ONLY_POSTS: &[&str] = &["/sayings/estimate", "/users/status"];
+
+// Whether a request would change stored state
+fn is_write(method: &Method, path: &str) -> bool {
+    match *method {
+        Method::GET | Method::HEAD | Method::OPTIONS => false,
+        Method::POST => !READ_ONLY_POSTS.contains(&path),
+        _ => true,
+    }
+}
+
+// With READ_ONLY=true, e.g. during maintenance or on replica instances, writes are answered
+// with 503 and only stored content is served
+pub async fn middleware(State(state): State<Arc<AppState>>, request: Request, next: Next) -> Response {
+    if state.config.server.read_only && is_write(request.method(), request.uri().path()) {
+        return ApiError::ReadOnly.into_response();
+    }
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_writes_are_recognized() {
+        assert!(!is_write(&Method::GET, "/sayings"));
+        assert!(!is_write(&Method::POST, "/sayings/estimate"));
+        assert!(!is_write(&Method::POST, "/users/status"));
+        assert!(is_write(&Method::POST, "/sayings"));
+        assert!(is_write(&Method::DELETE, "/admin/bans/user/alice"));
+        assert!(!is_write(&Method::GET, "/unsubscribe/token"));
+        assert!(is_write(&Method::POST, "/unsubscribe/token"));
+    }
+}
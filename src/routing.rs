@@ -0,0 +1,345 @@
+use chrono::{DateTime, Duration, Utc};
+use dashmap::DashMap;
+use rand::Rng;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use crate::config::{CanaryConfig, ModelRoutingConfig};
+
+// Routing changes kept for the admin dashboard
+const MAX_EVENTS: usize = 20;
+
+// One upstream call
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    at: DateTime<Utc>,
+    latency_ms: u64,
+    ok: bool,
+}
+
+// How a model has done over the window
+#[derive(Debug, Clone, Serialize)]
+pub struct ModelStats {
+    pub model: String,
+    pub calls: usize,
+    pub error_percent: u32,
+    // Average over successful calls
+    pub avg_latency_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RoutingEventKind {
+    // The primary model broke a threshold; new requests go to the fallback
+    Degraded,
+    // The primary model is within the thresholds again
+    Recovered,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RoutingEvent {
+    pub at: DateTime<Utc>,
+    pub model: String,
+    pub kind: RoutingEventKind,
+    pub reason: String,
+}
+
+#[derive(Debug, Default)]
+struct RoutingState {
+    // Set while requests are routed away from the primary
+    degraded_since: Option<DateTime<Utc>>,
+    last_probe: Option<DateTime<Utc>>,
+    events: VecDeque<RoutingEvent>,
+}
+
+// Rolling latency and error rates per model, and whether the primary is trusted with new requests
+#[derive(Debug)]
+pub struct ModelHealth {
+    config: ModelRoutingConfig,
+    // Model -> calls within the window, oldest first
+    samples: DashMap<String, VecDeque<Sample>>,
+    state: Mutex<RoutingState>,
+}
+
+impl ModelHealth {
+    pub fn new(config: ModelRoutingConfig) -> Self {
+        Self {
+            config,
+            samples: DashMap::new(),
+            state: Mutex::new(RoutingState::default()),
+        }
+    }
+
+    // The model a new request should use: the primary, unless it is degraded and there is
+    // a fallback to send it to instead. Probes of a degraded primary are let through.
+    pub fn route<'a>(&self, primary: &'a str, fallback: Option<&'a str>, now: DateTime<Utc>) -> &'a str {
+        let Some(fallback) = fallback.filter(|_| self.config.enabled) else {
+            return primary;
+        };
+
+        let mut state = self.state.lock().unwrap();
+        if state.degraded_since.is_none() {
+            return primary;
+        }
+        let probe_due = state.last_probe
+            .is_none_or(|at| now - at >= Duration::seconds(self.config.probe_seconds as i64));
+        if probe_due {
+            state.last_probe = Some(now);
+            return primary;
+        }
+        fallback
+    }
+
+    // Count a call and, for the primary model, switch routing when it crosses a threshold.
+    // Returns the routing change, if any.
+    pub fn record(&self, primary: &str, model: &str, latency_ms: u64, ok: bool, now: DateTime<Utc>) -> Option<RoutingEvent> {
+        {
+            let mut samples = self.samples.entry(model.to_string()).or_default();
+            samples.push_back(Sample { at: now, latency_ms, ok });
+            let window = Duration::seconds(self.config.window_seconds as i64);
+            while samples.front().is_some_and(|sample| sample.at + window <= now) {
+                samples.pop_front();
+            }
+        }
+
+        if !self.config.enabled || model != primary {
+            return None;
+        }
+
+        let stats = self.stats(model, now);
+        if stats.calls < self.config.min_samples {
+            return None;
+        }
+        let breach = self.breach(&stats);
+
+        let mut state = self.state.lock().unwrap();
+        let event = match (state.degraded_since, breach) {
+            (None, Some(reason)) => {
+                state.degraded_since = Some(now);
+                state.last_probe = Some(now);
+                // Recovery is judged on probes alone, not on the calls that caused this
+                self.samples.remove(model);
+                RoutingEvent { at: now, model: model.to_string(), kind: RoutingEventKind::Degraded, reason }
+            }
+            (Some(since), None) => {
+                state.degraded_since = None;
+                let reason = format!(
+                    "{}% errors, {} ms average over {} calls; routed away for {}s",
+                    stats.error_percent,
+                    stats.avg_latency_ms.unwrap_or(0),
+                    stats.calls,
+                    (now - since).num_seconds(),
+                );
+                RoutingEvent { at: now, model: model.to_string(), kind: RoutingEventKind::Recovered, reason }
+            }
+            _ => return None,
+        };
+
+        state.events.push_front(event.clone());
+        state.events.truncate(MAX_EVENTS);
+        Some(event)
+    }
+
+    // Which threshold the stats break, if any
+    fn breach(&self, stats: &ModelStats) -> Option<String> {
+        if stats.error_percent > self.config.max_error_percent {
+            return Some(format!("{}% of the last {} calls failed", stats.error_percent, stats.calls));
+        }
+        match stats.avg_latency_ms {
+            Some(latency) if latency > self.config.max_latency_ms => {
+                Some(format!("average latency of {} ms over the last {} calls", latency, stats.calls))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn stats(&self, model: &str, now: DateTime<Utc>) -> ModelStats {
+        let window = Duration::seconds(self.config.window_seconds as i64);
+        let samples: Vec<Sample> = self.samples
+            .get(model)
+            .map(|samples| samples.iter().filter(|sample| sample.at + window > now).copied().collect())
+            .unwrap_or_default();
+
+        let failures = samples.iter().filter(|sample| !sample.ok).count();
+        let latencies: Vec<u64> = samples.iter().filter(|sample| sample.ok).map(|sample| sample.latency_ms).collect();
+        ModelStats {
+            model: model.to_string(),
+            calls: samples.len(),
+            error_percent: if samples.is_empty() { 0 } else { (failures * 100 / samples.len()) as u32 },
+            avg_latency_ms: (!latencies.is_empty()).then(|| latencies.iter().sum::<u64>() / latencies.len() as u64),
+        }
+    }
+
+    // Stats of every model called recently, busiest first
+    pub fn snapshot(&self, now: DateTime<Utc>) -> Vec<ModelStats> {
+        let models: Vec<String> = self.samples.iter().map(|entry| entry.key().clone()).collect();
+        let mut stats: Vec<ModelStats> = models.iter()
+            .map(|model| self.stats(model, now))
+            .filter(|stats| stats.calls > 0)
+            .collect();
+        stats.sort_by(|a, b| b.calls.cmp(&a.calls).then_with(|| a.model.cmp(&b.model)));
+        stats
+    }
+
+    pub fn is_degraded(&self) -> bool {
+        self.state.lock().unwrap().degraded_since.is_some()
+    }
+
+    // Routing changes, newest first
+    pub fn events(&self) -> Vec<RoutingEvent> {
+        self.state.lock().unwrap().events.iter().cloned().collect()
+    }
+}
+
+// Outcomes of every call to a model since startup
+#[derive(Debug, Default, Clone, Copy)]
+struct Totals {
+    calls: u64,
+    failures: u64,
+    // Summed over successful calls
+    latency_ms: u64,
+    response_chars: u64,
+    degenerate: u64,
+}
+
+// How a model has done since startup, for comparing a canary with the primary
+#[derive(Debug, Clone, Serialize)]
+pub struct ModelComparison {
+    pub model: String,
+    pub canary: bool,
+    pub calls: u64,
+    pub error_percent: u32,
+    pub avg_latency_ms: Option<u64>,
+    pub avg_response_chars: Option<u64>,
+    // Empty or unusable outputs that had to be regenerated
+    pub degenerate_percent: u32,
+}
+
+// Sends CANARY_PERCENT of new requests to CANARY_MODEL and keeps running totals per model,
+// so the candidate can be judged against the primary before switching over
+#[derive(Debug)]
+pub struct Canary {
+    config: CanaryConfig,
+    totals: DashMap<String, Totals>,
+}
+
+impl Canary {
+    pub fn new(config: CanaryConfig) -> Self {
+        Self { config, totals: DashMap::new() }
+    }
+
+    pub fn model(&self) -> Option<&str> {
+        self.config.model.as_deref().filter(|_| self.config.percent > 0)
+    }
+
+    pub fn percent(&self) -> u32 {
+        self.config.percent
+    }
+
+    // The canary model for this request, when the dice say so
+    pub fn pick(&self) -> Option<&str> {
+        self.model().filter(|_| rand::thread_rng().gen_range(0..100) < self.config.percent)
+    }
+
+    pub fn record(&self, model: &str, latency_ms: u64, response_chars: Option<usize>) {
+        let mut totals = self.totals.entry(model.to_string()).or_default();
+        totals.calls += 1;
+        match response_chars {
+            Some(chars) => {
+                totals.latency_ms += latency_ms;
+                totals.response_chars += chars as u64;
+            }
+            None => totals.failures += 1,
+        }
+    }
+
+    pub fn record_degenerate(&self, model: &str) {
+        self.totals.entry(model.to_string()).or_default().degenerate += 1;
+    }
+
+    // Every model called since startup, busiest first
+    pub fn comparison(&self) -> Vec<ModelComparison> {
+        let percent = |part: u64, whole: u64| (part * 100).checked_div(whole).unwrap_or(0) as u32;
+        let mut models: Vec<ModelComparison> = self.totals.iter()
+            .map(|entry| {
+                let totals = *entry.value();
+                let succeeded = totals.calls - totals.failures;
+                ModelComparison {
+                    model: entry.key().clone(),
+                    canary: self.config.model.as_deref() == Some(entry.key().as_str()),
+                    calls: totals.calls,
+                    error_percent: percent(totals.failures, totals.calls),
+                    avg_latency_ms: (succeeded > 0).then(|| totals.latency_ms / succeeded),
+                    avg_response_chars: (succeeded > 0).then(|| totals.response_chars / succeeded),
+                    degenerate_percent: percent(totals.degenerate, succeeded),
+                }
+            })
+            .collect();
+        models.sort_by(|a, b| b.calls.cmp(&a.calls).then_with(|| a.model.cmp(&b.model)));
+        models
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_degrades_to_fallback_and_recovers_through_probes() {
+        let health = ModelHealth::new(ModelRoutingConfig {
+            enabled: true,
+            window_seconds: 300,
+            min_samples: 3,
+            max_error_percent: 50,
+            max_latency_ms: 5000,
+            probe_seconds: 30,
+        });
+        let start = Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap();
+        let at = |seconds: i64| start + Duration::seconds(seconds);
+
+        // Slow but successful calls break the latency threshold once there are enough of them
+        assert!(health.record("primary", "primary", 8000, true, at(0)).is_none());
+        assert!(health.record("primary", "primary", 9000, true, at(1)).is_none());
+        let event = health.record("primary", "primary", 7000, true, at(2)).unwrap();
+        assert_eq!(event.kind, RoutingEventKind::Degraded);
+        assert_eq!(health.route("primary", Some("fallback"), at(3)), "fallback");
+        // Without a fallback there is nowhere else to go
+        assert_eq!(health.route("primary", None, at(3)), "primary");
+
+        // Every probe interval one request still tries the primary
+        for probe in 1..=3 {
+            let now = at(probe * 30 + 2);
+            assert_eq!(health.route("primary", Some("fallback"), now), "primary");
+            assert_eq!(health.route("primary", Some("fallback"), now), "fallback");
+            let event = health.record("primary", "primary", 800, true, now);
+            assert_eq!(event.map(|event| event.kind), (probe == 3).then_some(RoutingEventKind::Recovered));
+        }
+        assert!(!health.is_degraded());
+        assert_eq!(health.events().len(), 2);
+    }
+
+    #[test]
+    fn test_canary_takes_its_share_and_compares_models() {
+        let canary = Canary::new(CanaryConfig { model: Some("candidate".to_string()), percent: 100 });
+        assert_eq!(canary.pick(), Some("candidate"));
+        let off = Canary::new(CanaryConfig { model: Some("candidate".to_string()), percent: 0 });
+        assert_eq!(off.pick(), None);
+
+        canary.record("primary", 1000, Some(40));
+        canary.record("primary", 3000, Some(60));
+        canary.record("candidate", 500, Some(20));
+        canary.record("candidate", 0, None);
+        canary.record_degenerate("candidate");
+
+        let models = canary.comparison();
+        assert_eq!(models.len(), 2);
+        let primary = models.iter().find(|m| m.model == "primary").unwrap();
+        assert!(!primary.canary);
+        assert_eq!((primary.avg_latency_ms, primary.avg_response_chars, primary.error_percent), (Some(2000), Some(50), 0));
+        let candidate = models.iter().find(|m| m.model == "candidate").unwrap();
+        assert!(candidate.canary);
+        assert_eq!((candidate.error_percent, candidate.degenerate_percent), (50, 100));
+    }
+}
@@ -0,0 +1,239 @@
+use anyhow::{anyhow, Context, Result};
+use chrono::Utc;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::bans::{Ban, BanMode, BanSubject};
+use crate::config::{Config, HttpClientConfig, ProviderType, StorageType};
+use crate::debug_log::DebugLog;
+use crate::glossary::Glossary;
+use crate::http_client;
+use crate::languages::{find_language, get_all_languages, DEFAULT_LANGUAGE_ID};
+use crate::metrics::Metrics;
+use crate::openrouter::OpenRouterClient;
+use crate::preset::Presets;
+use crate::storage::Storage;
+
+// Written and removed again by the storage check; never matches a real request
+const SENTINEL_USER_ID: &str = "__self_test__";
+const PING_TIMEOUT_SECS: u64 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckStatus {
+    Pass,
+    Fail,
+    Skip,
+}
+
+#[derive(Debug)]
+pub struct Check {
+    pub name: &'static str,
+    pub status: CheckStatus,
+    pub detail: String,
+}
+
+impl Check {
+    fn from_result(name: &'static str, result: Result<String>) -> Self {
+        match result {
+            Ok(detail) => Self { name, status: CheckStatus::Pass, detail },
+            Err(e) => Self { name, status: CheckStatus::Fail, detail: format!("{:#}", e) },
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Report {
+    pub checks: Vec<Check>,
+}
+
+impl Report {
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(|check| check.status != CheckStatus::Fail)
+    }
+
+    pub fn print(&self) {
+        println!("Self-test:");
+        for check in &self.checks {
+            let status = match check.status {
+                CheckStatus::Pass => "PASS",
+                CheckStatus::Fail => "FAIL",
+                CheckStatus::Skip => "SKIP",
+            };
+            println!("  [{}] {}: {}", status, check.name, check.detail);
+        }
+
+        let failed = self.checks.iter().filter(|check| check.status == CheckStatus::Fail).count();
+        println!("{}", if failed == 0 { "All checks passed".to_string() } else { format!("{} of {} checks failed", failed, self.checks.len()) });
+    }
+
+    // The boot-time report, one event per check with its fields for log pipelines
+    pub fn log(&self) {
+        for check in &self.checks {
+            match check.status {
+                CheckStatus::Pass => tracing::info!(check = check.name, status = "pass", detail = %check.detail, "Startup check passed"),
+                CheckStatus::Skip => tracing::info!(check = check.name, status = "skip", detail = %check.detail, "Startup check skipped"),
+                CheckStatus::Fail => tracing::error!(check = check.name, status = "fail", detail = %check.detail, "Startup check failed"),
+            }
+        }
+    }
+}
+
+// Everything the service needs before it can answer a request, checked from config alone
+// so a broken presets file or database is reported rather than aborting the boot
+pub async fn run(config: &Config) -> Report {
+    let upstream = match (config.self_test.ping_upstream, &config.openrouter.provider) {
+        (false, _) => Check { name: "upstream", status: CheckStatus::Skip, detail: "SELF_TEST_PING_UPSTREAM is not set".to_string() },
+        (true, ProviderType::Mock) => Check { name: "upstream", status: CheckStatus::Skip, detail: "mock provider".to_string() },
+        (true, ProviderType::OpenRouter) => Check::from_result("upstream", check_upstream(config).await),
+    };
+
+    Report {
+        checks: vec![
+            check_defaults(config),
+            Check::from_result("storage", check_storage(config).await),
+            Check::from_result("presets", check_presets(config)),
+            Check::from_result("languages", check_languages(config)),
+            upstream,
+        ],
+    }
+}
+
+// `--self-test` and STARTUP_SELF_TEST: print the report and fail when any check did
+pub async fn run_cli(config: &Config) -> Result<()> {
+    let report = run(config).await;
+    report.print();
+
+    if report.passed() {
+        Ok(())
+    } else {
+        Err(anyhow!("Self-test failed"))
+    }
+}
+
+// Informational: unset variables are expected, but a typo in a name shows up here
+fn check_defaults(config: &Config) -> Check {
+    let detail = match config.unset_variables.len() {
+        0 => format!("{} profile, every variable is set", config.profile.as_str()),
+        count => format!(
+            "{} profile, {} variables unset, defaults applied: {}",
+            config.profile.as_str(),
+            count,
+            config.unset_variables.join(", ")
+        ),
+    };
+    Check { name: "defaults", status: CheckStatus::Pass, detail }
+}
+
+async fn check_storage(config: &Config) -> Result<String> {
+    let storage = Storage::new(config.storage.clone())?;
+    // Even where the fallback policy allows memory storage, the check is about the configured backend
+    if matches!(config.storage.type_, StorageType::Sled) && storage.backend() != "sled" {
+        return Err(anyhow!("Sled database at {} could not be opened", config.storage.connection_string));
+    }
+
+    let ban = Ban {
+        subject: BanSubject::User(SENTINEL_USER_ID.to_string()),
+        mode: BanMode::ShadowBan,
+        reason: Some("startup self-test".to_string()),
+        created_at: Utc::now(),
+    };
+    let key = ban.subject.key();
+    storage.save_ban(&ban).await.context("Write failed")?;
+    let found = storage.list_bans().await.context("Read failed")?
+        .iter()
+        .any(|stored| stored.subject.key() == key);
+    storage.delete_ban(&key).await.context("Delete failed")?;
+
+    if !found {
+        return Err(anyhow!("A record that was written could not be read back"));
+    }
+    Ok(format!("{} backend, write, read and delete succeeded", storage.backend()))
+}
+
+fn check_presets(config: &Config) -> Result<String> {
+    let presets = Presets::from_file(&config.presets.file_path)?.with_max_rating(config.presets.max_rating);
+    let default = presets.get_default_preset()?;
+
+    let duplicates = presets.duplicate_ids();
+    if !duplicates.is_empty() {
+        return Err(anyhow!("Duplicate preset IDs in {}: {}", config.presets.file_path, duplicates.join(", ")));
+    }
+
+    for preset_id in &config.daily_saying.presets {
+        if presets.get_preset_by_id(preset_id).is_none() {
+            return Err(anyhow!("DAILY_SAYING_PRESETS names unknown preset {}", preset_id));
+        }
+    }
+
+    Ok(format!(
+        "{} presets from {}, default {}",
+        presets.get_all_presets().len(),
+        config.presets.file_path,
+        default.id
+    ))
+}
+
+fn check_languages(config: &Config) -> Result<String> {
+    if find_language(DEFAULT_LANGUAGE_ID).is_none() {
+        return Err(anyhow!("Default language {} is missing from the language table", DEFAULT_LANGUAGE_ID));
+    }
+
+    // Fallback sources may be unsupported IDs by design; only their targets must exist
+    let configured = [
+        ("CACHE_WARMUP_LANGUAGES", config.cache_warmup.languages.iter().collect::<Vec<_>>()),
+        ("DAILY_SAYING_LANGUAGES", config.daily_saying.languages.iter().collect()),
+        ("LANGUAGE_FALLBACKS", config.languages.fallbacks.values().collect()),
+    ];
+    for (variable, language_ids) in configured {
+        if let Some(language_id) = language_ids.into_iter().find(|id| find_language(id).is_none()) {
+            return Err(anyhow!("{} names unknown language {}", variable, language_id));
+        }
+    }
+
+    if let Some(path) = &config.languages.glossary_path {
+        Glossary::from_file(path)?;
+    }
+
+    Ok(format!("{} languages", get_all_languages().len()))
+}
+
+async fn check_upstream(config: &Config) -> Result<String> {
+    let http_client = http_client::build(&HttpClientConfig {
+        proxy_url: config.openrouter.proxy_url.clone().or_else(|| config.http_client.proxy_url.clone()),
+        ..config.http_client.clone()
+    })?;
+    let client = OpenRouterClient::new(
+        config.openrouter.clone(),
+        http_client,
+        Arc::new(Metrics::new()),
+        Arc::new(DebugLog::new(config)),
+    );
+
+    tokio::time::timeout(Duration::from_secs(PING_TIMEOUT_SECS), client.ping())
+        .await
+        .map_err(|_| anyhow!("OpenRouter did not answer within {}s", PING_TIMEOUT_SECS))??;
+    Ok(format!("{} accepted the API key", config.openrouter.base_url))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_configured_presets_and_languages_must_exist() {
+        let mut config = Config::from_env_with_provider(ProviderType::Mock);
+        config.presets.file_path = "presets.yaml".to_string();
+        config.languages.fallbacks.clear();
+        config.languages.glossary_path = None;
+        config.cache_warmup.languages = vec!["en".to_string()];
+        config.daily_saying.presets.clear();
+        config.daily_saying.languages.clear();
+        assert!(check_presets(&config).is_ok());
+        assert!(check_languages(&config).is_ok());
+
+        config.daily_saying.presets = vec!["no-such-preset".to_string()];
+        config.daily_saying.languages = vec!["xx".to_string()];
+        assert!(check_presets(&config).unwrap_err().to_string().contains("no-such-preset"));
+        assert!(check_languages(&config).unwrap_err().to_string().contains("DAILY_SAYING_LANGUAGES"));
+    }
+}
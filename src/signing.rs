@@ -0,0 +1,151 @@
+use anyhow::{anyhow, Context, Result};
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use serde::{Deserialize, Serialize};
+
+use crate::config::PresetsConfig;
+
+// Ed25519 signature over a preset's canonical JSON, carried in its export
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PresetSignature {
+    // Hex public key of the signer, matched against PRESETS_TRUSTED_KEYS
+    pub public_key: String,
+    pub signature: String,
+}
+
+// This deployment's key for signing exports and the publishers it trusts on import
+pub struct PresetKeys {
+    signing_key: Option<SigningKey>,
+    trusted: Vec<(String, VerifyingKey)>,
+    allow_unverified: bool,
+}
+
+impl PresetKeys {
+    // Fails on keys that aren't 32 hex-encoded bytes, so a typo can't quietly turn signing off
+    pub fn from_config(config: &PresetsConfig) -> Result<Self> {
+        let signing_key = config.signing_key.as_deref()
+            .map(|key| key_bytes(key).map(|bytes| SigningKey::from_bytes(&bytes)))
+            .transpose()
+            .context("Invalid PRESETS_SIGNING_KEY")?;
+        let trusted = config.trusted_keys.iter()
+            .map(|(publisher, key)| {
+                let key = key_bytes(key).and_then(|bytes| Ok(VerifyingKey::from_bytes(&bytes)?))
+                    .with_context(|| format!("Invalid key for publisher {} in PRESETS_TRUSTED_KEYS", publisher))?;
+                Ok((publisher.clone(), key))
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            signing_key,
+            trusted,
+            allow_unverified: config.allow_unverified_imports,
+        })
+    }
+
+    // Public key exports are signed with, to hand to deployments that should trust them
+    pub fn public_key(&self) -> Option<String> {
+        self.signing_key.as_ref().map(|key| hex(key.verifying_key().as_bytes()))
+    }
+
+    // None when no signing key is configured
+    pub fn sign(&self, message: &[u8]) -> Option<PresetSignature> {
+        let key = self.signing_key.as_ref()?;
+        Some(PresetSignature {
+            public_key: hex(key.verifying_key().as_bytes()),
+            signature: hex(&key.sign(message).to_bytes()),
+        })
+    }
+
+    // The trusted publisher whose signature covers `message`. Unsigned, unknown and tampered
+    // messages are refused, unless PRESETS_ALLOW_UNVERIFIED lets them through as None.
+    pub fn verify(&self, message: &[u8], signature: Option<&PresetSignature>) -> Result<Option<String>> {
+        match self.check(message, signature) {
+            Ok(publisher) => Ok(Some(publisher)),
+            Err(e) if self.allow_unverified => {
+                tracing::warn!("Accepting unverified preset: {}", e);
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    fn check(&self, message: &[u8], signature: Option<&PresetSignature>) -> Result<String> {
+        let signature = signature.ok_or_else(|| anyhow!("The preset is not signed"))?;
+        let public_key = key_bytes(&signature.public_key).context("Malformed public key in signature")?;
+        let (publisher, key) = self.trusted.iter()
+            .find(|(_, key)| key.as_bytes() == &public_key)
+            .ok_or_else(|| anyhow!("The preset is signed by a key that isn't trusted ({})", signature.public_key))?;
+
+        let bytes: [u8; 64] = decode_hex(&signature.signature)
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or_else(|| anyhow!("Malformed signature"))?;
+        key.verify_strict(message, &Signature::from_bytes(&bytes))
+            .map_err(|_| anyhow!("The signature doesn't match; the preset was changed after signing"))?;
+
+        Ok(publisher.clone())
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    let text = text.trim();
+    if !text.len().is_multiple_of(2) || !text.is_ascii() {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&text[i..i + 2], 16).ok())
+        .collect()
+}
+
+fn key_bytes(text: &str) -> Result<[u8; 32]> {
+    decode_hex(text)
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| anyhow!("expected 32 bytes as 64 hex digits"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::preset::ContentRating;
+
+    fn config(signing_key: Option<&str>, trusted_keys: Vec<(&str, String)>, allow_unverified_imports: bool) -> PresetsConfig {
+        PresetsConfig {
+            file_path: String::new(),
+            no_repeat: 1,
+            max_rating: ContentRating::Mature,
+            signing_key: signing_key.map(str::to_string),
+            trusted_keys: trusted_keys.into_iter().map(|(publisher, key)| (publisher.to_string(), key)).collect(),
+            allow_unverified_imports,
+            rng_seed: None,
+        }
+    }
+
+    #[test]
+    fn test_signed_presets_verify_against_the_trust_list() {
+        let publisher = PresetKeys::from_config(&config(Some(&"07".repeat(32)), Vec::new(), false)).unwrap();
+        let public_key = publisher.public_key().unwrap();
+        let signature = publisher.sign(b"preset").unwrap();
+
+        let importer = PresetKeys::from_config(&config(None, vec![("community", public_key)], false)).unwrap();
+        assert_eq!(importer.verify(b"preset", Some(&signature)).unwrap().as_deref(), Some("community"));
+        assert!(importer.verify(b"edited preset", Some(&signature)).is_err());
+        assert!(importer.verify(b"preset", None).is_err());
+
+        let stranger = PresetKeys::from_config(&config(Some(&"08".repeat(32)), Vec::new(), false)).unwrap();
+        assert!(importer.verify(b"preset", stranger.sign(b"preset").as_ref()).is_err());
+
+        // The override lets anything through, without naming a publisher
+        let lenient = PresetKeys::from_config(&config(None, Vec::new(), true)).unwrap();
+        assert_eq!(lenient.verify(b"preset", None).unwrap(), None);
+        assert_eq!(lenient.verify(b"preset", Some(&signature)).unwrap(), None);
+    }
+
+    #[test]
+    fn test_malformed_keys_are_rejected() {
+        assert!(PresetKeys::from_config(&config(Some("not hex"), Vec::new(), false)).is_err());
+        assert!(PresetKeys::from_config(&config(None, vec![("short", "abcd".to_string())], false)).is_err());
+    }
+}
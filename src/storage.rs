@@ -1,15 +1,57 @@
-use anyhow::{Result, Context};
-use chrono::Utc;
-use std::collections::{HashMap, HashSet};
-use std::path::Path;
+use anyhow::{anyhow, Result, Context};
+use chrono::{DateTime, Duration, Utc};
+use std::cmp::Reverse;
+use dashmap::DashMap;
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
-use uuid::Uuid;
 
-use crate::config::{StorageConfig, StorageType};
-use crate::models::{Saying, SayingSource, CacheKey};
+use crate::achievements::Achievement;
+use crate::audit::AuditEntry;
+use crate::bans::Ban;
+use crate::compression;
+use crate::user_hash::{self, UserIdHasher};
+use crate::exemptions::Exemption;
+use crate::flags::FlagOverride;
+use crate::preset::Preset;
+use crate::config::{StorageConfig, StorageFallbackPolicy, StorageType};
+use crate::migrations::{self, CONTENT_INDEX_TREE, SAYING_INDEX_TREE};
+use crate::models::{Collection, Conversation, Saying, SayingSource, CacheKey, CacheEntryInfo, UserPreferences, FreeformPromptEntry, ShadowOutput, QualityScore, PromptIndexEntry, PromptHistoryEntry, prompt_search_key, PresetStats, UserStats, PresetSelectionRecord, SayingFeedback, SayingTranslation, DailySaying, SayingShare};
+
+const COLLECTIONS_TREE: &str = "collections";
+const ACHIEVEMENTS_TREE: &str = "achievements";
+pub const PREFERENCES_TREE: &str = "preferences";
+pub const UNSUBSCRIBE_TOKENS_TREE: &str = "unsubscribe_tokens";
+const FREEFORM_LOG_TREE: &str = "freeform_prompts";
+const BANS_TREE: &str = "bans";
+const EXEMPTIONS_TREE: &str = "exemptions";
+const FEATURE_FLAGS_TREE: &str = "feature_flags";
+const IMPORTED_PRESETS_TREE: &str = "imported_presets";
+const USER_OWNERS_TREE: &str = "user_owners";
+const FEEDBACK_TREE: &str = "feedback";
+const PRESET_STATS_TREE: &str = "preset_stats";
+pub const RECENT_PRESETS_TREE: &str = "recent_presets";
+pub const PRESET_SELECTIONS_TREE: &str = "preset_selections";
+const TRANSLATIONS_TREE: &str = "translations";
+const DAILY_SAYINGS_TREE: &str = "daily_sayings";
+const SAYING_SHARES_TREE: &str = "saying_shares";
+const SHARE_CARDS_TREE: &str = "share_cards";
+pub const USER_STATS_TREE: &str = "user_stats";
+const AUDIT_LOG_TREE: &str = "audit_log";
+const SAYING_TAGS_TREE: &str = "saying_tags";
+const PROMPT_INDEX_TREE: &str = "prompt_index";
+const PROMPT_HISTORY_TREE: &str = "prompt_history";
+const SHADOW_OUTPUTS_TREE: &str = "shadow_outputs";
+const QUALITY_SCORES_TREE: &str = "quality_scores";
+const CONVERSATIONS_TREE: &str = "conversations";
 
 pub struct Storage {
     inner: StorageImpl,
+    dedupe_by_content: bool,
+    max_sayings_per_user: usize,
+    retention_days: i64,
+    // Set when STORAGE_USER_ID_SALT is, so records are keyed on hashes of user IDs
+    user_ids: Option<UserIdHasher>,
 }
 
 enum StorageImpl {
@@ -18,461 +60,2926 @@ enum StorageImpl {
 }
 
 impl Storage {
-    pub fn new(config: StorageConfig) -> Self {
-        let inner = match config.type_ {
-            StorageType::Memory => StorageImpl::Memory(MemoryStorage::new()),
-            StorageType::SQLite => {
-                // Fallback to memory for now
-                tracing::warn!("SQLite storage not implemented yet, using memory storage instead");
-                StorageImpl::Memory(MemoryStorage::new())
-            }
-            StorageType::Redis => {
-                // Fallback to memory for now
-                tracing::warn!("Redis storage not implemented yet, using memory storage instead");
+    // Fails when the configured backend can't be used, unless STORAGE_FALLBACK_POLICY=memory
+    // allows carrying on with memory storage
+    pub fn new(config: StorageConfig) -> Result<Self> {
+        let opened = match config.type_ {
+            StorageType::Memory => Ok(StorageImpl::Memory(MemoryStorage::new())),
+            StorageType::SQLite => Err(anyhow!("SQLite storage is not implemented yet")),
+            StorageType::Redis => Err(anyhow!("Redis storage is not implemented yet")),
+            StorageType::Sled => SledStorage::new(&config.connection_string)
+                .map(|storage| StorageImpl::Sled(storage.with_compression(config.compression_level)))
+                .with_context(|| format!("Failed to open Sled storage at {}", config.connection_string)),
+        };
+
+        let inner = match (opened, config.fallback_policy) {
+            (Ok(inner), _) => inner,
+            (Err(e), StorageFallbackPolicy::Memory) => {
+                tracing::error!("{:#}", e);
+                tracing::warn!("Falling back to memory storage; nothing will survive a restart");
                 StorageImpl::Memory(MemoryStorage::new())
             }
-            StorageType::Sled => {
-                match SledStorage::new(&config.connection_string) {
-                    Ok(storage) => StorageImpl::Sled(storage),
-                    Err(e) => {
-                        tracing::error!("Failed to initialize Sled storage: {}", e);
-                        tracing::warn!("Falling back to memory storage");
-                        StorageImpl::Memory(MemoryStorage::new())
-                    }
-                }
+            (Err(e), StorageFallbackPolicy::Fail) => {
+                return Err(e.context("Refusing to fall back to memory storage (STORAGE_FALLBACK_POLICY=fail)"));
             }
         };
 
-        Self { inner }
+        Ok(Self {
+            inner,
+            dedupe_by_content: config.dedupe_by_content,
+            max_sayings_per_user: config.max_sayings_per_user,
+            retention_days: config.retention_days,
+            user_ids: config.user_id_salt.map(UserIdHasher::new),
+        })
+    }
+
+    // The user ID records are stored under: its salted hash when user IDs are hashed, so IDs
+    // read back from storage are hashes too, and passing those in again is fine
+    pub fn user_key(&self, user_id: &str) -> String {
+        user_hash::user_key(self.user_ids.as_ref(), user_id)
+    }
+
+    // For the in-memory lists (bans, exemptions, access policies) that compare user IDs the
+    // way storage keys them
+    pub fn user_id_hasher(&self) -> Option<UserIdHasher> {
+        self.user_ids.clone()
+    }
+
+    pub fn hashes_user_ids(&self) -> bool {
+        self.user_ids.is_some()
+    }
+
+    // Whether two user IDs, each plain or as read back from storage, name the same user
+    pub fn same_user(&self, a: &str, b: &str) -> bool {
+        self.user_key(a) == self.user_key(b)
     }
 
-    pub async fn save_saying(&self, user_id: &str, saying: Saying) -> Result<Saying> {
+    // The backend actually in use, which is memory when the configured one failed to open and
+    // the fallback policy allowed it
+    pub fn backend(&self) -> &'static str {
         match &self.inner {
+            StorageImpl::Memory(_) => "memory",
+            StorageImpl::Sled(_) => "sled",
+        }
+    }
+
+    // Add a saying to the user's history, then drop what the history limits no longer allow
+    pub async fn save_saying(&self, user_id: &str, saying: Arc<Saying>) -> Result<Arc<Saying>> {
+        let user_id = &self.user_key(user_id);
+        let saying = match &self.inner {
             StorageImpl::Memory(storage) => storage.save_saying(user_id, saying),
             StorageImpl::Sled(storage) => storage.save_saying(user_id, saying),
+        }?;
+        
+        if self.max_sayings_per_user > 0 || self.retention_days > 0 {
+            let cutoff = (self.retention_days > 0).then(|| Utc::now() - Duration::days(self.retention_days));
+            let pruned = match &self.inner {
+                StorageImpl::Memory(storage) => storage.prune_sayings(user_id, self.max_sayings_per_user, cutoff),
+                StorageImpl::Sled(storage) => storage.prune_sayings(user_id, self.max_sayings_per_user, cutoff),
+            }?;
+            if pruned > 0 {
+                tracing::debug!("Pruned {} sayings from the history of user {}", pruned, user_id);
+            }
+        }
+        
+        Ok(saying)
+    }
+
+    // Every global cache entry, newest first
+    pub async fn list_cache_entries(&self) -> Result<Vec<CacheEntryInfo>> {
+        match &self.inner {
+            StorageImpl::Memory(storage) => storage.list_cache_entries(),
+            StorageImpl::Sled(storage) => storage.list_cache_entries(),
+        }
+    }
+
+    // Drop global cache entries matching the given preset and/or prompt (None matches anything),
+    // returning how many were removed. Users' own histories are untouched.
+    pub async fn invalidate_cache(&self, preset_id: Option<&str>, prompt: Option<&str>) -> Result<usize> {
+        match &self.inner {
+            StorageImpl::Memory(storage) => storage.invalidate_cache(preset_id, prompt),
+            StorageImpl::Sled(storage) => storage.invalidate_cache(preset_id, prompt),
+        }
+    }
+
+    // Mark a preset's global cache entries created before `before` as stale, returning how many changed
+    pub async fn mark_cache_stale(&self, preset_id: &str, before: DateTime<Utc>) -> Result<usize> {
+        match &self.inner {
+            StorageImpl::Memory(storage) => storage.mark_cache_stale(preset_id, before),
+            StorageImpl::Sled(storage) => storage.mark_cache_stale(preset_id, before),
+        }
+    }
+
+    // Put a saying in the global cache without attributing it to any user
+    pub async fn cache_saying(&self, saying: Arc<Saying>) -> Result<()> {
+        match &self.inner {
+            StorageImpl::Memory(storage) => storage.cache_saying(saying),
+            StorageImpl::Sled(storage) => storage.cache_saying(saying),
+        }
+    }
+
+    // The user's sayings carrying `tag` (already normalized), newest first
+    pub async fn get_sayings_by_tag(&self, user_id: &str, tag: &str, limit: usize) -> Result<Vec<Arc<Saying>>> {
+        let user_id = &self.user_key(user_id);
+        match &self.inner {
+            StorageImpl::Memory(storage) => storage.get_sayings_by_tag(user_id, tag, limit),
+            StorageImpl::Sled(storage) => storage.get_sayings_by_tag(user_id, tag, limit),
         }
     }
 
-    pub async fn get_last_saying(&self, user_id: &str) -> Result<Option<Saying>> {
+    pub async fn get_last_saying(&self, user_id: &str) -> Result<Option<Arc<Saying>>> {
+        let user_id = &self.user_key(user_id);
         match &self.inner {
             StorageImpl::Memory(storage) => storage.get_last_saying(user_id),
             StorageImpl::Sled(storage) => storage.get_last_saying(user_id),
         }
     }
 
-    pub async fn get_sayings(&self, user_id: &str, limit: usize) -> Result<Vec<Saying>> {
+    pub async fn get_sayings(&self, user_id: &str, limit: usize) -> Result<Vec<Arc<Saying>>> {
+        let user_id = &self.user_key(user_id);
         match &self.inner {
             StorageImpl::Memory(storage) => storage.get_sayings(user_id, limit),
             StorageImpl::Sled(storage) => storage.get_sayings(user_id, limit),
         }
     }
 
-    // Find a saying that matches a prompt and preset_id
-    pub async fn find_cached_saying(&self, prompt: &str, preset_id: Option<&str>) -> Result<Option<Saying>> {
+    // Find a saying generated from the same prompts, language and model
+    pub async fn find_cached_saying(&self, key: &CacheKey) -> Result<Option<Arc<Saying>>> {
         match &self.inner {
-            StorageImpl::Memory(storage) => storage.find_cached_saying(prompt, preset_id),
-            StorageImpl::Sled(storage) => storage.find_cached_saying(prompt, preset_id),
+            StorageImpl::Memory(storage) => storage.find_cached_saying(key),
+            StorageImpl::Sled(storage) => storage.find_cached_saying(key),
         }
     }
     
-    // Gets any cached sayings from any user (useful for serving during rate-limiting)
-    pub async fn get_any_cached_sayings(&self, limit: usize) -> Result<Vec<Saying>> {
+    // Gets any cached sayings from any user (useful for serving during rate-limiting).
+    // With content dedupe enabled, a quote stored under several prompts is returned once.
+    pub async fn get_any_cached_sayings(&self, limit: usize) -> Result<Vec<Arc<Saying>>> {
         match &self.inner {
-            StorageImpl::Memory(storage) => storage.get_any_cached_sayings(limit),
-            StorageImpl::Sled(storage) => storage.get_any_cached_sayings(limit),
+            StorageImpl::Memory(storage) => storage.get_any_cached_sayings(limit, self.dedupe_by_content),
+            StorageImpl::Sled(storage) => storage.get_any_cached_sayings(limit, self.dedupe_by_content),
         }
     }
-}
 
-#[derive(Clone)]
-struct MemoryStorage {
-    // Map of user_id -> list of sayings
-    sayings: Arc<Mutex<HashMap<String, Vec<Saying>>>>,
-    // Global cache by prompt + preset
-    global_cache: Arc<Mutex<HashMap<CacheKey, Saying>>>,
-}
+    // Look up a saying by its ID through the secondary index, returning its owner as well
+    pub async fn get_saying_by_id(&self, saying_id: &str) -> Result<Option<(String, Arc<Saying>)>> {
+        match &self.inner {
+            StorageImpl::Memory(storage) => storage.get_saying_by_id(saying_id),
+            StorageImpl::Sled(storage) => storage.get_saying_by_id(saying_id),
+        }
+    }
 
-impl MemoryStorage {
-    fn new() -> Self {
-        Self {
-            sayings: Arc::new(Mutex::new(HashMap::new())),
-            global_cache: Arc::new(Mutex::new(HashMap::new())),
+    // Swap a saying in its owner's history for an updated copy with the same ID, returning
+    // false when the user has no such saying. Global cache entries keep the old copy.
+    pub async fn replace_saying(&self, user_id: &str, saying: Arc<Saying>) -> Result<bool> {
+        let user_id = &self.user_key(user_id);
+        match &self.inner {
+            StorageImpl::Memory(storage) => storage.replace_saying(user_id, saying),
+            StorageImpl::Sled(storage) => storage.replace_saying(user_id, saying),
         }
     }
 
-    fn save_saying(&self, user_id: &str, saying: Saying) -> Result<Saying> {
-        // Add to user's sayings
-        let mut sayings_map = self.sayings.lock().unwrap();
-        
-        // Get or create the user's saying list
-        let user_sayings = sayings_map.entry(user_id.to_string()).or_insert_with(Vec::new);
-        
-        // Add the new saying
-        let saying_to_save = saying.clone();
-        user_sayings.push(saying_to_save.clone());
-        
-        // Sort by created_at date (newest first)
-        user_sayings.sort_by(|a, b| b.created_at.cmp(&a.created_at));
-        
-        // Add to global cache if it's not an LLM source (we only cache non-LLM entries)
-        if !matches!(saying.source, SayingSource::LLM) {
-            let cache_key = CacheKey::from_saying(&saying);
-            let mut global_cache = self.global_cache.lock().unwrap();
-            global_cache.insert(cache_key, saying.clone());
+    // Create or update a collection
+    pub async fn save_collection(&self, collection: Collection) -> Result<Collection> {
+        let user_id = collection.user_id.clone();
+        let collection = Collection { user_id: self.user_key(&user_id), ..collection };
+        let saved = match &self.inner {
+            StorageImpl::Memory(storage) => storage.save_collection(collection),
+            StorageImpl::Sled(storage) => storage.save_collection(collection),
+        }?;
+        Ok(Collection { user_id, ..saved })
+    }
+
+    pub async fn get_collection(&self, collection_id: &str) -> Result<Option<Collection>> {
+        match &self.inner {
+            StorageImpl::Memory(storage) => storage.get_collection(collection_id),
+            StorageImpl::Sled(storage) => storage.get_collection(collection_id),
         }
-        
-        Ok(saying)
     }
 
-    fn get_last_saying(&self, user_id: &str) -> Result<Option<Saying>> {
-        let sayings_map = self.sayings.lock().unwrap();
-        
-        // Get user's sayings if they exist
-        if let Some(user_sayings) = sayings_map.get(user_id) {
-            if !user_sayings.is_empty() {
-                // Return the first saying (newest one due to sorting)
-                return Ok(Some(user_sayings[0].clone()));
-            }
+    // All collections owned by a user, newest first
+    pub async fn get_collections(&self, user_id: &str) -> Result<Vec<Collection>> {
+        let key = &self.user_key(user_id);
+        let collections = match &self.inner {
+            StorageImpl::Memory(storage) => storage.get_collections(key),
+            StorageImpl::Sled(storage) => storage.get_collections(key),
+        }?;
+        Ok(collections.into_iter().map(|collection| Collection { user_id: user_id.to_string(), ..collection }).collect())
+    }
+
+    pub async fn find_collection_by_share_token(&self, token: &str) -> Result<Option<Collection>> {
+        match &self.inner {
+            StorageImpl::Memory(storage) => storage.find_collection_by_share_token(token),
+            StorageImpl::Sled(storage) => storage.find_collection_by_share_token(token),
         }
-        
-        Ok(None)
     }
 
-    fn get_sayings(&self, user_id: &str, limit: usize) -> Result<Vec<Saying>> {
-        let sayings_map = self.sayings.lock().unwrap();
-        
-        // Get user's sayings if they exist
-        if let Some(user_sayings) = sayings_map.get(user_id) {
-            let mut result = user_sayings.clone();
-            
-            if result.len() > limit {
-                result.truncate(limit);
-            }
-            
-            return Ok(result);
+    // Create or update a conversation
+    pub async fn save_conversation(&self, conversation: &Conversation) -> Result<()> {
+        let conversation = &Conversation { user_id: self.user_key(&conversation.user_id), ..conversation.clone() };
+        match &self.inner {
+            StorageImpl::Memory(storage) => storage.save_conversation(conversation),
+            StorageImpl::Sled(storage) => storage.save_conversation(conversation),
         }
-        
-        Ok(Vec::new())
     }
 
-    fn find_cached_saying(&self, prompt: &str, preset_id: Option<&str>) -> Result<Option<Saying>> {
-        // First check the global cache for direct match
-        let cache_key = CacheKey::new(
-            preset_id.map(|id| id.to_string()), 
-            prompt.to_string()
-        );
-        
-        let global_cache = self.global_cache.lock().unwrap();
-        if let Some(cached) = global_cache.get(&cache_key) {
-            // We found a direct match in the global cache
-            return Ok(Some(cached.clone()));
+    pub async fn get_conversation(&self, user_id: &str, conversation_id: &str) -> Result<Option<Conversation>> {
+        let key = &self.user_key(user_id);
+        let conversation = match &self.inner {
+            StorageImpl::Memory(storage) => storage.get_conversation(key, conversation_id),
+            StorageImpl::Sled(storage) => storage.get_conversation(key, conversation_id),
+        }?;
+        Ok(conversation.map(|conversation| Conversation { user_id: user_id.to_string(), ..conversation }))
+    }
+
+    // All of a user's conversations, most recently active first
+    pub async fn get_conversations(&self, user_id: &str) -> Result<Vec<Conversation>> {
+        let key = &self.user_key(user_id);
+        let conversations = match &self.inner {
+            StorageImpl::Memory(storage) => storage.get_conversations(key),
+            StorageImpl::Sled(storage) => storage.get_conversations(key),
+        }?;
+        Ok(conversations.into_iter().map(|conversation| Conversation { user_id: user_id.to_string(), ..conversation }).collect())
+    }
+
+    // Whether there was a conversation to delete
+    pub async fn delete_conversation(&self, user_id: &str, conversation_id: &str) -> Result<bool> {
+        let user_id = &self.user_key(user_id);
+        match &self.inner {
+            StorageImpl::Memory(storage) => storage.delete_conversation(user_id, conversation_id),
+            StorageImpl::Sled(storage) => storage.delete_conversation(user_id, conversation_id),
         }
+    }
 
-        // Fall back to checking all user sayings
-        let sayings_map = self.sayings.lock().unwrap();
-        
-        // Search through all users' sayings to find a matching prompt and preset
-        for user_sayings in sayings_map.values() {
-            for saying in user_sayings {
-                if saying.prompt == prompt && 
-                   saying.preset_id.as_deref() == preset_id && 
-                   !matches!(saying.source, SayingSource::LLM) {
-                    // Found a match from cache or database
-                    return Ok(Some(saying.clone()));
-                }
-            }
+    pub async fn get_achievements(&self, user_id: &str) -> Result<Vec<Achievement>> {
+        let user_id = &self.user_key(user_id);
+        match &self.inner {
+            StorageImpl::Memory(storage) => storage.get_achievements(user_id),
+            StorageImpl::Sled(storage) => storage.get_achievements(user_id),
         }
-        
-        // No match found
-        Ok(None)
     }
 
-    fn get_any_cached_sayings(&self, limit: usize) -> Result<Vec<Saying>> {
-        // First try to get sayings from the global cache
-        let global_cache = self.global_cache.lock().unwrap();
-        let mut all_cached_sayings: Vec<Saying> = global_cache.values().cloned().collect();
-        
-        // If we don't have enough, fall back to the per-user sayings
-        if all_cached_sayings.len() < limit {
-            let sayings_map = self.sayings.lock().unwrap();
-            
-            // Collect sayings from all users, preferring non-LLM sources
-            for user_sayings in sayings_map.values() {
-                for saying in user_sayings {
-                    if !matches!(saying.source, SayingSource::LLM) {
-                        // Check if we already have this saying in our result (from global cache)
-                        let is_duplicate = all_cached_sayings.iter().any(|s| 
-                            s.prompt == saying.prompt && s.preset_id == saying.preset_id
-                        );
-                        
-                        if !is_duplicate {
-                            all_cached_sayings.push(saying.clone());
-                        }
-                    }
-                }
-            }
-            
-            // If we still don't have enough, include LLM sources as a fallback
-            if all_cached_sayings.len() < limit {
-                for user_sayings in sayings_map.values() {
-                    for saying in user_sayings {
-                        if matches!(saying.source, SayingSource::LLM) {
-                            let is_duplicate = all_cached_sayings.iter().any(|s| 
-                                s.prompt == saying.prompt && s.preset_id == saying.preset_id
-                            );
-                            
-                            if !is_duplicate {
-                                all_cached_sayings.push(saying.clone());
-                            }
-                        }
-                    }
-                }
-            }
+    // Replace the stored badges of a user
+    pub async fn save_achievements(&self, user_id: &str, achievements: &[Achievement]) -> Result<()> {
+        let user_id = &self.user_key(user_id);
+        match &self.inner {
+            StorageImpl::Memory(storage) => storage.save_achievements(user_id, achievements),
+            StorageImpl::Sled(storage) => storage.save_achievements(user_id, achievements),
         }
-        
-        // Sort by date (newest first)
-        all_cached_sayings.sort_by(|a, b| b.created_at.cmp(&a.created_at));
-        
-        // Limit the results
-        if all_cached_sayings.len() > limit {
-            all_cached_sayings.truncate(limit);
+    }
+
+    // Most recent sayings across all users, with their owners
+    pub async fn get_recent_sayings(&self, limit: usize) -> Result<Vec<(String, Arc<Saying>)>> {
+        match &self.inner {
+            StorageImpl::Memory(storage) => storage.get_recent_sayings(limit),
+            StorageImpl::Sled(storage) => storage.get_recent_sayings(limit),
         }
-        
-        Ok(all_cached_sayings)
     }
-}
 
-struct SledStorage {
-    db: sled::Db,
-}
+    // Preferences default to empty for users who never set any
+    pub async fn get_preferences(&self, user_id: &str) -> Result<UserPreferences> {
+        let user_id = &self.user_key(user_id);
+        match &self.inner {
+            StorageImpl::Memory(storage) => storage.get_preferences(user_id),
+            StorageImpl::Sled(storage) => storage.get_preferences(user_id),
+        }
+    }
 
-impl SledStorage {
-    fn new(path: &str) -> Result<Self> {
-        let db = sled::open(path).context("Failed to open Sled database")?;
-        
-        // Ensure the global cache tree exists
-        db.open_tree("global_cache").context("Failed to create global cache tree")?;
-        
-        Ok(Self { db })
+    pub async fn save_preferences(&self, user_id: &str, preferences: &UserPreferences) -> Result<()> {
+        let user_id = &self.user_key(user_id);
+        match &self.inner {
+            StorageImpl::Memory(storage) => storage.save_preferences(user_id, preferences),
+            StorageImpl::Sled(storage) => storage.save_preferences(user_id, preferences),
+        }
     }
 
-    fn save_saying(&self, user_id: &str, saying: Saying) -> Result<Saying> {
-        // Get existing sayings for the user
-        let mut sayings = self.get_sayings(user_id, usize::MAX)?;
-        
-        // Add the new saying
-        let saying_to_save = saying.clone();
-        sayings.push(saying_to_save);
-        
-        // Sort by created_at date (newest first)
-        sayings.sort_by(|a, b| b.created_at.cmp(&a.created_at));
-        
-        // Serialize and save user sayings
-        let serialized = serde_json::to_vec(&sayings).context("Failed to serialize sayings")?;
-        self.db.insert(user_id.as_bytes(), serialized).context("Failed to insert into Sled database")?;
-        
-        // Add to global cache if it's not an LLM source
-        if !matches!(saying.source, SayingSource::LLM) {
-            let global_tree = self.db.open_tree("global_cache").context("Failed to open global cache tree")?;
-            
-            // Create a unique key based on preset + prompt
-            let cache_key = CacheKey::from_saying(&saying);
-            let key_bytes = serde_json::to_vec(&cache_key).context("Failed to serialize cache key")?;
-            
-            // Store the saying in the global cache
-            let serialized_saying = serde_json::to_vec(&saying).context("Failed to serialize saying for cache")?;
-            global_tree.insert(key_bytes, serialized_saying).context("Failed to insert into global cache")?;
+    // The user an email unsubscribe token was issued to, as storage keys them
+    pub async fn find_unsubscribe_token(&self, token: &str) -> Result<Option<String>> {
+        match &self.inner {
+            StorageImpl::Memory(storage) => storage.find_unsubscribe_token(token),
+            StorageImpl::Sled(storage) => storage.find_unsubscribe_token(token),
         }
-        
-        Ok(saying)
     }
 
-    fn get_last_saying(&self, user_id: &str) -> Result<Option<Saying>> {
-        // Try to get all sayings for the user
-        let sayings = self.get_sayings(user_id, 1)?;
-        
-        // Return the first one if any exist
-        if sayings.is_empty() {
-            Ok(None)
-        } else {
-            Ok(Some(sayings[0].clone()))
+    // Every user who has stored preferences
+    pub async fn list_preferences(&self) -> Result<Vec<(String, UserPreferences)>> {
+        match &self.inner {
+            StorageImpl::Memory(storage) => storage.list_preferences(),
+            StorageImpl::Sled(storage) => storage.list_preferences(),
         }
     }
 
-    fn get_sayings(&self, user_id: &str, limit: usize) -> Result<Vec<Saying>> {
-        // Try to get the user's sayings from the database
-        match self.db.get(user_id.as_bytes()) {
-            Ok(Some(ivec)) => {
-                // Deserialize the sayings
-                let mut sayings: Vec<Saying> = serde_json::from_slice(&ivec)
-                    .context("Failed to deserialize sayings from Sled")?;
-                
-                // Sort and limit
-                sayings.sort_by(|a, b| b.created_at.cmp(&a.created_at));
-                if sayings.len() > limit {
-                    sayings.truncate(limit);
-                }
-                
-                Ok(sayings)
-            }
-            Ok(None) => Ok(Vec::new()), // No sayings for this user yet
-            Err(e) => Err(anyhow::anyhow!("Sled error: {}", e)),
+    // Record a freeform prompt, keeping only the newest `keep` entries
+    pub async fn log_freeform_prompt(&self, entry: &FreeformPromptEntry, keep: usize) -> Result<()> {
+        let entry = &FreeformPromptEntry { user_id: self.user_key(&entry.user_id), ..entry.clone() };
+        match &self.inner {
+            StorageImpl::Memory(storage) => storage.log_freeform_prompt(entry, keep),
+            StorageImpl::Sled(storage) => storage.log_freeform_prompt(entry, keep),
         }
     }
 
-    fn find_cached_saying(&self, prompt: &str, preset_id: Option<&str>) -> Result<Option<Saying>> {
-        // First check the global cache for direct match
-        let global_tree = self.db.open_tree("global_cache").context("Failed to open global cache tree")?;
-        
-        let cache_key = CacheKey::new(
-            preset_id.map(|id| id.to_string()), 
-            prompt.to_string()
-        );
-        
-        let key_bytes = serde_json::to_vec(&cache_key).context("Failed to serialize cache key")?;
-        
-        // Check if we have this key in the global cache
-        if let Ok(Some(ivec)) = global_tree.get(&key_bytes) {
-            let saying: Saying = serde_json::from_slice(&ivec)
-                .context("Failed to deserialize saying from global cache")?;
-            return Ok(Some(saying));
+    // Most recent freeform prompts, newest first
+    pub async fn get_freeform_prompts(&self, limit: usize) -> Result<Vec<FreeformPromptEntry>> {
+        match &self.inner {
+            StorageImpl::Memory(storage) => storage.get_freeform_prompts(limit),
+            StorageImpl::Sled(storage) => storage.get_freeform_prompts(limit),
         }
-        
-        // Fall back to checking all user sayings
-        for result in self.db.iter() {
-            let (key, ivec) = result.context("Failed to iterate Sled database")?;
-            
-            // Skip the global cache tree
-            if key.starts_with(b"__") {
-                continue;
-            }
-            
-            // Deserialize the sayings
-            let sayings: Vec<Saying> = serde_json::from_slice(&ivec)
-                .context("Failed to deserialize sayings from Sled")?;
-            
-            // Look for a matching prompt and preset
-            for saying in sayings {
-                if saying.prompt == prompt && 
-                   saying.preset_id.as_deref() == preset_id && 
-                   !matches!(saying.source, SayingSource::LLM) {
-                    // Found a match from cache or database
-                    return Ok(Some(saying));
-                }
-            }
+    }
+
+    pub async fn save_shadow_output(&self, output: &ShadowOutput) -> Result<()> {
+        match &self.inner {
+            StorageImpl::Memory(storage) => storage.save_shadow_output(output),
+            StorageImpl::Sled(storage) => storage.save_shadow_output(output),
         }
-        
-        // No match found
-        Ok(None)
     }
 
-    fn get_any_cached_sayings(&self, limit: usize) -> Result<Vec<Saying>> {
-        let mut all_cached_sayings = Vec::new();
-        
-        // First try the global cache
-        let global_tree = self.db.open_tree("global_cache").context("Failed to open global cache tree")?;
-        
-        for result in global_tree.iter() {
-            let (_, ivec) = result.context("Failed to iterate global cache")?;
-            
-            let saying: Saying = serde_json::from_slice(&ivec)
-                .context("Failed to deserialize saying from global cache")?;
-            
-            all_cached_sayings.push(saying);
-            
-            if all_cached_sayings.len() >= limit {
-                // Sort by date (newest first) and return
-                all_cached_sayings.sort_by(|a, b| b.created_at.cmp(&a.created_at));
-                return Ok(all_cached_sayings);
-            }
+    // Most recent shadow outputs, newest first
+    pub async fn get_shadow_outputs(&self, limit: usize) -> Result<Vec<ShadowOutput>> {
+        match &self.inner {
+            StorageImpl::Memory(storage) => storage.get_shadow_outputs(limit),
+            StorageImpl::Sled(storage) => storage.get_shadow_outputs(limit),
         }
-        
-        // If we don't have enough from global cache, check user sayings
-        let mut seen_keys = HashSet::new();
-        
-        // Add all non-LLM sayings to our collection
-        for result in self.db.iter() {
-            let (key, ivec) = result.context("Failed to iterate Sled database")?;
-            
-            // Skip the global cache tree and other internal trees
-            if key.starts_with(b"__") {
-                continue;
-            }
-            
-            // Deserialize the sayings
-            let sayings: Vec<Saying> = serde_json::from_slice(&ivec)
-                .context("Failed to deserialize sayings from Sled")?;
+    }
+
+    // A saying has at most one score; grading it again replaces it
+    pub async fn save_quality_score(&self, score: &QualityScore) -> Result<()> {
+        match &self.inner {
+            StorageImpl::Memory(storage) => storage.save_quality_score(score),
+            StorageImpl::Sled(storage) => storage.save_quality_score(score),
+        }
+    }
+
+    pub async fn list_quality_scores(&self) -> Result<Vec<QualityScore>> {
+        match &self.inner {
+            StorageImpl::Memory(storage) => storage.list_quality_scores(),
+            StorageImpl::Sled(storage) => storage.list_quality_scores(),
+        }
+    }
+
+    // Count a use of a prompt the user typed, for suggestions
+    pub async fn record_prompt(&self, user_id: &str, prompt: &str, used_at: DateTime<Utc>) -> Result<()> {
+        let user_id = &self.user_key(user_id);
+        match &self.inner {
+            StorageImpl::Memory(storage) => storage.record_prompt(user_id, prompt, used_at),
+            StorageImpl::Sled(storage) => storage.record_prompt(user_id, prompt, used_at),
+        }
+    }
+
+    // The user's indexed prompts whose search key starts with `prefix` (see
+    // `prompt_search_key`), most recently used first
+    pub async fn suggest_prompts(&self, user_id: &str, prefix: &str, limit: usize) -> Result<Vec<PromptIndexEntry>> {
+        let user_id = &self.user_key(user_id);
+        let mut entries = match &self.inner {
+            StorageImpl::Memory(storage) => storage.find_prompts(user_id, prefix),
+            StorageImpl::Sled(storage) => storage.find_prompts(user_id, prefix),
+        }?;
+        entries.sort_by_key(|entry| Reverse(entry.last_used_at));
+        entries.truncate(limit);
+        Ok(entries)
+    }
+
+    // Record a submitted prompt, keeping only the user's newest `keep` entries
+    pub async fn push_prompt_history(&self, user_id: &str, entry: PromptHistoryEntry, keep: usize) -> Result<()> {
+        let user_id = &self.user_key(user_id);
+        match &self.inner {
+            StorageImpl::Memory(storage) => storage.push_prompt_history(user_id, entry, keep),
+            StorageImpl::Sled(storage) => storage.push_prompt_history(user_id, entry, keep),
+        }
+    }
+
+    // The user's submitted prompts, newest first
+    pub async fn get_prompt_history(&self, user_id: &str, limit: usize) -> Result<Vec<PromptHistoryEntry>> {
+        let user_id = &self.user_key(user_id);
+        match &self.inner {
+            StorageImpl::Memory(storage) => storage.get_prompt_history(user_id, limit),
+            StorageImpl::Sled(storage) => storage.get_prompt_history(user_id, limit),
+        }
+    }
+
+    // Create or replace the ban for a subject
+    pub async fn save_ban(&self, ban: &Ban) -> Result<()> {
+        match &self.inner {
+            StorageImpl::Memory(storage) => storage.save_ban(ban),
+            StorageImpl::Sled(storage) => storage.save_ban(ban),
+        }
+    }
+
+    pub async fn delete_ban(&self, key: &str) -> Result<()> {
+        match &self.inner {
+            StorageImpl::Memory(storage) => storage.delete_ban(key),
+            StorageImpl::Sled(storage) => storage.delete_ban(key),
+        }
+    }
+
+    pub async fn list_bans(&self) -> Result<Vec<Ban>> {
+        match &self.inner {
+            StorageImpl::Memory(storage) => storage.list_bans(),
+            StorageImpl::Sled(storage) => storage.list_bans(),
+        }
+    }
+
+    // Create or replace the rate-limit exemption for a subject
+    pub async fn save_exemption(&self, exemption: &Exemption) -> Result<()> {
+        match &self.inner {
+            StorageImpl::Memory(storage) => storage.save_exemption(exemption),
+            StorageImpl::Sled(storage) => storage.save_exemption(exemption),
+        }
+    }
+
+    pub async fn delete_exemption(&self, key: &str) -> Result<()> {
+        match &self.inner {
+            StorageImpl::Memory(storage) => storage.delete_exemption(key),
+            StorageImpl::Sled(storage) => storage.delete_exemption(key),
+        }
+    }
+
+    pub async fn list_exemptions(&self) -> Result<Vec<Exemption>> {
+        match &self.inner {
+            StorageImpl::Memory(storage) => storage.list_exemptions(),
+            StorageImpl::Sled(storage) => storage.list_exemptions(),
+        }
+    }
+
+    // Create or replace a preset imported through the admin API
+    pub async fn save_imported_preset(&self, preset: &Preset) -> Result<()> {
+        match &self.inner {
+            StorageImpl::Memory(storage) => storage.save_imported_preset(preset),
+            StorageImpl::Sled(storage) => storage.save_imported_preset(preset),
+        }
+    }
+
+    pub async fn list_imported_presets(&self) -> Result<Vec<Preset>> {
+        match &self.inner {
+            StorageImpl::Memory(storage) => storage.list_imported_presets(),
+            StorageImpl::Sled(storage) => storage.list_imported_presets(),
+        }
+    }
+
+    // Create or replace the admin override of a feature flag
+    pub async fn save_flag_override(&self, flag: &FlagOverride) -> Result<()> {
+        match &self.inner {
+            StorageImpl::Memory(storage) => storage.save_flag_override(flag),
+            StorageImpl::Sled(storage) => storage.save_flag_override(flag),
+        }
+    }
+
+    pub async fn delete_flag_override(&self, name: &str) -> Result<()> {
+        match &self.inner {
+            StorageImpl::Memory(storage) => storage.delete_flag_override(name),
+            StorageImpl::Sled(storage) => storage.delete_flag_override(name),
+        }
+    }
+
+    pub async fn list_flag_overrides(&self) -> Result<Vec<FlagOverride>> {
+        match &self.inner {
+            StorageImpl::Memory(storage) => storage.list_flag_overrides(),
+            StorageImpl::Sled(storage) => storage.list_flag_overrides(),
+        }
+    }
+
+    // Count a saying generated with a preset
+    pub async fn record_preset_use(&self, preset_id: &str, at: DateTime<Utc>) -> Result<()> {
+        match &self.inner {
+            StorageImpl::Memory(storage) => storage.record_preset_use(preset_id, at),
+            StorageImpl::Sled(storage) => storage.record_preset_use(preset_id, at),
+        }
+    }
+
+    // Store a rating (replacing any earlier one for the saying) and fold it into its preset's stats
+    pub async fn save_feedback(&self, feedback: &SayingFeedback) -> Result<()> {
+        let feedback = &SayingFeedback { user_id: self.user_key(&feedback.user_id), ..feedback.clone() };
+        match &self.inner {
+            StorageImpl::Memory(storage) => storage.save_feedback(feedback),
+            StorageImpl::Sled(storage) => storage.save_feedback(feedback),
+        }
+    }
+
+    pub async fn list_feedback(&self) -> Result<Vec<SayingFeedback>> {
+        match &self.inner {
+            StorageImpl::Memory(storage) => storage.list_feedback(),
+            StorageImpl::Sled(storage) => storage.list_feedback(),
+        }
+    }
+
+    pub async fn list_preset_stats(&self) -> Result<Vec<PresetStats>> {
+        match &self.inner {
+            StorageImpl::Memory(storage) => storage.list_preset_stats(),
+            StorageImpl::Sled(storage) => storage.list_preset_stats(),
+        }
+    }
+
+    // Presets recently selected for the user, newest first
+    pub async fn get_recent_presets(&self, user_id: &str) -> Result<Vec<String>> {
+        let user_id = &self.user_key(user_id);
+        match &self.inner {
+            StorageImpl::Memory(storage) => storage.get_recent_presets(user_id),
+            StorageImpl::Sled(storage) => storage.get_recent_presets(user_id),
+        }
+    }
+
+    // Record a selection, keeping only the newest `keep` entries
+    pub async fn push_recent_preset(&self, user_id: &str, preset_id: &str, keep: usize) -> Result<()> {
+        let user_id = &self.user_key(user_id);
+        match &self.inner {
+            StorageImpl::Memory(storage) => storage.push_recent_preset(user_id, preset_id, keep),
+            StorageImpl::Sled(storage) => storage.push_recent_preset(user_id, preset_id, keep),
+        }
+    }
+
+    // The user's last persisted preset selection, which may have expired
+    pub async fn get_preset_selection(&self, user_id: &str) -> Result<Option<PresetSelectionRecord>> {
+        let user_id = &self.user_key(user_id);
+        match &self.inner {
+            StorageImpl::Memory(storage) => storage.get_preset_selection(user_id),
+            StorageImpl::Sled(storage) => storage.get_preset_selection(user_id),
+        }
+    }
+
+    pub async fn save_preset_selection(&self, user_id: &str, selection: &PresetSelectionRecord) -> Result<()> {
+        let user_id = &self.user_key(user_id);
+        match &self.inner {
+            StorageImpl::Memory(storage) => storage.save_preset_selection(user_id, selection),
+            StorageImpl::Sled(storage) => storage.save_preset_selection(user_id, selection),
+        }
+    }
+
+    // Token the user's history is bound to, if any
+    pub async fn get_user_owner(&self, user_id: &str) -> Result<Option<String>> {
+        let user_id = &self.user_key(user_id);
+        match &self.inner {
+            StorageImpl::Memory(storage) => storage.get_user_owner(user_id),
+            StorageImpl::Sled(storage) => storage.get_user_owner(user_id),
+        }
+    }
+
+    // Bind the user to `token` unless they already have an owner; returns the owner either way
+    pub async fn claim_user_owner(&self, user_id: &str, token: &str) -> Result<String> {
+        let user_id = &self.user_key(user_id);
+        match &self.inner {
+            StorageImpl::Memory(storage) => storage.claim_user_owner(user_id, token),
+            StorageImpl::Sled(storage) => storage.claim_user_owner(user_id, token),
+        }
+    }
+
+    // Bind the user to `token`, replacing any owner they had
+    pub async fn set_user_owner(&self, user_id: &str, token: &str) -> Result<()> {
+        let user_id = &self.user_key(user_id);
+        match &self.inner {
+            StorageImpl::Memory(storage) => storage.set_user_owner(user_id, token),
+            StorageImpl::Sled(storage) => storage.set_user_owner(user_id, token),
+        }
+    }
+
+    // A cached translation of a saying, if it was translated into the language before
+    pub async fn get_translation(&self, saying_id: &str, language_id: &str) -> Result<Option<SayingTranslation>> {
+        match &self.inner {
+            StorageImpl::Memory(storage) => storage.get_translation(saying_id, language_id),
+            StorageImpl::Sled(storage) => storage.get_translation(saying_id, language_id),
+        }
+    }
+
+    pub async fn save_translation(&self, translation: &SayingTranslation) -> Result<()> {
+        match &self.inner {
+            StorageImpl::Memory(storage) => storage.save_translation(translation),
+            StorageImpl::Sled(storage) => storage.save_translation(translation),
+        }
+    }
+
+    // The latest daily saying for a preset and language, which may be from an earlier day
+    pub async fn get_daily_saying(&self, preset_id: &str, language_id: &str) -> Result<Option<DailySaying>> {
+        match &self.inner {
+            StorageImpl::Memory(storage) => storage.get_daily_saying(preset_id, language_id),
+            StorageImpl::Sled(storage) => storage.get_daily_saying(preset_id, language_id),
+        }
+    }
+
+    // Replace the daily saying for its preset and language
+    pub async fn save_daily_saying(&self, daily: &DailySaying) -> Result<()> {
+        match &self.inner {
+            StorageImpl::Memory(storage) => storage.save_daily_saying(daily),
+            StorageImpl::Sled(storage) => storage.save_daily_saying(daily),
+        }
+    }
+
+    // The share link of a saying, if it was shared
+    pub async fn get_saying_share(&self, saying_id: &str) -> Result<Option<SayingShare>> {
+        match &self.inner {
+            StorageImpl::Memory(storage) => storage.get_saying_share(saying_id),
+            StorageImpl::Sled(storage) => storage.get_saying_share(saying_id),
+        }
+    }
+
+    pub async fn save_saying_share(&self, share: &SayingShare) -> Result<()> {
+        let share = &SayingShare { user_id: self.user_key(&share.user_id), ..share.clone() };
+        match &self.inner {
+            StorageImpl::Memory(storage) => storage.save_saying_share(share),
+            StorageImpl::Sled(storage) => storage.save_saying_share(share),
+        }
+    }
+
+    pub async fn find_saying_share_by_token(&self, token: &str) -> Result<Option<SayingShare>> {
+        match &self.inner {
+            StorageImpl::Memory(storage) => storage.find_saying_share_by_token(token),
+            StorageImpl::Sled(storage) => storage.find_saying_share_by_token(token),
+        }
+    }
+
+    // A previously rendered share card (PNG)
+    pub async fn get_share_card(&self, token: &str) -> Result<Option<Vec<u8>>> {
+        match &self.inner {
+            StorageImpl::Memory(storage) => storage.get_share_card(token),
+            StorageImpl::Sled(storage) => storage.get_share_card(token),
+        }
+    }
+
+    pub async fn save_share_card(&self, token: &str, png: &[u8]) -> Result<()> {
+        match &self.inner {
+            StorageImpl::Memory(storage) => storage.save_share_card(token, png),
+            StorageImpl::Sled(storage) => storage.save_share_card(token, png),
+        }
+    }
+
+    // Counts and token totals over the user's sayings
+    pub async fn get_user_stats(&self, user_id: &str) -> Result<UserStats> {
+        let key = &self.user_key(user_id);
+        let stats = match &self.inner {
+            StorageImpl::Memory(storage) => storage.get_user_stats(key),
+            StorageImpl::Sled(storage) => storage.get_user_stats(key),
+        }?;
+        Ok(UserStats { user_id: user_id.to_string(), ..stats })
+    }
+
+    // Tokens reported for the user's sayings created at or after `since`, e.g. this rate-limit window
+    pub async fn tokens_used_since(&self, user_id: &str, since: DateTime<Utc>) -> Result<u64> {
+        let user_id = &self.user_key(user_id);
+        match &self.inner {
+            StorageImpl::Memory(storage) => storage.tokens_used_since(user_id, since),
+            StorageImpl::Sled(storage) => storage.tokens_used_since(user_id, since),
+        }
+    }
+
+    // Add an entry to the admin audit log. Fails if its seq is already taken, so entries
+    // are never overwritten.
+    pub async fn append_audit_entry(&self, entry: &AuditEntry) -> Result<()> {
+        match &self.inner {
+            StorageImpl::Memory(storage) => storage.append_audit_entry(entry),
+            StorageImpl::Sled(storage) => storage.append_audit_entry(entry),
+        }
+    }
+
+    pub async fn last_audit_entry(&self) -> Result<Option<AuditEntry>> {
+        match &self.inner {
+            StorageImpl::Memory(storage) => storage.last_audit_entry(),
+            StorageImpl::Sled(storage) => storage.last_audit_entry(),
+        }
+    }
+
+    // The whole admin audit log, oldest first
+    pub async fn get_audit_entries(&self) -> Result<Vec<AuditEntry>> {
+        match &self.inner {
+            StorageImpl::Memory(storage) => storage.get_audit_entries(),
+            StorageImpl::Sled(storage) => storage.get_audit_entries(),
+        }
+    }
+}
+
+// Split a newest-first history into the sayings to keep and those to drop: unpinned sayings
+// beyond the newest `max` unpinned ones (0 for no cap) or created before `cutoff`. Pinned
+// sayings are always kept and don't count towards the cap.
+fn prune_history(sayings: Vec<Arc<Saying>>, max: usize, cutoff: Option<DateTime<Utc>>) -> (Vec<Arc<Saying>>, Vec<Arc<Saying>>) {
+    let mut unpinned = 0;
+    sayings.into_iter().partition(|saying| {
+        if saying.pinned {
+            return true;
+        }
+        unpinned += 1;
+        (max == 0 || unpinned <= max) && cutoff.is_none_or(|cutoff| saying.created_at >= cutoff)
+    })
+}
+
+// Tag index keys are `user_id NUL tag NUL saying_id`, so one prefix covers a user's sayings
+// with a tag
+fn tag_index_key(user_id: &str, tag: &str, saying_id: &str) -> Vec<u8> {
+    let mut key = tag_index_prefix(user_id, tag);
+    key.extend_from_slice(saying_id.as_bytes());
+    key
+}
+
+fn tag_index_prefix(user_id: &str, tag: &str) -> Vec<u8> {
+    let mut key = Vec::with_capacity(user_id.len() + tag.len() + 2);
+    key.extend_from_slice(user_id.as_bytes());
+    key.push(0);
+    key.extend_from_slice(tag.as_bytes());
+    key.push(0);
+    key
+}
+
+// Whether a global cache key is selected by an invalidation filter; None matches anything
+fn cache_key_matches(key: &CacheKey, preset_id: Option<&str>, prompt: Option<&str>) -> bool {
+    preset_id.is_none_or(|id| key.preset_id.as_deref() == Some(id)) && prompt.is_none_or(|prompt| key.prompt == prompt)
+
+}
+
+#[derive(Clone)]
+struct MemoryStorage {
+    // Map of user_id -> list of sayings, sharded so users don't contend with each other.
+    // Sayings are shared behind Arc so reads and cache entries don't copy their contents.
+    sayings: Arc<DashMap<String, Vec<Arc<Saying>>>>,
+    // Global cache by prompt + preset
+    global_cache: Arc<DashMap<CacheKey, Arc<Saying>>>,
+    // Secondary index of content hash -> global cache keys holding that content
+    content_index: Arc<DashMap<String, HashSet<CacheKey>>>,
+    // Secondary index of saying_id -> user_id
+    saying_index: Arc<DashMap<String, String>>,
+    // Map of collection_id -> collection
+    collections: Arc<DashMap<String, Collection>>,
+    // Map of user_id -> awarded badges
+    achievements: Arc<DashMap<String, Vec<Achievement>>>,
+    // Map of user_id -> preferences
+    preferences: Arc<DashMap<String, UserPreferences>>,
+    // Map of email unsubscribe token -> user_id
+    unsubscribe_tokens: Arc<DashMap<String, String>>,
+    // Freeform prompt audit entries, newest first
+    freeform_log: Arc<Mutex<VecDeque<FreeformPromptEntry>>>,
+    // Map of entry id -> shadow model output
+    shadow_outputs: Arc<DashMap<String, ShadowOutput>>,
+    // Map of saying_id -> quality score
+    quality_scores: Arc<DashMap<String, QualityScore>>,
+    // Map of user_id -> conversation id -> conversation
+    conversations: Arc<DashMap<String, BTreeMap<String, Conversation>>>,
+    // Map of user_id -> prompt search key -> indexed prompt, ordered for prefix lookups
+    prompt_index: Arc<DashMap<String, BTreeMap<String, PromptIndexEntry>>>,
+    // Map of user_id -> submitted prompts, newest first
+    prompt_history: Arc<DashMap<String, Vec<PromptHistoryEntry>>>,
+    // Map of ban subject key -> ban
+    bans: Arc<DashMap<String, Ban>>,
+    // Map of exemption subject key -> rate-limit exemption
+    exemptions: Arc<DashMap<String, Exemption>>,
+    // Map of flag name -> admin override
+    flag_overrides: Arc<DashMap<String, FlagOverride>>,
+    // Map of preset_id -> imported preset
+    imported_presets: Arc<DashMap<String, Preset>>,
+    // Map of user_id -> token that owns the user's history
+    user_owners: Arc<DashMap<String, String>>,
+    // Map of saying_id -> feedback
+    feedback: Arc<DashMap<String, SayingFeedback>>,
+    // Map of preset_id -> usage statistics
+    preset_stats: Arc<DashMap<String, PresetStats>>,
+    // Map of user_id -> recently selected preset IDs, newest first
+    recent_presets: Arc<DashMap<String, Vec<String>>>,
+    // Map of user_id -> preset selection for the current window
+    preset_selections: Arc<DashMap<String, PresetSelectionRecord>>,
+    // Map of saying_id:language_id -> translation
+    translations: Arc<DashMap<String, SayingTranslation>>,
+    // Map of preset_id:language_id -> latest daily saying
+    daily_sayings: Arc<DashMap<String, DailySaying>>,
+    // Map of saying_id -> share link
+    saying_shares: Arc<DashMap<String, SayingShare>>,
+    // Map of share token -> rendered PNG card
+    share_cards: Arc<DashMap<String, Vec<u8>>>,
+    // Map of user_id -> aggregate of the user's sayings
+    user_stats: Arc<DashMap<String, UserStats>>,
+    // Map of seq -> admin audit log entry
+    audit_log: Arc<DashMap<u64, AuditEntry>>,
+}
+
+impl MemoryStorage {
+    fn new() -> Self {
+        Self {
+            sayings: Arc::new(DashMap::new()),
+            global_cache: Arc::new(DashMap::new()),
+            content_index: Arc::new(DashMap::new()),
+            saying_index: Arc::new(DashMap::new()),
+            collections: Arc::new(DashMap::new()),
+            achievements: Arc::new(DashMap::new()),
+            preferences: Arc::new(DashMap::new()),
+            unsubscribe_tokens: Arc::new(DashMap::new()),
+            freeform_log: Arc::new(Mutex::new(VecDeque::new())),
+            shadow_outputs: Arc::new(DashMap::new()),
+            quality_scores: Arc::new(DashMap::new()),
+            conversations: Arc::new(DashMap::new()),
+            prompt_index: Arc::new(DashMap::new()),
+            prompt_history: Arc::new(DashMap::new()),
+            bans: Arc::new(DashMap::new()),
+            exemptions: Arc::new(DashMap::new()),
+            flag_overrides: Arc::new(DashMap::new()),
+            imported_presets: Arc::new(DashMap::new()),
+            user_owners: Arc::new(DashMap::new()),
+            feedback: Arc::new(DashMap::new()),
+            preset_stats: Arc::new(DashMap::new()),
+            recent_presets: Arc::new(DashMap::new()),
+            preset_selections: Arc::new(DashMap::new()),
+            translations: Arc::new(DashMap::new()),
+            daily_sayings: Arc::new(DashMap::new()),
+            saying_shares: Arc::new(DashMap::new()),
+            share_cards: Arc::new(DashMap::new()),
+            user_stats: Arc::new(DashMap::new()),
+            audit_log: Arc::new(DashMap::new()),
+        }
+    }
+
+    fn save_saying(&self, user_id: &str, saying: Arc<Saying>) -> Result<Arc<Saying>> {
+        // Add to user's sayings, scoping the shard guard to this block
+        {
+            // Get or create the user's saying list
+            let mut user_sayings = self.sayings.entry(user_id.to_string()).or_default();
+            
+            // Add the new saying
+            user_sayings.push(saying.clone());
+            
+            // Sort by created_at date (newest first)
+            user_sayings.sort_by_key(|s| Reverse(s.created_at));
+        }
+        
+        self.saying_index.insert(saying.id.clone(), user_id.to_string());
+        self.user_stats
+            .entry(user_id.to_string())
+            .or_insert_with(|| UserStats::new(user_id))
+            .record(&saying);
+        
+        // Add to global cache if it's not an LLM source (we only cache non-LLM entries)
+        if !matches!(saying.source, SayingSource::LLM) {
+            self.cache_saying(saying.clone())?;
+        }
+        
+        Ok(saying)
+    }
+
+    fn cache_saying(&self, saying: Arc<Saying>) -> Result<()> {
+        let cache_key = CacheKey::from_saying(&saying);
+        let content_hash = saying.content_hash();
+        
+        // Move the key out of its old content's index entry when its content changes
+        if let Some(previous) = self.global_cache.insert(cache_key.clone(), saying) {
+            let previous_hash = previous.content_hash();
+            if previous_hash != content_hash {
+                self.content_index.remove_if_mut(&previous_hash, |_, keys| {
+                    keys.remove(&cache_key);
+                    keys.is_empty()
+                });
+            }
+        }
+        self.content_index.entry(content_hash).or_default().insert(cache_key);
+        
+        Ok(())
+    }
+
+    fn list_cache_entries(&self) -> Result<Vec<CacheEntryInfo>> {
+        let mut entries = self.global_cache
+            .iter()
+            .map(|entry| {
+                let size = serde_json::to_vec(entry.value().as_ref()).context("Failed to serialize saying for cache")?.len();
+                Ok(CacheEntryInfo::new(entry.value(), size))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        entries.sort_by_key(|entry| Reverse(entry.created_at));
+        
+        Ok(entries)
+    }
+
+    fn invalidate_cache(&self, preset_id: Option<&str>, prompt: Option<&str>) -> Result<usize> {
+        let keys: Vec<CacheKey> = self.global_cache
+            .iter()
+            .filter(|entry| cache_key_matches(entry.key(), preset_id, prompt))
+            .map(|entry| entry.key().clone())
+            .collect();
+        
+        for key in &keys {
+            if let Some((_, saying)) = self.global_cache.remove(key) {
+                self.content_index.remove_if_mut(&saying.content_hash(), |_, keys| {
+                    keys.remove(key);
+                    keys.is_empty()
+                });
+            }
+        }
+        
+        Ok(keys.len())
+    }
+
+    fn mark_cache_stale(&self, preset_id: &str, before: DateTime<Utc>) -> Result<usize> {
+        let mut marked = 0;
+        for mut entry in self.global_cache.iter_mut() {
+            let saying = entry.value();
+            if saying.preset_id.as_deref() == Some(preset_id) && saying.created_at < before && !saying.stale {
+                *entry.value_mut() = Arc::new(Saying { stale: true, ..saying.as_ref().clone() });
+                marked += 1;
+            }
+        }
+        
+        Ok(marked)
+    }
+
+    fn get_last_saying(&self, user_id: &str) -> Result<Option<Arc<Saying>>> {
+        // Return the first saying (newest one due to sorting)
+        Ok(self.sayings
+            .get(user_id)
+            .and_then(|user_sayings| user_sayings.first().cloned()))
+    }
+
+    fn get_sayings(&self, user_id: &str, limit: usize) -> Result<Vec<Arc<Saying>>> {
+        // Get user's sayings if they exist, cloning only what the caller asked for
+        Ok(self.sayings
+            .get(user_id)
+            .map(|user_sayings| user_sayings.iter().take(limit).cloned().collect())
+            .unwrap_or_default())
+    }
+
+    fn get_sayings_by_tag(&self, user_id: &str, tag: &str, limit: usize) -> Result<Vec<Arc<Saying>>> {
+        // The whole history is in memory, so a scan serves as the index
+        Ok(self.sayings
+            .get(user_id)
+            .map(|user_sayings| user_sayings.iter().filter(|s| s.tags.iter().any(|t| t == tag)).take(limit).cloned().collect())
+            .unwrap_or_default())
+    }
+
+    fn get_saying_by_id(&self, saying_id: &str) -> Result<Option<(String, Arc<Saying>)>> {
+        let Some(user_id) = self.saying_index.get(saying_id).map(|entry| entry.clone()) else {
+            return Ok(None);
+        };
+        
+        let saying = self.sayings
+            .get(&user_id)
+            .and_then(|user_sayings| user_sayings.iter().find(|s| s.id == saying_id).cloned());
+        
+        Ok(saying.map(|saying| (user_id, saying)))
+    }
+
+    fn replace_saying(&self, user_id: &str, saying: Arc<Saying>) -> Result<bool> {
+        let Some(mut user_sayings) = self.sayings.get_mut(user_id) else {
+            return Ok(false);
+        };
+        
+        match user_sayings.iter_mut().find(|s| s.id == saying.id) {
+            Some(stored) => {
+                *stored = saying;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn prune_sayings(&self, user_id: &str, max: usize, cutoff: Option<DateTime<Utc>>) -> Result<usize> {
+        // Scope the shard guard so the index is updated without holding it
+        let removed = {
+            let Some(mut user_sayings) = self.sayings.get_mut(user_id) else {
+                return Ok(0);
+            };
+            let (kept, removed) = prune_history(std::mem::take(&mut *user_sayings), max, cutoff);
+            *user_sayings = kept;
+            removed
+        };
+        
+        for saying in &removed {
+            self.saying_index.remove(&saying.id);
+        }
+        Ok(removed.len())
+    }
+
+    fn find_cached_saying(&self, cache_key: &CacheKey) -> Result<Option<Arc<Saying>>> {
+        // First check the global cache for direct match
+        if let Some(cached) = self.global_cache.get(cache_key) {
+            // We found a direct match in the global cache
+            return Ok(Some(cached.clone()));
+        }
+
+        // Fall back to checking all user sayings
+        for user_sayings in self.sayings.iter() {
+            for saying in user_sayings.value() {
+                if !matches!(saying.source, SayingSource::LLM) && !saying.is_edited() && CacheKey::from_saying(saying) == *cache_key {
+                    // Found a match from cache or database
+                    return Ok(Some(saying.clone()));
+                }
+            }
+        }
+        
+        // No match found
+        Ok(None)
+    }
+
+    fn get_any_cached_sayings(&self, limit: usize, dedupe_by_content: bool) -> Result<Vec<Arc<Saying>>> {
+        // First try to get sayings from the global cache (fresh entries only), one entry per
+        // distinct content if deduping
+        let mut all_cached_sayings: Vec<Arc<Saying>> = if dedupe_by_content {
+            self.content_index
+                .iter()
+                .filter_map(|keys| keys.iter().find_map(|key| {
+                    self.global_cache.get(key).filter(|s| !s.stale).map(|s| s.clone())
+                }))
+                .collect()
+        } else {
+            self.global_cache
+                .iter()
+                .filter(|entry| !entry.stale)
+                .map(|entry| entry.value().clone())
+                .collect()
+        };
+        let mut seen_content: HashSet<String> = all_cached_sayings.iter().map(|s| s.content_hash()).collect();
+        
+        // If we don't have enough, fall back to the per-user sayings
+        if all_cached_sayings.len() < limit {
+            // Collect sayings from all users, preferring non-LLM sources
+            for user_sayings in self.sayings.iter() {
+                for saying in user_sayings.value() {
+                    if !matches!(saying.source, SayingSource::LLM) && !saying.is_edited() {
+                        // Check if we already have this saying in our result (from global cache)
+                        let key = CacheKey::from_saying(saying);
+                        let is_duplicate = all_cached_sayings.iter().any(|s| CacheKey::from_saying(s) == key) || (dedupe_by_content && seen_content.contains(&saying.content_hash()));
+                        
+                        if !is_duplicate {
+                            seen_content.insert(saying.content_hash());
+                            all_cached_sayings.push(saying.clone());
+                        }
+                    }
+                }
+            }
+            
+            // If we still don't have enough, include LLM sources as a fallback
+            if all_cached_sayings.len() < limit {
+                for user_sayings in self.sayings.iter() {
+                    for saying in user_sayings.value() {
+                        if matches!(saying.source, SayingSource::LLM) && !saying.is_edited() {
+                            let key = CacheKey::from_saying(saying);
+                            let is_duplicate = all_cached_sayings.iter().any(|s| CacheKey::from_saying(s) == key) || (dedupe_by_content && seen_content.contains(&saying.content_hash()));
+                            
+                            if !is_duplicate {
+                                seen_content.insert(saying.content_hash());
+                                all_cached_sayings.push(saying.clone());
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        
+        // Sort by date (newest first)
+        all_cached_sayings.sort_by_key(|s| Reverse(s.created_at));
+        
+        // Limit the results
+        if all_cached_sayings.len() > limit {
+            all_cached_sayings.truncate(limit);
+        }
+        
+        Ok(all_cached_sayings)
+    }
+}
+
+struct SledStorage {
+    db: sled::Db,
+    // zstd level for user histories and global cache entries; 0 writes plain JSON
+    compression_level: i32,
+    // Entries in the freeform prompt log, counted once on open as sled's len() walks the tree
+    freeform_log_len: Arc<AtomicUsize>,
+}
+
+impl SledStorage {
+    fn new(path: &str) -> Result<Self> {
+        let db = sled::open(path).context("Failed to open Sled database")?;
+        
+        // Ensure the global cache and ID index trees exist
+        db.open_tree("global_cache").context("Failed to create global cache tree")?;
+        db.open_tree(SAYING_INDEX_TREE).context("Failed to create saying index tree")?;
+        db.open_tree(CONTENT_INDEX_TREE).context("Failed to create content index tree")?;
+        db.open_tree(SAYING_TAGS_TREE).context("Failed to create saying tags tree")?;
+        db.open_tree(PROMPT_INDEX_TREE).context("Failed to create prompt index tree")?;
+        db.open_tree(PROMPT_HISTORY_TREE).context("Failed to create prompt history tree")?;
+        db.open_tree(COLLECTIONS_TREE).context("Failed to create collections tree")?;
+        db.open_tree(ACHIEVEMENTS_TREE).context("Failed to create achievements tree")?;
+        db.open_tree(PREFERENCES_TREE).context("Failed to create preferences tree")?;
+        db.open_tree(UNSUBSCRIBE_TOKENS_TREE).context("Failed to create unsubscribe tokens tree")?;
+        let freeform_log_len = db.open_tree(FREEFORM_LOG_TREE).context("Failed to create freeform prompt log tree")?.len();
+        db.open_tree(SHADOW_OUTPUTS_TREE).context("Failed to create shadow outputs tree")?;
+        db.open_tree(QUALITY_SCORES_TREE).context("Failed to create quality scores tree")?;
+        db.open_tree(CONVERSATIONS_TREE).context("Failed to create conversations tree")?;
+        db.open_tree(BANS_TREE).context("Failed to create bans tree")?;
+        db.open_tree(EXEMPTIONS_TREE).context("Failed to create exemptions tree")?;
+        db.open_tree(FEATURE_FLAGS_TREE).context("Failed to create feature flags tree")?;
+        db.open_tree(IMPORTED_PRESETS_TREE).context("Failed to create imported presets tree")?;
+        db.open_tree(USER_OWNERS_TREE).context("Failed to create user owners tree")?;
+        db.open_tree(FEEDBACK_TREE).context("Failed to create feedback tree")?;
+        db.open_tree(PRESET_STATS_TREE).context("Failed to create preset stats tree")?;
+        db.open_tree(RECENT_PRESETS_TREE).context("Failed to create recent presets tree")?;
+        db.open_tree(PRESET_SELECTIONS_TREE).context("Failed to create preset selections tree")?;
+        db.open_tree(TRANSLATIONS_TREE).context("Failed to create translations tree")?;
+        db.open_tree(DAILY_SAYINGS_TREE).context("Failed to create daily sayings tree")?;
+        db.open_tree(SAYING_SHARES_TREE).context("Failed to create saying shares tree")?;
+        db.open_tree(SHARE_CARDS_TREE).context("Failed to create share cards tree")?;
+        db.open_tree(USER_STATS_TREE).context("Failed to create user stats tree")?;
+        db.open_tree(AUDIT_LOG_TREE).context("Failed to create audit log tree")?;
+        
+        // Upgrade data written by older builds before anything reads it
+        let report = migrations::run_migrations(&db, false)?;
+        for step in &report.steps {
+            tracing::info!("Applied schema migration v{}: {} ({} records)", step.version, step.description, step.records_changed);
+        }
+        
+        Ok(Self { db, compression_level: 0, freeform_log_len: Arc::new(AtomicUsize::new(freeform_log_len)) })
+    }
+
+    // Values already stored are read whatever the level, and take it on when next written
+    fn with_compression(self, compression_level: i32) -> Self {
+        Self { compression_level, ..self }
+    }
+
+    fn save_saying(&self, user_id: &str, saying: Arc<Saying>) -> Result<Arc<Saying>> {
+        // Get existing sayings for the user
+        let mut sayings = self.get_sayings(user_id, usize::MAX)?;
+        
+        // Add the new saying
+        sayings.push(saying.clone());
+        
+        // Sort by created_at date (newest first)
+        sayings.sort_by_key(|s| Reverse(s.created_at));
+        
+        // Serialize and save user sayings
+        let serialized = compression::encode(serde_json::to_vec(&sayings).context("Failed to serialize sayings")?, self.compression_level)?;
+        self.db.insert(user_id.as_bytes(), serialized).context("Failed to insert into Sled database")?;
+        
+        // Keep the ID index pointing at the owning user's record
+        let index_tree = self.db.open_tree(SAYING_INDEX_TREE).context("Failed to open saying index tree")?;
+        index_tree.insert(saying.id.as_bytes(), user_id.as_bytes()).context("Failed to update saying index")?;
+        self.update_tag_index(user_id, &saying.id, &[], &saying.tags)?;
+        
+        // Keep the stats aggregate in step; histories from before it existed are counted in full
+        let stats_tree = self.db.open_tree(USER_STATS_TREE).context("Failed to open user stats tree")?;
+        let stats = match stats_tree.get(user_id.as_bytes()).context("Failed to read user stats")? {
+            Some(ivec) => {
+                let mut stats: UserStats = serde_json::from_slice(&ivec).context("Failed to deserialize user stats")?;
+                stats.record(&saying);
+                stats
+            }
+            None => UserStats::from_sayings(user_id, sayings.iter().map(|s| s.as_ref())),
+        };
+        let serialized = serde_json::to_vec(&stats).context("Failed to serialize user stats")?;
+        stats_tree.insert(user_id.as_bytes(), serialized).context("Failed to insert user stats")?;
+        
+        // Add to global cache if it's not an LLM source
+        if !matches!(saying.source, SayingSource::LLM) {
+            self.cache_saying(saying.clone())?;
+        }
+        
+        Ok(saying)
+    }
+
+    fn cache_saying(&self, saying: Arc<Saying>) -> Result<()> {
+        let global_tree = self.db.open_tree("global_cache").context("Failed to open global cache tree")?;
+        
+        // Create a unique key based on preset + prompt
+        let cache_key = CacheKey::from_saying(&saying);
+        let key_bytes = serde_json::to_vec(&cache_key).context("Failed to serialize cache key")?;
+        
+        // Store the saying in the global cache
+        let serialized_saying = compression::encode(serde_json::to_vec(saying.as_ref()).context("Failed to serialize saying for cache")?, self.compression_level)?;
+        let previous = global_tree.insert(&key_bytes, serialized_saying).context("Failed to insert into global cache")?;
+        
+        // Keep the content index in step, dropping the entry for the content this key used to hold
+        let content_tree = self.db.open_tree(CONTENT_INDEX_TREE).context("Failed to open content index tree")?;
+        let content_hash = saying.content_hash();
+        if let Some(previous) = previous {
+            let previous: Saying = serde_json::from_slice(&compression::decode(&previous)?)
+                .context("Failed to deserialize saying from global cache")?;
+            if previous.content_hash() != content_hash {
+                content_tree.remove(migrations::content_index_key(&previous.content_hash(), &key_bytes))
+                    .context("Failed to update content index")?;
+            }
+        }
+        content_tree.insert(migrations::content_index_key(&content_hash, &key_bytes), key_bytes)
+            .context("Failed to update content index")?;
+        
+        Ok(())
+    }
+
+    fn list_cache_entries(&self) -> Result<Vec<CacheEntryInfo>> {
+        let global_tree = self.db.open_tree("global_cache").context("Failed to open global cache tree")?;
+        let mut entries = Vec::new();
+        
+        for result in global_tree.iter() {
+            let (_, ivec) = result.context("Failed to iterate global cache")?;
+            let saying: Saying = serde_json::from_slice(&compression::decode(&ivec)?)
+                .context("Failed to deserialize saying from global cache")?;
+            entries.push(CacheEntryInfo::new(&saying, ivec.len()));
+        }
+        entries.sort_by_key(|entry| Reverse(entry.created_at));
+        
+        Ok(entries)
+    }
+
+    fn invalidate_cache(&self, preset_id: Option<&str>, prompt: Option<&str>) -> Result<usize> {
+        let global_tree = self.db.open_tree("global_cache").context("Failed to open global cache tree")?;
+        let content_tree = self.db.open_tree(CONTENT_INDEX_TREE).context("Failed to open content index tree")?;
+        let mut removed = 0;
+        
+        for result in global_tree.iter() {
+            let (key, ivec) = result.context("Failed to iterate global cache")?;
+            let cache_key: CacheKey = serde_json::from_slice(&key).context("Failed to deserialize cache key")?;
+            if !cache_key_matches(&cache_key, preset_id, prompt) {
+                continue;
+            }
+            
+            let saying: Saying = serde_json::from_slice(&compression::decode(&ivec)?)
+                .context("Failed to deserialize saying from global cache")?;
+            global_tree.remove(&key).context("Failed to remove from global cache")?;
+            content_tree.remove(migrations::content_index_key(&saying.content_hash(), &key))
+                .context("Failed to update content index")?;
+            removed += 1;
+        }
+        
+        Ok(removed)
+    }
+
+    fn mark_cache_stale(&self, preset_id: &str, before: DateTime<Utc>) -> Result<usize> {
+        let global_tree = self.db.open_tree("global_cache").context("Failed to open global cache tree")?;
+        let mut marked = 0;
+        
+        for result in global_tree.iter() {
+            let (key, ivec) = result.context("Failed to iterate global cache")?;
+            let mut saying: Saying = serde_json::from_slice(&compression::decode(&ivec)?)
+                .context("Failed to deserialize saying from global cache")?;
+            
+            if saying.preset_id.as_deref() == Some(preset_id) && saying.created_at < before && !saying.stale {
+                saying.stale = true;
+                let serialized = compression::encode(serde_json::to_vec(&saying).context("Failed to serialize saying for cache")?, self.compression_level)?;
+                global_tree.insert(key, serialized).context("Failed to update global cache")?;
+                marked += 1;
+            }
+        }
+        
+        Ok(marked)
+    }
+
+    fn get_last_saying(&self, user_id: &str) -> Result<Option<Arc<Saying>>> {
+        // Try to get all sayings for the user
+        let sayings = self.get_sayings(user_id, 1)?;
+        
+        // Return the first one if any exist
+        Ok(sayings.into_iter().next())
+    }
+
+    fn get_sayings(&self, user_id: &str, limit: usize) -> Result<Vec<Arc<Saying>>> {
+        // Try to get the user's sayings from the database
+        match self.db.get(user_id.as_bytes()) {
+            Ok(Some(ivec)) => {
+                // Deserialize the sayings
+                let mut sayings: Vec<Arc<Saying>> = serde_json::from_slice(&compression::decode(&ivec)?)
+                    .context("Failed to deserialize sayings from Sled")?;
+                
+                // Sort and limit
+                sayings.sort_by_key(|s| Reverse(s.created_at));
+                if sayings.len() > limit {
+                    sayings.truncate(limit);
+                }
+                
+                Ok(sayings)
+            }
+            Ok(None) => Ok(Vec::new()), // No sayings for this user yet
+            Err(e) => Err(anyhow::anyhow!("Sled error: {}", e)),
+        }
+    }
+
+    fn get_saying_by_id(&self, saying_id: &str) -> Result<Option<(String, Arc<Saying>)>> {
+        let index_tree = self.db.open_tree(SAYING_INDEX_TREE).context("Failed to open saying index tree")?;
+        
+        let Some(user_id) = index_tree.get(saying_id.as_bytes()).context("Failed to read saying index")? else {
+            return Ok(None);
+        };
+        let user_id = String::from_utf8(user_id.to_vec()).context("Corrupt user ID in saying index")?;
+        
+        let saying = self.get_sayings(&user_id, usize::MAX)?
+            .into_iter()
+            .find(|s| s.id == saying_id);
+        
+        Ok(saying.map(|saying| (user_id, saying)))
+    }
+
+    fn replace_saying(&self, user_id: &str, saying: Arc<Saying>) -> Result<bool> {
+        let mut sayings = self.get_sayings(user_id, usize::MAX)?;
+        
+        let Some(stored) = sayings.iter_mut().find(|s| s.id == saying.id) else {
+            return Ok(false);
+        };
+        let old_tags = stored.tags.clone();
+        *stored = saying.clone();
+        
+        let serialized = compression::encode(serde_json::to_vec(&sayings).context("Failed to serialize sayings")?, self.compression_level)?;
+        self.db.insert(user_id.as_bytes(), serialized).context("Failed to insert into Sled database")?;
+        self.update_tag_index(user_id, &saying.id, &old_tags, &saying.tags)?;
+        Ok(true)
+    }
+
+    // Move a saying's tag index entries from its old tags to its new ones
+    fn update_tag_index(&self, user_id: &str, saying_id: &str, old_tags: &[String], new_tags: &[String]) -> Result<()> {
+        let tags_tree = self.db.open_tree(SAYING_TAGS_TREE).context("Failed to open saying tags tree")?;
+        for tag in old_tags.iter().filter(|tag| !new_tags.contains(tag)) {
+            tags_tree.remove(tag_index_key(user_id, tag, saying_id)).context("Failed to update tag index")?;
+        }
+        for tag in new_tags.iter().filter(|tag| !old_tags.contains(tag)) {
+            tags_tree.insert(tag_index_key(user_id, tag, saying_id), &b""[..]).context("Failed to update tag index")?;
+        }
+        Ok(())
+    }
+
+    fn get_sayings_by_tag(&self, user_id: &str, tag: &str, limit: usize) -> Result<Vec<Arc<Saying>>> {
+        let tags_tree = self.db.open_tree(SAYING_TAGS_TREE).context("Failed to open saying tags tree")?;
+        let prefix = tag_index_prefix(user_id, tag);
+        
+        let mut saying_ids = HashSet::new();
+        for result in tags_tree.scan_prefix(&prefix) {
+            let (key, _) = result.context("Failed to iterate tag index")?;
+            saying_ids.insert(String::from_utf8(key[prefix.len()..].to_vec()).context("Corrupt saying ID in tag index")?);
+        }
+        if saying_ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        
+        Ok(self.get_sayings(user_id, usize::MAX)?
+            .into_iter()
+            .filter(|saying| saying_ids.contains(&saying.id))
+            .take(limit)
+            .collect())
+    }
+
+    fn prune_sayings(&self, user_id: &str, max: usize, cutoff: Option<DateTime<Utc>>) -> Result<usize> {
+        let (kept, removed) = prune_history(self.get_sayings(user_id, usize::MAX)?, max, cutoff);
+        if removed.is_empty() {
+            return Ok(0);
+        }
+        
+        let serialized = compression::encode(serde_json::to_vec(&kept).context("Failed to serialize sayings")?, self.compression_level)?;
+        self.db.insert(user_id.as_bytes(), serialized).context("Failed to insert into Sled database")?;
+        
+        let index_tree = self.db.open_tree(SAYING_INDEX_TREE).context("Failed to open saying index tree")?;
+        for saying in &removed {
+            index_tree.remove(saying.id.as_bytes()).context("Failed to update saying index")?;
+            self.update_tag_index(user_id, &saying.id, &saying.tags, &[])?;
+        }
+        Ok(removed.len())
+    }
+
+    fn find_cached_saying(&self, cache_key: &CacheKey) -> Result<Option<Arc<Saying>>> {
+        // First check the global cache for direct match
+        let global_tree = self.db.open_tree("global_cache").context("Failed to open global cache tree")?;
+        
+        let key_bytes = serde_json::to_vec(cache_key).context("Failed to serialize cache key")?;
+        
+        // Check if we have this key in the global cache
+        if let Ok(Some(ivec)) = global_tree.get(&key_bytes) {
+            let saying: Arc<Saying> = serde_json::from_slice(&compression::decode(&ivec)?)
+                .context("Failed to deserialize saying from global cache")?;
+            return Ok(Some(saying));
+        }
+        
+        // Fall back to checking all user sayings
+        for result in self.db.iter() {
+            let (key, ivec) = result.context("Failed to iterate Sled database")?;
+            
+            // Skip the global cache tree
+            if key.starts_with(b"__") {
+                continue;
+            }
+            
+            // Deserialize the sayings
+            let sayings: Vec<Arc<Saying>> = serde_json::from_slice(&compression::decode(&ivec)?)
+                .context("Failed to deserialize sayings from Sled")?;
+            
+            // Look for a matching prompt and preset
+            for saying in sayings {
+                if !matches!(saying.source, SayingSource::LLM) && !saying.is_edited() && CacheKey::from_saying(&saying) == *cache_key {
+                    // Found a match from cache or database
+                    return Ok(Some(saying));
+                }
+            }
+        }
+        
+        // No match found
+        Ok(None)
+    }
+
+    fn get_any_cached_sayings(&self, limit: usize, dedupe_by_content: bool) -> Result<Vec<Arc<Saying>>> {
+        let mut all_cached_sayings = Vec::new();
+        let mut seen_content = HashSet::new();
+        
+        // First try the global cache, skipping stale entries
+        let global_tree = self.db.open_tree("global_cache").context("Failed to open global cache tree")?;
+        
+        let global_sayings: Vec<Arc<Saying>> = if dedupe_by_content {
+            self.distinct_global_cache_entries(&global_tree, limit)?
+        } else {
+            global_tree
+                .iter()
+                .map(|result| {
+                    let (_, ivec) = result.context("Failed to iterate global cache")?;
+                    serde_json::from_slice::<Arc<Saying>>(&compression::decode(&ivec)?).context("Failed to deserialize saying from global cache")
+                })
+                .filter(|saying| !matches!(saying, Ok(saying) if saying.stale))
+                .take(limit)
+                .collect::<Result<_>>()?
+        };
+        
+        for saying in global_sayings {
+            seen_content.insert(saying.content_hash());
+            all_cached_sayings.push(saying);
+            
+            if all_cached_sayings.len() >= limit {
+                // Sort by date (newest first) and return
+                all_cached_sayings.sort_by_key(|s| Reverse(s.created_at));
+                return Ok(all_cached_sayings);
+            }
+        }
+        
+        // If we don't have enough from global cache, check user sayings
+        let mut seen_keys = HashSet::new();
+        
+        // Add all non-LLM sayings to our collection
+        for result in self.db.iter() {
+            let (key, ivec) = result.context("Failed to iterate Sled database")?;
+            
+            // Skip the global cache tree and other internal trees
+            if key.starts_with(b"__") {
+                continue;
+            }
+            
+            // Deserialize the sayings
+            let sayings: Vec<Arc<Saying>> = serde_json::from_slice(&compression::decode(&ivec)?)
+                .context("Failed to deserialize sayings from Sled")?;
+            
+            for saying in &sayings {
+                if !matches!(saying.source, SayingSource::LLM) && !saying.is_edited() {
+                    // Create a cache key to track duplicates
+                    let cache_key = CacheKey::from_saying(saying);
+                    let duplicate_content = dedupe_by_content && seen_content.contains(&saying.content_hash());
+                    
+                    if !seen_keys.contains(&cache_key) && !duplicate_content {
+                        seen_keys.insert(cache_key);
+                        seen_content.insert(saying.content_hash());
+                        all_cached_sayings.push(saying.clone());
+                        
+                        if all_cached_sayings.len() >= limit {
+                            break;
+                        }
+                    }
+                }
+            }
+            
+            if all_cached_sayings.len() >= limit {
+                break;
+            }
+        }
+        
+        // If we still don't have enough, include LLM sources as a fallback
+        if all_cached_sayings.len() < limit {
+            for result in self.db.iter() {
+                let (key, ivec) = result.context("Failed to iterate Sled database")?;
+                
+                // Skip the global cache tree and other internal trees
+                if key.starts_with(b"__") {
+                    continue;
+                }
+                
+                // Deserialize the sayings
+                let sayings: Vec<Arc<Saying>> = serde_json::from_slice(&compression::decode(&ivec)?)
+                    .context("Failed to deserialize sayings from Sled")?;
+                
+                for saying in &sayings {
+                    if matches!(saying.source, SayingSource::LLM) && !saying.is_edited() {
+                        // Create a cache key to track duplicates
+                        let cache_key = CacheKey::from_saying(saying);
+                        let duplicate_content = dedupe_by_content && seen_content.contains(&saying.content_hash());
+                        
+                        if !seen_keys.contains(&cache_key) && !duplicate_content {
+                            seen_keys.insert(cache_key);
+                            seen_content.insert(saying.content_hash());
+                            all_cached_sayings.push(saying.clone());
+                            
+                            if all_cached_sayings.len() >= limit {
+                                break;
+                            }
+                        }
+                    }
+                }
+                
+                if all_cached_sayings.len() >= limit {
+                    break;
+                }
+            }
+        }
+        
+        // Sort by date (newest first)
+        all_cached_sayings.sort_by_key(|s| Reverse(s.created_at));
+        
+        Ok(all_cached_sayings)
+    }
+
+    // The first fresh global cache entry for each distinct content, found through the content index.
+    // Index keys start with the content hash, so entries for the same content are adjacent.
+    fn distinct_global_cache_entries(&self, global_tree: &sled::Tree, limit: usize) -> Result<Vec<Arc<Saying>>> {
+        let content_tree = self.db.open_tree(CONTENT_INDEX_TREE).context("Failed to open content index tree")?;
+        let mut sayings = Vec::new();
+        let mut last_hash: Option<Vec<u8>> = None;
+        
+        for result in content_tree.iter() {
+            let (index_key, cache_key) = result.context("Failed to iterate content index")?;
+            let hash = index_key.split(|&b| b == b'/').next().unwrap_or_default().to_vec();
+            if last_hash.as_ref() == Some(&hash) {
+                continue;
+            }
+            
+            if let Some(ivec) = global_tree.get(&cache_key).context("Failed to read global cache")? {
+                let saying: Arc<Saying> = serde_json::from_slice(&compression::decode(&ivec)?).context("Failed to deserialize saying from global cache")?;
+                if saying.stale {
+                    continue;
+                }
+                sayings.push(saying);
+                last_hash = Some(hash);
+                
+                if sayings.len() >= limit {
+                    break;
+                }
+            }
+        }
+        
+        Ok(sayings)
+    }
+}
+
+// Collections
+impl MemoryStorage {
+    fn save_collection(&self, collection: Collection) -> Result<Collection> {
+        self.collections.insert(collection.id.clone(), collection.clone());
+        Ok(collection)
+    }
+
+    fn get_collection(&self, collection_id: &str) -> Result<Option<Collection>> {
+        Ok(self.collections.get(collection_id).map(|c| c.clone()))
+    }
+
+    fn get_collections(&self, user_id: &str) -> Result<Vec<Collection>> {
+        let mut collections: Vec<Collection> = self.collections
+            .iter()
+            .filter(|c| c.user_id == user_id)
+            .map(|c| c.clone())
+            .collect();
+        
+        collections.sort_by_key(|c| Reverse(c.created_at));
+        Ok(collections)
+    }
+
+    fn find_collection_by_share_token(&self, token: &str) -> Result<Option<Collection>> {
+        Ok(self.collections
+            .iter()
+            .find(|c| c.share_token.as_deref() == Some(token))
+            .map(|c| c.clone()))
+    }
+}
+
+impl SledStorage {
+    fn save_collection(&self, collection: Collection) -> Result<Collection> {
+        let tree = self.db.open_tree(COLLECTIONS_TREE).context("Failed to open collections tree")?;
+        
+        let serialized = serde_json::to_vec(&collection).context("Failed to serialize collection")?;
+        tree.insert(collection.id.as_bytes(), serialized).context("Failed to insert collection")?;
+        
+        Ok(collection)
+    }
+
+    fn get_collection(&self, collection_id: &str) -> Result<Option<Collection>> {
+        let tree = self.db.open_tree(COLLECTIONS_TREE).context("Failed to open collections tree")?;
+        
+        match tree.get(collection_id.as_bytes()).context("Failed to read collection")? {
+            Some(ivec) => Ok(Some(serde_json::from_slice(&ivec).context("Failed to deserialize collection")?)),
+            None => Ok(None),
+        }
+    }
+
+    fn all_collections(&self) -> Result<Vec<Collection>> {
+        let tree = self.db.open_tree(COLLECTIONS_TREE).context("Failed to open collections tree")?;
+        
+        tree.iter()
+            .map(|result| {
+                let (_, ivec) = result.context("Failed to iterate collections")?;
+                serde_json::from_slice(&ivec).context("Failed to deserialize collection")
+            })
+            .collect()
+    }
+
+    fn get_collections(&self, user_id: &str) -> Result<Vec<Collection>> {
+        let mut collections: Vec<Collection> = self.all_collections()?
+            .into_iter()
+            .filter(|c| c.user_id == user_id)
+            .collect();
+        
+        collections.sort_by_key(|c| Reverse(c.created_at));
+        Ok(collections)
+    }
+
+    fn find_collection_by_share_token(&self, token: &str) -> Result<Option<Collection>> {
+        Ok(self.all_collections()?
+            .into_iter()
+            .find(|c| c.share_token.as_deref() == Some(token)))
+    }
+}
+
+// Conversations
+impl MemoryStorage {
+    fn save_conversation(&self, conversation: &Conversation) -> Result<()> {
+        self.conversations
+            .entry(conversation.user_id.clone())
+            .or_default()
+            .insert(conversation.id.clone(), conversation.clone());
+        Ok(())
+    }
+
+    fn get_conversation(&self, user_id: &str, conversation_id: &str) -> Result<Option<Conversation>> {
+        Ok(self.conversations
+            .get(user_id)
+            .and_then(|conversations| conversations.get(conversation_id).cloned()))
+    }
+
+    fn get_conversations(&self, user_id: &str) -> Result<Vec<Conversation>> {
+        let mut conversations: Vec<Conversation> = self.conversations
+            .get(user_id)
+            .map(|conversations| conversations.values().cloned().collect())
+            .unwrap_or_default();
+        conversations.sort_by_key(|c| Reverse(c.updated_at));
+        Ok(conversations)
+    }
+
+    fn delete_conversation(&self, user_id: &str, conversation_id: &str) -> Result<bool> {
+        Ok(self.conversations
+            .get_mut(user_id)
+            .is_some_and(|mut conversations| conversations.remove(conversation_id).is_some()))
+    }
+}
+
+// Keys are `user_id NUL conversation id`, so a prefix scan finds a user's conversations
+fn conversation_key(user_id: &str, conversation_id: &str) -> Vec<u8> {
+    let mut key = Vec::with_capacity(user_id.len() + 1 + conversation_id.len());
+    key.extend_from_slice(user_id.as_bytes());
+    key.push(0);
+    key.extend_from_slice(conversation_id.as_bytes());
+    key
+}
+
+impl SledStorage {
+    fn save_conversation(&self, conversation: &Conversation) -> Result<()> {
+        let tree = self.db.open_tree(CONVERSATIONS_TREE).context("Failed to open conversations tree")?;
+        
+        let serialized = serde_json::to_vec(conversation).context("Failed to serialize conversation")?;
+        tree.insert(conversation_key(&conversation.user_id, &conversation.id), serialized)
+            .context("Failed to insert conversation")?;
+        Ok(())
+    }
+
+    fn get_conversation(&self, user_id: &str, conversation_id: &str) -> Result<Option<Conversation>> {
+        let tree = self.db.open_tree(CONVERSATIONS_TREE).context("Failed to open conversations tree")?;
+        
+        match tree.get(conversation_key(user_id, conversation_id)).context("Failed to read conversation")? {
+            Some(ivec) => Ok(Some(serde_json::from_slice(&ivec).context("Failed to deserialize conversation")?)),
+            None => Ok(None),
+        }
+    }
+
+    fn get_conversations(&self, user_id: &str) -> Result<Vec<Conversation>> {
+        let tree = self.db.open_tree(CONVERSATIONS_TREE).context("Failed to open conversations tree")?;
+        
+        let mut conversations = tree.scan_prefix(conversation_key(user_id, ""))
+            .map(|result| {
+                let (_, ivec) = result.context("Failed to iterate conversations")?;
+                serde_json::from_slice(&ivec).context("Failed to deserialize conversation")
+            })
+            .collect::<Result<Vec<Conversation>>>()?;
+        conversations.sort_by_key(|c| Reverse(c.updated_at));
+        Ok(conversations)
+    }
+
+    fn delete_conversation(&self, user_id: &str, conversation_id: &str) -> Result<bool> {
+        let tree = self.db.open_tree(CONVERSATIONS_TREE).context("Failed to open conversations tree")?;
+        
+        let removed = tree.remove(conversation_key(user_id, conversation_id)).context("Failed to delete conversation")?;
+        Ok(removed.is_some())
+    }
+}
+
+// Achievements
+impl MemoryStorage {
+    fn get_achievements(&self, user_id: &str) -> Result<Vec<Achievement>> {
+        Ok(self.achievements.get(user_id).map(|a| a.clone()).unwrap_or_default())
+    }
+
+    fn save_achievements(&self, user_id: &str, achievements: &[Achievement]) -> Result<()> {
+        self.achievements.insert(user_id.to_string(), achievements.to_vec());
+        Ok(())
+    }
+}
+
+impl SledStorage {
+    fn get_achievements(&self, user_id: &str) -> Result<Vec<Achievement>> {
+        let tree = self.db.open_tree(ACHIEVEMENTS_TREE).context("Failed to open achievements tree")?;
+        
+        match tree.get(user_id.as_bytes()).context("Failed to read achievements")? {
+            Some(ivec) => Ok(serde_json::from_slice(&ivec).context("Failed to deserialize achievements")?),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    fn save_achievements(&self, user_id: &str, achievements: &[Achievement]) -> Result<()> {
+        let tree = self.db.open_tree(ACHIEVEMENTS_TREE).context("Failed to open achievements tree")?;
+        
+        let serialized = serde_json::to_vec(achievements).context("Failed to serialize achievements")?;
+        tree.insert(user_id.as_bytes(), serialized).context("Failed to insert achievements")?;
+        Ok(())
+    }
+}
+
+// User enumeration for scans across histories
+impl SledStorage {
+    fn list_user_ids(&self) -> Result<Vec<String>> {
+        let mut user_ids = Vec::new();
+        
+        for key in self.db.iter().keys() {
+            let key = key.context("Failed to iterate Sled database")?;
             
-            for saying in &sayings {
-                if !matches!(saying.source, SayingSource::LLM) {
-                    // Create a cache key to track duplicates
-                    let cache_key = CacheKey::from_saying(saying);
-                    
-                    if !seen_keys.contains(&cache_key) {
-                        seen_keys.insert(cache_key);
-                        all_cached_sayings.push(saying.clone());
-                        
-                        if all_cached_sayings.len() >= limit {
-                            break;
-                        }
-                    }
+            // Skip internal keys
+            if key.starts_with(b"__") {
+                continue;
+            }
+            
+            user_ids.push(String::from_utf8_lossy(&key).into_owned());
+        }
+        
+        Ok(user_ids)
+    }
+}
+
+// Cross-user views for operators
+impl MemoryStorage {
+    fn get_recent_sayings(&self, limit: usize) -> Result<Vec<(String, Arc<Saying>)>> {
+        let mut recent: Vec<(String, Arc<Saying>)> = Vec::new();
+        
+        for user_sayings in self.sayings.iter() {
+            // Each list is sorted newest first, so only its head can make the cut
+            for saying in user_sayings.value().iter().take(limit) {
+                recent.push((user_sayings.key().clone(), saying.clone()));
+            }
+        }
+        
+        recent.sort_by_key(|(_, s)| Reverse(s.created_at));
+        recent.truncate(limit);
+        Ok(recent)
+    }
+}
+
+impl SledStorage {
+    fn get_recent_sayings(&self, limit: usize) -> Result<Vec<(String, Arc<Saying>)>> {
+        let mut recent: Vec<(String, Arc<Saying>)> = Vec::new();
+        
+        for user_id in self.list_user_ids()? {
+            for saying in self.get_sayings(&user_id, limit)? {
+                recent.push((user_id.clone(), saying));
+            }
+        }
+        
+        recent.sort_by_key(|(_, s)| Reverse(s.created_at));
+        recent.truncate(limit);
+        Ok(recent)
+    }
+}
+
+// Preferences
+impl MemoryStorage {
+    fn get_preferences(&self, user_id: &str) -> Result<UserPreferences> {
+        Ok(self.preferences.get(user_id).map(|p| p.clone()).unwrap_or_default())
+    }
+
+    fn save_preferences(&self, user_id: &str, preferences: &UserPreferences) -> Result<()> {
+        let previous = self.preferences.insert(user_id.to_string(), preferences.clone());
+        
+        let (stale, token) = unsubscribe_token_change(previous.as_ref(), preferences);
+        if let Some(stale) = stale {
+            self.unsubscribe_tokens.remove(stale);
+        }
+        if let Some(token) = token {
+            self.unsubscribe_tokens.insert(token.to_string(), user_id.to_string());
+        }
+        Ok(())
+    }
+
+    fn find_unsubscribe_token(&self, token: &str) -> Result<Option<String>> {
+        Ok(self.unsubscribe_tokens.get(token).map(|user_id| user_id.clone()))
+    }
+
+    fn list_preferences(&self) -> Result<Vec<(String, UserPreferences)>> {
+        Ok(self.preferences
+            .iter()
+            .map(|entry| (entry.key().clone(), entry.value().clone()))
+            .collect())
+    }
+}
+
+impl SledStorage {
+    fn get_preferences(&self, user_id: &str) -> Result<UserPreferences> {
+        let tree = self.db.open_tree(PREFERENCES_TREE).context("Failed to open preferences tree")?;
+        
+        match tree.get(user_id.as_bytes()).context("Failed to read preferences")? {
+            Some(ivec) => Ok(serde_json::from_slice(&ivec).context("Failed to deserialize preferences")?),
+            None => Ok(UserPreferences::default()),
+        }
+    }
+
+    fn save_preferences(&self, user_id: &str, preferences: &UserPreferences) -> Result<()> {
+        let tree = self.db.open_tree(PREFERENCES_TREE).context("Failed to open preferences tree")?;
+        
+        let serialized = serde_json::to_vec(preferences).context("Failed to serialize preferences")?;
+        let previous = tree.insert(user_id.as_bytes(), serialized).context("Failed to insert preferences")?;
+        let previous: Option<UserPreferences> = previous.and_then(|ivec| serde_json::from_slice(&ivec).ok());
+        
+        let index = self.db.open_tree(UNSUBSCRIBE_TOKENS_TREE).context("Failed to open unsubscribe tokens tree")?;
+        let (stale, token) = unsubscribe_token_change(previous.as_ref(), preferences);
+        if let Some(stale) = stale {
+            index.remove(stale.as_bytes()).context("Failed to remove unsubscribe token")?;
+        }
+        if let Some(token) = token {
+            index.insert(token.as_bytes(), user_id.as_bytes()).context("Failed to index unsubscribe token")?;
+        }
+        Ok(())
+    }
+
+    fn find_unsubscribe_token(&self, token: &str) -> Result<Option<String>> {
+        let index = self.db.open_tree(UNSUBSCRIBE_TOKENS_TREE).context("Failed to open unsubscribe tokens tree")?;
+        Ok(index.get(token.as_bytes())
+            .context("Failed to read unsubscribe token")?
+            .map(|user_id| String::from_utf8_lossy(&user_id).into_owned()))
+    }
+
+    fn list_preferences(&self) -> Result<Vec<(String, UserPreferences)>> {
+        let tree = self.db.open_tree(PREFERENCES_TREE).context("Failed to open preferences tree")?;
+        
+        tree.iter()
+            .map(|result| {
+                let (key, ivec) = result.context("Failed to iterate preferences")?;
+                let preferences = serde_json::from_slice(&ivec).context("Failed to deserialize preferences")?;
+                Ok((String::from_utf8_lossy(&key).into_owned(), preferences))
+            })
+            .collect()
+    }
+}
+
+// The unsubscribe token a save makes stale, if any, and the one to index
+fn unsubscribe_token_change<'a>(previous: Option<&'a UserPreferences>, current: &'a UserPreferences) -> (Option<&'a str>, Option<&'a str>) {
+    let token = current.email.as_ref().map(|email| email.unsubscribe_token.as_str());
+    let stale = previous
+        .and_then(|previous| previous.email.as_ref())
+        .map(|email| email.unsubscribe_token.as_str())
+        .filter(|stale| Some(*stale) != token);
+    (stale, token)
+}
+
+// Freeform prompt audit log
+impl MemoryStorage {
+    fn log_freeform_prompt(&self, entry: &FreeformPromptEntry, keep: usize) -> Result<()> {
+        let mut log = self.freeform_log.lock().unwrap();
+        log.push_front(entry.clone());
+        log.truncate(keep);
+        Ok(())
+    }
+
+    fn get_freeform_prompts(&self, limit: usize) -> Result<Vec<FreeformPromptEntry>> {
+        Ok(self.freeform_log.lock().unwrap().iter().take(limit).cloned().collect())
+    }
+}
+
+impl SledStorage {
+    fn log_freeform_prompt(&self, entry: &FreeformPromptEntry, keep: usize) -> Result<()> {
+        let tree = self.db.open_tree(FREEFORM_LOG_TREE).context("Failed to open freeform prompt log tree")?;
+        
+        let serialized = serde_json::to_vec(entry).context("Failed to serialize freeform prompt")?;
+        if tree.insert(entry.storage_key().as_bytes(), serialized).context("Failed to insert freeform prompt")?.is_none() {
+            self.freeform_log_len.fetch_add(1, Ordering::Relaxed);
+        }
+        
+        // Keys sort chronologically, so the oldest entries are at the front
+        while self.freeform_log_len.load(Ordering::Relaxed) > keep {
+            if tree.pop_min().context("Failed to drop old freeform prompt")?.is_none() {
+                break;
+            }
+            self.freeform_log_len.fetch_sub(1, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+
+    fn get_freeform_prompts(&self, limit: usize) -> Result<Vec<FreeformPromptEntry>> {
+        let tree = self.db.open_tree(FREEFORM_LOG_TREE).context("Failed to open freeform prompt log tree")?;
+        
+        // Keys sort chronologically, so the newest entries are at the end
+        tree.iter()
+            .rev()
+            .take(limit)
+            .map(|result| {
+                let (_, ivec) = result.context("Failed to iterate freeform prompts")?;
+                serde_json::from_slice(&ivec).context("Failed to deserialize freeform prompt")
+            })
+            .collect()
+    }
+}
+
+// Shadow model outputs
+impl MemoryStorage {
+    fn save_shadow_output(&self, output: &ShadowOutput) -> Result<()> {
+        self.shadow_outputs.insert(output.id.clone(), output.clone());
+        Ok(())
+    }
+
+    fn get_shadow_outputs(&self, limit: usize) -> Result<Vec<ShadowOutput>> {
+        let mut outputs: Vec<ShadowOutput> = self.shadow_outputs
+            .iter()
+            .map(|entry| entry.value().clone())
+            .collect();
+        outputs.sort_by_key(|output| Reverse(output.created_at));
+        outputs.truncate(limit);
+        Ok(outputs)
+    }
+}
+
+impl SledStorage {
+    fn save_shadow_output(&self, output: &ShadowOutput) -> Result<()> {
+        let tree = self.db.open_tree(SHADOW_OUTPUTS_TREE).context("Failed to open shadow outputs tree")?;
+        
+        let serialized = serde_json::to_vec(output).context("Failed to serialize shadow output")?;
+        tree.insert(output.storage_key().as_bytes(), serialized).context("Failed to insert shadow output")?;
+        Ok(())
+    }
+
+    fn get_shadow_outputs(&self, limit: usize) -> Result<Vec<ShadowOutput>> {
+        let tree = self.db.open_tree(SHADOW_OUTPUTS_TREE).context("Failed to open shadow outputs tree")?;
+        
+        // Keys sort chronologically, so the newest entries are at the end
+        tree.iter()
+            .rev()
+            .take(limit)
+            .map(|result| {
+                let (_, ivec) = result.context("Failed to iterate shadow outputs")?;
+                serde_json::from_slice(&ivec).context("Failed to deserialize shadow output")
+            })
+            .collect()
+    }
+}
+
+// Quality scores
+impl MemoryStorage {
+    fn save_quality_score(&self, score: &QualityScore) -> Result<()> {
+        self.quality_scores.insert(score.saying_id.clone(), score.clone());
+        Ok(())
+    }
+
+    fn list_quality_scores(&self) -> Result<Vec<QualityScore>> {
+        Ok(self.quality_scores.iter().map(|entry| entry.value().clone()).collect())
+    }
+}
+
+impl SledStorage {
+    fn save_quality_score(&self, score: &QualityScore) -> Result<()> {
+        let tree = self.db.open_tree(QUALITY_SCORES_TREE).context("Failed to open quality scores tree")?;
+        
+        let serialized = serde_json::to_vec(score).context("Failed to serialize quality score")?;
+        tree.insert(score.saying_id.as_bytes(), serialized).context("Failed to insert quality score")?;
+        Ok(())
+    }
+
+    fn list_quality_scores(&self) -> Result<Vec<QualityScore>> {
+        let tree = self.db.open_tree(QUALITY_SCORES_TREE).context("Failed to open quality scores tree")?;
+        
+        tree.iter()
+            .map(|result| {
+                let (_, ivec) = result.context("Failed to iterate quality scores")?;
+                serde_json::from_slice(&ivec).context("Failed to deserialize quality score")
+            })
+            .collect()
+    }
+}
+
+// Prompt index for suggestions
+impl MemoryStorage {
+    fn record_prompt(&self, user_id: &str, prompt: &str, used_at: DateTime<Utc>) -> Result<()> {
+        let mut index = self.prompt_index.entry(user_id.to_string()).or_default();
+        let key = prompt_search_key(prompt);
+        let entry = PromptIndexEntry::record(index.remove(&key), prompt, used_at);
+        index.insert(key, entry);
+        Ok(())
+    }
+
+    fn find_prompts(&self, user_id: &str, prefix: &str) -> Result<Vec<PromptIndexEntry>> {
+        Ok(self.prompt_index
+            .get(user_id)
+            .map(|index| {
+                index.range(prefix.to_string()..)
+                    .take_while(|(key, _)| key.starts_with(prefix))
+                    .map(|(_, entry)| entry.clone())
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+}
+
+// Keys are `user_id NUL search key`, so a prefix scan finds a user's matching prompts
+fn prompt_index_key(user_id: &str, search_key: &str) -> Vec<u8> {
+    let mut key = Vec::with_capacity(user_id.len() + 1 + search_key.len());
+    key.extend_from_slice(user_id.as_bytes());
+    key.push(0);
+    key.extend_from_slice(search_key.as_bytes());
+    key
+}
+
+impl SledStorage {
+    fn record_prompt(&self, user_id: &str, prompt: &str, used_at: DateTime<Utc>) -> Result<()> {
+        let tree = self.db.open_tree(PROMPT_INDEX_TREE).context("Failed to open prompt index tree")?;
+        let key = prompt_index_key(user_id, &prompt_search_key(prompt));
+        
+        let entry = match tree.get(&key).context("Failed to read prompt index")? {
+            Some(ivec) => Some(serde_json::from_slice(&ivec).context("Failed to deserialize prompt index entry")?),
+            None => None,
+        };
+        let serialized = serde_json::to_vec(&PromptIndexEntry::record(entry, prompt, used_at))
+            .context("Failed to serialize prompt index entry")?;
+        tree.insert(key, serialized).context("Failed to insert prompt index entry")?;
+        Ok(())
+    }
+
+    fn find_prompts(&self, user_id: &str, prefix: &str) -> Result<Vec<PromptIndexEntry>> {
+        let tree = self.db.open_tree(PROMPT_INDEX_TREE).context("Failed to open prompt index tree")?;
+        
+        tree.scan_prefix(prompt_index_key(user_id, prefix))
+            .map(|result| {
+                let (_, ivec) = result.context("Failed to iterate prompt index")?;
+                serde_json::from_slice(&ivec).context("Failed to deserialize prompt index entry")
+            })
+            .collect()
+    }
+}
+
+// Submitted prompt history
+impl MemoryStorage {
+    fn push_prompt_history(&self, user_id: &str, entry: PromptHistoryEntry, keep: usize) -> Result<()> {
+        let mut history = self.prompt_history.entry(user_id.to_string()).or_default();
+        history.insert(0, entry);
+        history.truncate(keep);
+        Ok(())
+    }
+
+    fn get_prompt_history(&self, user_id: &str, limit: usize) -> Result<Vec<PromptHistoryEntry>> {
+        Ok(self.prompt_history
+            .get(user_id)
+            .map(|history| history.iter().take(limit).cloned().collect())
+            .unwrap_or_default())
+    }
+}
+
+impl SledStorage {
+    fn push_prompt_history(&self, user_id: &str, entry: PromptHistoryEntry, keep: usize) -> Result<()> {
+        let tree = self.db.open_tree(PROMPT_HISTORY_TREE).context("Failed to open prompt history tree")?;
+        
+        let mut history = self.get_prompt_history(user_id, usize::MAX)?;
+        history.insert(0, entry);
+        history.truncate(keep);
+        
+        let serialized = serde_json::to_vec(&history).context("Failed to serialize prompt history")?;
+        tree.insert(user_id.as_bytes(), serialized).context("Failed to insert prompt history")?;
+        Ok(())
+    }
+
+    fn get_prompt_history(&self, user_id: &str, limit: usize) -> Result<Vec<PromptHistoryEntry>> {
+        let tree = self.db.open_tree(PROMPT_HISTORY_TREE).context("Failed to open prompt history tree")?;
+        
+        let mut history: Vec<PromptHistoryEntry> = match tree.get(user_id.as_bytes()).context("Failed to read prompt history")? {
+            Some(ivec) => serde_json::from_slice(&ivec).context("Failed to deserialize prompt history")?,
+            None => Vec::new(),
+        };
+        history.truncate(limit);
+        Ok(history)
+    }
+}
+
+// Bans
+impl MemoryStorage {
+    fn save_ban(&self, ban: &Ban) -> Result<()> {
+        self.bans.insert(ban.subject.key(), ban.clone());
+        Ok(())
+    }
+
+    fn delete_ban(&self, key: &str) -> Result<()> {
+        self.bans.remove(key);
+        Ok(())
+    }
+
+    fn list_bans(&self) -> Result<Vec<Ban>> {
+        Ok(self.bans.iter().map(|entry| entry.value().clone()).collect())
+    }
+}
+
+impl SledStorage {
+    fn save_ban(&self, ban: &Ban) -> Result<()> {
+        let tree = self.db.open_tree(BANS_TREE).context("Failed to open bans tree")?;
+        
+        let serialized = serde_json::to_vec(ban).context("Failed to serialize ban")?;
+        tree.insert(ban.subject.key().as_bytes(), serialized).context("Failed to insert ban")?;
+        Ok(())
+    }
+
+    fn delete_ban(&self, key: &str) -> Result<()> {
+        let tree = self.db.open_tree(BANS_TREE).context("Failed to open bans tree")?;
+        
+        tree.remove(key.as_bytes()).context("Failed to remove ban")?;
+        Ok(())
+    }
+
+    fn list_bans(&self) -> Result<Vec<Ban>> {
+        let tree = self.db.open_tree(BANS_TREE).context("Failed to open bans tree")?;
+        
+        tree.iter()
+            .map(|result| {
+                let (_, ivec) = result.context("Failed to iterate bans")?;
+                serde_json::from_slice(&ivec).context("Failed to deserialize ban")
+            })
+            .collect()
+    }
+}
+
+// Rate-limit exemptions
+impl MemoryStorage {
+    fn save_exemption(&self, exemption: &Exemption) -> Result<()> {
+        self.exemptions.insert(exemption.subject.key(), exemption.clone());
+        Ok(())
+    }
+
+    fn delete_exemption(&self, key: &str) -> Result<()> {
+        self.exemptions.remove(key);
+        Ok(())
+    }
+
+    fn list_exemptions(&self) -> Result<Vec<Exemption>> {
+        Ok(self.exemptions.iter().map(|entry| entry.value().clone()).collect())
+    }
+}
+
+impl SledStorage {
+    fn save_exemption(&self, exemption: &Exemption) -> Result<()> {
+        let tree = self.db.open_tree(EXEMPTIONS_TREE).context("Failed to open exemptions tree")?;
+        
+        let serialized = serde_json::to_vec(exemption).context("Failed to serialize exemption")?;
+        tree.insert(exemption.subject.key().as_bytes(), serialized).context("Failed to insert exemption")?;
+        Ok(())
+    }
+
+    fn delete_exemption(&self, key: &str) -> Result<()> {
+        let tree = self.db.open_tree(EXEMPTIONS_TREE).context("Failed to open exemptions tree")?;
+        
+        tree.remove(key.as_bytes()).context("Failed to remove exemption")?;
+        Ok(())
+    }
+
+    fn list_exemptions(&self) -> Result<Vec<Exemption>> {
+        let tree = self.db.open_tree(EXEMPTIONS_TREE).context("Failed to open exemptions tree")?;
+        
+        tree.iter()
+            .map(|result| {
+                let (_, ivec) = result.context("Failed to iterate exemptions")?;
+                serde_json::from_slice(&ivec).context("Failed to deserialize exemption")
+            })
+            .collect()
+    }
+}
+
+// Feature flag overrides
+impl MemoryStorage {
+    fn save_flag_override(&self, flag: &FlagOverride) -> Result<()> {
+        self.flag_overrides.insert(flag.name.clone(), flag.clone());
+        Ok(())
+    }
+
+    fn delete_flag_override(&self, name: &str) -> Result<()> {
+        self.flag_overrides.remove(name);
+        Ok(())
+    }
+
+    fn list_flag_overrides(&self) -> Result<Vec<FlagOverride>> {
+        Ok(self.flag_overrides.iter().map(|entry| entry.value().clone()).collect())
+    }
+}
+
+impl SledStorage {
+    fn save_flag_override(&self, flag: &FlagOverride) -> Result<()> {
+        let tree = self.db.open_tree(FEATURE_FLAGS_TREE).context("Failed to open feature flags tree")?;
+        
+        let serialized = serde_json::to_vec(flag).context("Failed to serialize flag override")?;
+        tree.insert(flag.name.as_bytes(), serialized).context("Failed to insert flag override")?;
+        Ok(())
+    }
+
+    fn delete_flag_override(&self, name: &str) -> Result<()> {
+        let tree = self.db.open_tree(FEATURE_FLAGS_TREE).context("Failed to open feature flags tree")?;
+        
+        tree.remove(name.as_bytes()).context("Failed to remove flag override")?;
+        Ok(())
+    }
+
+    fn list_flag_overrides(&self) -> Result<Vec<FlagOverride>> {
+        let tree = self.db.open_tree(FEATURE_FLAGS_TREE).context("Failed to open feature flags tree")?;
+        
+        tree.iter()
+            .map(|result| {
+                let (_, ivec) = result.context("Failed to iterate flag overrides")?;
+                serde_json::from_slice(&ivec).context("Failed to deserialize flag override")
+            })
+            .collect()
+    }
+}
+
+// Imported presets
+impl MemoryStorage {
+    fn save_imported_preset(&self, preset: &Preset) -> Result<()> {
+        self.imported_presets.insert(preset.id.clone(), preset.clone());
+        Ok(())
+    }
+
+    fn list_imported_presets(&self) -> Result<Vec<Preset>> {
+        let mut presets: Vec<Preset> = self.imported_presets.iter().map(|entry| entry.value().clone()).collect();
+        presets.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(presets)
+    }
+}
+
+impl SledStorage {
+    fn save_imported_preset(&self, preset: &Preset) -> Result<()> {
+        let tree = self.db.open_tree(IMPORTED_PRESETS_TREE).context("Failed to open imported presets tree")?;
+        
+        let serialized = serde_json::to_vec(preset).context("Failed to serialize imported preset")?;
+        tree.insert(preset.id.as_bytes(), serialized).context("Failed to insert imported preset")?;
+        Ok(())
+    }
+
+    // In ID order, as sled keeps them
+    fn list_imported_presets(&self) -> Result<Vec<Preset>> {
+        let tree = self.db.open_tree(IMPORTED_PRESETS_TREE).context("Failed to open imported presets tree")?;
+        
+        tree.iter()
+            .map(|result| {
+                let (_, ivec) = result.context("Failed to iterate imported presets")?;
+                serde_json::from_slice(&ivec).context("Failed to deserialize imported preset")
+            })
+            .collect()
+    }
+}
+
+// Feedback and preset usage statistics
+impl MemoryStorage {
+    fn record_preset_use(&self, preset_id: &str, at: DateTime<Utc>) -> Result<()> {
+        self.preset_stats
+            .entry(preset_id.to_string())
+            .or_insert_with(|| PresetStats::new(preset_id))
+            .record_use(at);
+        Ok(())
+    }
+
+    fn save_feedback(&self, feedback: &SayingFeedback) -> Result<()> {
+        let previous = self.feedback.insert(feedback.saying_id.clone(), feedback.clone());
+        
+        if let Some(preset_id) = &feedback.preset_id {
+            self.preset_stats
+                .entry(preset_id.clone())
+                .or_insert_with(|| PresetStats::new(preset_id))
+                .record_feedback(feedback.score, previous.map(|previous| previous.score));
+        }
+        Ok(())
+    }
+
+    fn list_feedback(&self) -> Result<Vec<SayingFeedback>> {
+        Ok(self.feedback.iter().map(|entry| entry.value().clone()).collect())
+    }
+
+    fn list_preset_stats(&self) -> Result<Vec<PresetStats>> {
+        Ok(self.preset_stats.iter().map(|entry| entry.value().clone()).collect())
+    }
+}
+
+impl SledStorage {
+    fn update_preset_stats(&self, preset_id: &str, update: impl Fn(&mut PresetStats)) -> Result<()> {
+        let tree = self.db.open_tree(PRESET_STATS_TREE).context("Failed to open preset stats tree")?;
+        
+        // update_and_fetch retries the closure on contention, so concurrent
+        // requests can't overwrite each other's increments
+        let mut failure = None;
+        tree.update_and_fetch(preset_id.as_bytes(), |current| {
+            failure = None;
+            let mut stats = match current.map(serde_json::from_slice::<PresetStats>).transpose() {
+                Ok(stats) => stats.unwrap_or_else(|| PresetStats::new(preset_id)),
+                Err(e) => {
+                    failure = Some(e);
+                    return current.map(<[u8]>::to_vec);
                 }
-            }
+            };
+            update(&mut stats);
             
-            if all_cached_sayings.len() >= limit {
-                break;
+            match serde_json::to_vec(&stats) {
+                Ok(serialized) => Some(serialized),
+                Err(e) => {
+                    failure = Some(e);
+                    current.map(<[u8]>::to_vec)
+                }
             }
+        }).context("Failed to update preset stats")?;
+        
+        match failure {
+            Some(e) => Err(e).context("Failed to (de)serialize preset stats"),
+            None => Ok(()),
         }
+    }
+
+    fn record_preset_use(&self, preset_id: &str, at: DateTime<Utc>) -> Result<()> {
+        self.update_preset_stats(preset_id, |stats| stats.record_use(at))
+    }
+
+    fn save_feedback(&self, feedback: &SayingFeedback) -> Result<()> {
+        let tree = self.db.open_tree(FEEDBACK_TREE).context("Failed to open feedback tree")?;
         
-        // If we still don't have enough, include LLM sources as a fallback
-        if all_cached_sayings.len() < limit {
-            for result in self.db.iter() {
-                let (key, ivec) = result.context("Failed to iterate Sled database")?;
-                
-                // Skip the global cache tree and other internal trees
-                if key.starts_with(b"__") {
-                    continue;
-                }
-                
-                // Deserialize the sayings
-                let sayings: Vec<Saying> = serde_json::from_slice(&ivec)
-                    .context("Failed to deserialize sayings from Sled")?;
-                
-                for saying in &sayings {
-                    if matches!(saying.source, SayingSource::LLM) {
-                        // Create a cache key to track duplicates
-                        let cache_key = CacheKey::from_saying(saying);
-                        
-                        if !seen_keys.contains(&cache_key) {
-                            seen_keys.insert(cache_key);
-                            all_cached_sayings.push(saying.clone());
-                            
-                            if all_cached_sayings.len() >= limit {
-                                break;
-                            }
-                        }
-                    }
-                }
-                
-                if all_cached_sayings.len() >= limit {
-                    break;
-                }
+        let serialized = serde_json::to_vec(feedback).context("Failed to serialize feedback")?;
+        let previous = tree.insert(feedback.saying_id.as_bytes(), serialized).context("Failed to insert feedback")?
+            .map(|ivec| serde_json::from_slice::<SayingFeedback>(&ivec))
+            .transpose()
+            .context("Failed to deserialize feedback")?;
+        
+        if let Some(preset_id) = &feedback.preset_id {
+            self.update_preset_stats(preset_id, |stats| {
+                stats.record_feedback(feedback.score, previous.as_ref().map(|previous| previous.score))
+            })?;
+        }
+        Ok(())
+    }
+
+    fn list_feedback(&self) -> Result<Vec<SayingFeedback>> {
+        let tree = self.db.open_tree(FEEDBACK_TREE).context("Failed to open feedback tree")?;
+        
+        tree.iter()
+            .map(|result| {
+                let (_, ivec) = result.context("Failed to iterate feedback")?;
+                serde_json::from_slice(&ivec).context("Failed to deserialize feedback")
+            })
+            .collect()
+    }
+
+    fn list_preset_stats(&self) -> Result<Vec<PresetStats>> {
+        let tree = self.db.open_tree(PRESET_STATS_TREE).context("Failed to open preset stats tree")?;
+        
+        tree.iter()
+            .map(|result| {
+                let (_, ivec) = result.context("Failed to iterate preset stats")?;
+                serde_json::from_slice(&ivec).context("Failed to deserialize preset stats")
+            })
+            .collect()
+    }
+}
+
+// Recent preset selections
+impl MemoryStorage {
+    fn get_recent_presets(&self, user_id: &str) -> Result<Vec<String>> {
+        Ok(self.recent_presets.get(user_id).map(|recent| recent.clone()).unwrap_or_default())
+    }
+
+    fn push_recent_preset(&self, user_id: &str, preset_id: &str, keep: usize) -> Result<()> {
+        let mut recent = self.recent_presets.entry(user_id.to_string()).or_default();
+        recent.insert(0, preset_id.to_string());
+        recent.truncate(keep);
+        Ok(())
+    }
+}
+
+impl SledStorage {
+    fn get_recent_presets(&self, user_id: &str) -> Result<Vec<String>> {
+        let tree = self.db.open_tree(RECENT_PRESETS_TREE).context("Failed to open recent presets tree")?;
+        
+        match tree.get(user_id.as_bytes()).context("Failed to read recent presets")? {
+            Some(ivec) => serde_json::from_slice(&ivec).context("Failed to deserialize recent presets"),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    fn push_recent_preset(&self, user_id: &str, preset_id: &str, keep: usize) -> Result<()> {
+        let tree = self.db.open_tree(RECENT_PRESETS_TREE).context("Failed to open recent presets tree")?;
+        
+        let mut recent = self.get_recent_presets(user_id)?;
+        recent.insert(0, preset_id.to_string());
+        recent.truncate(keep);
+        
+        let serialized = serde_json::to_vec(&recent).context("Failed to serialize recent presets")?;
+        tree.insert(user_id.as_bytes(), serialized).context("Failed to insert recent presets")?;
+        Ok(())
+    }
+}
+
+// Preset selections
+impl MemoryStorage {
+    fn get_preset_selection(&self, user_id: &str) -> Result<Option<PresetSelectionRecord>> {
+        Ok(self.preset_selections.get(user_id).map(|selection| selection.clone()))
+    }
+
+    fn save_preset_selection(&self, user_id: &str, selection: &PresetSelectionRecord) -> Result<()> {
+        self.preset_selections.insert(user_id.to_string(), selection.clone());
+        Ok(())
+    }
+}
+
+impl SledStorage {
+    fn get_preset_selection(&self, user_id: &str) -> Result<Option<PresetSelectionRecord>> {
+        let tree = self.db.open_tree(PRESET_SELECTIONS_TREE).context("Failed to open preset selections tree")?;
+        
+        match tree.get(user_id.as_bytes()).context("Failed to read preset selection")? {
+            Some(ivec) => Ok(Some(serde_json::from_slice(&ivec).context("Failed to deserialize preset selection")?)),
+            None => Ok(None),
+        }
+    }
+
+    fn save_preset_selection(&self, user_id: &str, selection: &PresetSelectionRecord) -> Result<()> {
+        let tree = self.db.open_tree(PRESET_SELECTIONS_TREE).context("Failed to open preset selections tree")?;
+        
+        let serialized = serde_json::to_vec(selection).context("Failed to serialize preset selection")?;
+        tree.insert(user_id.as_bytes(), serialized).context("Failed to insert preset selection")?;
+        Ok(())
+    }
+}
+
+// History owners
+impl MemoryStorage {
+    fn get_user_owner(&self, user_id: &str) -> Result<Option<String>> {
+        Ok(self.user_owners.get(user_id).map(|owner| owner.clone()))
+    }
+
+    fn claim_user_owner(&self, user_id: &str, token: &str) -> Result<String> {
+        Ok(self.user_owners.entry(user_id.to_string()).or_insert_with(|| token.to_string()).clone())
+    }
+
+    fn set_user_owner(&self, user_id: &str, token: &str) -> Result<()> {
+        self.user_owners.insert(user_id.to_string(), token.to_string());
+        Ok(())
+    }
+}
+
+impl SledStorage {
+    fn get_user_owner(&self, user_id: &str) -> Result<Option<String>> {
+        let tree = self.db.open_tree(USER_OWNERS_TREE).context("Failed to open user owners tree")?;
+        
+        match tree.get(user_id.as_bytes()).context("Failed to read user owner")? {
+            Some(ivec) => Ok(Some(String::from_utf8_lossy(&ivec).into_owned())),
+            None => Ok(None),
+        }
+    }
+
+    fn claim_user_owner(&self, user_id: &str, token: &str) -> Result<String> {
+        let tree = self.db.open_tree(USER_OWNERS_TREE).context("Failed to open user owners tree")?;
+        
+        // Compare-and-swap so two first requests can't both become the owner
+        match tree.compare_and_swap(user_id.as_bytes(), None as Option<&[u8]>, Some(token.as_bytes()))
+            .context("Failed to claim user owner")?
+        {
+            Ok(()) => Ok(token.to_string()),
+            Err(existing) => Ok(existing.current
+                .map(|ivec| String::from_utf8_lossy(&ivec).into_owned())
+                .unwrap_or_default()),
+        }
+    }
+
+    fn set_user_owner(&self, user_id: &str, token: &str) -> Result<()> {
+        let tree = self.db.open_tree(USER_OWNERS_TREE).context("Failed to open user owners tree")?;
+        
+        tree.insert(user_id.as_bytes(), token.as_bytes()).context("Failed to set user owner")?;
+        Ok(())
+    }
+}
+
+// Translations, keyed by saying and language
+fn translation_key(saying_id: &str, language_id: &str) -> String {
+    format!("{}:{}", saying_id, language_id)
+}
+
+impl MemoryStorage {
+    fn get_translation(&self, saying_id: &str, language_id: &str) -> Result<Option<SayingTranslation>> {
+        Ok(self.translations.get(&translation_key(saying_id, language_id)).map(|translation| translation.clone()))
+    }
+
+    fn save_translation(&self, translation: &SayingTranslation) -> Result<()> {
+        self.translations.insert(translation_key(&translation.saying_id, &translation.language_id), translation.clone());
+        Ok(())
+    }
+}
+
+impl SledStorage {
+    fn get_translation(&self, saying_id: &str, language_id: &str) -> Result<Option<SayingTranslation>> {
+        let tree = self.db.open_tree(TRANSLATIONS_TREE).context("Failed to open translations tree")?;
+        
+        match tree.get(translation_key(saying_id, language_id).as_bytes()).context("Failed to read translation")? {
+            Some(ivec) => Ok(Some(serde_json::from_slice(&ivec).context("Failed to deserialize translation")?)),
+            None => Ok(None),
+        }
+    }
+
+    fn save_translation(&self, translation: &SayingTranslation) -> Result<()> {
+        let tree = self.db.open_tree(TRANSLATIONS_TREE).context("Failed to open translations tree")?;
+        
+        let serialized = serde_json::to_vec(translation).context("Failed to serialize translation")?;
+        tree.insert(translation_key(&translation.saying_id, &translation.language_id).as_bytes(), serialized)
+            .context("Failed to insert translation")?;
+        Ok(())
+    }
+}
+
+// Daily sayings, keyed by preset and language
+fn daily_key(preset_id: &str, language_id: &str) -> String {
+    format!("{}:{}", preset_id, language_id)
+}
+
+impl MemoryStorage {
+    fn get_daily_saying(&self, preset_id: &str, language_id: &str) -> Result<Option<DailySaying>> {
+        Ok(self.daily_sayings.get(&daily_key(preset_id, language_id)).map(|daily| daily.clone()))
+    }
+
+    fn save_daily_saying(&self, daily: &DailySaying) -> Result<()> {
+        self.daily_sayings.insert(daily_key(&daily.preset_id, &daily.language_id), daily.clone());
+        Ok(())
+    }
+}
+
+impl SledStorage {
+    fn get_daily_saying(&self, preset_id: &str, language_id: &str) -> Result<Option<DailySaying>> {
+        let tree = self.db.open_tree(DAILY_SAYINGS_TREE).context("Failed to open daily sayings tree")?;
+        
+        match tree.get(daily_key(preset_id, language_id).as_bytes()).context("Failed to read daily saying")? {
+            Some(ivec) => Ok(Some(serde_json::from_slice(&ivec).context("Failed to deserialize daily saying")?)),
+            None => Ok(None),
+        }
+    }
+
+    fn save_daily_saying(&self, daily: &DailySaying) -> Result<()> {
+        let tree = self.db.open_tree(DAILY_SAYINGS_TREE).context("Failed to open daily sayings tree")?;
+        
+        let serialized = serde_json::to_vec(daily).context("Failed to serialize daily saying")?;
+        tree.insert(daily_key(&daily.preset_id, &daily.language_id).as_bytes(), serialized)
+            .context("Failed to insert daily saying")?;
+        Ok(())
+    }
+}
+
+// Saying shares and their cards
+impl MemoryStorage {
+    fn get_saying_share(&self, saying_id: &str) -> Result<Option<SayingShare>> {
+        Ok(self.saying_shares.get(saying_id).map(|share| share.clone()))
+    }
+
+    fn save_saying_share(&self, share: &SayingShare) -> Result<()> {
+        self.saying_shares.insert(share.saying_id.clone(), share.clone());
+        Ok(())
+    }
+
+    fn find_saying_share_by_token(&self, token: &str) -> Result<Option<SayingShare>> {
+        Ok(self.saying_shares
+            .iter()
+            .find(|share| share.token == token)
+            .map(|share| share.clone()))
+    }
+
+    fn get_share_card(&self, token: &str) -> Result<Option<Vec<u8>>> {
+        Ok(self.share_cards.get(token).map(|png| png.clone()))
+    }
+
+    fn save_share_card(&self, token: &str, png: &[u8]) -> Result<()> {
+        self.share_cards.insert(token.to_string(), png.to_vec());
+        Ok(())
+    }
+}
+
+impl SledStorage {
+    fn get_saying_share(&self, saying_id: &str) -> Result<Option<SayingShare>> {
+        let tree = self.db.open_tree(SAYING_SHARES_TREE).context("Failed to open saying shares tree")?;
+        
+        match tree.get(saying_id.as_bytes()).context("Failed to read saying share")? {
+            Some(ivec) => Ok(Some(serde_json::from_slice(&ivec).context("Failed to deserialize saying share")?)),
+            None => Ok(None),
+        }
+    }
+
+    fn save_saying_share(&self, share: &SayingShare) -> Result<()> {
+        let tree = self.db.open_tree(SAYING_SHARES_TREE).context("Failed to open saying shares tree")?;
+        
+        let serialized = serde_json::to_vec(share).context("Failed to serialize saying share")?;
+        tree.insert(share.saying_id.as_bytes(), serialized).context("Failed to insert saying share")?;
+        Ok(())
+    }
+
+    fn find_saying_share_by_token(&self, token: &str) -> Result<Option<SayingShare>> {
+        let tree = self.db.open_tree(SAYING_SHARES_TREE).context("Failed to open saying shares tree")?;
+        
+        for item in tree.iter() {
+            let (_, value) = item.context("Failed to read saying share")?;
+            let share: SayingShare = serde_json::from_slice(&value).context("Failed to deserialize saying share")?;
+            if share.token == token {
+                return Ok(Some(share));
+            }
+        }
+        Ok(None)
+    }
+
+    fn get_share_card(&self, token: &str) -> Result<Option<Vec<u8>>> {
+        let tree = self.db.open_tree(SHARE_CARDS_TREE).context("Failed to open share cards tree")?;
+        
+        Ok(tree.get(token.as_bytes()).context("Failed to read share card")?.map(|ivec| ivec.to_vec()))
+    }
+
+    fn save_share_card(&self, token: &str, png: &[u8]) -> Result<()> {
+        let tree = self.db.open_tree(SHARE_CARDS_TREE).context("Failed to open share cards tree")?;
+        
+        tree.insert(token.as_bytes(), png).context("Failed to insert share card")?;
+        Ok(())
+    }
+}
+
+// User statistics
+fn tokens_since(sayings: &[Arc<Saying>], since: DateTime<Utc>) -> u64 {
+    // Newest first, so stop at the first saying from before the cutoff
+    sayings.iter()
+        .take_while(|saying| saying.created_at >= since)
+        .filter_map(|saying| saying.usage.as_ref())
+        .map(|usage| u64::from(usage.total_tokens.unwrap_or(0)))
+        .sum()
+}
+
+impl MemoryStorage {
+    fn get_user_stats(&self, user_id: &str) -> Result<UserStats> {
+        Ok(self.user_stats
+            .get(user_id)
+            .map(|stats| stats.clone())
+            .unwrap_or_else(|| UserStats::new(user_id)))
+    }
+
+    fn tokens_used_since(&self, user_id: &str, since: DateTime<Utc>) -> Result<u64> {
+        Ok(self.sayings
+            .get(user_id)
+            .map(|user_sayings| tokens_since(&user_sayings, since))
+            .unwrap_or(0))
+    }
+}
+
+impl SledStorage {
+    fn get_user_stats(&self, user_id: &str) -> Result<UserStats> {
+        let tree = self.db.open_tree(USER_STATS_TREE).context("Failed to open user stats tree")?;
+        
+        match tree.get(user_id.as_bytes()).context("Failed to read user stats")? {
+            Some(ivec) => serde_json::from_slice(&ivec).context("Failed to deserialize user stats"),
+            // Not written yet for users whose history predates the aggregate
+            None => {
+                let sayings = self.get_sayings(user_id, usize::MAX)?;
+                Ok(UserStats::from_sayings(user_id, sayings.iter().map(|s| s.as_ref())))
+            }
+        }
+    }
+
+    fn tokens_used_since(&self, user_id: &str, since: DateTime<Utc>) -> Result<u64> {
+        Ok(tokens_since(&self.get_sayings(user_id, usize::MAX)?, since))
+    }
+}
+
+// Admin audit log
+impl MemoryStorage {
+    fn append_audit_entry(&self, entry: &AuditEntry) -> Result<()> {
+        match self.audit_log.entry(entry.seq) {
+            dashmap::mapref::entry::Entry::Occupied(_) => Err(anyhow!("Audit log entry {} already exists", entry.seq)),
+            dashmap::mapref::entry::Entry::Vacant(vacant) => {
+                vacant.insert(entry.clone());
+                Ok(())
+            }
+        }
+    }
+
+    fn last_audit_entry(&self) -> Result<Option<AuditEntry>> {
+        Ok(self.audit_log
+            .iter()
+            .max_by_key(|entry| *entry.key())
+            .map(|entry| entry.value().clone()))
+    }
+
+    fn get_audit_entries(&self) -> Result<Vec<AuditEntry>> {
+        let mut entries: Vec<AuditEntry> = self.audit_log
+            .iter()
+            .map(|entry| entry.value().clone())
+            .collect();
+        entries.sort_by_key(|entry| entry.seq);
+        Ok(entries)
+    }
+}
+
+impl SledStorage {
+    fn append_audit_entry(&self, entry: &AuditEntry) -> Result<()> {
+        let tree = self.db.open_tree(AUDIT_LOG_TREE).context("Failed to open audit log tree")?;
+        let serialized = serde_json::to_vec(entry).context("Failed to serialize audit log entry")?;
+        
+        // Big-endian keys sort in seq order; compare-and-swap against nothing so an
+        // existing entry is never replaced
+        tree.compare_and_swap(entry.seq.to_be_bytes(), None::<&[u8]>, Some(serialized.as_slice()))
+            .context("Failed to write audit log entry")?
+            .map_err(|_| anyhow!("Audit log entry {} already exists", entry.seq))?;
+        Ok(())
+    }
+
+    fn last_audit_entry(&self) -> Result<Option<AuditEntry>> {
+        let tree = self.db.open_tree(AUDIT_LOG_TREE).context("Failed to open audit log tree")?;
+        tree.last()
+            .context("Failed to read audit log")?
+            .map(|(_, ivec)| serde_json::from_slice(&ivec).context("Failed to deserialize audit log entry"))
+            .transpose()
+    }
+
+    fn get_audit_entries(&self) -> Result<Vec<AuditEntry>> {
+        let tree = self.db.open_tree(AUDIT_LOG_TREE).context("Failed to open audit log tree")?;
+        tree.iter()
+            .map(|result| {
+                let (_, ivec) = result.context("Failed to iterate audit log")?;
+                serde_json::from_slice(&ivec).context("Failed to deserialize audit log entry")
+            })
+            .collect()
+    }
+}
+
+// Trees keyed on a user ID, alone or followed by a NUL and the rest of the key
+const USER_KEYED_TREES: &[&str] = &[
+    SAYING_TAGS_TREE, PROMPT_INDEX_TREE, PROMPT_HISTORY_TREE, ACHIEVEMENTS_TREE, PREFERENCES_TREE, USER_OWNERS_TREE,
+    RECENT_PRESETS_TREE, PRESET_SELECTIONS_TREE, USER_STATS_TREE, CONVERSATIONS_TREE,
+];
+
+// Trees whose records carry a `user_id` field
+const USER_FIELD_TREES: &[&str] = &[
+    COLLECTIONS_TREE, CONVERSATIONS_TREE, FEEDBACK_TREE, SAYING_SHARES_TREE, USER_STATS_TREE, FREEFORM_LOG_TREE,
+];
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct UserIdHashReport {
+    pub records_changed: usize,
+    // Records left under their plaintext ID because the hashed ID already holds something else
+    pub conflicts: usize,
+}
+
+// Move what a Sled database holds under plaintext user IDs to their salted hashes, for
+// `--migrate --hash-user-ids`. Hashed records are skipped, so an interrupted run can be repeated.
+pub fn hash_user_ids(db: &sled::Db, hasher: &UserIdHasher, dry_run: bool) -> Result<UserIdHashReport> {
+    let mut report = UserIdHashReport::default();
+
+    // Histories live in the default tree
+    rekey_user_records(db, hasher, dry_run, &mut report)?;
+    for name in USER_KEYED_TREES {
+        let tree = db.open_tree(name).with_context(|| format!("Failed to open {} tree", name))?;
+        rekey_user_records(&tree, hasher, dry_run, &mut report)?;
+    }
+    for name in USER_FIELD_TREES {
+        let tree = db.open_tree(name).with_context(|| format!("Failed to open {} tree", name))?;
+        hash_user_id_fields(&tree, hasher, dry_run, &mut report)?;
+    }
+    for name in [BANS_TREE, EXEMPTIONS_TREE] {
+        let tree = db.open_tree(name).with_context(|| format!("Failed to open {} tree", name))?;
+        rekey_user_subjects(&tree, hasher, dry_run, &mut report)?;
+    }
+    let flags_tree = db.open_tree(FEATURE_FLAGS_TREE).context("Failed to open feature flags tree")?;
+    hash_flag_users(&flags_tree, hasher, dry_run, &mut report)?;
+
+    // The ID and unsubscribe token indexes point at user keys
+    for name in [SAYING_INDEX_TREE, UNSUBSCRIBE_TOKENS_TREE] {
+        let index_tree = db.open_tree(name).with_context(|| format!("Failed to open {} tree", name))?;
+        for result in index_tree.iter() {
+            let (indexed, user_id) = result.with_context(|| format!("Failed to iterate {} tree", name))?;
+            let user_id = String::from_utf8_lossy(&user_id);
+            if user_hash::is_hashed(&user_id) {
+                continue;
+            }
+            report.records_changed += 1;
+            if !dry_run {
+                index_tree.insert(indexed, hasher.hash(&user_id).as_bytes())
+                    .with_context(|| format!("Failed to update {} tree", name))?;
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+fn rekey_user_records(tree: &sled::Tree, hasher: &UserIdHasher, dry_run: bool, report: &mut UserIdHashReport) -> Result<()> {
+    for result in tree.iter() {
+        let (key, value) = result.context("Failed to iterate user records")?;
+        // Internal keys, not users
+        if key.starts_with(b"__") {
+            continue;
+        }
+
+        let split = key.iter().position(|&byte| byte == 0).unwrap_or(key.len());
+        let user_id = String::from_utf8_lossy(&key[..split]);
+        if user_hash::is_hashed(&user_id) {
+            continue;
+        }
+        let mut hashed_key = hasher.hash(&user_id).into_bytes();
+        hashed_key.extend_from_slice(&key[split..]);
+
+        match tree.get(&hashed_key).context("Failed to read user record")? {
+            // The user came back after hashing was turned on; merging is left to the operator
+            Some(existing) if existing != value => {
+                tracing::warn!("Not moving a record of user {}: its hashed key already holds another one", user_id);
+                report.conflicts += 1;
+                continue;
+            }
+            // Copied by an earlier run that stopped before removing the original
+            Some(_) => {}
+            None if dry_run => {}
+            None => {
+                tree.insert(&hashed_key, value).context("Failed to write user record")?;
+            }
+        }
+        report.records_changed += 1;
+        if !dry_run {
+            tree.remove(&key).context("Failed to remove user record")?;
+        }
+    }
+    Ok(())
+}
+
+fn hash_user_id_fields(tree: &sled::Tree, hasher: &UserIdHasher, dry_run: bool, report: &mut UserIdHashReport) -> Result<()> {
+    for result in tree.iter() {
+        let (key, value) = result.context("Failed to iterate user records")?;
+        let mut record: serde_json::Value = serde_json::from_slice(&value).context("Failed to parse user record")?;
+        let hashed = match record.get("user_id").and_then(serde_json::Value::as_str) {
+            Some(user_id) if !user_hash::is_hashed(user_id) => hasher.hash(user_id),
+            _ => continue,
+        };
+
+        record["user_id"] = serde_json::Value::String(hashed);
+        report.records_changed += 1;
+        if !dry_run {
+            tree.insert(key, serde_json::to_vec(&record)?).context("Failed to update user record")?;
+        }
+    }
+    Ok(())
+}
+
+// Bans and exemptions of users, keyed `user:<id>` with the ID in the record's subject too
+fn rekey_user_subjects(tree: &sled::Tree, hasher: &UserIdHasher, dry_run: bool, report: &mut UserIdHashReport) -> Result<()> {
+    for result in tree.iter() {
+        let (key, value) = result.context("Failed to iterate user subjects")?;
+        let Some(user_id) = key.strip_prefix(b"user:") else {
+            continue;
+        };
+        let user_id = String::from_utf8_lossy(user_id);
+        if user_hash::is_hashed(&user_id) {
+            continue;
+        }
+
+        let hashed = hasher.hash(&user_id);
+        let mut record: serde_json::Value = serde_json::from_slice(&value).context("Failed to parse user subject")?;
+        record["subject"]["user"] = serde_json::Value::String(hashed.clone());
+        let record = serde_json::to_vec(&record)?;
+        let hashed_key = format!("user:{}", hashed);
+
+        match tree.get(&hashed_key).context("Failed to read user subject")? {
+            // Added again under the hash after hashing was turned on
+            Some(existing) if existing != record => {
+                tracing::warn!("Not moving the entry for user {}: its hashed key already holds another one", user_id);
+                report.conflicts += 1;
+                continue;
+            }
+            Some(_) => {}
+            None if dry_run => {}
+            None => {
+                tree.insert(hashed_key.as_bytes(), record).context("Failed to write user subject")?;
             }
         }
-        
-        // Sort by date (newest first)
-        all_cached_sayings.sort_by(|a, b| b.created_at.cmp(&a.created_at));
-        
-        Ok(all_cached_sayings)
+        report.records_changed += 1;
+        if !dry_run {
+            tree.remove(&key).context("Failed to remove user subject")?;
+        }
+    }
+    Ok(())
+}
+
+// Users named in feature flag overrides
+fn hash_flag_users(tree: &sled::Tree, hasher: &UserIdHasher, dry_run: bool, report: &mut UserIdHashReport) -> Result<()> {
+    for result in tree.iter() {
+        let (key, value) = result.context("Failed to iterate flag overrides")?;
+        let mut flag: FlagOverride = serde_json::from_slice(&value).context("Failed to parse flag override")?;
+        if flag.rule.users.iter().all(|user_id| user_hash::is_hashed(user_id)) {
+            continue;
+        }
+
+        flag.rule.users = flag.rule.users.iter().map(|user_id| hasher.hash(user_id)).collect();
+        report.records_changed += 1;
+        if !dry_run {
+            tree.insert(key, serde_json::to_vec(&flag)?).context("Failed to update flag override")?;
+        }
     }
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::path::PathBuf;
+    use crate::models::{ConversationMessage, MessageRole, OpenRouterUsage};
+    use proptest::prelude::*;
     use tempfile::tempdir;
-    use uuid::Uuid;
 
     #[test]
     fn test_memory_storage_find_cached_saying() {
@@ -485,29 +2992,23 @@ mod tests {
         let preset_id = Some("test_preset".to_string());
         
         let llm_saying = Saying {
-            id: Uuid::new_v4().to_string(),
-            content: "LLM generated content".to_string(),
-            prompt: prompt.to_string(),
-            created_at: Utc::now(),
-            source: SayingSource::LLM,
             preset_id: preset_id.clone(),
+            ..Saying::new("LLM generated content".to_string(), prompt.to_string(), SayingSource::LLM)
         };
         
         let cached_saying = Saying {
-            id: Uuid::new_v4().to_string(),
-            content: "Cached content".to_string(),
-            prompt: prompt.to_string(),
-            created_at: Utc::now(),
-            source: SayingSource::Cache,
             preset_id: preset_id.clone(),
+            prompt_hash: Some(crate::models::prompt_hash("system", prompt, "en", "model")),
+            ..Saying::new("Cached content".to_string(), prompt.to_string(), SayingSource::Cache)
         };
         
         // Save sayings
-        storage.save_saying(user_id, llm_saying.clone()).unwrap();
-        storage.save_saying(user_id, cached_saying.clone()).unwrap();
+        storage.save_saying(user_id, Arc::new(llm_saying.clone())).unwrap();
+        storage.save_saying(user_id, Arc::new(cached_saying.clone())).unwrap();
         
         // Test finding cached saying
-        let result = storage.find_cached_saying(prompt, preset_id.as_deref()).unwrap();
+        let key = |system_prompt: &str, prompt: &str| CacheKey::new(preset_id.clone(), system_prompt, prompt.to_string(), "en", "model");
+        let result = storage.find_cached_saying(&key("system", prompt)).unwrap();
         
         // Should find cached_saying, not llm_saying
         assert!(result.is_some());
@@ -516,8 +3017,11 @@ mod tests {
         assert!(matches!(found.source, SayingSource::Cache));
         
         // Test with non-existent prompt
-        let no_result = storage.find_cached_saying("nonexistent", preset_id.as_deref()).unwrap();
+        let no_result = storage.find_cached_saying(&key("system", "nonexistent")).unwrap();
         assert!(no_result.is_none());
+        
+        // Another preset's system prompt with the same user prompt is a different entry
+        assert!(storage.find_cached_saying(&key("other system", prompt)).unwrap().is_none());
     }
 
     #[test]
@@ -535,29 +3039,23 @@ mod tests {
         let preset_id = Some("test_preset".to_string());
         
         let llm_saying = Saying {
-            id: Uuid::new_v4().to_string(),
-            content: "LLM generated content".to_string(),
-            prompt: prompt.to_string(),
-            created_at: Utc::now(),
-            source: SayingSource::LLM,
             preset_id: preset_id.clone(),
+            ..Saying::new("LLM generated content".to_string(), prompt.to_string(), SayingSource::LLM)
         };
         
         let cached_saying = Saying {
-            id: Uuid::new_v4().to_string(),
-            content: "Cached content".to_string(),
-            prompt: prompt.to_string(),
-            created_at: Utc::now(),
-            source: SayingSource::Cache,
             preset_id: preset_id.clone(),
+            prompt_hash: Some(crate::models::prompt_hash("system", prompt, "en", "model")),
+            ..Saying::new("Cached content".to_string(), prompt.to_string(), SayingSource::Cache)
         };
         
         // Save sayings
-        storage.save_saying(user_id, llm_saying.clone()).unwrap();
-        storage.save_saying(user_id, cached_saying.clone()).unwrap();
+        storage.save_saying(user_id, Arc::new(llm_saying.clone())).unwrap();
+        storage.save_saying(user_id, Arc::new(cached_saying.clone())).unwrap();
         
         // Test finding cached saying
-        let result = storage.find_cached_saying(prompt, preset_id.as_deref()).unwrap();
+        let key = |system_prompt: &str, prompt: &str| CacheKey::new(preset_id.clone(), system_prompt, prompt.to_string(), "en", "model");
+        let result = storage.find_cached_saying(&key("system", prompt)).unwrap();
         
         // Should find cached_saying, not llm_saying
         assert!(result.is_some());
@@ -566,7 +3064,695 @@ mod tests {
         assert!(matches!(found.source, SayingSource::Cache));
         
         // Test with non-existent prompt
-        let no_result = storage.find_cached_saying("nonexistent", preset_id.as_deref()).unwrap();
+        let no_result = storage.find_cached_saying(&key("system", "nonexistent")).unwrap();
         assert!(no_result.is_none());
+        
+        // Another preset's system prompt with the same user prompt is a different entry
+        assert!(storage.find_cached_saying(&key("other system", prompt)).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_sled_storage_get_saying_by_id() {
+        let temp_dir = tempdir().unwrap();
+        let db_path = temp_dir.path().join("test-sled-db");
+        let storage = SledStorage::new(db_path.to_str().unwrap()).unwrap();
+        
+        let saying = Saying::new("Indexed content".to_string(), "test prompt".to_string(), SayingSource::LLM);
+        storage.save_saying("indexed_user", Arc::new(saying.clone())).unwrap();
+        
+        let (user_id, found) = storage.get_saying_by_id(&saying.id).unwrap().unwrap();
+        assert_eq!(user_id, "indexed_user");
+        assert_eq!(found.content, saying.content);
+        
+        assert!(storage.get_saying_by_id("missing").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_sled_storage_edited_saying_keeps_versions() {
+        let temp_dir = tempdir().unwrap();
+        let db_path = temp_dir.path().join("test-sled-db");
+        let storage = SledStorage::new(db_path.to_str().unwrap()).unwrap();
+        
+        let saying = Saying::new("Generated".to_string(), "test prompt".to_string(), SayingSource::Cache);
+        storage.save_saying("editor", Arc::new(saying.clone())).unwrap();
+        
+        let mut edited = saying.clone();
+        edited.edit("Tweaked".to_string());
+        assert!(storage.replace_saying("editor", Arc::new(edited)).unwrap());
+        assert!(!storage.replace_saying("someone_else", Arc::new(saying.clone())).unwrap());
+        
+        let (_, found) = storage.get_saying_by_id(&saying.id).unwrap().unwrap();
+        assert_eq!(found.content, "Tweaked");
+        let versions: Vec<_> = found.versions.iter().map(|v| (v.content.as_str(), v.edited)).collect();
+        assert_eq!(versions, [("Generated", false), ("Tweaked", true)]);
+        
+        // Other users are still served the generated text, never the edit
+        let pool = storage.get_any_cached_sayings(10, false).unwrap();
+        assert!(pool.iter().all(|s| s.content == "Generated"));
+    }
+
+    #[test]
+    fn test_sled_storage_pruning_skips_pinned_sayings() {
+        let temp_dir = tempdir().unwrap();
+        let db_path = temp_dir.path().join("test-sled-db");
+        let storage = SledStorage::new(db_path.to_str().unwrap()).unwrap();
+        
+        let saying_at = |content: &str, days_ago: i64, pinned: bool| Saying {
+            created_at: Utc::now() - chrono::Duration::days(days_ago),
+            pinned,
+            ..Saying::new(content.to_string(), "prompt".to_string(), SayingSource::LLM)
+        };
+        let old_pinned = saying_at("old pinned", 30, true);
+        let old = saying_at("old", 20, false);
+        for saying in [old_pinned.clone(), old.clone(), saying_at("older", 3, false), saying_at("newer", 2, false), saying_at("newest", 1, false)] {
+            storage.save_saying("keeper", Arc::new(saying)).unwrap();
+        }
+        
+        // Keep two unpinned sayings from the last week
+        assert_eq!(storage.prune_sayings("keeper", 2, Some(Utc::now() - chrono::Duration::days(7))).unwrap(), 2);
+        
+        let contents: Vec<_> = storage.get_sayings("keeper", usize::MAX).unwrap().iter().map(|s| s.content.clone()).collect();
+        assert_eq!(contents, ["newest", "newer", "old pinned"]);
+        assert!(storage.get_saying_by_id(&old.id).unwrap().is_none());
+        assert!(storage.get_saying_by_id(&old_pinned.id).unwrap().is_some());
+        assert_eq!(storage.prune_sayings("keeper", 2, None).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_sled_storage_tag_index_follows_edits() {
+        let temp_dir = tempdir().unwrap();
+        let db_path = temp_dir.path().join("test-sled-db");
+        let storage = SledStorage::new(db_path.to_str().unwrap()).unwrap();
+        
+        let tagged = |content: &str, tags: &[&str]| Saying {
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            ..Saying::new(content.to_string(), "prompt".to_string(), SayingSource::LLM)
+        };
+        let first = tagged("first", &["motivation", "work"]);
+        storage.save_saying("tagger", Arc::new(first.clone())).unwrap();
+        storage.save_saying("tagger", Arc::new(tagged("second", &["work"]))).unwrap();
+        storage.save_saying("other", Arc::new(tagged("theirs", &["motivation"]))).unwrap();
+        
+        let contents = |tag: &str| -> Vec<String> {
+            storage.get_sayings_by_tag("tagger", tag, 10).unwrap().iter().map(|s| s.content.clone()).collect()
+        };
+        assert_eq!(contents("motivation"), ["first"]);
+        assert_eq!(contents("work"), ["second", "first"]);
+        
+        let retagged = Saying { tags: vec!["focus".to_string()], ..first };
+        assert!(storage.replace_saying("tagger", Arc::new(retagged)).unwrap());
+        assert!(contents("motivation").is_empty());
+        assert_eq!(contents("work"), ["second"]);
+        assert_eq!(contents("focus"), ["first"]);
+    }
+
+    #[test]
+    fn test_sled_storage_prompt_index_prefix_search() {
+        let temp_dir = tempdir().unwrap();
+        let db_path = temp_dir.path().join("test-sled-db");
+        let storage = SledStorage::new(db_path.to_str().unwrap()).unwrap();
+        
+        let now = Utc::now();
+        storage.record_prompt("typist", "Tell me about  patience", now).unwrap();
+        storage.record_prompt("typist", "tell me about patience", now).unwrap();
+        storage.record_prompt("typist", "Teach me chess", now).unwrap();
+        storage.record_prompt("other", "Tell me a joke", now).unwrap();
+        
+        let found = storage.find_prompts("typist", &prompt_search_key("TELL me")).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].prompt, "tell me about patience");
+        assert_eq!(found[0].uses, 2);
+        assert_eq!(storage.find_prompts("typist", "te").unwrap().len(), 2);
+        assert!(storage.find_prompts("typist", "joke").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_sled_storage_prompt_history_keeps_newest() {
+        let temp_dir = tempdir().unwrap();
+        let db_path = temp_dir.path().join("test-sled-db");
+        let storage = SledStorage::new(db_path.to_str().unwrap()).unwrap();
+        
+        for prompt in ["first", "second", "third"] {
+            let entry = PromptHistoryEntry::new(prompt.to_string(), "en".to_string(), crate::models::PromptOutcome::Failed);
+            storage.push_prompt_history("retrier", entry, 2).unwrap();
+        }
+        
+        let prompts: Vec<_> = storage.get_prompt_history("retrier", 10).unwrap().into_iter().map(|e| e.prompt).collect();
+        assert_eq!(prompts, ["third", "second"]);
+        assert_eq!(storage.get_prompt_history("retrier", 1).unwrap().len(), 1);
+        assert!(storage.get_prompt_history("someone_else", 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_sled_storage_freeform_prompts_newest_first() {
+        let temp_dir = tempdir().unwrap();
+        let db_path = temp_dir.path().join("test-sled-db");
+        let storage = SledStorage::new(db_path.to_str().unwrap()).unwrap();
+
+        for prompt in ["first", "second", "third"] {
+            storage.log_freeform_prompt(&FreeformPromptEntry::new("test_user".to_string(), prompt.to_string()), 100).unwrap();
+        }
+
+        let prompts: Vec<String> = storage.get_freeform_prompts(2).unwrap()
+            .into_iter()
+            .map(|entry| entry.prompt)
+            .collect();
+        assert_eq!(prompts, vec!["third", "second"]);
+    }
+
+    #[test]
+    fn test_freeform_prompt_log_keeps_newest() {
+        let temp_dir = tempdir().unwrap();
+        let db_path = temp_dir.path().join("test-sled-db");
+        let sled = SledStorage::new(db_path.to_str().unwrap()).unwrap();
+        let memory = MemoryStorage::new();
+
+        for prompt in ["first", "second", "third"] {
+            let entry = FreeformPromptEntry::new("test_user".to_string(), prompt.to_string());
+            sled.log_freeform_prompt(&entry, 2).unwrap();
+            memory.log_freeform_prompt(&entry, 2).unwrap();
+        }
+
+        for prompts in [sled.get_freeform_prompts(10).unwrap(), memory.get_freeform_prompts(10).unwrap()] {
+            let prompts: Vec<String> = prompts.into_iter().map(|entry| entry.prompt).collect();
+            assert_eq!(prompts, vec!["third", "second"]);
+        }
+        
+        // The count survives a reopen
+        drop(sled);
+        let sled = SledStorage::new(db_path.to_str().unwrap()).unwrap();
+        sled.log_freeform_prompt(&FreeformPromptEntry::new("test_user".to_string(), "fourth".to_string()), 2).unwrap();
+        assert_eq!(sled.get_freeform_prompts(10).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_sled_storage_conversations_are_per_user() {
+        let temp_dir = tempdir().unwrap();
+        let db_path = temp_dir.path().join("test-sled-db");
+        let storage = SledStorage::new(db_path.to_str().unwrap()).unwrap();
+
+        let message = |role: MessageRole, content: &str| ConversationMessage { role, content: content.to_string(), created_at: Utc::now() };
+        let older = Conversation {
+            messages: vec![message(MessageRole::User, "Hello")],
+            ..Conversation::new("alice".to_string(), None)
+        };
+        storage.save_conversation(&older).unwrap();
+        let newer = Conversation {
+            messages: vec![message(MessageRole::User, "What now?"), message(MessageRole::Assistant, "Rest.")],
+            updated_at: older.updated_at + Duration::seconds(1),
+            ..Conversation::new("alice".to_string(), Some("oracle".to_string()))
+        };
+        storage.save_conversation(&newer).unwrap();
+        // A user ID that extends another's doesn't show up in its prefix scan
+        storage.save_conversation(&Conversation::new("alice2".to_string(), None)).unwrap();
+
+        let conversations = storage.get_conversations("alice").unwrap();
+        assert_eq!(conversations.iter().map(|c| c.id.as_str()).collect::<Vec<_>>(), [newer.id.as_str(), older.id.as_str()]);
+        assert_eq!(storage.get_conversation("alice", &newer.id).unwrap().unwrap().messages.len(), 2);
+        assert!(storage.get_conversation("bob", &newer.id).unwrap().is_none());
+
+        assert!(storage.delete_conversation("alice", &older.id).unwrap());
+        assert!(!storage.delete_conversation("alice", &older.id).unwrap());
+        assert_eq!(storage.get_conversations("alice").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_sled_storage_collections_are_per_user_and_found_by_share_token() {
+        let temp_dir = tempdir().unwrap();
+        let db_path = temp_dir.path().join("test-sled-db");
+        let storage = SledStorage::new(db_path.to_str().unwrap()).unwrap();
+
+        let collection = |user_id: &str, name: &str, created_at: DateTime<Utc>| Collection {
+            id: uuid::Uuid::new_v4().to_string(),
+            user_id: user_id.to_string(),
+            name: name.to_string(),
+            saying_ids: vec!["saying-1".to_string()],
+            created_at,
+            share_token: None,
+        };
+        let now = Utc::now();
+        let older = storage.save_collection(collection("alice", "Favorites", now)).unwrap();
+        let newer = storage.save_collection(collection("alice", "Morning", now + Duration::seconds(1))).unwrap();
+        storage.save_collection(collection("bob", "Bob's", now)).unwrap();
+
+        let names: Vec<String> = storage.get_collections("alice").unwrap().into_iter().map(|c| c.name).collect();
+        assert_eq!(names, ["Morning", "Favorites"]);
+        assert_eq!(storage.get_collection(&older.id).unwrap().unwrap().saying_ids, ["saying-1"]);
+        assert!(storage.get_collection("missing").unwrap().is_none());
+
+        // Unshared collections can't be found by any token, shared ones only by their own
+        assert!(storage.find_collection_by_share_token("token").unwrap().is_none());
+        storage.save_collection(Collection { share_token: Some("token".to_string()), ..newer.clone() }).unwrap();
+        assert_eq!(storage.find_collection_by_share_token("token").unwrap().unwrap().id, newer.id);
+        assert!(storage.find_collection_by_share_token("other").unwrap().is_none());
+        assert_eq!(storage.get_collections("alice").unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_sled_storage_shadow_outputs_newest_first() {
+        let temp_dir = tempdir().unwrap();
+        let db_path = temp_dir.path().join("test-sled-db");
+        let storage = SledStorage::new(db_path.to_str().unwrap()).unwrap();
+
+        for (i, saying_id) in ["first", "second", "third"].iter().enumerate() {
+            storage.save_shadow_output(&ShadowOutput {
+                id: uuid::Uuid::new_v4().to_string(),
+                saying_id: saying_id.to_string(),
+                preset_id: None,
+                prompt: "prompt".to_string(),
+                primary_model: Some("primary".to_string()),
+                primary_content: "primary answer".to_string(),
+                shadow_model: "shadow".to_string(),
+                shadow_content: (i != 1).then(|| "shadow answer".to_string()),
+                shadow_error: (i == 1).then(|| "timed out".to_string()),
+                shadow_latency_ms: 100,
+                created_at: Utc::now() + Duration::seconds(i as i64),
+            }).unwrap();
+        }
+
+        let outputs = storage.get_shadow_outputs(2).unwrap();
+        assert_eq!(outputs.iter().map(|o| o.saying_id.as_str()).collect::<Vec<_>>(), ["third", "second"]);
+        assert_eq!(outputs[1].shadow_error.as_deref(), Some("timed out"));
+    }
+
+    #[test]
+    fn test_sled_storage_dedupes_fallback_pool_by_content() {
+        let temp_dir = tempdir().unwrap();
+        let db_path = temp_dir.path().join("test-sled-db");
+        let storage = SledStorage::new(db_path.to_str().unwrap()).unwrap();
+        
+        // The same quote cached under two prompts, differing only in case and spacing
+        for (prompt, content) in [("first prompt", "Fortune  favors the bold."), ("second prompt", "fortune favors the bold."), ("third prompt", "Carpe diem.")] {
+            storage.save_saying("user", Arc::new(Saying::new(content.to_string(), prompt.to_string(), SayingSource::Cache))).unwrap();
+        }
+        
+        assert_eq!(storage.get_any_cached_sayings(3, false).unwrap().len(), 3);
+        assert_eq!(storage.get_any_cached_sayings(10, true).unwrap().len(), 2);
+        
+        // Replacing a key's content moves it to the new content's index entry
+        storage.save_saying("user", Arc::new(Saying::new("Veni, vidi, vici.".to_string(), "first prompt".to_string(), SayingSource::Cache))).unwrap();
+        assert_eq!(storage.get_any_cached_sayings(10, true).unwrap().len(), 3);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_memory_storage_stale_entries_leave_fallback_pool() {
+        let storage = MemoryStorage::new();
+        let cached = |content: &str, prompt: &str| Arc::new(Saying {
+            preset_id: Some("daily".to_string()),
+            ..Saying::new(content.to_string(), prompt.to_string(), SayingSource::Cache)
+        });
+        
+        let old = cached("Yesterday's quote", "old prompt");
+        storage.cache_saying(old.clone()).unwrap();
+        let fresh = cached("Today's quote", "new prompt");
+        storage.cache_saying(fresh.clone()).unwrap();
+        
+        assert_eq!(storage.mark_cache_stale("daily", fresh.created_at).unwrap(), 1);
+        assert_eq!(storage.mark_cache_stale("daily", fresh.created_at).unwrap(), 0);
+        
+        let pool = storage.get_any_cached_sayings(10, false).unwrap();
+        assert_eq!(pool.len(), 1);
+        assert_eq!(pool[0].content, fresh.content);
+        
+        // Still answers an exact prompt match
+        assert!(storage.find_cached_saying(&CacheKey::from_saying(&old)).unwrap().unwrap().stale);
+    }
+
+    #[test]
+    fn test_sled_storage_invalidate_cache() {
+        let temp_dir = tempdir().unwrap();
+        let db_path = temp_dir.path().join("test-sled-db");
+        let storage = SledStorage::new(db_path.to_str().unwrap()).unwrap();
+        
+        for (preset_id, prompt) in [("a", "one"), ("a", "two"), ("b", "one")] {
+            storage.cache_saying(Arc::new(Saying {
+                preset_id: Some(preset_id.to_string()),
+                ..Saying::new(format!("{} {}", preset_id, prompt), prompt.to_string(), SayingSource::Cache)
+            })).unwrap();
+        }
+        assert_eq!(storage.list_cache_entries().unwrap().len(), 3);
+        
+        assert_eq!(storage.invalidate_cache(Some("a"), Some("one")).unwrap(), 1);
+        assert_eq!(storage.invalidate_cache(None, Some("one")).unwrap(), 1);
+        
+        let remaining = storage.list_cache_entries().unwrap();
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0].prompt, "two");
+        
+        // The content index no longer points at removed entries
+        assert_eq!(storage.get_any_cached_sayings(10, true).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_sled_storage_preset_stats() {
+        let temp_dir = tempdir().unwrap();
+        let db_path = temp_dir.path().join("test-sled-db");
+        let storage = SledStorage::new(db_path.to_str().unwrap()).unwrap();
+        
+        let now = Utc::now();
+        storage.record_preset_use("oracle", now).unwrap();
+        storage.record_preset_use("oracle", now).unwrap();
+        
+        let feedback = |saying_id: &str, score: u8| SayingFeedback {
+            saying_id: saying_id.to_string(),
+            user_id: "user".to_string(),
+            preset_id: Some("oracle".to_string()),
+            prompt: "p".to_string(),
+            score,
+            created_at: now,
+        };
+        storage.save_feedback(&feedback("s1", 5)).unwrap();
+        storage.save_feedback(&feedback("s2", 1)).unwrap();
+        // Re-rating replaces the earlier score instead of counting twice
+        storage.save_feedback(&feedback("s2", 2)).unwrap();
+        
+        let stats = storage.list_preset_stats().unwrap();
+        assert_eq!(stats.len(), 1);
+        assert_eq!(stats[0].sayings, 2);
+        assert_eq!(stats[0].last_used_at, Some(now));
+        assert_eq!(stats[0].feedback_count, 2);
+        assert_eq!(stats[0].average_feedback(), Some(3.5));
+    }
+
+    #[test]
+    fn test_sled_storage_concurrent_preset_uses_are_all_counted() {
+        let temp_dir = tempdir().unwrap();
+        let db_path = temp_dir.path().join("test-sled-db");
+        let storage = Arc::new(SledStorage::new(db_path.to_str().unwrap()).unwrap());
+        
+        let now = Utc::now();
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let storage = storage.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..25 {
+                        storage.record_preset_use("oracle", now).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        
+        assert_eq!(storage.list_preset_stats().unwrap()[0].sayings, 200);
+    }
+
+
+    #[test]
+    fn test_sled_storage_preset_selection_survives_reopen() {
+        let temp_dir = tempdir().unwrap();
+        let db_path = temp_dir.path().join("test-sled-db");
+        let selection = PresetSelectionRecord {
+            preset_id: "oracle".to_string(),
+            selected_at: Utc::now(),
+            expires_at: Utc::now() + chrono::Duration::hours(1),
+            pinned: true,
+        };
+        
+        {
+            let storage = SledStorage::new(db_path.to_str().unwrap()).unwrap();
+            storage.save_preset_selection("user", &selection).unwrap();
+            storage.push_recent_preset("user", "fortune", 2).unwrap();
+            storage.push_recent_preset("user", "oracle", 2).unwrap();
+            storage.push_recent_preset("user", "oracle", 2).unwrap();
+        }
+        
+        let storage = SledStorage::new(db_path.to_str().unwrap()).unwrap();
+        let restored = storage.get_preset_selection("user").unwrap().unwrap();
+        assert_eq!(restored.preset_id, "oracle");
+        assert!(restored.pinned);
+        assert_eq!(storage.get_recent_presets("user").unwrap(), ["oracle", "oracle"]);
+        assert!(storage.get_preset_selection("other").unwrap().is_none());
+    }
+
+
+    #[test]
+    fn test_sled_storage_first_claim_owns_user() {
+        let temp_dir = tempdir().unwrap();
+        let db_path = temp_dir.path().join("test-sled-db");
+        let storage = SledStorage::new(db_path.to_str().unwrap()).unwrap();
+        
+        assert_eq!(storage.get_user_owner("alice").unwrap(), None);
+        assert_eq!(storage.claim_user_owner("alice", "first").unwrap(), "first");
+        assert_eq!(storage.claim_user_owner("alice", "second").unwrap(), "first");
+        assert_eq!(storage.get_user_owner("alice").unwrap().as_deref(), Some("first"));
+        
+        // Operators can rebind a user
+        storage.set_user_owner("alice", "third").unwrap();
+        assert_eq!(storage.get_user_owner("alice").unwrap().as_deref(), Some("third"));
+    }
+
+    #[test]
+    fn test_sled_storage_translations_keyed_by_language() {
+        let temp_dir = tempdir().unwrap();
+        let db_path = temp_dir.path().join("test-sled-db");
+        let storage = SledStorage::new(db_path.to_str().unwrap()).unwrap();
+        
+        storage.save_translation(&SayingTranslation {
+            saying_id: "s1".to_string(),
+            language_id: "fr".to_string(),
+            content: "Bonjour".to_string(),
+            created_at: Utc::now(),
+        }).unwrap();
+        
+        assert_eq!(storage.get_translation("s1", "fr").unwrap().unwrap().content, "Bonjour");
+        assert!(storage.get_translation("s1", "de").unwrap().is_none());
+        assert!(storage.get_translation("s2", "fr").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_sled_storage_user_stats_include_older_history() {
+        let temp_dir = tempdir().unwrap();
+        let db_path = temp_dir.path().join("test-sled-db");
+        let storage = SledStorage::new(db_path.to_str().unwrap()).unwrap();
+        
+        let older = Saying {
+            preset_id: Some("oracle".to_string()),
+            language_id: Some("fr".to_string()),
+            usage: Some(OpenRouterUsage { prompt_tokens: Some(10), completion_tokens: Some(5), total_tokens: Some(15) }),
+            created_at: Utc::now() - chrono::Duration::days(2),
+            ..Saying::new("Older".to_string(), "prompt".to_string(), SayingSource::LLM)
+        };
+        storage.save_saying("user", Arc::new(older.clone())).unwrap();
+        // Simulate a history written before the aggregate existed
+        storage.db.open_tree(USER_STATS_TREE).unwrap().clear().unwrap();
+        assert_eq!(storage.get_user_stats("user").unwrap().sayings, 1);
+        
+        storage.save_saying("user", Arc::new(Saying {
+            preset_id: Some("oracle".to_string()),
+            ..Saying::new("Newer".to_string(), "prompt".to_string(), SayingSource::Cache)
+        })).unwrap();
+        
+        let stats = storage.get_user_stats("user").unwrap();
+        assert_eq!(stats.sayings, 2);
+        assert_eq!(stats.by_preset["oracle"], 2);
+        assert_eq!(stats.by_language["fr"], 1);
+        assert_eq!(stats.by_source["llm"], 1);
+        assert_eq!(stats.by_source["cache"], 1);
+        assert_eq!(stats.total_tokens, 15);
+        assert_eq!(stats.first_activity_at, Some(older.created_at));
+        assert!(stats.last_activity_at > Some(older.created_at));
+        // A day without sayings in between
+        assert_eq!(stats.streak(crate::streaks::offset_from_minutes(0), Utc::now()), crate::streaks::Streak { current: 1, longest: 1 });
+        assert_eq!(storage.get_user_stats("nobody").unwrap().sayings, 0);
+        
+        // Only the older saying reported tokens
+        assert_eq!(storage.tokens_used_since("user", Utc::now() - chrono::Duration::days(3)).unwrap(), 15);
+        assert_eq!(storage.tokens_used_since("user", Utc::now() - chrono::Duration::days(1)).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_user_ids_are_stored_hashed() {
+        let temp_dir = tempdir().unwrap();
+        let db_path = temp_dir.path().join("test-sled-db");
+        let config = |user_id_salt: Option<&str>| StorageConfig {
+            type_: StorageType::Sled,
+            connection_string: db_path.to_str().unwrap().to_string(),
+            dedupe_by_content: false,
+            max_sayings_per_user: 0,
+            retention_days: 0,
+            fallback_policy: StorageFallbackPolicy::Fail,
+            compression_level: 0,
+            user_id_salt: user_id_salt.map(str::to_string),
+        };
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        let saying = Arc::new(Saying::new("Content".to_string(), "prompt".to_string(), SayingSource::LLM));
+        let conversation = Conversation::new("device-1234".to_string(), None);
+        
+        // Written before hashing was turned on
+        runtime.block_on(async {
+            let storage = Storage::new(config(None)).unwrap();
+            storage.save_saying("device-1234", saying.clone()).await.unwrap();
+            storage.save_conversation(&conversation).await.unwrap();
+            storage.save_ban(&Ban { subject: crate::bans::BanSubject::User("device-1234".to_string()), mode: crate::bans::BanMode::Ban, reason: None, created_at: Utc::now() }).await.unwrap();
+            storage.save_exemption(&Exemption {
+                subject: crate::exemptions::ExemptSubject::User("device-1234".to_string()),
+                reason: None,
+                created_at: Utc::now(),
+            }).await.unwrap();
+            storage.save_flag_override(&FlagOverride {
+                name: "testers".to_string(),
+                rule: crate::flags::FlagRule { users: vec!["device-1234".to_string()], ..Default::default() },
+                updated_at: Utc::now(),
+            }).await.unwrap();
+        });
+        
+        {
+            let db = sled::open(&db_path).unwrap();
+            let hasher = UserIdHasher::new("pepper");
+            let preview = hash_user_ids(&db, &hasher, true).unwrap();
+            assert!(db.contains_key("device-1234").unwrap());
+            assert_eq!(hash_user_ids(&db, &hasher, false).unwrap(), preview);
+            assert_eq!(hash_user_ids(&db, &hasher, false).unwrap(), UserIdHashReport::default());
+            
+            // Nothing in the file names the user any more
+            for name in db.tree_names() {
+                for entry in db.open_tree(name).unwrap().iter() {
+                    let (key, value) = entry.unwrap();
+                    assert!(!String::from_utf8_lossy(&key).contains("device-1234"));
+                    assert!(!String::from_utf8_lossy(&value).contains("device-1234"));
+                }
+            }
+        }
+        
+        runtime.block_on(async {
+            let storage = Storage::new(config(Some("pepper"))).unwrap();
+            assert_eq!(storage.get_sayings("device-1234", 10).await.unwrap()[0].id, saying.id);
+            let restored = storage.get_conversation("device-1234", &conversation.id).await.unwrap().unwrap();
+            assert_eq!(restored.user_id, "device-1234");
+            
+            // Owners read back by saying ID are hashed, and work as user IDs all the same
+            let (owner, _) = storage.get_saying_by_id(&saying.id).await.unwrap().unwrap();
+            assert!(user_hash::is_hashed(&owner));
+            assert!(storage.same_user(&owner, "device-1234"));
+            assert_eq!(storage.get_sayings(&owner, 10).await.unwrap().len(), 1);
+            
+            // Bans are found again by the hashed subject
+            let bans = crate::bans::BanList::new(storage.user_id_hasher());
+            bans.load(&storage).await.unwrap();
+            assert_eq!(bans.mode_for("device-1234", None), Some(crate::bans::BanMode::Ban));
+        });
+    }
+
+    #[test]
+    fn test_compressed_and_plain_histories_read_alike() {
+        let temp_dir = tempdir().unwrap();
+        let db_path = temp_dir.path().join("test-sled-db");
+        let saying = |content: &str| Arc::new(Saying::new(content.repeat(20), "prompt".to_string(), SayingSource::Database));
+        let plain = saying("Ein langer Spruch. ");
+        let compressed = saying("一句很长的话。");
+        
+        {
+            let storage = SledStorage::new(db_path.to_str().unwrap()).unwrap();
+            storage.save_saying("plain", plain.clone()).unwrap();
+        }
+        
+        // Turning compression on leaves what is already stored readable
+        let storage = SledStorage::new(db_path.to_str().unwrap()).unwrap().with_compression(3);
+        storage.save_saying("compressed", compressed.clone()).unwrap();
+        let stored = storage.db.get("compressed").unwrap().unwrap();
+        assert!(stored.len() < serde_json::to_vec(std::slice::from_ref(&compressed)).unwrap().len());
+        
+        assert_eq!(storage.get_sayings("plain", 10).unwrap()[0].content, plain.content);
+        assert_eq!(storage.get_sayings("compressed", 10).unwrap()[0].content, compressed.content);
+        assert_eq!(storage.find_cached_saying(&CacheKey::from_saying(&compressed)).unwrap().unwrap().content, compressed.content);
+        
+        // And turning it off again leaves the compressed values readable
+        drop(storage);
+        let storage = SledStorage::new(db_path.to_str().unwrap()).unwrap();
+        assert_eq!(storage.get_saying_by_id(&compressed.id).unwrap().unwrap().1.content, compressed.content);
+    }
+
+    #[test]
+    fn test_fallback_policy_decides_whether_a_broken_backend_is_replaced() {
+        let temp_dir = tempdir().unwrap();
+        // A plain file where the database directory should be
+        let blocker = temp_dir.path().join("not-a-directory");
+        std::fs::write(&blocker, "").unwrap();
+        let config = |fallback_policy| StorageConfig {
+            type_: StorageType::Sled,
+            connection_string: blocker.join("db").to_str().unwrap().to_string(),
+            dedupe_by_content: false,
+            max_sayings_per_user: 0,
+            retention_days: 0,
+            fallback_policy,
+            compression_level: 0,
+            user_id_salt: None,
+        };
+
+        assert!(Storage::new(config(StorageFallbackPolicy::Fail)).is_err());
+        assert_eq!(Storage::new(config(StorageFallbackPolicy::Memory)).unwrap().backend(), "memory");
+    }
+
+    fn saying_strategy() -> impl Strategy<Value = Saying> {
+        let source = prop_oneof![Just(SayingSource::LLM), Just(SayingSource::Cache), Just(SayingSource::Database)];
+        (
+            any::<String>(),
+            any::<String>(),
+            source,
+            proptest::option::of("[a-z-]{1,12}"),
+            proptest::option::of("[a-z]{2}"),
+            proptest::collection::vec("[a-z0-9-]{1,10}", 0..3),
+            any::<bool>(),
+            0i64..4_000_000_000,
+        )
+            .prop_map(|(content, prompt, source, preset_id, language_id, tags, pinned, seconds)| Saying {
+                preset_id,
+                language_id,
+                tags,
+                pinned,
+                created_at: DateTime::from_timestamp(seconds, 0).unwrap(),
+                ..Saying::new(content, prompt, source)
+            })
+    }
+
+    // Saving and reading back through the public API, so both backends are held to the same
+    // contract; compared as JSON since that is what Sled stores
+    fn assert_round_trip(storage: Storage, saying: Saying) -> std::result::Result<(), TestCaseError> {
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        runtime.block_on(async {
+            let expected = serde_json::to_value(&saying).unwrap();
+            storage.save_saying("user", Arc::new(saying.clone())).await.unwrap();
+
+            let (user_id, stored) = storage.get_saying_by_id(&saying.id).await.unwrap().expect("saved saying not found");
+            prop_assert_eq!(user_id, "user");
+            prop_assert_eq!(serde_json::to_value(stored.as_ref()).unwrap(), expected.clone());
+
+            // Only sayings not fresh from the model go into the global cache
+            let cached = storage.find_cached_saying(&CacheKey::from_saying(&saying)).await.unwrap();
+            match saying.source {
+                SayingSource::LLM => prop_assert!(cached.is_none()),
+                _ => prop_assert_eq!(serde_json::to_value(cached.expect("cached saying not found").as_ref()).unwrap(), expected),
+            }
+            Ok(())
+        })
+    }
+
+    fn storage(inner: StorageImpl) -> Storage {
+        Storage { inner, dedupe_by_content: false, max_sayings_per_user: 0, retention_days: 0, user_ids: None }
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(32))]
+
+        #[test]
+        fn prop_memory_round_trip(saying in saying_strategy()) {
+            assert_round_trip(storage(StorageImpl::Memory(MemoryStorage::new())), saying)?;
+        }
+
+        #[test]
+        fn prop_sled_round_trip(saying in saying_strategy()) {
+            let temp_dir = tempdir().unwrap();
+            let sled = SledStorage::new(temp_dir.path().join("db").to_str().unwrap()).unwrap();
+            assert_round_trip(storage(StorageImpl::Sled(sled)), saying)?;
+        }
+
+        #[test]
+        fn prop_compressed_sled_round_trip(saying in saying_strategy()) {
+            let temp_dir = tempdir().unwrap();
+            let sled = SledStorage::new(temp_dir.path().join("db").to_str().unwrap()).unwrap().with_compression(3);
+            assert_round_trip(storage(StorageImpl::Sled(sled)), saying)?;
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use chrono::{DateTime, Duration, FixedOffset, NaiveDate, Utc};
+use serde::Serialize;
+
+// Consecutive days on which a user generated at least one saying
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Streak {
+    pub current: u32,
+    pub longest: u32,
+}
+
+// Compute streaks from saying timestamps, bucketing them into calendar days in the
+// user's timezone. The current streak stays alive until the end of the day after the
+// last active day, so users who haven't generated yet today don't see it drop to zero.
+pub fn compute_streak<I>(timestamps: I, offset: FixedOffset, now: DateTime<Utc>) -> Streak
+where
+    I: IntoIterator<Item = DateTime<Utc>>,
+{
+    let runs = daily_runs(timestamps, offset);
+    let Some(&(last_day, run)) = runs.last() else {
+        return Streak::default();
+    };
+
+    let longest = runs.iter().map(|&(_, run)| run).max().unwrap_or(0);
+    let today = now.with_timezone(&offset).date_naive();
+    let current = if today - last_day <= Duration::days(1) { run } else { 0 };
+
+    Streak { current, longest }
+}
+
+// Every active calendar day in the user's timezone, oldest first, with the length of the
+// streak that ends on it
+pub fn daily_runs<I>(timestamps: I, offset: FixedOffset) -> Vec<(NaiveDate, u32)>
+where
+    I: IntoIterator<Item = DateTime<Utc>>,
+{
+    let mut days: Vec<NaiveDate> = timestamps
+        .into_iter()
+        .map(|ts| ts.with_timezone(&offset).date_naive())
+        .collect();
+    days.sort_unstable();
+    days.dedup();
+
+    let mut runs: Vec<(NaiveDate, u32)> = Vec::with_capacity(days.len());
+    for day in days {
+        let run = match runs.last() {
+            Some(&(prev, run)) if day - prev == Duration::days(1) => run + 1,
+            _ => 1,
+        };
+        runs.push((day, run));
+    }
+    runs
+}
+
+// Convert a UTC offset in minutes (east positive) into a FixedOffset, clamping to valid range
+pub fn offset_from_minutes(minutes: i32) -> FixedOffset {
+    let seconds = minutes.clamp(-14 * 60, 14 * 60) * 60;
+    FixedOffset::east_opt(seconds).unwrap_or_else(|| FixedOffset::east_opt(0).unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(day: u32, hour: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 3, day, hour, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn test_streak_counts_consecutive_days() {
+        let utc = offset_from_minutes(0);
+        let history = vec![at(1, 10), at(2, 9), at(2, 20), at(3, 8), at(5, 12), at(6, 12)];
+
+        // Still alive on the day after the last activity
+        assert_eq!(compute_streak(history.clone(), utc, at(7, 12)), Streak { current: 2, longest: 3 });
+        // Broken after a missed day
+        assert_eq!(compute_streak(history, utc, at(8, 12)), Streak { current: 0, longest: 3 });
+    }
+
+    #[test]
+    fn test_streak_uses_user_timezone() {
+        // 23:00 UTC on the 1st is already the 2nd in UTC+2, so these are consecutive days there
+        let history = vec![at(1, 1), at(1, 23)];
+
+        assert_eq!(compute_streak(history.clone(), offset_from_minutes(0), at(2, 12)).current, 1);
+        assert_eq!(compute_streak(history, offset_from_minutes(120), at(2, 12)).current, 2);
+    }
+
+    #[test]
+    fn test_stats_streak_matches_history_in_any_timezone() {
+        use crate::models::{Saying, SayingSource, UserStats};
+
+        let history: Vec<Saying> = [at(1, 1), at(1, 23), at(2, 23), at(3, 12), at(3, 22), at(3, 23), at(5, 0)]
+            .into_iter()
+            .map(|created_at| Saying { created_at, ..Saying::new("c".to_string(), "p".to_string(), SayingSource::LLM) })
+            .collect();
+        let stats = UserStats::from_sayings("user", &history);
+
+        for minutes in [0, 7, 120, -330, 345, 14 * 60, -12 * 60] {
+            let offset = offset_from_minutes(minutes);
+            let expected = compute_streak(history.iter().map(|saying| saying.created_at), offset, at(5, 12));
+            assert_eq!(stats.streak(offset, at(5, 12)), expected, "offset {} minutes", minutes);
+        }
+    }
+}
@@ -0,0 +1,77 @@
+use std::sync::OnceLock;
+use tiktoken_rs::CoreBPE;
+
+use crate::config::ModelPrice;
+
+// Chat formats wrap every message in a few framing tokens, and prime the reply with a few more
+const TOKENS_PER_MESSAGE: usize = 4;
+const TOKENS_PER_REPLY: usize = 3;
+
+// cl100k_base doesn't match every model's tokenizer, but it is close enough to budget prompts.
+// Building it parses the bundled vocabulary, so it is done once.
+fn bpe() -> &'static CoreBPE {
+    static BPE: OnceLock<CoreBPE> = OnceLock::new();
+    BPE.get_or_init(|| tiktoken_rs::cl100k_base().expect("bundled cl100k_base vocabulary is valid"))
+}
+
+pub fn count(text: &str) -> usize {
+    bpe().encode_ordinary(text).len()
+}
+
+// Estimated prompt size of a system + user chat completion request
+pub fn estimate_chat(system_prompt: &str, user_prompt: &str) -> usize {
+    estimate_messages([system_prompt, user_prompt])
+}
+
+// Estimated prompt size of a chat completion request with messages of these contents
+pub fn estimate_messages<'a>(contents: impl IntoIterator<Item = &'a str>) -> usize {
+    contents.into_iter().map(|content| count(content) + TOKENS_PER_MESSAGE).sum::<usize>() + TOKENS_PER_REPLY
+}
+
+// Cost in USD of a call at the given price per million prompt and completion tokens
+pub fn cost(price: ModelPrice, prompt_tokens: usize, completion_tokens: usize) -> f64 {
+    (prompt_tokens as f64 * price.prompt + completion_tokens as f64 * price.completion) / 1_000_000.0
+}
+
+// Cut `text` down to at most `max_tokens` tokens; None when it already fits
+pub fn truncate(text: &str, max_tokens: usize) -> Option<String> {
+    let tokens = bpe().encode_ordinary(text);
+    if tokens.len() <= max_tokens {
+        return None;
+    }
+
+    // A token boundary can fall inside a multi-byte character, drop tokens until it decodes
+    let mut end = max_tokens;
+    loop {
+        if let Ok(truncated) = bpe().decode(tokens[..end].to_vec()) {
+            return Some(truncated);
+        }
+        end -= 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_truncate_keeps_prefix_within_budget() {
+        let text = "The quick brown fox jumps over the lazy dog. ".repeat(20);
+        assert!(truncate(&text, 1000).is_none());
+
+        let truncated = truncate(&text, 10).unwrap();
+        assert!(text.starts_with(&truncated));
+        assert!(count(&truncated) <= 10);
+
+        // Never splits a multi-byte character
+        let truncated = truncate(&"日本語のことわざ".repeat(10), 5).unwrap();
+        assert!(count(&truncated) <= 5);
+    }
+
+    #[test]
+    fn test_cost_is_priced_per_million_tokens() {
+        let price = ModelPrice { prompt: 0.5, completion: 1.5 };
+        assert_eq!(cost(price, 1_000_000, 0), 0.5);
+        assert_eq!(cost(price, 2000, 1000), 0.0025);
+    }
+}
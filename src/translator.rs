@@ -0,0 +1,176 @@
+use anyhow::{anyhow, Context, Result};
+use axum::async_trait;
+use reqwest::Client;
+use serde_json::json;
+
+use crate::config::{TranslationConfig, TranslatorKind};
+use crate::glossary::Glossary;
+use crate::languages::{self, Language};
+use crate::openrouter::OpenRouterClient;
+
+// Turns English sayings into other languages. The LLM translator works inside the generation
+// prompt; dedicated services get English from the model and translate it afterwards.
+#[async_trait]
+pub trait Translator: Send + Sync {
+    // Short name for logs and the dashboard
+    fn name(&self) -> &'static str;
+
+    // Whether translation instructions go into the generation prompt instead of a separate call
+    fn translates_in_prompt(&self) -> bool {
+        false
+    }
+
+    async fn translate(&self, text: &str, language: &Language) -> Result<String>;
+}
+
+// The language model translates its own output, as it always has
+pub struct LlmTranslator {
+    openrouter: OpenRouterClient,
+    glossary: Glossary,
+}
+
+#[async_trait]
+impl Translator for LlmTranslator {
+    fn name(&self) -> &'static str {
+        "llm"
+    }
+
+    fn translates_in_prompt(&self) -> bool {
+        true
+    }
+
+    async fn translate(&self, text: &str, language: &Language) -> Result<String> {
+        let system_prompt = languages::translate_only_prompt(language, &self.glossary);
+        Ok(self.openrouter.get_saying_with_system(&system_prompt, text).await?.content)
+    }
+}
+
+pub struct DeepLTranslator {
+    client: Client,
+    api_key: String,
+    base_url: String,
+}
+
+#[async_trait]
+impl Translator for DeepLTranslator {
+    fn name(&self) -> &'static str {
+        "deepl"
+    }
+
+    async fn translate(&self, text: &str, language: &Language) -> Result<String> {
+        let response = self.client
+            .post(format!("{}/v2/translate", self.base_url))
+            .header("Authorization", format!("DeepL-Auth-Key {}", self.api_key))
+            .json(&json!({
+                "text": [text],
+                "source_lang": "EN",
+                "target_lang": deepl_target(&language.id),
+            }))
+            .send()
+            .await
+            .context("Failed to reach DeepL")?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(anyhow!("DeepL returned error {}: {}", status.as_u16(), body));
+        }
+
+        let body: serde_json::Value = response.json().await.context("Failed to parse DeepL response")?;
+        body["translations"][0]["text"]
+            .as_str()
+            .map(str::to_string)
+            .ok_or_else(|| anyhow!("DeepL response has no translation"))
+    }
+}
+
+// DeepL target codes are upper case and need a variant for English, Portuguese and Chinese
+fn deepl_target(language_id: &str) -> String {
+    match language_id {
+        "en" => "EN-US".to_string(),
+        "pt" => "PT-PT".to_string(),
+        "zh-TW" => "ZH-HANT".to_string(),
+        "zh-CN" => "ZH-HANS".to_string(),
+        other => other.to_uppercase(),
+    }
+}
+
+pub struct GoogleTranslator {
+    client: Client,
+    api_key: String,
+    base_url: String,
+}
+
+#[async_trait]
+impl Translator for GoogleTranslator {
+    fn name(&self) -> &'static str {
+        "google"
+    }
+
+    async fn translate(&self, text: &str, language: &Language) -> Result<String> {
+        let response = self.client
+            .post(format!("{}/language/translate/v2", self.base_url))
+            .query(&[("key", self.api_key.as_str())])
+            .json(&json!({
+                "q": text,
+                "source": "en",
+                "target": language.id,
+                "format": "text",
+            }))
+            .send()
+            .await
+            .context("Failed to reach Google Translate")?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(anyhow!("Google Translate returned error {}: {}", status.as_u16(), body));
+        }
+
+        let body: serde_json::Value = response.json().await.context("Failed to parse Google Translate response")?;
+        body["data"]["translations"][0]["translatedText"]
+            .as_str()
+            .map(str::to_string)
+            .ok_or_else(|| anyhow!("Google Translate response has no translation"))
+    }
+}
+
+pub fn from_config(config: &TranslationConfig, openrouter: OpenRouterClient, client: Client, glossary: Glossary) -> Box<dyn Translator> {
+    if config.provider != TranslatorKind::Llm && config.api_key.is_empty() {
+        tracing::warn!("TRANSLATION_PROVIDER is {:?} but TRANSLATION_API_KEY is not set; translations will fail", config.provider);
+    }
+
+    match config.provider {
+        TranslatorKind::Llm => Box::new(LlmTranslator { openrouter, glossary }),
+        TranslatorKind::DeepL => Box::new(DeepLTranslator {
+            client,
+            api_key: config.api_key.clone(),
+            // Free-plan keys end in `:fx` and are served from a separate host
+            base_url: config.base_url.clone().unwrap_or_else(|| {
+                if config.api_key.ends_with(":fx") {
+                    "https://api-free.deepl.com".to_string()
+                } else {
+                    "https://api.deepl.com".to_string()
+                }
+            }),
+        }),
+        TranslatorKind::Google => Box::new(GoogleTranslator {
+            client,
+            api_key: config.api_key.clone(),
+            base_url: config.base_url.clone().unwrap_or_else(|| "https://translation.googleapis.com".to_string()),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deepl_target_codes() {
+        assert_eq!(deepl_target("en"), "EN-US");
+        assert_eq!(deepl_target("zh-TW"), "ZH-HANT");
+        assert_eq!(deepl_target("zh-CN"), "ZH-HANS");
+        assert_eq!(deepl_target("ja"), "JA");
+    }
+}
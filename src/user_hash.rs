@@ -0,0 +1,66 @@
+use sha2::{Digest, Sha256};
+
+// Start of a hashed user ID. User IDs in requests can't start with it, so IDs read back from
+// storage (by the leaderboard, say) can be passed in again without being hashed twice.
+pub const HASHED_PREFIX: &str = "uid-sha256:";
+
+// Salted SHA-256 of user IDs, so a copy of the database doesn't name the users in it
+#[derive(Debug, Clone)]
+pub struct UserIdHasher {
+    salt: String,
+}
+
+impl UserIdHasher {
+    pub fn new(salt: impl Into<String>) -> Self {
+        Self { salt: salt.into() }
+    }
+
+    // The ID as storage keeps it; IDs that are already hashed come back as they are
+    pub fn hash(&self, user_id: &str) -> String {
+        if is_hashed(user_id) {
+            return user_id.to_string();
+        }
+
+        let mut hasher = Sha256::new();
+        hasher.update(self.salt.as_bytes());
+        hasher.update([0]);
+        hasher.update(user_id.as_bytes());
+        let digest: String = hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect();
+        format!("{}{}", HASHED_PREFIX, digest)
+    }
+}
+
+// The ID records are stored under: its hash when there's a hasher, else the ID itself
+pub fn user_key(hasher: Option<&UserIdHasher>, user_id: &str) -> String {
+    match hasher {
+        Some(hasher) => hasher.hash(user_id),
+        None => user_id.to_string(),
+    }
+}
+
+pub fn is_hashed(user_id: &str) -> bool {
+    user_id
+        .strip_prefix(HASHED_PREFIX)
+        .is_some_and(|digest| digest.len() == 64 && digest.bytes().all(|byte| byte.is_ascii_hexdigit()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hashes_depend_on_the_salt_and_are_not_hashed_twice() {
+        let hasher = UserIdHasher::new("pepper");
+        let hashed = hasher.hash("device-1234");
+
+        assert!(is_hashed(&hashed));
+        assert!(!hashed.contains("device-1234"));
+        assert_eq!(hasher.hash("device-1234"), hashed);
+        assert_eq!(hasher.hash(&hashed), hashed);
+        assert_ne!(hasher.hash("device-5678"), hashed);
+        assert_ne!(UserIdHasher::new("salt").hash("device-1234"), hashed);
+
+        // Only the exact form counts as hashed
+        assert!(!is_hashed("uid-sha256:not-a-digest"));
+    }
+}
@@ -0,0 +1,187 @@
+use anyhow::Result;
+use std::sync::Arc;
+
+use crate::invalidation::Invalidation;
+use crate::languages::{self, TranslationMode, DEFAULT_LANGUAGE_ID};
+use crate::metrics::Metrics;
+use crate::models::{self, Saying, SayingSource};
+use crate::openrouter::GenerationOptions;
+use crate::preset::Preset;
+use crate::AppState;
+
+// Which (preset, language) pairs to generate, at most `budget` of them. Every preset is
+// covered in one language before the next language starts, so a small budget still
+// spreads across presets.
+fn targets<'a>(presets: &'a [Preset], languages: &'a [String], budget: usize) -> Vec<(&'a Preset, &'a str)> {
+    languages
+        .iter()
+        .flat_map(|language_id| presets.iter().map(move |preset| (preset, language_id.as_str())))
+        .take(budget)
+        .collect()
+}
+
+// The global cache is served to everyone, so hidden and tiered presets are left out
+pub(crate) fn public_presets(state: &AppState) -> Vec<Preset> {
+    state.presets.get_all_presets()
+        .into_iter()
+        .filter(|preset| preset.is_listed_for(0))
+        .collect()
+}
+
+// Generate one saying per preset per configured language straight into the global cache.
+// Returns how many were cached; a failed generation uses up its share of the budget.
+pub async fn warm_up(state: &AppState) -> Result<usize> {
+    let config = &state.config.cache_warmup;
+    let known = languages::get_all_languages();
+    let language_ids: Vec<String> = config.languages
+        .iter()
+        .filter(|id| {
+            let supported = known.iter().any(|language| &language.id == *id);
+            if !supported {
+                tracing::warn!("Skipping unknown cache warm-up language: {}", id);
+            }
+            supported
+        })
+        .cloned()
+        .collect();
+    let presets = public_presets(state);
+
+    let mut cached = 0;
+    for (preset, language_id) in targets(&presets, &language_ids, config.budget) {
+        match generate(state, preset, language_id).await {
+            Ok(saying) => {
+                state.storage.cache_saying(saying).await?;
+                cached += 1;
+            }
+            Err(e) => {
+                tracing::warn!("Cache warm-up failed for preset {} in {}: {:#}", preset.id, language_id, e);
+            }
+        }
+    }
+
+    Ok(cached)
+}
+
+pub(crate) async fn generate(state: &AppState, preset: &Preset, language_id: &str) -> Result<Arc<Saying>> {
+    let prompt = state.presets.random_user_prompt(&preset.id)?;
+    let mode = preset.translation_mode.unwrap_or_default();
+    // A translation service gets English from the model, like in regular generation
+    let prompt_language = if state.translator.translates_in_prompt() { language_id } else { DEFAULT_LANGUAGE_ID };
+    let system_prompt = languages::with_translation_prompt(state.config.branding.apply(preset.system_prompt.clone()), prompt_language, mode, &state.glossary);
+    let options = GenerationOptions {
+        provider: preset.provider.clone(),
+        constraints: Some(preset.constraints.clone()),
+        translated: mode == TranslationMode::Bilingual && prompt_language != DEFAULT_LANGUAGE_ID,
+        // Background work doesn't jump the line
+        priority: 0,
+        model: None,
+        mock: false,
+    };
+
+    let saying = state.openrouter.get_saying_with_options(&system_prompt, &prompt, &options).await
+        .inspect_err(|_| Metrics::incr(&state.metrics.upstream_errors))?;
+    Metrics::incr(&state.metrics.sayings_generated);
+
+    let content = if prompt_language == language_id {
+        languages::parse_response(saying.content, language_id, mode)
+    } else {
+        let translation = state.translator.translate(&saying.content, &languages::get_language_by_id(language_id)).await?;
+        languages::compose(saying.content, translation, mode)
+    };
+
+    // Stored as a cache entry so it is eligible for the global cache like any other
+    let prompt_hash = models::prompt_hash(&system_prompt, &prompt, language_id, saying.model.as_deref().unwrap_or_default());
+    Ok(Arc::new(Saying {
+        content,
+        source: SayingSource::Cache,
+        preset_id: Some(preset.id.clone()),
+        language_id: Some(language_id.to_string()),
+        prompt_hash: Some(prompt_hash),
+        ..saying
+    }))
+}
+
+// Give every preset a fresh cache entry and retire the older ones from the fallback pool.
+// A preset whose generation fails keeps its current entries.
+pub async fn refresh_presets(state: &AppState) -> Result<usize> {
+    let mut refreshed = 0;
+    for preset in public_presets(state) {
+        let saying = match generate(state, &preset, DEFAULT_LANGUAGE_ID).await {
+            Ok(saying) => saying,
+            Err(e) => {
+                tracing::warn!("Cache refresh failed for preset {}: {:#}", preset.id, e);
+                continue;
+            }
+        };
+
+        state.storage.cache_saying(saying.clone()).await?;
+        let stale = state.storage.mark_cache_stale(&preset.id, saying.created_at).await?;
+        state.invalidations.publish(Invalidation::StaleCache { preset_id: preset.id.clone(), before: saying.created_at }).await;
+        tracing::debug!("Refreshed cache for preset {}, {} entries now stale", preset.id, stale);
+        refreshed += 1;
+    }
+
+    Ok(refreshed)
+}
+
+// Warm the cache in the background so startup isn't held up by LLM calls
+pub fn spawn_task(state: Arc<AppState>) {
+    if !state.config.cache_warmup.enabled || !state.leader.is_leader() {
+        return;
+    }
+
+    tokio::spawn(async move {
+        match warm_up(&state).await {
+            Ok(cached) => tracing::info!("Cache warm-up stored {} sayings", cached),
+            Err(e) => tracing::error!("Cache warm-up failed: {:#}", e),
+        }
+    });
+}
+
+// Refresh every preset's cache entry once per interval (daily by default). The first run
+// is one interval after startup; warm-up covers the cache before that.
+pub fn spawn_refresh_task(state: Arc<AppState>) {
+    if !state.config.cache_refresh.enabled {
+        return;
+    }
+
+    let period = std::time::Duration::from_secs(state.config.cache_refresh.interval_hours.max(1) * 3600);
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+        loop {
+            ticker.tick().await;
+            if !state.leader.is_leader() {
+                continue;
+            }
+            match refresh_presets(&state).await {
+                Ok(refreshed) => tracing::info!("Refreshed cached sayings for {} presets", refreshed),
+                Err(e) => tracing::error!("Failed to refresh cached sayings: {:#}", e),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_targets_cover_presets_before_languages() {
+        let presets: Vec<Preset> = ["a", "b", "c"]
+            .iter()
+            .map(|id| serde_json::from_value(serde_json::json!({
+                "id": id, "name": id, "description": "", "tags": [], "button_text": "",
+                "loading_text": "", "instruction_text": "", "system_prompt": "", "user_prompts": ["p"],
+            })).unwrap())
+            .collect();
+        let languages = vec!["en".to_string(), "fr".to_string()];
+
+        let pairs: Vec<(&str, &str)> = targets(&presets, &languages, 4)
+            .into_iter()
+            .map(|(preset, language)| (preset.id.as_str(), language))
+            .collect();
+        assert_eq!(pairs, [("a", "en"), ("b", "en"), ("c", "en"), ("a", "fr")]);
+        assert_eq!(targets(&presets, &languages, 100).len(), 6);
+    }
+}